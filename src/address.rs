@@ -0,0 +1,155 @@
+use crate::encoding::Encoding;
+use crate::line::Line;
+use crate::special;
+
+// バッファの中の位置の、いくつかの表し方を互いに変える
+//   バイト: 保存したときのファイルの先頭からのバイト数 (0 始まり)。改行は LineEnding の長さに数える
+//   (行, 列): 行の番号と、行の中の文字の添字 (Cursor)
+//   表示上の列: 折り返さないときの、行頭からの画面上の幅。タブや全角の文字は1より広い
+// 読み込むときに行末の空白は落とすので、そういう行より後ろのバイトは保存するまでディスクのファイルとずれる
+
+// バッファの中の位置。行の番号と、行の中の文字の添字 (どちらも 0 始まり)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Cursor {
+    pub row: usize,
+    pub column: usize,
+}
+
+// 改行の書き方。読み込んだファイルに合わせて、保存するときも同じように書く
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    CrLf,
+}
+
+impl LineEnding {
+    // 最初の改行の書き方にする。改行がなければ Lf
+    pub fn detect(bytes: &[u8]) -> Self {
+        match bytes.iter().position(|&b| b == b'\n') {
+            Some(end) if end > 0 && bytes[end - 1] == b'\r' => LineEnding::CrLf,
+            _ => LineEnding::Lf,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+        }
+    }
+
+    fn len(self) -> usize {
+        self.as_str().len()
+    }
+}
+
+// 行の文字 (か、その先頭の一部) を保存したときのバイト数。改行は数えない
+pub fn line_bytes<'a>(chars: impl IntoIterator<Item = &'a char>, encoding: Encoding) -> usize {
+    chars.into_iter().map(|&c| encoding.char_len(c)).sum()
+}
+
+// 各行の先頭のバイト位置の控え。ステータス行のように何度も数えるところで、毎回ファイルの先頭から足さない
+// 前から順に数えたところまで持ち、行を変えたらその行より後ろを捨てて、次に要るときに数え直す
+#[derive(Default)]
+pub struct LineStarts {
+    // starts[k] は k 行目の先頭
+    starts: Vec<usize>,
+    // 数えたときの文字コードと改行。変わっていたら数え直す
+    counted: Option<(Encoding, LineEnding)>,
+}
+
+impl LineStarts {
+    // row 行目を変えた。次の行から後ろの先頭がずれる
+    pub fn invalidate(&mut self, row: usize) {
+        self.starts.truncate(row + 1);
+    }
+
+    pub fn clear(&mut self) {
+        self.starts.clear();
+    }
+
+    // row 行目までの先頭を数えておく
+    pub fn fill(&mut self, buffer: &[Line], row: usize, encoding: Encoding, ending: LineEnding) {
+        if self.counted != Some((encoding, ending)) {
+            self.starts.clear();
+            self.counted = Some((encoding, ending));
+        }
+        if self.starts.is_empty() {
+            self.starts.push(0);
+        }
+        while self.starts.len() <= row {
+            let last = self.starts.len() - 1;
+            self.starts.push(self.starts[last] + line_bytes(buffer[last].iter(), encoding) + ending.len());
+        }
+    }
+
+    // 数えてあれば、ファイルの先頭から pos までのバイト数
+    pub fn offset(&self, buffer: &[Line], pos: Cursor, encoding: Encoding) -> Option<usize> {
+        let start = self.starts.get(pos.row)?;
+        Some(start + line_bytes(buffer[pos.row].iter().take(pos.column), encoding))
+    }
+
+    // ファイルの先頭から pos までのバイト数
+    pub fn byte_offset(&mut self, buffer: &[Line], pos: Cursor, encoding: Encoding, ending: LineEnding) -> usize {
+        self.fill(buffer, pos.row, encoding, ending);
+        self.starts[pos.row] + line_bytes(buffer[pos.row].iter().take(pos.column), encoding)
+    }
+
+    // 保存したときのファイルの大きさ
+    pub fn byte_size(&mut self, buffer: &[Line], encoding: Encoding, ending: LineEnding) -> usize {
+        let last = buffer.len() - 1;
+        self.fill(buffer, last, encoding, ending);
+        self.starts[last] + line_bytes(buffer[last].iter(), encoding) + ending.len()
+    }
+
+    // byte_offset の逆。offset バイト目を含む文字の位置
+    // 改行のバイトなら行末、ファイルより後ろなら None
+    pub fn position_at_byte(
+        &mut self,
+        buffer: &[Line],
+        offset: usize,
+        encoding: Encoding,
+        ending: LineEnding,
+    ) -> Option<Cursor> {
+        self.fill(buffer, buffer.len() - 1, encoding, ending);
+        // 先頭が offset より後ろにない最後の行
+        let row = self.starts.partition_point(|&start| start <= offset) - 1;
+        let line = &buffer[row];
+        let mut end = self.starts[row];
+        for (column, &c) in line.iter().enumerate() {
+            end += encoding.char_len(c);
+            if offset < end {
+                return Some(Cursor { row, column });
+            }
+        }
+        (offset < end + ending.len()).then_some(Cursor { row, column: line.len() })
+    }
+}
+
+// 行の column 文字目までの UTF-8 でのバイト数。正規表現に渡す文字列の中の位置に使う
+pub fn char_to_byte(line: &[char], column: usize) -> usize {
+    line[..column].iter().map(|c| c.len_utf8()).sum()
+}
+
+// char_to_byte の逆。文字列の byte バイト目が何文字目か
+pub fn byte_to_char(text: &str, byte: usize) -> usize {
+    text[..byte].chars().count()
+}
+
+// 行頭から column 文字目までの表示上の幅
+pub fn display_column(line: &Line, column: usize, escapes: bool) -> usize {
+    line.iter().take(column).map(|&c| special::display_width(c, escapes)).sum()
+}
+
+// display_column の逆。表示上の列 x にある文字の添字。行末より右なら行末
+pub fn column_at_display(line: &[char], x: usize, escapes: bool) -> usize {
+    let mut width = 0;
+    for (column, &c) in line.iter().enumerate() {
+        width += special::display_width(c, escapes);
+        if width > x {
+            return column;
+        }
+    }
+    line.len()
+}
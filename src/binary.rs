@@ -0,0 +1,53 @@
+use crate::layout::LONG_LINE;
+use crate::Line;
+
+// git と同じく、先頭のこれだけのバイトに NUL があればバイナリとみなす
+const SNIFF_LEN: usize = 8000;
+
+// 1行に表示するバイト数
+const HEX_WIDTH: usize = 16;
+
+pub fn is_binary(bytes: &[u8]) -> bool {
+    bytes[..bytes.len().min(SNIFF_LEN)].contains(&0)
+}
+
+// 一番長い行のバイト数が LONG_LINE を超えていればその長さ
+pub fn long_line(bytes: &[u8]) -> Option<usize> {
+    bytes
+        .split(|&b| b == b'\n')
+        .map(|line| line.len())
+        .max()
+        .filter(|&len| len > LONG_LINE)
+}
+
+// `xxd` 風の16進ダンプ
+pub fn hex_dump(bytes: &[u8]) -> Vec<Line> {
+    let mut lines: Vec<Line> = bytes
+        .chunks(HEX_WIDTH)
+        .enumerate()
+        .map(|(i, chunk)| {
+            let mut line = format!("{:08x}:", i * HEX_WIDTH);
+            for (k, b) in chunk.iter().enumerate() {
+                if k % 2 == 0 {
+                    line.push(' ');
+                }
+                line.push_str(&format!("{:02x}", b));
+            }
+            // ASCII 部分の位置を揃える
+            let missing = (HEX_WIDTH - chunk.len()) * 2 + HEX_WIDTH / 2 - chunk.len().div_ceil(2);
+            line.push_str(&" ".repeat(missing + 2));
+            line.extend(chunk.iter().map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                }
+            }));
+            Line::from(line.as_str())
+        })
+        .collect();
+    if lines.is_empty() {
+        lines.push(Line::new());
+    }
+    lines
+}
@@ -0,0 +1,67 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use crate::logger;
+use crate::project;
+
+// メモをつけて印をつけた行
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bookmark {
+    pub row: usize,
+    pub note: String,
+}
+
+// プロジェクトごとに、状態ディレクトリの bookmarks の下に1つのファイルで持つ
+// 1行に1つ、ルートからの相対パス、行番号、メモをタブで区切って書く
+fn store(root: &Path) -> Option<PathBuf> {
+    let name: String = root
+        .to_string_lossy()
+        .chars()
+        .map(|c| if c == '/' || c == '\\' { '%' } else { c })
+        .collect();
+    Some(logger::state_dir()?.join("bookmarks").join(name))
+}
+
+fn read_all(root: &Path) -> Vec<(String, Bookmark)> {
+    let text = match store(root).and_then(|path| fs::read_to_string(path).ok()) {
+        Some(text) => text,
+        None => return Vec::new(),
+    };
+    text.lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(3, '\t');
+            let file = fields.next()?.to_string();
+            let row = fields.next()?.parse().ok()?;
+            let note = fields.next().unwrap_or("").to_string();
+            Some((file, Bookmark { row, note }))
+        })
+        .collect()
+}
+
+// file の印を行の順に返す
+pub fn load(root: &Path, file: &Path) -> Vec<Bookmark> {
+    let key = project::relative_key(root, file);
+    let mut bookmarks: Vec<Bookmark> = read_all(root)
+        .into_iter()
+        .filter(|(file, _)| *file == key)
+        .map(|(_, bookmark)| bookmark)
+        .collect();
+    bookmarks.sort_by_key(|bookmark| bookmark.row);
+    bookmarks
+}
+
+// file の印を bookmarks に置き換える。ほかのファイルの印はそのまま残す
+pub fn save(root: &Path, file: &Path, bookmarks: &[Bookmark]) -> io::Result<()> {
+    let path = store(root).ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no state directory"))?;
+    let key = project::relative_key(root, file);
+    let mut all: Vec<(String, Bookmark)> = read_all(root).into_iter().filter(|(file, _)| *file != key).collect();
+    all.extend(bookmarks.iter().map(|bookmark| (key.clone(), bookmark.clone())));
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let text: String = all
+        .iter()
+        .map(|(file, bookmark)| format!("{}\t{}\t{}\n", file, bookmark.row, bookmark.note.replace(['\t', '\n'], " ")))
+        .collect();
+    fs::write(path, text)
+}
@@ -0,0 +1,102 @@
+use std::env;
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+
+// 書き込みと読み出しに使うコマンド。上から順に試す
+struct Tool {
+    // この環境変数があるときだけ使う。None ならいつでも試す
+    needs: Option<&'static str>,
+    copy: &'static [&'static str],
+    paste: &'static [&'static str],
+}
+
+const TOOLS: &[Tool] = &[
+    Tool { needs: Some("WAYLAND_DISPLAY"), copy: &["wl-copy"], paste: &["wl-paste", "--no-newline"] },
+    Tool {
+        needs: Some("DISPLAY"),
+        copy: &["xclip", "-selection", "clipboard"],
+        paste: &["xclip", "-selection", "clipboard", "-o"],
+    },
+    Tool { needs: Some("DISPLAY"), copy: &["xsel", "--clipboard", "--input"], paste: &["xsel", "--clipboard", "--output"] },
+    Tool { needs: None, copy: &["pbcopy"], paste: &["pbpaste"] },
+];
+
+// システムのクリップボード
+// コマンドが使えないとき (SSH の先など) は、OSC 52 で端末のクリップボードに書く
+// OSC 52 は読み出せない端末が多いので、貼り付けは最後にコピーしたものを使う
+#[derive(Default)]
+pub struct Clipboard {
+    last: String,
+}
+
+impl Clipboard {
+    pub fn copy<T: Write>(&mut self, out: &mut T, text: &str) -> io::Result<()> {
+        self.last = text.to_string();
+        for tool in available() {
+            match run_copy(tool.copy, text) {
+                Ok(()) => return Ok(()),
+                Err(e) => log_debug!("{}: {}", tool.copy[0], e),
+            }
+        }
+        write!(out, "\x1b]52;c;{}\x07", base64(text.as_bytes()))?;
+        out.flush()
+    }
+
+    pub fn paste(&self) -> String {
+        for tool in available() {
+            match run_paste(tool.paste) {
+                Ok(text) => return text,
+                Err(e) => log_debug!("{}: {}", tool.paste[0], e),
+            }
+        }
+        self.last.clone()
+    }
+}
+
+fn available() -> impl Iterator<Item = &'static Tool> {
+    TOOLS
+        .iter()
+        .filter(|tool| tool.needs.is_none_or(|name| env::var_os(name).is_some_and(|value| !value.is_empty())))
+}
+
+fn run_copy(args: &[&str], text: &str) -> io::Result<()> {
+    // xclip は裏に残ってクリップボードを持ち続けるので、出力をつながない
+    let mut child = Command::new(args[0])
+        .args(&args[1..])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(text.as_bytes())?;
+    }
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(io::Error::other(format!("exited with {}", status)));
+    }
+    Ok(())
+}
+
+fn run_paste(args: &[&str]) -> io::Result<String> {
+    let output = Command::new(args[0]).args(&args[1..]).stdin(Stdio::null()).stderr(Stdio::null()).output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!("exited with {}", output.status)));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const TABLE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut result = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - i * 8));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                result.push(TABLE[(n >> (18 - i * 6)) as usize & 63] as char);
+            } else {
+                result.push('=');
+            }
+        }
+    }
+    result
+}
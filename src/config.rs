@@ -0,0 +1,206 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use termion::event::Key;
+use crate::keymap::{self, KeyAction};
+use crate::options::{AmbiguousWidth, Encoding};
+use crate::status::StatusLayout;
+
+// 設定ファイルの値。書いていない項目は None で、コマンドラインの指定はこれより優先する
+#[derive(Debug, Default)]
+pub struct Config {
+    pub tab_width: Option<usize>,
+    // Tab キーで空白を入れるか
+    pub expand_tab: Option<bool>,
+    pub line_numbers: Option<bool>,
+    pub relative_numbers: Option<bool>,
+    pub wrap: Option<bool>,
+    pub theme: Option<String>,
+    // 既定のキーの割り当てに重ねるもの (emacs)
+    pub keymap: Option<String>,
+    pub autowrite: Option<bool>,
+    pub focus_autosave: Option<bool>,
+    pub auto_reload: Option<bool>,
+    pub verify_save: Option<bool>,
+    pub visual_lines: Option<bool>,
+    pub scroll_bind: Option<bool>,
+    pub mouse: Option<bool>,
+    pub vim: Option<bool>,
+    pub sticky_keys: Option<bool>,
+    pub save_kill_ring: Option<bool>,
+    pub encoding: Option<Encoding>,
+    pub ambiguous_width: Option<AmbiguousWidth>,
+    pub status: Option<StatusLayout>,
+    pub large_file: Option<u64>,
+    // 同じキーを続けて受け付けない時間 (ミリ秒)
+    pub debounce: Option<u64>,
+    // [keys] の割り当て。操作が None ならそのキーの割り当てを消す
+    pub keys: Vec<(Vec<Key>, Option<KeyAction>)>,
+}
+
+// ~/.config/textedit/config.toml (XDG_CONFIG_HOME があればその下)
+pub fn default_path() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("textedit").join("config.toml"))
+}
+
+// 読めた項目の設定と、読めなかった行の説明を返す。間違った行があっても残りは使う
+pub fn load(path: &Path) -> io::Result<(Config, Vec<String>)> {
+    let text = fs::read_to_string(path)?;
+    let mut config = Config::default();
+    let mut errors = Vec::new();
+    // 今読んでいる表。最初は表の外
+    let mut table = String::new();
+    for (k, line) in text.lines().enumerate() {
+        if let Err(e) = config.parse_line(line, &mut table) {
+            errors.push(format!("{}:{}: {}", path.display(), k + 1, e));
+        }
+    }
+    Ok((config, errors))
+}
+
+// TOML の値のうち、設定に使うもの
+enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+}
+
+impl Config {
+    fn parse_line(&mut self, line: &str, table: &mut String) -> Result<(), String> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(());
+        }
+        if let Some(name) = line.strip_prefix('[') {
+            let name = name.split('#').next().unwrap_or_default().trim_end();
+            *table = name.strip_suffix(']').ok_or_else(|| "expected ]".to_string())?.trim().to_string();
+            if table != "keys" {
+                return Err(format!("unknown table: [{}]", table));
+            }
+            return Ok(());
+        }
+        // 知らない表の中身は、表の名前を知らせたので読み飛ばす
+        if !table.is_empty() && table != "keys" {
+            return Ok(());
+        }
+        // キーは "ctrl-x ctrl-s" のように引用符で囲んでもよい
+        let (key, value) = match line.strip_prefix('"') {
+            Some(quoted) => {
+                let (key, rest) = quoted.split_once('"').ok_or_else(|| "unterminated key".to_string())?;
+                let value = rest.trim_start().strip_prefix('=').ok_or_else(|| "expected key = value".to_string())?;
+                (key, value)
+            },
+            None => line.split_once('=').ok_or_else(|| "expected key = value".to_string())?,
+        };
+        let key = key.trim();
+        let value = parse_value(value.trim())?;
+        if table == "keys" {
+            let keys = keymap::parse_keys(key)?;
+            let action = match string(value)?.as_str() {
+                "none" => None,
+                action => Some(action.parse()?),
+            };
+            self.keys.push((keys, action));
+            return Ok(());
+        }
+        match key {
+            "tab_width" => self.tab_width = Some(width(value)?),
+            "expand_tab" => self.expand_tab = Some(boolean(value)?),
+            "line_numbers" => self.line_numbers = Some(boolean(value)?),
+            "relative_numbers" => self.relative_numbers = Some(boolean(value)?),
+            "wrap" => self.wrap = Some(boolean(value)?),
+            "theme" => self.theme = Some(string(value)?),
+            "keymap" => self.keymap = Some(string(value)?),
+            "autowrite" => self.autowrite = Some(boolean(value)?),
+            "focus_autosave" => self.focus_autosave = Some(boolean(value)?),
+            "auto_reload" => self.auto_reload = Some(boolean(value)?),
+            "verify_save" => self.verify_save = Some(boolean(value)?),
+            "visual_lines" => self.visual_lines = Some(boolean(value)?),
+            "scroll_bind" => self.scroll_bind = Some(boolean(value)?),
+            "mouse" => self.mouse = Some(boolean(value)?),
+            "vim" => self.vim = Some(boolean(value)?),
+            "sticky_keys" => self.sticky_keys = Some(boolean(value)?),
+            "save_kill_ring" => self.save_kill_ring = Some(boolean(value)?),
+            "encoding" => self.encoding = Some(string(value)?.parse()?),
+            "ambiwidth" => self.ambiguous_width = Some(string(value)?.parse()?),
+            "status" => self.status = Some(string(value)?.parse()?),
+            "debounce" => self.debounce = Some(integer(value)?.try_into().map_err(|_| "expected milliseconds")?),
+            "large_file" => self.large_file = Some(integer(value)?.try_into().map_err(|_| "expected a size in MB")?),
+            _ => return Err(format!("unknown setting: {}", key)),
+        }
+        Ok(())
+    }
+}
+
+// "..." (\ でエスケープできる)、'...'、整数、true と false。行末の # からはコメント
+fn parse_value(text: &str) -> Result<Value, String> {
+    let (value, rest) = if let Some(body) = text.strip_prefix('"') {
+        let mut value = String::new();
+        let mut chars = body.char_indices();
+        let end = loop {
+            match chars.next() {
+                Some((i, '"')) => break i + 1,
+                Some((_, '\\')) => match chars.next() {
+                    Some((_, 'n')) => value.push('\n'),
+                    Some((_, 't')) => value.push('\t'),
+                    Some((_, c @ ('"' | '\\'))) => value.push(c),
+                    Some((_, c)) => return Err(format!("unknown escape: \\{}", c)),
+                    None => return Err("unterminated string".to_string()),
+                },
+                Some((_, c)) => value.push(c),
+                None => return Err("unterminated string".to_string()),
+            }
+        };
+        (Value::String(value), &body[end..])
+    } else if let Some(body) = text.strip_prefix('\'') {
+        let end = body.find('\'').ok_or_else(|| "unterminated string".to_string())?;
+        (Value::String(body[..end].to_string()), &body[end + 1..])
+    } else {
+        let (word, rest) = text.split_once('#').map(|(word, _)| (word, "")).unwrap_or((text, ""));
+        let word = word.trim();
+        let value = match word {
+            "true" => Value::Boolean(true),
+            "false" => Value::Boolean(false),
+            _ => Value::Integer(word.replace('_', "").parse().map_err(|_| format!("invalid value: {}", word))?),
+        };
+        (value, rest)
+    };
+    let rest = rest.trim();
+    if !rest.is_empty() && !rest.starts_with('#') {
+        return Err(format!("unexpected text after the value: {}", rest));
+    }
+    Ok(value)
+}
+
+fn string(value: Value) -> Result<String, String> {
+    match value {
+        Value::String(s) => Ok(s),
+        _ => Err("expected a string".to_string()),
+    }
+}
+
+fn boolean(value: Value) -> Result<bool, String> {
+    match value {
+        Value::Boolean(b) => Ok(b),
+        _ => Err("expected true or false".to_string()),
+    }
+}
+
+fn integer(value: Value) -> Result<i64, String> {
+    match value {
+        Value::Integer(n) => Ok(n),
+        _ => Err("expected an integer".to_string()),
+    }
+}
+
+fn width(value: Value) -> Result<usize, String> {
+    match integer(value)? {
+        n @ 1..=16 => Ok(n as usize),
+        _ => Err("expected a width from 1 to 16".to_string()),
+    }
+}
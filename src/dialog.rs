@@ -0,0 +1,85 @@
+use termion::event::Key;
+
+// キーを処理した結果
+pub enum Answer {
+    Pending,
+    Chosen(char),
+    Cancel,
+}
+
+// 取り返しのつかない操作の前に聞く、ボタンを並べた質問
+// ボタンはその文字のキーで選ぶ。左右の矢印と Tab で選んでいるボタンを動かし、Enter で押す。Esc でやめる
+pub struct Dialog {
+    question: String,
+    // (キー, 名前)
+    buttons: Vec<(char, String)>,
+    selected: usize,
+}
+
+impl Dialog {
+    pub fn new(question: &str) -> Self {
+        Dialog { question: question.to_string(), buttons: Vec::new(), selected: 0 }
+    }
+
+    // はい/いいえ。うっかり Enter を押しても何もしないように「いいえ」を選んでおく
+    pub fn yes_no(question: &str) -> Self {
+        Dialog::new(question)
+            .button('y', &tr!("button-yes"))
+            .button('n', &tr!("button-no"))
+            .default_button('n')
+    }
+
+    pub fn button(mut self, key: char, label: &str) -> Self {
+        self.buttons.push((key, label.to_string()));
+        self
+    }
+
+    // 最初に選んでおくボタン
+    pub fn default_button(mut self, key: char) -> Self {
+        if let Some(index) = self.buttons.iter().position(|&(k, _)| k == key) {
+            self.selected = index;
+        }
+        self
+    }
+
+    pub fn handle(&mut self, key: Key) -> Answer {
+        match key {
+            Key::Esc | Key::Ctrl('c') => return Answer::Cancel,
+            Key::Char('\n') => return Answer::Chosen(self.buttons[self.selected].0),
+            Key::Left | Key::BackTab => {
+                self.selected = (self.selected + self.buttons.len() - 1) % self.buttons.len();
+            },
+            Key::Right | Key::Char('\t') => {
+                self.selected = (self.selected + 1) % self.buttons.len();
+            },
+            Key::Char(c) => {
+                let c = c.to_ascii_lowercase();
+                if self.buttons.iter().any(|&(k, _)| k == c) {
+                    return Answer::Chosen(c);
+                }
+            },
+            _ => {},
+        }
+        Answer::Pending
+    }
+
+    pub fn question(&self) -> &str {
+        &self.question
+    }
+
+    // ボタンを並べた1行。選んでいるボタンは < > で囲む
+    pub fn buttons(&self) -> String {
+        self.buttons
+            .iter()
+            .enumerate()
+            .map(|(index, (key, label))| {
+                if index == self.selected {
+                    format!("< {} ({}) >", label, key)
+                } else {
+                    format!("  {} ({})  ", label, key)
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
@@ -0,0 +1,100 @@
+use std::str::FromStr;
+use crate::theme::{Style, Theme};
+use crate::Highlight;
+
+// 書き出す形式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Html,
+    // 端末の色のエスケープシーケンス
+    Ansi,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "html" => Ok(Format::Html),
+            "ansi" => Ok(Format::Ansi),
+            _ => Err(format!("unknown export format: {}", s)),
+        }
+    }
+}
+
+// 同じ見た目が続く部分ごとに分けた1行
+pub type Line = Vec<(Highlight, String)>;
+
+// どちらも画面と同じテーマの色で書く
+pub fn render(format: Format, title: &str, lines: &[Line], theme: &Theme) -> String {
+    match format {
+        Format::Html => html(title, lines, theme),
+        Format::Ansi => ansi(lines, theme),
+    }
+}
+
+// 見た目ごとの class と、テーマでのその見た目
+fn classes(theme: &Theme) -> [(&'static str, Style); 6] {
+    [
+        ("comment", theme.comment),
+        ("over", theme.over_limit),
+        ("special", theme.special),
+        ("keyword", theme.keyword),
+        ("string", theme.string),
+        ("number", theme.number),
+    ]
+}
+
+fn html(title: &str, lines: &[Line], theme: &Theme) -> String {
+    let mut out = String::new();
+    out.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    out.push_str(&format!("<title>{}</title>\n", escape_html(title)));
+    out.push_str("<style>\n");
+    out.push_str(&format!("pre {{ {} }}\n", theme.paper.css(theme.paper)));
+    for (class, style) in classes(theme) {
+        out.push_str(&format!(".{} {{ {} }}\n", class, style.css(theme.paper)));
+    }
+    out.push_str("</style>\n</head>\n<body>\n<pre>");
+    for line in lines {
+        for (highlight, text) in line {
+            let text = escape_html(text);
+            match highlight {
+                Highlight::Normal => out.push_str(&text),
+                Highlight::Comment => out.push_str(&format!("<span class=\"comment\">{}</span>", text)),
+                Highlight::OverLimit => out.push_str(&format!("<span class=\"over\">{}</span>", text)),
+                Highlight::Special => out.push_str(&format!("<span class=\"special\">{}</span>", text)),
+                Highlight::Keyword => out.push_str(&format!("<span class=\"keyword\">{}</span>", text)),
+                Highlight::String => out.push_str(&format!("<span class=\"string\">{}</span>", text)),
+                Highlight::Number => out.push_str(&format!("<span class=\"number\">{}</span>", text)),
+            }
+        }
+        out.push('\n');
+    }
+    out.push_str("</pre>\n</body>\n</html>\n");
+    out
+}
+
+fn ansi(lines: &[Line], theme: &Theme) -> String {
+    let mut out = String::new();
+    for line in lines {
+        for (highlight, text) in line {
+            out.push_str(&theme.highlight(*highlight).paint(text).to_string());
+        }
+        out.push('\n');
+    }
+    out
+}
+
+fn escape_html(s: &str) -> String {
+    let mut escaped = String::new();
+    for c in s.chars() {
+        match c {
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '&' => escaped.push_str("&amp;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
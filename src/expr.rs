@@ -0,0 +1,319 @@
+use std::env;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// 式の値
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Number(f64),
+    Text(String),
+}
+
+// 整数になる数は小数点をつけずに出す
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Number(n) if n.fract() == 0.0 && n.abs() < 1e15 => write!(f, "{}", *n as i64),
+            Value::Number(n) => write!(f, "{}", n),
+            Value::Text(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+// 小さな式を計算する
+//   数と + - * / % ^ と括弧
+//   "文字列" と、文字列を + でつなげること
+//   $HOME のような環境変数
+//   env("NAME"), date(), date("%H:%M"), sqrt, abs, round, floor, ceil, min, max
+pub fn eval(source: &str) -> Result<Value, String> {
+    let mut parser = Parser { chars: source.chars().collect(), pos: 0 };
+    let value = parser.expr()?;
+    parser.skip_spaces();
+    match parser.peek() {
+        Some(c) => Err(format!("unexpected '{}'", c)),
+        None => Ok(value),
+    }
+}
+
+// 入力欄で確定する前に式を確かめる
+pub fn check(source: &str) -> Result<(), String> {
+    eval(source).map(|_| ())
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn skip_spaces(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    // 空白を飛ばして c があれば読む
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_spaces();
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(format!("expected '{}'", c))
+        }
+    }
+
+    fn expr(&mut self) -> Result<Value, String> {
+        let mut value = self.term()?;
+        loop {
+            if self.eat('+') {
+                let rhs = self.term()?;
+                value = match (value, rhs) {
+                    (Value::Number(a), Value::Number(b)) => Value::Number(a + b),
+                    (a, b) => Value::Text(format!("{}{}", a, b)),
+                };
+            } else if self.eat('-') {
+                let a = number(value)?;
+                value = Value::Number(a - number(self.term()?)?);
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn term(&mut self) -> Result<Value, String> {
+        let mut value = self.unary()?;
+        loop {
+            let op = if self.eat('*') {
+                '*'
+            } else if self.eat('/') {
+                '/'
+            } else if self.eat('%') {
+                '%'
+            } else {
+                return Ok(value);
+            };
+            let a = number(value)?;
+            let b = number(self.unary()?)?;
+            if op != '*' && b == 0.0 {
+                return Err("division by zero".to_string());
+            }
+            value = Value::Number(match op {
+                '*' => a * b,
+                '/' => a / b,
+                _ => a % b,
+            });
+        }
+    }
+
+    fn unary(&mut self) -> Result<Value, String> {
+        if self.eat('-') {
+            return Ok(Value::Number(-number(self.unary()?)?));
+        }
+        let base = self.atom()?;
+        // ^ は右から結びつく
+        if self.eat('^') {
+            let exponent = number(self.unary()?)?;
+            return Ok(Value::Number(number(base)?.powf(exponent)));
+        }
+        Ok(base)
+    }
+
+    fn atom(&mut self) -> Result<Value, String> {
+        self.skip_spaces();
+        let c = match self.peek() {
+            Some(c) => c,
+            None => return Err("unexpected end of expression".to_string()),
+        };
+        if c == '(' {
+            self.pos += 1;
+            let value = self.expr()?;
+            self.expect(')')?;
+            return Ok(value);
+        }
+        if c == '"' {
+            return self.string();
+        }
+        if c.is_ascii_digit() || c == '.' {
+            let start = self.pos;
+            while self.peek().is_some_and(|c| c.is_ascii_digit() || c == '.') {
+                self.pos += 1;
+            }
+            let text: String = self.chars[start..self.pos].iter().collect();
+            return text.parse().map(Value::Number).map_err(|_| format!("invalid number: {}", text));
+        }
+        if c == '$' {
+            self.pos += 1;
+            let name = self.name();
+            return env_var(&name);
+        }
+        if c.is_alphabetic() || c == '_' {
+            let name = self.name();
+            let args = self.args()?;
+            return call(&name, args);
+        }
+        Err(format!("unexpected '{}'", c))
+    }
+
+    fn name(&mut self) -> String {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_alphanumeric() || c == '_') {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos].iter().collect()
+    }
+
+    // "..." の中では \" と \\ と \n が使える
+    fn string(&mut self) -> Result<Value, String> {
+        self.pos += 1;
+        let mut text = String::new();
+        loop {
+            match self.peek() {
+                Some('"') => {
+                    self.pos += 1;
+                    return Ok(Value::Text(text));
+                },
+                Some('\\') => {
+                    self.pos += 1;
+                    match self.peek() {
+                        Some('n') => text.push('\n'),
+                        Some('t') => text.push('\t'),
+                        Some(c) => text.push(c),
+                        None => break,
+                    }
+                    self.pos += 1;
+                },
+                Some(c) => {
+                    text.push(c);
+                    self.pos += 1;
+                },
+                None => break,
+            }
+        }
+        Err("unterminated string".to_string())
+    }
+
+    // 関数の引数。括弧がなければ引数なしで呼ぶ
+    fn args(&mut self) -> Result<Vec<Value>, String> {
+        let mut args = Vec::new();
+        if !self.eat('(') {
+            return Ok(args);
+        }
+        if self.eat(')') {
+            return Ok(args);
+        }
+        loop {
+            args.push(self.expr()?);
+            if self.eat(')') {
+                return Ok(args);
+            }
+            self.expect(',')?;
+        }
+    }
+}
+
+fn number(value: Value) -> Result<f64, String> {
+    match value {
+        Value::Number(n) => Ok(n),
+        Value::Text(s) => s.trim().parse().map_err(|_| format!("not a number: \"{}\"", s)),
+    }
+}
+
+fn env_var(name: &str) -> Result<Value, String> {
+    if name.is_empty() {
+        return Err("expected a variable name after '$'".to_string());
+    }
+    env::var(name).map(Value::Text).map_err(|_| format!("environment variable not set: {}", name))
+}
+
+fn call(name: &str, args: Vec<Value>) -> Result<Value, String> {
+    let unary = |f: fn(f64) -> f64| -> Result<Value, String> {
+        match args.as_slice() {
+            [x] => Ok(Value::Number(f(number(x.clone())?))),
+            _ => Err(format!("{}() takes 1 argument", name)),
+        }
+    };
+    match name {
+        "env" => match args.as_slice() {
+            [name] => env_var(&name.to_string()),
+            _ => Err("env() takes 1 argument".to_string()),
+        },
+        "date" => {
+            let format = match args.as_slice() {
+                [] => "%Y-%m-%d".to_string(),
+                [format] => format.to_string(),
+                _ => return Err("date() takes at most 1 argument".to_string()),
+            };
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0);
+            Ok(Value::Text(format_time(now, &format)))
+        },
+        "sqrt" => unary(f64::sqrt),
+        "abs" => unary(f64::abs),
+        "round" => unary(f64::round),
+        "floor" => unary(f64::floor),
+        "ceil" => unary(f64::ceil),
+        "min" | "max" => {
+            let numbers = args.into_iter().map(number).collect::<Result<Vec<_>, _>>()?;
+            let pick = if name == "min" { f64::min } else { f64::max };
+            numbers
+                .into_iter()
+                .reduce(pick)
+                .map(Value::Number)
+                .ok_or_else(|| format!("{}() needs at least 1 argument", name))
+        },
+        "pi" if args.is_empty() => Ok(Value::Number(std::f64::consts::PI)),
+        _ => Err(format!("unknown function: {}", name)),
+    }
+}
+
+// UNIX 時間を format にしたがって書く (UTC)。%Y %m %d %H %M %S %% が使える
+pub fn format_time(seconds: i64, format: &str) -> String {
+    // http://howardhinnant.github.io/date_algorithms.html の civil_from_days
+    let days = seconds.div_euclid(86400);
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    let time = seconds.rem_euclid(86400);
+
+    let mut out = String::new();
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('Y') => out.push_str(&format!("{:04}", year)),
+            Some('m') => out.push_str(&format!("{:02}", month)),
+            Some('d') => out.push_str(&format!("{:02}", day)),
+            Some('H') => out.push_str(&format!("{:02}", time / 3600)),
+            Some('M') => out.push_str(&format!("{:02}", time / 60 % 60)),
+            Some('S') => out.push_str(&format!("{:02}", time % 60)),
+            Some('%') => out.push('%'),
+            Some(c) => {
+                out.push('%');
+                out.push(c);
+            },
+            None => out.push('%'),
+        }
+    }
+    out
+}
@@ -0,0 +1,373 @@
+use git2::{BranchType, DiffFormat, ObjectType, Oid, Repository, Status, StatusOptions};
+use std::path::{Path, PathBuf};
+
+// `git status` の1エントリ
+pub struct StatusEntry {
+    // リポジトリのルートからの相対パス
+    pub path: PathBuf,
+    // インデックス側の状態 (M, A, D, R)
+    pub staged: Option<char>,
+    // ワークツリー側の状態 (M, D, ?)
+    pub unstaged: Option<char>,
+}
+
+impl StatusEntry {
+    pub fn line(&self) -> String {
+        format!(
+            "{}{} {}",
+            self.staged.unwrap_or(' '),
+            self.unstaged.unwrap_or(' '),
+            self.path.display()
+        )
+    }
+}
+
+// 今のブランチと、上流のブランチより進んでいる/遅れているコミットの数
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Branch {
+    // HEAD が切り離されていればコミットの短いハッシュ
+    pub name: String,
+    pub ahead: usize,
+    pub behind: usize,
+    // コミットしていない変更があるか
+    pub dirty: bool,
+}
+
+impl Branch {
+    pub fn label(&self) -> String {
+        let mut label = self.name.clone();
+        if self.ahead > 0 {
+            label.push_str(&format!(" ↑{}", self.ahead));
+        }
+        if self.behind > 0 {
+            label.push_str(&format!(" ↓{}", self.behind));
+        }
+        if self.dirty {
+            label.push_str(" *");
+        }
+        label
+    }
+}
+
+// path を含むリポジトリを探す
+pub fn open_repository(path: &Path) -> Result<Repository, git2::Error> {
+    Repository::discover(path)
+}
+
+pub fn status(repo: &Repository) -> Result<Vec<StatusEntry>, git2::Error> {
+    let mut options = StatusOptions::new();
+    options.include_untracked(true).recurse_untracked_dirs(true);
+    let statuses = repo.statuses(Some(&mut options))?;
+
+    let mut entries = Vec::new();
+    for entry in statuses.iter() {
+        let path = match entry.path() {
+            Some(path) => PathBuf::from(path),
+            None => continue,
+        };
+        let status = entry.status();
+        let staged = if status.contains(Status::INDEX_NEW) {
+            Some('A')
+        } else if status.contains(Status::INDEX_MODIFIED) {
+            Some('M')
+        } else if status.contains(Status::INDEX_DELETED) {
+            Some('D')
+        } else if status.contains(Status::INDEX_RENAMED) {
+            Some('R')
+        } else {
+            None
+        };
+        let unstaged = if status.contains(Status::WT_NEW) {
+            Some('?')
+        } else if status.contains(Status::WT_MODIFIED) {
+            Some('M')
+        } else if status.contains(Status::WT_DELETED) {
+            Some('D')
+        } else {
+            None
+        };
+        if staged.is_some() || unstaged.is_some() {
+            entries.push(StatusEntry {
+                path,
+                staged,
+                unstaged,
+            });
+        }
+    }
+    Ok(entries)
+}
+
+pub fn stage(repo: &Repository, path: &Path) -> Result<(), git2::Error> {
+    let mut index = repo.index()?;
+    let exists = repo
+        .workdir()
+        .map(|dir| dir.join(path).exists())
+        .unwrap_or(false);
+    if exists {
+        index.add_path(path)?;
+    } else {
+        index.remove_path(path)?;
+    }
+    index.write()
+}
+
+pub fn unstage(repo: &Repository, path: &Path) -> Result<(), git2::Error> {
+    match repo.head() {
+        Ok(head) => {
+            let commit = head.peel(ObjectType::Commit)?;
+            repo.reset_default(Some(&commit), [path])
+        },
+        // まだコミットが無いときはインデックスから消すだけ
+        Err(_) => {
+            let mut index = repo.index()?;
+            index.remove_path(path)?;
+            index.write()
+        },
+    }
+}
+
+// HEAD とインデックスの差分 (`git diff --cached`)
+pub fn staged_diff(repo: &Repository) -> Result<Vec<String>, git2::Error> {
+    let tree = repo.head().ok().and_then(|head| head.peel_to_tree().ok());
+    let diff = repo.diff_tree_to_index(tree.as_ref(), None, None)?;
+
+    let mut lines = Vec::new();
+    diff.print(DiffFormat::Patch, |_, _, line| {
+        let content = String::from_utf8_lossy(line.content());
+        let prefix = match line.origin() {
+            c @ ('+' | '-' | ' ') => c.to_string(),
+            _ => String::new(),
+        };
+        for l in content.lines() {
+            lines.push(format!("{}{}", prefix, l).replace('\t', "    "));
+        }
+        true
+    })?;
+    Ok(lines)
+}
+
+// インデックスの内容で HEAD にコミットする
+pub fn commit(repo: &Repository, message: &str) -> Result<Oid, git2::Error> {
+    let signature = repo.signature()?;
+    let tree_id = repo.index()?.write_tree()?;
+    let tree = repo.find_tree(tree_id)?;
+    let parent = repo.head().ok().and_then(|head| head.peel_to_commit().ok());
+    let parents: Vec<_> = parent.iter().collect();
+    repo.commit(Some("HEAD"), &signature, &signature, message, &tree, &parents)
+}
+
+// ファイルの過去のリビジョン
+pub struct Revision {
+    pub oid: Oid,
+    pub summary: String,
+    pub author: String,
+    // UNIX 時間 (秒)
+    pub time: i64,
+}
+
+impl Revision {
+    pub fn line(&self) -> String {
+        format!(
+            "{:.7} {} {} {}",
+            self.oid,
+            format_date(self.time),
+            self.author,
+            self.summary
+        )
+    }
+}
+
+pub fn current_branch(repo: &Repository) -> Result<Branch, git2::Error> {
+    let head = repo.head()?;
+    let name = match head.target() {
+        Some(oid) if !head.is_branch() => format!("{:.7}", oid),
+        _ => head.shorthand().unwrap_or("HEAD").to_string(),
+    };
+    // 上流のブランチがなければ数えない
+    let (ahead, behind) = match head.target().filter(|_| head.is_branch()) {
+        Some(local) => repo
+            .find_branch(&name, BranchType::Local)
+            .and_then(|branch| branch.upstream())
+            .ok()
+            .and_then(|upstream| upstream.get().target())
+            .and_then(|upstream| repo.graph_ahead_behind(local, upstream).ok())
+            .unwrap_or((0, 0)),
+        None => (0, 0),
+    };
+    let dirty = !status(repo)?.is_empty();
+    Ok(Branch { name, ahead, behind, dirty })
+}
+
+// path のワークツリーからの相対パス
+pub fn relative_path(repo: &Repository, path: &Path) -> Option<PathBuf> {
+    let workdir = repo.workdir()?.canonicalize().ok()?;
+    let path = path.canonicalize().ok()?;
+    path.strip_prefix(workdir).ok().map(Path::to_path_buf)
+}
+
+// HEAD から辿れるコミットのうち、path を変更したもの (新しい順)
+pub fn file_history(repo: &Repository, path: &Path) -> Result<Vec<Revision>, git2::Error> {
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push_head()?;
+
+    let mut revisions = Vec::new();
+    for oid in revwalk {
+        let commit = repo.find_commit(oid?)?;
+        let blob = commit.tree()?.get_path(path).ok().map(|entry| entry.id());
+        if blob.is_none() {
+            continue;
+        }
+        let parent_blob = commit
+            .parent(0)
+            .ok()
+            .and_then(|parent| parent.tree().ok())
+            .and_then(|tree| tree.get_path(path).ok())
+            .map(|entry| entry.id());
+        if blob != parent_blob {
+            revisions.push(Revision {
+                oid: commit.id(),
+                summary: commit.summary().unwrap_or("").to_string(),
+                author: commit.author().name().unwrap_or("").to_string(),
+                time: commit.time().seconds(),
+            });
+        }
+    }
+    Ok(revisions)
+}
+
+// あるリビジョンでのファイルの内容
+pub fn contents_at(repo: &Repository, oid: Oid, path: &Path) -> Result<String, git2::Error> {
+    let entry = repo.find_commit(oid)?.tree()?.get_path(path)?;
+    let blob = repo.find_blob(entry.id())?;
+    Ok(String::from_utf8_lossy(blob.content()).into_owned())
+}
+
+// 差分を取る前に行をそろえるかどうか。そろえた後で同じになる行は変わっていないとみなす
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DiffFilter {
+    // 空白の違いだけの変更を無視する (`git diff -w`)
+    pub ignore_whitespace: bool,
+    pub ignore_case: bool,
+}
+
+impl DiffFilter {
+    fn is_none(self) -> bool {
+        !self.ignore_whitespace && !self.ignore_case
+    }
+
+    fn normalize(self, line: &str) -> String {
+        let line = if self.ignore_whitespace {
+            line.chars().filter(|c| !c.is_whitespace()).collect()
+        } else {
+            line.to_string()
+        };
+        if self.ignore_case {
+            line.to_lowercase()
+        } else {
+            line
+        }
+    }
+}
+
+// old から new への unified diff
+pub fn diff_text(old: &str, new: &str, path: &Path, filter: DiffFilter) -> Result<Vec<String>, git2::Error> {
+    if filter.is_none() {
+        return patch_lines(old, new, path);
+    }
+    // そろえた行どうしで差分を取り、差分の行を元の行に戻す
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+    let normalize = |lines: &[&str]| lines.iter().map(|line| filter.normalize(line) + "\n").collect::<String>();
+    let diff = patch_lines(&normalize(&old_lines), &normalize(&new_lines), path)?;
+
+    let mut in_hunk = false;
+    // 次の行の、古い方と新しい方での行番号 (0 始まり)
+    let (mut old_row, mut new_row) = (0, 0);
+    let original = |lines: &[&str], row: usize| lines.get(row).map(|line| line.replace('\t', "    ")).unwrap_or_default();
+    Ok(diff
+        .into_iter()
+        .filter_map(|text| {
+            if let Some((old_start, new_start)) = hunk_starts(&text) {
+                in_hunk = true;
+                old_row = old_start;
+                new_row = new_start;
+                return Some(text);
+            }
+            if !in_hunk {
+                return Some(text);
+            }
+            match text.chars().next() {
+                Some('-') => {
+                    old_row += 1;
+                    Some(format!("-{}", original(&old_lines, old_row - 1)))
+                },
+                Some('+') => {
+                    new_row += 1;
+                    Some(format!("+{}", original(&new_lines, new_row - 1)))
+                },
+                Some(' ') => {
+                    old_row += 1;
+                    new_row += 1;
+                    Some(format!(" {}", original(&new_lines, new_row - 1)))
+                },
+                // そろえた内容では行末の改行の有無がわからないので出さない
+                _ => None,
+            }
+        })
+        .collect())
+}
+
+fn patch_lines(old: &str, new: &str, path: &Path) -> Result<Vec<String>, git2::Error> {
+    let mut patch =
+        git2::Patch::from_buffers(old.as_bytes(), Some(path), new.as_bytes(), Some(path), None)?;
+    let buf = patch.to_buf()?;
+    Ok(String::from_utf8_lossy(&buf)
+        .lines()
+        .map(|line| line.replace('\t', "    "))
+        .collect())
+}
+
+// 差分の中で、新しい方の row 行目 (0 始まり) かその後ろを表している最初の行
+// 本文より後ろに差分がなければ diff.len()。差分でなければ None
+pub fn diff_line_of(diff: &[String], row: usize) -> Option<usize> {
+    let mut in_hunk = false;
+    // 次の行の、新しい方での行番号 (0 始まり)
+    let mut line = 0;
+    for (k, text) in diff.iter().enumerate() {
+        if let Some(start) = hunk_start(text) {
+            if start >= row {
+                return Some(k);
+            }
+            in_hunk = true;
+            line = start;
+        } else if in_hunk && !text.starts_with('-') && !text.starts_with('\\') {
+            if line >= row {
+                return Some(k);
+            }
+            line += 1;
+        }
+    }
+    in_hunk.then_some(diff.len())
+}
+
+// "@@ -a,b +c,d @@" の c を 0 始まりにしたもの
+fn hunk_start(line: &str) -> Option<usize> {
+    hunk_starts(line).map(|(_, new)| new)
+}
+
+// "@@ -a,b +c,d @@" の a と c を 0 始まりにしたもの
+fn hunk_starts(line: &str) -> Option<(usize, usize)> {
+    let mut ranges = line.strip_prefix("@@ ")?.split_whitespace();
+    let start = |range: &str| -> Option<usize> {
+        let start: usize = range.split(',').next()?.parse().ok()?;
+        Some(start.saturating_sub(1))
+    };
+    let old = start(ranges.next()?.strip_prefix('-')?)?;
+    let new = start(ranges.next()?.strip_prefix('+')?)?;
+    Some((old, new))
+}
+
+// UNIX 時間を YYYY-MM-DD にする (UTC)
+fn format_date(seconds: i64) -> String {
+    crate::expr::format_time(seconds, "%Y-%m-%d")
+}
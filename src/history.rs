@@ -0,0 +1,149 @@
+use std::cmp::min;
+use crate::{Cursor, Line};
+
+// 覚えておく操作の数。古いものから捨てる
+const LIMIT: usize = 1000;
+
+// バッファへの1つの変更
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Edit {
+    Insert { pos: Cursor, c: char },
+    Remove { pos: Cursor, c: char },
+    // pos で行を2つに分けた
+    Split { pos: Cursor },
+    // row 行目に次の行をつなげた。column はつなげる前の row 行目の長さ
+    Join { row: usize, column: usize },
+    Replace { row: usize, old: Vec<char>, new: Vec<char> },
+}
+
+impl Edit {
+    // 取り消すための変更
+    pub fn inverse(&self) -> Edit {
+        match self.clone() {
+            Edit::Insert { pos, c } => Edit::Remove { pos, c },
+            Edit::Remove { pos, c } => Edit::Insert { pos, c },
+            Edit::Split { pos } => Edit::Join { row: pos.row, column: pos.column },
+            Edit::Join { row, column } => Edit::Split { pos: Cursor { row, column } },
+            Edit::Replace { row, old, new } => Edit::Replace { row, old: new, new: old },
+        }
+    }
+
+    // 変更する前の pos が、変更した後にどこになるか。変更したところより前の位置は動かない
+    pub fn shift(&self, pos: Cursor) -> Cursor {
+        let Cursor { row, column } = pos;
+        match self {
+            Edit::Insert { pos: at, .. } if row == at.row && column >= at.column => Cursor { row, column: column + 1 },
+            Edit::Remove { pos: at, .. } if row == at.row && column > at.column => Cursor { row, column: column - 1 },
+            Edit::Split { pos: at } if row == at.row && column >= at.column => {
+                Cursor { row: row + 1, column: column - at.column }
+            },
+            Edit::Split { pos: at } if row > at.row => Cursor { row: row + 1, column },
+            Edit::Join { row: at, column: len } if row == at + 1 => Cursor { row: *at, column: column + len },
+            Edit::Join { row: at, .. } if row > at + 1 => Cursor { row: row - 1, column },
+            // 行の終わりにいれば終わりのまま、途中にいれば新しい行に収まるようにする
+            Edit::Replace { row: at, old, new } if row == *at && column >= old.len() => {
+                Cursor { row, column: column + new.len() - old.len() }
+            },
+            Edit::Replace { row: at, new, .. } if row == *at => Cursor { row, column: min(column, new.len()) },
+            _ => pos,
+        }
+    }
+}
+
+// 画面に出していないバッファに変更を当てる。取り消した後の内容を先に見せるときに使う
+pub fn apply(buffer: &mut Vec<Line>, edit: &Edit) {
+    match edit {
+        Edit::Insert { pos, c } => buffer[pos.row].insert(pos.column, *c),
+        Edit::Remove { pos, .. } => {
+            buffer[pos.row].remove(pos.column);
+        },
+        Edit::Split { pos } => {
+            let rest = buffer[pos.row].split_off(pos.column);
+            buffer.insert(pos.row + 1, rest);
+        },
+        Edit::Join { row, .. } => {
+            let line = buffer.remove(row + 1);
+            buffer[*row].append(line);
+        },
+        Edit::Replace { row, new, .. } => buffer[*row] = Line::from(new.clone()),
+    }
+}
+
+// まとめて取り消す変更と、その前後のカーソルの位置
+#[derive(Clone)]
+pub struct Group {
+    pub edits: Vec<Edit>,
+    pub before: Cursor,
+    pub after: Cursor,
+}
+
+// 取り消しとやり直しの履歴
+// 変更は区切る (seal) まで1つにまとめる。続けて打った文字は1回で取り消せる
+#[derive(Default)]
+pub struct History {
+    undo: Vec<Group>,
+    redo: Vec<Group>,
+    // まだ区切っていない変更
+    open: Option<Group>,
+    // 取り消しとやり直しの最中は記録しない
+    paused: bool,
+}
+
+impl History {
+    // cursor は変更する前のカーソルの位置
+    pub fn record(&mut self, edit: Edit, cursor: Cursor) {
+        if self.paused {
+            return;
+        }
+        self.redo.clear();
+        self.open
+            .get_or_insert(Group { edits: Vec::new(), before: cursor, after: cursor })
+            .edits
+            .push(edit);
+    }
+
+    // ここまでの変更を1回で取り消せるようにまとめる。cursor は変更した後のカーソルの位置
+    pub fn seal(&mut self, cursor: Cursor) {
+        if let Some(mut group) = self.open.take() {
+            group.after = cursor;
+            self.undo.push(group);
+            if self.undo.len() > LIMIT {
+                self.undo.remove(0);
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        *self = History::default();
+    }
+
+    // 取り消せるまとまり (古い順) と、やり直せるまとまり (次にやり直すものが最後)
+    pub fn groups(&self) -> (&[Group], &[Group]) {
+        (&self.undo, &self.redo)
+    }
+
+    // 取り消す変更。取り消したらやり直せるように finish_undo に返す
+    pub fn start_undo(&mut self, cursor: Cursor) -> Option<Group> {
+        self.seal(cursor);
+        let group = self.undo.pop()?;
+        self.paused = true;
+        Some(group)
+    }
+
+    pub fn finish_undo(&mut self, group: Group) {
+        self.paused = false;
+        self.redo.push(group);
+    }
+
+    pub fn start_redo(&mut self, cursor: Cursor) -> Option<Group> {
+        self.seal(cursor);
+        let group = self.redo.pop()?;
+        self.paused = true;
+        Some(group)
+    }
+
+    pub fn finish_redo(&mut self, group: Group) {
+        self.paused = false;
+        self.undo.push(group);
+    }
+}
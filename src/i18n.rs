@@ -0,0 +1,342 @@
+use std::env;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// 画面に出すメッセージの言語
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lang {
+    En,
+    Ja,
+}
+
+static JAPANESE: AtomicBool = AtomicBool::new(false);
+
+// (キー, 英語, 日本語)。{} は引数で順に置き換えられる
+const CATALOG: &[(&str, &str, &str)] = &[
+    ("expression", "= ", "= "),
+    ("goto-line", "Go to line: ", "移動する行: "),
+    ("goto-line-placeholder", "line or line:column", "行 か 行:桁"),
+    ("invalid-line", "Not a line number: {}", "行の番号ではありません: {}"),
+    ("expression-placeholder", "1+2*3, $HOME, date(\"%H:%M\")", "1+2*3, $HOME, date(\"%H:%M\")"),
+    ("unknown-theme", "Unknown theme: {} (themes: {})", "不明なテーマです: {} (テーマ: {})"),
+    ("unknown-keymap", "Unknown keymap: {} (keymaps: {})", "不明なキーの割り当てです: {} (割り当て: {})"),
+    ("config-error", "Config: {}", "設定ファイル: {}"),
+    ("key-prefix", "{} -", "{} -"),
+    ("key-unbound", "{} is not bound", "{} には何も割り当てられていません"),
+    ("config-not-found", "Config file not found: {}", "設定ファイルが見つかりません: {}"),
+    (
+        "binary-file",
+        "binary file (NUL bytes), showing hex dump; :text to view as text",
+        "バイナリファイル (NUL を含む) なので16進ダンプで表示しています。:text でテキストとして表示",
+    ),
+    (
+        "long-lines",
+        "very long lines; :hex for hex dump, :ro to make read-only",
+        "とても長い行があります。:hex で16進ダンプ、:ro で読み取り専用",
+    ),
+    (
+        "invalid-encoding",
+        "invalid encoding, opened read-only; :hex for hex dump",
+        "文字コードが不正なので読み取り専用で開きました。:hex で16進ダンプ",
+    ),
+    (
+        "announce-line",
+        "Line {} of {}, column {}: {}",
+        "{}/{} 行目 {} 桁: {}",
+    ),
+    (
+        "changed-on-disk",
+        "File changed on disk; :e to reload",
+        "ファイルが外部で変更されました。:e で読み直し",
+    ),
+    (
+        "reloaded",
+        "\"{}\" reloaded (changed on disk)",
+        "\"{}\" を読み直しました (外部で変更されました)",
+    ),
+    (
+        "revert-question",
+        "Discard unsaved changes and reload from disk?",
+        "保存していない変更を捨てて読み直しますか?",
+    ),
+    ("reverted", "\"{}\" reverted", "\"{}\" を読み直しました"),
+    (
+        "reverted-trashed",
+        "\"{}\" reverted; :trash restores the discarded changes",
+        "\"{}\" を読み直しました。捨てた変更は :trash で戻せます",
+    ),
+    ("exported", "Exported to {}", "{} に書き出しました"),
+    ("export-failed", "Export failed: {}", "書き出せませんでした: {}"),
+    ("no-locations", "Location list is empty", "位置の一覧は空です"),
+    ("location", "({}/{}) {}", "({}/{}) {}"),
+    (
+        "locations-help",
+        "Enter: jump  q: close  (Alt+n/Alt+p: next/previous)",
+        "Enter: 移動  q: 閉じる  (Alt+n/Alt+p: 次/前)",
+    ),
+    (
+        "listed-special",
+        "{} special characters; Alt+n/Alt+p to step, Alt+l to list",
+        "特殊な文字が {} 個あります。Alt+n/Alt+p で移動、Alt+l で一覧",
+    ),
+    ("scanning-todo", "Scanning {} for TODO", "{} の TODO を探しています"),
+    ("scan-cancelled", "Scan cancelled", "探すのをやめました"),
+    ("no-todo", "No TODO, FIXME or XXX found", "TODO、FIXME、XXX は見つかりませんでした"),
+    ("todo-help", "{} found  Enter: jump  q: close", "{} 個  Enter: 移動  q: 閉じる"),
+    ("stats-header", "    keys    lines        time  file", "    打鍵     行数        時間  ファイル"),
+    ("no-closed-files", "No closed files to reopen", "開き直せるファイルはありません"),
+    ("zoomed", "Pane hidden; Alt+z to restore", "ペインを隠しました。Alt+z で戻す"),
+    ("unknown-layout", "Unknown layout: {}", "不明な画面の割り振りです: {}"),
+    ("pane-size", "Pane up to {}% of the screen", "ペインは画面の {}% まで"),
+    ("pane-collapsed", "{} lines (Alt+c to expand)", "{} 行 (Alt+c で開く)"),
+    ("no-completions", "No completions", "補完の候補はありません"),
+    ("input-required", "Enter a value", "入力してください"),
+    ("command-placeholder", "command (Tab to complete, Up for history)", "コマンド (Tab で補完、↑ で履歴)"),
+    ("loading", "Loading {}", "{} を読み込み中"),
+    ("esc-to-cancel", "(Esc to cancel)", "(Esc で中断)"),
+    ("load-cancelled", "Loading {} cancelled", "{} の読み込みを中断しました"),
+    ("welcome-recent", "Recent files:", "最近のファイル:"),
+    ("welcome-new-file", "New file...", "新しいファイル..."),
+    ("welcome-keys", "Enter: open  Up/Down: select  Esc or type: empty buffer", "Enter: 開く  ↑/↓: 選ぶ  Esc か文字を打つ: 空のバッファ"),
+    ("welcome-commands", "Alt+x: command  Ctrl+S: save  Ctrl+C: quit", "Alt+x: コマンド  Ctrl+S: 保存  Ctrl+C: 終了"),
+    ("new-file", "New file: ", "新しいファイル: "),
+    (
+        "large-file-question",
+        "{} is {} MB. Open it anyway?",
+        "{} は {} MB あります。開きますか?",
+    ),
+    ("head-only", "first {} MB only, read-only", "先頭の {} MB のみ、読み取り専用"),
+    ("open-cancelled", "Did not open {}", "{} を開きませんでした"),
+    ("oldest-change", "Already at oldest change", "これより前の変更はありません"),
+    ("newest-change", "Already at newest change", "これより後の変更はありません"),
+    ("bookmark-note", "Note: ", "メモ: "),
+    ("bookmark-added", "Bookmarked line {}", "{} 行目に印をつけました"),
+    ("bookmark-removed", "Removed bookmark on line {}", "{} 行目の印を外しました"),
+    ("not-bookmarked", "No bookmark on this line", "この行には印がありません"),
+    ("bookmark", "Bookmark", "印"),
+    ("no-bookmarks", "No bookmarks", "印はありません"),
+    (
+        "listed-bookmarks",
+        "{} bookmarks; Alt+n/Alt+p to step, Alt+l to list",
+        "印が {} 個あります。Alt+n/Alt+p で移動、Alt+l で一覧",
+    ),
+    ("mark-set", "Set mark {}", "印 {} をつけました"),
+    ("no-mark", "Mark {} is not set", "印 {} はついていません"),
+    ("invalid-mark", "Invalid mark name: {} (use a to z)", "印の名前が正しくありません: {} (a から z)"),
+    ("no-marks", "No marks", "名前のついた印はありません"),
+    (
+        "listed-marks",
+        "{} marks; Alt+n/Alt+p to step, Alt+l to list",
+        "名前のついた印が {} 個あります。Alt+n/Alt+p で移動、Alt+l で一覧",
+    ),
+    ("jump-oldest", "Already at oldest position", "これより前の位置はありません"),
+    ("jump-newest", "Already at newest position", "これより後の位置はありません"),
+    ("journal-failed", "Cannot open journal {}: {}", "記録のファイル {} を開けません: {}"),
+    ("nothing-to-cancel", "Nothing to cancel (Ctrl+C quits)", "やめるものはありません (Ctrl+C で終了)"),
+    ("search", "Search: ", "検索: "),
+    ("not-found", "Not found", "見つかりません"),
+    ("regex-search", "Regex search: ", "正規表現で検索: "),
+    ("no-previous-search", "No previous search", "前に検索したものがありません"),
+    ("kill-ring-empty", "Nothing to yank", "貼り付けるものがありません"),
+    ("kills-help", "Enter: paste at the cursor  q: close", "Enter: カーソルの位置に貼り付ける  q: 閉じる"),
+    ("copied", "Copied {} characters", "{} 文字コピーしました"),
+    ("nothing-selected", "Nothing is selected", "何も選んでいません"),
+    ("split-selection", "Split selection by regex: ", "正規表現で選択を分ける: "),
+    ("selections", "{} selections", "{} 個を選んでいます"),
+    ("invalid-range", "Invalid line range: {}", "行の範囲が正しくありません: {}"),
+    ("invalid-offset", "Invalid byte offset: {}", "バイトの位置が正しくありません: {}"),
+    (
+        "offset-out-of-range",
+        "Byte offset {} is past the end of the file ({} bytes)",
+        "バイトの位置 {} はファイルの終わり ({} バイト) より後ろです",
+    ),
+    ("open-dropped", "Open {}?", "{} を開きますか?"),
+    ("over-limit", "Longer than {} characters", "{} 文字を超えています"),
+    ("second-line-not-blank", "Leave the second line blank", "2行目は空けてください"),
+    ("substituted", "Replaced {} occurrences", "{} 箇所を置換しました"),
+    ("replace-pattern", "Replace: ", "置換: "),
+    ("replace-regex-pattern", "Replace regex: ", "正規表現で置換: "),
+    ("replace-with", "Replace {} with: ", "{} を置き換える文字列: "),
+    ("replace-question", "Replace this match? ({} replaced so far)", "ここを置き換えますか? (ここまで {} 箇所)"),
+    ("invalid-substitute", "Usage: s/old/new/[g]", "使い方: s/置換前/置換後/[g]"),
+    ("file-info", "\"{}\" {}L", "\"{}\" {}行"),
+    ("no-name", "[No Name]", "[無名]"),
+    (
+        "save-failed-question",
+        "{} -- what now?",
+        "{} -- どうしますか?",
+    ),
+    ("save-as", "Save as: ", "別名で保存: "),
+    ("status-read-only", "RO", "読取専用"),
+    ("status-exit", "exit {}", "終了 {}"),
+    ("status-killed", "killed", "強制終了"),
+    ("status-mark", "mark", "選択"),
+    ("status-normal", "NORMAL", "ノーマル"),
+    ("status-insert", "INSERT", "挿入"),
+    ("status-visual", "VISUAL", "ビジュアル"),
+    ("status-position", "Ln {}/{}, Col {}", "{}/{} 行 {} 桁"),
+    ("status-columns", "Offset {}, Char {}, Disp {}", "先頭から {} バイト {} 文字 表示 {} 桁"),
+    (
+        "cursor-info",
+        "Line {}: char {}/{}, display column {}, byte offset {}/{} in line, {}/{} in file",
+        "{} 行目: {}/{} 文字目、表示 {} 桁目、行頭から {}/{} バイト、先頭から {}/{} バイト",
+    ),
+    ("create-failed", "Cannot create the file: {}", "ファイルを作れません: {}"),
+    ("no-undo-history", "No changes to undo or redo", "取り消しややり直しのできる変更はありません"),
+    ("undo-original", "{} oldest", "{} 一番古い状態"),
+    ("undo-entry", "{} {}: {} edits at line {}", "{} {}: {} 行目で {} 個の変更"),
+    (
+        "undo-list-help",
+        "Up/Down: preview  Enter: go to this state  q: close",
+        "上下: 先に見る  Enter: この状態にする  q: 閉じる",
+    ),
+    ("buffer-switched", "Buffer {}/{}: {}", "バッファ {}/{}: {}"),
+    ("only-buffer", "No other buffers", "ほかのバッファはありません"),
+    ("status-args", "({} of {})", "({}/{})"),
+    ("no-file-args", "Only one file was given on the command line", "コマンドラインで渡したファイルは1つだけです"),
+    ("last-file-arg", "Already at the last file", "これが最後のファイルです"),
+    ("first-file-arg", "Already at the first file", "これが最初のファイルです"),
+    ("file-arg", "File {} of {}: {}", "ファイル {}/{}: {}"),
+    ("only-window", "No other windows", "ほかの窓はありません"),
+    ("open-buffer", "Open in new buffer: ", "新しいバッファに開く: "),
+    ("close-question", "{} has unsaved changes. Save before closing?", "{} は保存していません。閉じる前に保存しますか?"),
+    ("quit-question", "{} has unsaved changes. Save before quitting?", "{} は保存していません。終わる前に保存しますか?"),
+    ("quit-unsaved", "Unsaved changes (:q! quits anyway)", "保存していない変更があります (:q! なら捨てて終わる)"),
+    ("vim-on", "Vim mode on (i to insert, Esc to return to normal mode)", "vim モードにしました (i で挿入、Esc でノーマルモードに戻ります)"),
+    ("vim-off", "Vim mode off", "vim モードをやめました"),
+    ("scroll-bind-on", "The diff pane scrolls with the text", "差分のペインを本文と一緒にスクロールします"),
+    ("scroll-bind-off", "The diff pane scrolls on its own", "差分のペインを本文と別にスクロールします"),
+    ("diff-whitespace-on", "Diffs ignore whitespace changes", "差分で空白の違いを無視します"),
+    ("diff-whitespace-off", "Diffs show whitespace changes", "差分で空白の違いも出します"),
+    ("diff-case-on", "Diffs ignore case", "差分で大文字と小文字を区別しません"),
+    ("diff-case-off", "Diffs match case", "差分で大文字と小文字を区別します"),
+    ("overwrite-question", "{} already exists. Overwrite it?", "{} はもうあります。上書きしますか?"),
+    ("read-failed", "Cannot read {}: {}", "{} を読めません: {}"),
+    ("command-failed", "{} failed: {}", "{} が失敗しました: {}"),
+    ("running", "Running {}", "{} を実行中"),
+    ("command-cancelled", "{} cancelled", "{} を中断しました"),
+    ("command-output", "Output of {} ({} lines)", "{} の出力 ({}行)"),
+    ("no-command", "No command given", "コマンドがありません"),
+    ("inserted-lines", "Inserted {} lines", "{} 行を入れました"),
+    ("rename-failed", "Rename failed: {}", "名前を変えられませんでした: {}"),
+    ("renamed", "Renamed to \"{}\"", "\"{}\" に名前を変えました"),
+    ("delete-question", "Delete \"{}\" from disk?", "\"{}\" をディスクから消しますか?"),
+    ("delete-failed", "Delete failed: {}", "消せませんでした: {}"),
+    ("deleted", "Deleted \"{}\"; Ctrl+S writes it back", "\"{}\" を消しました。Ctrl+S で書き戻せます"),
+    ("trash-failed", "Cannot back up to the trash: {}", "ごみ箱に控えを取れません: {}"),
+    ("trash-empty", "The trash is empty", "ごみ箱は空です"),
+    ("trash-help", "Enter: restore into the buffer  q: close", "Enter: バッファに戻す  q: 閉じる"),
+    (
+        "trash-restored",
+        "Restored \"{}\" from the trash; save to keep it",
+        "\"{}\" をごみ箱から戻しました。保存すると残ります",
+    ),
+    ("trash-not-text", "Cannot restore {}: not text", "{} は文字のファイルではないので戻せません"),
+    ("button-yes", "Yes", "はい"),
+    ("button-no", "No", "いいえ"),
+    ("button-all", "All", "すべて"),
+    ("button-quit", "Quit", "終わる"),
+    ("button-cancel", "Cancel", "やめる"),
+    ("button-retry", "Retry", "再試行"),
+    ("button-save-as", "Save as", "別名で保存"),
+    ("button-save", "Save", "保存"),
+    ("button-discard", "Discard", "破棄"),
+    ("button-dump", "Dump to emergency file", "緊急ファイルに退避"),
+    ("button-open-fully", "Open fully", "すべて開く"),
+    ("button-open-head", "Read-only first {} MB", "先頭の {} MB を読み取り専用で"),
+    ("buffer-dumped", "Buffer dumped to {}", "バッファを {} に退避しました"),
+    ("dump-failed", "Emergency dump failed: {}", "緊急ファイルに退避できませんでした: {}"),
+    ("save-cancelled", "Save cancelled", "保存を中止しました"),
+    ("no-file-name", "No file name", "ファイル名がありません"),
+    ("unknown-command", "Unknown command: {}", "不明なコマンドです: {}"),
+    ("read-only", "Buffer is read-only", "バッファは読み取り専用です"),
+    ("no-special", "No special characters", "特殊な文字はありません"),
+    ("removed-special", "Removed {} special characters", "特殊な文字を {} 個削除しました"),
+    ("written", "\"{}\" {}L, {}B written", "\"{}\" {}行, {}バイト書き込みました"),
+    ("appended", "\"{}\" {}L, {}B appended", "\"{}\" に {}行, {}バイト追加しました"),
+    ("save-failed", "Save failed: {}", "保存できませんでした: {}"),
+    (
+        "commit-empty",
+        "Aborting commit due to empty commit message",
+        "コミットメッセージが空なのでコミットを中止しました",
+    ),
+    ("committed", "Committed {}", "{} をコミットしました"),
+    ("commit-failed", "Commit failed: {}", "コミットできませんでした: {}"),
+    (
+        "status-clean",
+        "nothing to commit, working tree clean",
+        "コミットするものはありません",
+    ),
+    (
+        "status-help",
+        "s: stage  u: unstage  Enter: open  c: commit  q: close",
+        "s: ステージ  u: 取り消し  Enter: 開く  c: コミット  q: 閉じる",
+    ),
+    ("no-history", "No history for this file", "このファイルの履歴はありません"),
+    (
+        "history-help",
+        "Enter: open read-only  d: diff with buffer  q: close",
+        "Enter: 読み取り専用で開く  d: バッファとの差分  q: 閉じる",
+    ),
+    ("no-differences", "No differences", "差分はありません"),
+    (
+        "viewing-revision",
+        "Viewing {} at {} (read-only, :e to return)",
+        "{} の {} を表示しています (読み取り専用、:e で戻る)",
+    ),
+    (
+        "not-a-terminal",
+        "stdin and stdout must be a terminal",
+        "標準入力と標準出力が端末ではありません",
+    ),
+];
+
+// LC_ALL, LC_MESSAGES, LANG の順に見て言語を決める
+pub fn detect() -> Lang {
+    let locale = ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|name| env::var(name).ok())
+        .find(|value| !value.is_empty())
+        .unwrap_or_default();
+    if locale.starts_with("ja") {
+        Lang::Ja
+    } else {
+        Lang::En
+    }
+}
+
+pub fn set_lang(lang: Lang) {
+    JAPANESE.store(lang == Lang::Ja, Ordering::Relaxed);
+}
+
+// キーに対応するメッセージ。カタログに無ければキーをそのまま返す
+pub fn text(key: &'static str) -> &'static str {
+    let japanese = JAPANESE.load(Ordering::Relaxed);
+    CATALOG
+        .iter()
+        .find(|(k, _, _)| *k == key)
+        .map(|&(_, en, ja)| if japanese { ja } else { en })
+        .unwrap_or(key)
+}
+
+// {} を引数で順に置き換える
+pub fn format(template: &str, args: &[String]) -> String {
+    let mut result = String::new();
+    let mut args = args.iter();
+    let mut rest = template;
+    while let Some(i) = rest.find("{}") {
+        result.push_str(&rest[..i]);
+        result.push_str(args.next().map(String::as_str).unwrap_or(""));
+        rest = &rest[i + 2..];
+    }
+    result.push_str(rest);
+    result
+}
+
+macro_rules! tr {
+    ($key:expr) => {
+        $crate::i18n::text($key).to_string()
+    };
+    ($key:expr, $($arg:expr),+) => {
+        $crate::i18n::format($crate::i18n::text($key), &[$(format!("{}", $arg)),+])
+    };
+}
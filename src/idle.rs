@@ -0,0 +1,105 @@
+use std::collections::VecDeque;
+use std::mem;
+use std::time::{Duration, Instant};
+
+// 最後の入力からこれだけ経ったら手が止まったとみなす
+const IDLE_AFTER: Duration = Duration::from_millis(300);
+
+// 急がない仕事。少しずつ進められるように、どこまで進んだかを持つ
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Task {
+    // 画面外の行の配置を前もって計算しておく
+    WarmLayouts { row: usize },
+    // ファイルが外部で変更されていないか調べる
+    CheckDisk,
+    // ステータス行に出す git のブランチを調べ直す
+    GitBranch,
+}
+
+// 仕事を一度進めた結果
+pub enum Step {
+    // まだ続きがある
+    Continue(Task),
+    Done,
+    // 終わったが、画面を描き直す必要がある
+    Redraw,
+}
+
+// 決まった間隔で繰り返す仕事
+struct Periodic {
+    task: Task,
+    interval: Duration,
+    next: Instant,
+}
+
+// 入力が止まっている間にだけ仕事を進める
+pub struct Scheduler {
+    queue: VecDeque<Task>,
+    periodic: Vec<Periodic>,
+    last_input: Instant,
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Scheduler {
+            queue: VecDeque::new(),
+            periodic: Vec::new(),
+            last_input: Instant::now(),
+        }
+    }
+}
+
+impl Scheduler {
+    // 同じ種類の仕事がすでにあれば最初からやり直す
+    pub fn schedule(&mut self, task: Task) {
+        self.queue.retain(|t| mem::discriminant(t) != mem::discriminant(&task));
+        self.queue.push_back(task);
+    }
+
+    // interval ごとに、手が止まっていれば task をする
+    pub fn every(&mut self, task: Task, interval: Duration) {
+        self.periodic.push(Periodic {
+            task,
+            interval,
+            next: Instant::now() + interval,
+        });
+    }
+
+    // 入力が来た
+    pub fn touch(&mut self) {
+        self.last_input = Instant::now();
+    }
+
+    // 次の仕事を始めるまで待つ時間。仕事がなければ None
+    pub fn wait(&self) -> Option<Duration> {
+        let idle = IDLE_AFTER.saturating_sub(self.last_input.elapsed());
+        if !self.queue.is_empty() {
+            return Some(idle);
+        }
+        let now = Instant::now();
+        self.periodic
+            .iter()
+            .map(|p| p.next.saturating_duration_since(now))
+            .min()
+            .map(|due| due.max(idle))
+    }
+
+    pub fn take(&mut self) -> Option<Task> {
+        if let Some(task) = self.queue.pop_front() {
+            return Some(task);
+        }
+        let now = Instant::now();
+        for p in self.periodic.iter_mut() {
+            if p.next <= now {
+                p.next = now + p.interval;
+                return Some(p.task);
+            }
+        }
+        None
+    }
+
+    // 途中まで進めた仕事を、他の仕事より先に続ける
+    pub fn resume(&mut self, task: Task) {
+        self.queue.push_front(task);
+    }
+}
@@ -0,0 +1,204 @@
+use std::collections::VecDeque;
+use std::io::{self, stdin};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+use termion::event::{Event, Key};
+use termion::input::TermReadEventsAndRaw;
+use crate::progress::Cancel;
+use crate::translate::Translator;
+
+// 貼り付けた文字列の前後に端末が送ってくる合図
+pub const PASTE_START: &[u8] = b"\x1b[200~";
+pub const PASTE_END: &[u8] = b"\x1b[201~";
+// 裏のスレッドで調べていた git のブランチがわかった合図。端末からは来ない
+pub const GIT_BRANCH_READY: &[u8] = b"\x1b[git-branch]";
+
+// 裏のスレッドの仕事が終わったことを、入力と同じ道で知らせて待っている処理を起こす
+#[derive(Clone, Default)]
+pub struct Waker {
+    // 入力のスレッドがなければ (画面がなければ) None
+    tx: Option<Sender<io::Result<Event>>>,
+}
+
+impl Waker {
+    pub fn wake(&self, signal: &[u8]) {
+        if let Some(tx) = self.tx.as_ref() {
+            let _ = tx.send(Ok(Event::Unsupported(signal.to_vec())));
+        }
+    }
+}
+
+// 端末からの入力。別スレッドで読むので、まだ処理していない入力があるかどうかがわかる
+pub struct Input {
+    rx: Receiver<io::Result<Event>>,
+    // 先に読んでしまったがまだ処理していない入力
+    peeked: VecDeque<io::Result<Event>>,
+    cancel: Cancel,
+    waker: Waker,
+}
+
+impl Input {
+    // debounce より短い間に同じキーがまた届いたら、押し間違いとみなして捨てる (0 なら捨てない)
+    pub fn spawn(debounce: Duration) -> Self {
+        let (tx, rx) = mpsc::channel();
+        let waker = Waker { tx: Some(tx.clone()) };
+        let cancel = Cancel::connected();
+        let watcher = cancel.clone();
+        let translator = Translator::load();
+        thread::spawn(move || {
+            // 最後に受け付けたキーとその時刻。押し続けても debounce ごとに1回は受け付ける
+            let mut accepted: Option<(Key, Instant)> = None;
+            let mut pasting = false;
+            for evt in stdin().events_and_raw().map(|evt| translator.translate(evt)) {
+                // 時間のかかる処理の途中の Esc は、その処理の中断に使う
+                if let Ok(Event::Key(Key::Esc)) = evt {
+                    if watcher.on_escape() {
+                        continue;
+                    }
+                }
+                match &evt {
+                    Ok(Event::Unsupported(bytes)) if bytes == PASTE_START => pasting = true,
+                    Ok(Event::Unsupported(bytes)) if bytes == PASTE_END => pasting = false,
+                    // 貼り付けた文字列は同じ文字が続いても捨てない
+                    Ok(Event::Key(key)) if !pasting && !debounce.is_zero() => {
+                        let now = Instant::now();
+                        if accepted.is_some_and(|(last, at)| last == *key && now - at < debounce) {
+                            log_debug!("ignored {:?} within {:?} of the last one", key, debounce);
+                            continue;
+                        }
+                        accepted = Some((*key, now));
+                    },
+                    _ => {},
+                }
+                if tx.send(evt).is_err() {
+                    break;
+                }
+            }
+        });
+        Input {
+            rx,
+            peeked: VecDeque::new(),
+            cancel,
+            waker,
+        }
+    }
+
+    // 時間のかかる処理を Esc で中断できるようにする合図
+    pub fn cancel(&self) -> Cancel {
+        self.cancel.clone()
+    }
+
+    pub fn waker(&self) -> Waker {
+        self.waker.clone()
+    }
+
+    // すぐに読める入力が残っているか
+    pub fn pending(&mut self) -> bool {
+        if self.peeked.is_empty() {
+            match self.rx.try_recv() {
+                Ok(evt) => self.peeked.push_back(evt),
+                Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => {},
+            }
+        }
+        !self.peeked.is_empty()
+    }
+
+    // timeout までに入力が来たか。来た入力は取っておく
+    pub fn poll(&mut self, timeout: Duration) -> bool {
+        if self.pending() {
+            return true;
+        }
+        match self.rx.recv_timeout(timeout) {
+            Ok(evt) => {
+                self.peeked.push_back(evt);
+                true
+            },
+            Err(_) => false,
+        }
+    }
+
+    // 貼り付けの開始の合図の後、終わりの合図 end までに来た文字をまとめて返す
+    pub fn read_paste(&mut self, end: &[u8]) -> io::Result<String> {
+        let mut text = String::new();
+        for evt in self.by_ref() {
+            match evt? {
+                Event::Unsupported(bytes) if bytes == end => break,
+                Event::Key(Key::Char(c)) => text.push(c),
+                _ => {},
+            }
+        }
+        Ok(text)
+    }
+
+    // 端末からの応答 (カーソル位置の報告など) を timeout まで待つ
+    // 途中で来たキー入力は後で処理できるように取っておく
+    pub fn wait_reply<F: Fn(&[u8]) -> bool>(&mut self, timeout: Duration, is_reply: F) -> Option<Vec<u8>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                return None;
+            }
+            match self.rx.recv_timeout(deadline - now) {
+                Ok(Ok(Event::Unsupported(bytes))) if is_reply(&bytes) => return Some(bytes),
+                Ok(evt) => self.peeked.push_back(evt),
+                Err(_) => return None,
+            }
+        }
+    }
+}
+
+// ボタンを押さずにマウスを動かしたときの報告 (SGR 形式の ESC [ < 35 ; 列 ; 行 M) から、画面の (行, 列) を 0 始まりで返す
+pub fn mouse_motion(bytes: &[u8]) -> Option<(usize, usize)> {
+    let body = std::str::from_utf8(bytes.strip_prefix(b"\x1b[<35;")?.strip_suffix(b"M")?).ok()?;
+    let (col, row) = body.split_once(';')?;
+    let col: usize = col.parse().ok()?;
+    let row: usize = row.parse().ok()?;
+    Some((row.checked_sub(1)?, col.checked_sub(1)?))
+}
+
+// Shift を押しながらの矢印キー (ESC [ 1 ; 2 A など) なら、その矢印キー
+pub fn shift_arrow(bytes: &[u8]) -> Option<Key> {
+    match bytes.strip_prefix(b"\x1b[1;2")? {
+        b"A" => Some(Key::Up),
+        b"B" => Some(Key::Down),
+        b"C" => Some(Key::Right),
+        b"D" => Some(Key::Left),
+        _ => None,
+    }
+}
+
+// Ctrl を押しながらの Home, End, 左右の矢印 (ESC [ 1 ; 5 H など) なら、Ctrl をつけないキー
+// termion のキーでは Ctrl をつけて表せないので、呼ぶ側で操作にする
+pub fn ctrl_key(bytes: &[u8]) -> Option<Key> {
+    match bytes {
+        b"\x1b[1;5H" | b"\x1b[1;5~" | b"\x1b[7^" => Some(Key::Home),
+        b"\x1b[1;5F" | b"\x1b[4;5~" | b"\x1b[8^" => Some(Key::End),
+        b"\x1b[1;5D" | b"\x1bOd" => Some(Key::Left),
+        b"\x1b[1;5C" | b"\x1bOc" => Some(Key::Right),
+        _ => None,
+    }
+}
+
+// Alt を押しながらの左右の矢印 (ESC [ 1 ; 3 D など) なら、Alt をつけないキー
+pub fn alt_key(bytes: &[u8]) -> Option<Key> {
+    match bytes {
+        b"\x1b[1;3D" => Some(Key::Left),
+        b"\x1b[1;3C" => Some(Key::Right),
+        _ => None,
+    }
+}
+
+// Shift+F3 (ESC [ 1 ; 2 R か ESC [ 1 3 ; 2 ~)
+pub fn is_shift_f3(bytes: &[u8]) -> bool {
+    bytes == b"\x1b[1;2R" || bytes == b"\x1b[13;2~"
+}
+
+impl Iterator for Input {
+    type Item = io::Result<Event>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.peeked.pop_front().or_else(|| self.rx.recv().ok())
+    }
+}
@@ -0,0 +1,79 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::history::Edit;
+
+// 編集をすべて、行った順にファイルの末尾へ書き足していく記録 (--journal)
+// 書いたものは書き換えも消しもしないので、後から編集をそのとおりに再生できる
+// 1行に1つ、時刻 (UNIX 時間のミリ秒)、操作、引数をタブで区切って書く
+//   open PATH LINES    ファイルを読み込んだ (名前のないバッファは -)。以降の編集はこのファイルに対するもの
+//   insert ROW COLUMN CHAR / remove ROW COLUMN CHAR
+//   split ROW COLUMN / join ROW COLUMN
+//   replace ROW OLD NEW
+//   save PATH
+//   switch PATH        開いていたほかのバッファに切り替えた。以降の編集はこのファイルに対するもの
+// 行と列は 0 始まり。文字列のタブ、改行、復帰、\ は \t、\n、\r、\\ にする
+pub struct Journal {
+    // 書けなくなったら None にして、あとは書かない
+    file: Option<File>,
+}
+
+impl Journal {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new().append(true).create(true).open(path)?;
+        Ok(Journal { file: Some(file) })
+    }
+
+    pub fn opened(&mut self, path: Option<&Path>, lines: usize) {
+        let path = path.map(|path| path.canonicalize().unwrap_or_else(|_| path.to_path_buf()));
+        let name = path.map(|path| escape(&path.display().to_string())).unwrap_or_else(|| "-".to_string());
+        self.write(&format!("open\t{}\t{}", name, lines));
+    }
+
+    pub fn edit(&mut self, edit: &Edit) {
+        let line = match edit {
+            Edit::Insert { pos, c } => format!("insert\t{}\t{}\t{}", pos.row, pos.column, escape(&c.to_string())),
+            Edit::Remove { pos, c } => format!("remove\t{}\t{}\t{}", pos.row, pos.column, escape(&c.to_string())),
+            Edit::Split { pos } => format!("split\t{}\t{}", pos.row, pos.column),
+            Edit::Join { row, column } => format!("join\t{}\t{}", row, column),
+            Edit::Replace { row, old, new } => {
+                let old: String = old.iter().collect();
+                let new: String = new.iter().collect();
+                format!("replace\t{}\t{}\t{}", row, escape(&old), escape(&new))
+            },
+        };
+        self.write(&line);
+    }
+
+    pub fn switched(&mut self, path: Option<&Path>) {
+        let path = path.map(|path| path.canonicalize().unwrap_or_else(|_| path.to_path_buf()));
+        let name = path.map(|path| escape(&path.display().to_string())).unwrap_or_else(|| "-".to_string());
+        self.write(&format!("switch\t{}", name));
+    }
+
+    pub fn saved(&mut self, path: &Path) {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        self.write(&format!("save\t{}", escape(&path.display().to_string())));
+    }
+
+    fn write(&mut self, line: &str) {
+        let file = match self.file.as_mut() {
+            Some(file) => file,
+            None => return,
+        };
+        let millis = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+        // 1行を1回で書いて、ほかの書き手と行が混ざらないようにする
+        if let Err(e) = file.write_all(format!("{}\t{}\n", millis, line).as_bytes()) {
+            log_error!("cannot write the journal, stopped journaling: {}", e);
+            self.file = None;
+        }
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n").replace('\r', "\\r")
+}
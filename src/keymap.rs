@@ -0,0 +1,451 @@
+use std::str::FromStr;
+use termion::event::Key;
+
+// キーに割り当てられる操作
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyAction {
+    // 選んでいればコピー、選んでいなければ終わる
+    CopyOrQuit,
+    Quit,
+    Copy,
+    Cut,
+    Paste,
+    Save,
+    Undo,
+    Redo,
+    Search,
+    SearchNext,
+    // 選択を広げて、次と前に一致するところまで選ぶ
+    SelectNextMatch,
+    SelectPreviousMatch,
+    KillLine,
+    KillWholeLine,
+    Yank,
+    InsertExpression,
+    GitStatus,
+    FileHistory,
+    NextLocation,
+    PreviousLocation,
+    LocationList,
+    ToggleBookmark,
+    EditBookmark,
+    Reopen,
+    Zoom,
+    GrowPane,
+    ShrinkPane,
+    CollapsePane,
+    RelativeNumbers,
+    OpenBuffer,
+    NextBuffer,
+    PreviousBuffer,
+    // コマンドラインで渡したファイルの次と前に移る
+    NextFile,
+    PreviousFile,
+    Split,
+    VerticalSplit,
+    CloseWindow,
+    NextWindow,
+    PreviousWindow,
+    // LAYOUT_PRESETS の何番目 (0 始まり)
+    Layout(usize),
+    Cancel,
+    Mark,
+    // 選択している範囲を、正規表現に一致するところごとの選択に分ける
+    SplitSelection,
+    // 次のキーに Ctrl や Alt をつける (固定キー)
+    StickyCtrl,
+    StickyAlt,
+    CommandPrompt,
+    // 上下は visual_lines にしたがって論理行か表示行で動き、alternate はその逆で動く
+    Up,
+    Down,
+    UpAlternate,
+    DownAlternate,
+    PageUp,
+    PageDown,
+    Left,
+    Right,
+    LineStart,
+    LineEnd,
+    BufferStart,
+    BufferEnd,
+    // 行の番号を聞いてそこに移る
+    GotoLine,
+    // 移動の履歴を戻る、進む
+    JumpBack,
+    JumpForward,
+    WordForward,
+    WordBackward,
+    Tab,
+    Backspace,
+    Delete,
+    // : のコマンドを実行する
+    Run(String),
+    // 割り当てのない文字を入れる
+    Insert(char),
+}
+
+// 操作の名前。設定ファイルではこの名前か ":コマンド" を書く
+const ACTION_NAMES: &[(&str, KeyAction)] = &[
+    ("copy-or-quit", KeyAction::CopyOrQuit),
+    ("quit", KeyAction::Quit),
+    ("copy", KeyAction::Copy),
+    ("cut", KeyAction::Cut),
+    ("paste", KeyAction::Paste),
+    ("save", KeyAction::Save),
+    ("undo", KeyAction::Undo),
+    ("redo", KeyAction::Redo),
+    ("search", KeyAction::Search),
+    ("search-next", KeyAction::SearchNext),
+    ("select-next-match", KeyAction::SelectNextMatch),
+    ("select-previous-match", KeyAction::SelectPreviousMatch),
+    ("kill-line", KeyAction::KillLine),
+    ("kill-whole-line", KeyAction::KillWholeLine),
+    ("yank", KeyAction::Yank),
+    ("insert-expression", KeyAction::InsertExpression),
+    ("git-status", KeyAction::GitStatus),
+    ("file-history", KeyAction::FileHistory),
+    ("next-location", KeyAction::NextLocation),
+    ("previous-location", KeyAction::PreviousLocation),
+    ("location-list", KeyAction::LocationList),
+    ("toggle-bookmark", KeyAction::ToggleBookmark),
+    ("edit-bookmark", KeyAction::EditBookmark),
+    ("reopen", KeyAction::Reopen),
+    ("zoom", KeyAction::Zoom),
+    ("grow-pane", KeyAction::GrowPane),
+    ("shrink-pane", KeyAction::ShrinkPane),
+    ("collapse-pane", KeyAction::CollapsePane),
+    ("relative-numbers", KeyAction::RelativeNumbers),
+    ("open-buffer", KeyAction::OpenBuffer),
+    ("next-buffer", KeyAction::NextBuffer),
+    ("previous-buffer", KeyAction::PreviousBuffer),
+    ("next-file", KeyAction::NextFile),
+    ("previous-file", KeyAction::PreviousFile),
+    ("split", KeyAction::Split),
+    ("vsplit", KeyAction::VerticalSplit),
+    ("close-window", KeyAction::CloseWindow),
+    ("next-window", KeyAction::NextWindow),
+    ("previous-window", KeyAction::PreviousWindow),
+    ("cancel", KeyAction::Cancel),
+    ("mark", KeyAction::Mark),
+    ("split-selection", KeyAction::SplitSelection),
+    ("sticky-ctrl", KeyAction::StickyCtrl),
+    ("sticky-alt", KeyAction::StickyAlt),
+    ("command", KeyAction::CommandPrompt),
+    ("up", KeyAction::Up),
+    ("down", KeyAction::Down),
+    ("up-alternate", KeyAction::UpAlternate),
+    ("down-alternate", KeyAction::DownAlternate),
+    ("page-up", KeyAction::PageUp),
+    ("page-down", KeyAction::PageDown),
+    ("left", KeyAction::Left),
+    ("right", KeyAction::Right),
+    ("line-start", KeyAction::LineStart),
+    ("line-end", KeyAction::LineEnd),
+    ("buffer-start", KeyAction::BufferStart),
+    ("buffer-end", KeyAction::BufferEnd),
+    ("goto-line", KeyAction::GotoLine),
+    ("jump-back", KeyAction::JumpBack),
+    ("jump-forward", KeyAction::JumpForward),
+    ("word-forward", KeyAction::WordForward),
+    ("word-backward", KeyAction::WordBackward),
+    ("tab", KeyAction::Tab),
+    ("backspace", KeyAction::Backspace),
+    ("delete", KeyAction::Delete),
+];
+
+impl FromStr for KeyAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Some(command) = s.strip_prefix(':') {
+            return Ok(KeyAction::Run(command.trim().to_string()));
+        }
+        if let Some(n) = s.strip_prefix("layout-").and_then(|n| n.parse::<usize>().ok()) {
+            if (1..=9).contains(&n) {
+                return Ok(KeyAction::Layout(n - 1));
+            }
+        }
+        ACTION_NAMES
+            .iter()
+            .find(|(name, _)| *name == s)
+            .map(|(_, action)| action.clone())
+            .ok_or_else(|| format!("unknown action: {}", s))
+    }
+}
+
+// 最初から割り当ててあるキー
+const DEFAULT_BINDINGS: &[(&str, &str)] = &[
+    ("ctrl-c", "copy-or-quit"),
+    ("ctrl-x", "cut"),
+    ("ctrl-v", "paste"),
+    ("ctrl-s", "save"),
+    ("ctrl-z", "undo"),
+    // Ctrl+Y は貼り付けに使うので、やり直しは Alt+y (kitty キーボードプロトコルなら Ctrl+Shift+Z も)
+    ("alt-y", "redo"),
+    ("ctrl-shift-z", "redo"),
+    ("ctrl-f", "search"),
+    ("ctrl-g", "goto-line"),
+    ("f3", "search-next"),
+    ("ctrl-k", "kill-line"),
+    ("ctrl-u", "kill-whole-line"),
+    ("ctrl-y", "yank"),
+    ("ctrl-r", "insert-expression"),
+    ("alt-g", "git-status"),
+    ("alt-h", "file-history"),
+    ("alt-n", "next-location"),
+    ("alt-p", "previous-location"),
+    ("alt-l", "location-list"),
+    ("alt-b", "toggle-bookmark"),
+    ("alt-shift-b", "edit-bookmark"),
+    ("alt-t", "reopen"),
+    ("alt-z", "zoom"),
+    ("alt-=", "grow-pane"),
+    ("alt-+", "grow-pane"),
+    ("alt--", "shrink-pane"),
+    ("alt-c", "collapse-pane"),
+    ("alt-#", "relative-numbers"),
+    ("ctrl-o", "open-buffer"),
+    ("alt-.", "next-buffer"),
+    ("alt-,", "previous-buffer"),
+    ("alt-}", "next-file"),
+    ("alt-{", "previous-file"),
+    ("alt-s", "split"),
+    ("alt-v", "vsplit"),
+    ("alt-q", "close-window"),
+    ("alt-o", "next-window"),
+    ("alt-shift-o", "previous-window"),
+    ("alt-1", "layout-1"),
+    ("alt-2", "layout-2"),
+    ("alt-3", "layout-3"),
+    ("alt-4", "layout-4"),
+    ("alt-5", "layout-5"),
+    ("alt-6", "layout-6"),
+    ("alt-7", "layout-7"),
+    ("alt-8", "layout-8"),
+    ("alt-9", "layout-9"),
+    ("esc", "cancel"),
+    ("ctrl-space", "mark"),
+    ("alt-shift-s", "split-selection"),
+    ("alt-x", "command"),
+    ("up", "up"),
+    ("down", "down"),
+    ("alt-k", "up-alternate"),
+    ("alt-j", "down-alternate"),
+    ("pageup", "page-up"),
+    ("pagedown", "page-down"),
+    ("left", "left"),
+    ("right", "right"),
+    ("home", "line-start"),
+    ("end", "line-end"),
+    ("ctrl-a", "line-start"),
+    ("ctrl-e", "line-end"),
+    ("tab", "tab"),
+    ("backspace", "backspace"),
+    ("delete", "delete"),
+];
+
+// 既定の表に重ねる割り当て。設定ファイルの keymap で選ぶ
+const PRESETS: &[(&str, &[(&str, &str)])] = &[
+    ("default", &[]),
+    (
+        "emacs",
+        &[
+            ("ctrl-a", "line-start"),
+            ("ctrl-e", "line-end"),
+            ("ctrl-f", "right"),
+            ("ctrl-b", "left"),
+            ("ctrl-n", "down"),
+            ("ctrl-p", "up"),
+            ("alt-f", "word-forward"),
+            ("alt-b", "word-backward"),
+            ("ctrl-v", "page-down"),
+            ("alt-v", "page-up"),
+            ("alt-<", "buffer-start"),
+            ("alt->", "buffer-end"),
+            ("ctrl-d", "delete"),
+            ("ctrl-k", "kill-line"),
+            ("ctrl-g", "cancel"),
+            ("ctrl-s", "search"),
+            // 選んだ範囲を消して覚える、覚える、貼り付ける
+            ("ctrl-w", "cut"),
+            ("alt-w", "copy"),
+            ("ctrl-y", "yank"),
+            ("ctrl-/", "undo"),
+            // Ctrl+X は切り取りではなく、続けて押すキーの始めにする
+            ("ctrl-x ctrl-s", "save"),
+            ("ctrl-x ctrl-c", "quit"),
+            ("ctrl-x u", "undo"),
+            ("ctrl-x o", "next-window"),
+            ("ctrl-x 0", "close-window"),
+            ("ctrl-x 2", "split"),
+            ("ctrl-x 3", "vsplit"),
+        ],
+    ),
+];
+
+pub fn preset_names() -> Vec<&'static str> {
+    PRESETS.iter().map(|(name, _)| *name).collect()
+}
+
+// Ctrl+Space は端末によって Null か Ctrl(' ') で届くので、どちらも同じキーとして扱う
+// Ctrl+/ は Ctrl+_ と同じ 0x1F で届き、termion は Ctrl('7') と読む
+fn normalize(key: Key) -> Key {
+    match key {
+        Key::Ctrl(' ') => Key::Null,
+        Key::Ctrl('7') | Key::Ctrl('_') => Key::Ctrl('/'),
+        key => key,
+    }
+}
+
+// "ctrl-s", "alt-x", "C-x", "M-x", "ctrl-shift-z", "f3", "up", "esc", "a" のように書いた1つのキー
+pub fn parse_key(text: &str) -> Result<Key, String> {
+    let mut rest = text;
+    let (mut ctrl, mut alt, mut shift) = (false, false, false);
+    loop {
+        let lower = rest.to_ascii_lowercase();
+        let prefix = ["ctrl-", "c-", "alt-", "m-", "meta-", "shift-", "s-"]
+            .iter()
+            .find(|prefix| lower.starts_with(*prefix) && rest.len() > prefix.len());
+        match prefix {
+            Some(&prefix) => {
+                match prefix {
+                    "ctrl-" | "c-" => ctrl = true,
+                    "shift-" | "s-" => shift = true,
+                    _ => alt = true,
+                }
+                rest = &rest[prefix.len()..];
+            },
+            None => break,
+        }
+    }
+    let mut chars = rest.chars();
+    let base = match (chars.next(), chars.next()) {
+        (Some(c), None) => Key::Char(c),
+        _ => match rest.to_ascii_lowercase().as_str() {
+            "up" => Key::Up,
+            "down" => Key::Down,
+            "left" => Key::Left,
+            "right" => Key::Right,
+            "home" => Key::Home,
+            "end" => Key::End,
+            "pageup" => Key::PageUp,
+            "pagedown" => Key::PageDown,
+            "backspace" => Key::Backspace,
+            "delete" | "del" => Key::Delete,
+            "insert" => Key::Insert,
+            "esc" | "escape" => Key::Esc,
+            "tab" => Key::Char('\t'),
+            "enter" | "return" => Key::Char('\n'),
+            "space" => Key::Char(' '),
+            name => match name.strip_prefix('f').and_then(|n| n.parse::<u8>().ok()) {
+                Some(n @ 1..=12) => Key::F(n),
+                _ => return Err(format!("unknown key: {}", text)),
+            },
+        },
+    };
+    let c = match base {
+        Key::Char(c) if shift => c.to_ascii_uppercase(),
+        Key::Char(c) => c,
+        key if !ctrl && !alt && !shift => return Ok(key),
+        _ => return Err(format!("modifiers work only with character keys: {}", text)),
+    };
+    match (ctrl, alt) {
+        (true, true) => Err(format!("ctrl and alt together are not supported: {}", text)),
+        // 端末は Ctrl と英字を小文字で送る。Shift つきは kitty キーボードプロトコルのときだけ届く
+        (true, false) if c == ' ' => Ok(Key::Null),
+        (true, false) if shift => Ok(Key::Ctrl(c)),
+        (true, false) => Ok(Key::Ctrl(c.to_ascii_lowercase())),
+        (false, true) => Ok(Key::Alt(c)),
+        (false, false) => Ok(Key::Char(c)),
+    }
+}
+
+// 空白で区切って続けて押すキーを並べたもの ("ctrl-x ctrl-s")
+pub fn parse_keys(text: &str) -> Result<Vec<Key>, String> {
+    let keys = text.split_whitespace().map(parse_key).collect::<Result<Vec<_>, _>>()?;
+    if keys.is_empty() {
+        return Err("no key".to_string());
+    }
+    Ok(keys)
+}
+
+// メッセージに出すキーの名前
+pub fn key_name(key: Key) -> String {
+    match key {
+        Key::Ctrl(c) if c.is_ascii_uppercase() => format!("ctrl-shift-{}", c.to_ascii_lowercase()),
+        Key::Ctrl(c) => format!("ctrl-{}", c),
+        Key::Null => "ctrl-space".to_string(),
+        Key::Alt(c) => format!("alt-{}", c),
+        Key::Char('\t') => "tab".to_string(),
+        Key::Char('\n') => "enter".to_string(),
+        Key::Char(' ') => "space".to_string(),
+        Key::Char(c) => c.to_string(),
+        Key::F(n) => format!("f{}", n),
+        Key::Esc => "esc".to_string(),
+        key => format!("{:?}", key).to_ascii_lowercase(),
+    }
+}
+
+pub fn keys_name(keys: &[Key]) -> String {
+    keys.iter().map(|&key| key_name(key)).collect::<Vec<_>>().join(" ")
+}
+
+// 押したキーの並びを引いた結果
+pub enum Lookup {
+    Found(KeyAction),
+    // もっと長い並びの途中なので、次のキーを待つ
+    Prefix,
+    Unbound,
+}
+
+// キーの並びと操作の表。起動するときに既定の表に設定ファイルの割り当てを重ねて作る
+pub struct Keymap {
+    bindings: Vec<(Vec<Key>, KeyAction)>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let mut keymap = Keymap { bindings: Vec::new() };
+        for (keys, action) in DEFAULT_BINDINGS {
+            keymap.bind(parse_keys(keys).expect("default key"), Some(action.parse().expect("default action")));
+        }
+        keymap
+    }
+}
+
+impl Keymap {
+    // 名前の割り当てを既定の表に重ねる。知らない名前なら false
+    pub fn apply_preset(&mut self, name: &str) -> bool {
+        let bindings = match PRESETS.iter().find(|(preset, _)| preset.eq_ignore_ascii_case(name)) {
+            Some((_, bindings)) => bindings,
+            None => return false,
+        };
+        for (keys, action) in bindings.iter() {
+            self.bind(parse_keys(keys).expect("preset key"), Some(action.parse().expect("preset action")));
+        }
+        true
+    }
+
+    // action が None なら割り当てを消す
+    pub fn bind(&mut self, keys: Vec<Key>, action: Option<KeyAction>) {
+        let keys: Vec<Key> = keys.into_iter().map(normalize).collect();
+        self.bindings.retain(|(bound, _)| *bound != keys);
+        if let Some(action) = action {
+            self.bindings.push((keys, action));
+        }
+    }
+
+    // 長い並びの始めに当たるキーは、それだけでは割り当てた操作をせずに次のキーを待つ
+    pub fn lookup(&self, keys: &[Key]) -> Lookup {
+        let keys: Vec<Key> = keys.iter().map(|&key| normalize(key)).collect();
+        if self.bindings.iter().any(|(bound, _)| bound.len() > keys.len() && bound.starts_with(&keys)) {
+            return Lookup::Prefix;
+        }
+        match self.bindings.iter().find(|(bound, _)| *bound == keys) {
+            Some((_, action)) => Lookup::Found(action.clone()),
+            None => Lookup::Unbound,
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use std::fs;
+use std::io;
+use crate::logger;
+
+// 覚えておく消した文字列の数。古いものから捨てる
+const LIMIT: usize = 30;
+
+// 消したりコピーしたりした文字列。システムのクリップボードがなくても、起動している間は貼り付けられる
+#[derive(Default)]
+pub struct KillRing {
+    kills: Vec<String>,
+    // 続けて消したものは、一番新しいものにつなげる
+    append: bool,
+}
+
+impl KillRing {
+    // 消した文字列を覚える。直前も消していたら、その後ろにつなげる
+    pub fn kill(&mut self, text: String) {
+        match self.kills.last_mut() {
+            Some(last) if self.append => last.push_str(&text),
+            _ => self.push(text),
+        }
+        self.append = true;
+    }
+
+    // つなげずに新しく覚える
+    pub fn push(&mut self, text: String) {
+        self.kills.push(text);
+        if self.kills.len() > LIMIT {
+            self.kills.remove(0);
+        }
+    }
+
+    // 消す以外の操作をした。次に消したものは別に覚える
+    pub fn interrupt(&mut self) {
+        self.append = false;
+    }
+
+    pub fn yank(&self) -> Option<&str> {
+        self.kills.last().map(String::as_str)
+    }
+
+    // 新しい順の覚えているもの
+    pub fn entries(&self) -> impl Iterator<Item = &str> {
+        self.kills.iter().rev().map(String::as_str)
+    }
+
+    // 新しい順で index 番目のものを一番新しいものにして返す。次からは yank でも貼り付けられる
+    pub fn select(&mut self, index: usize) -> Option<&str> {
+        let position = self.kills.len().checked_sub(index + 1)?;
+        let text = self.kills.remove(position);
+        self.kills.push(text);
+        self.append = false;
+        self.yank()
+    }
+
+    // 前のセッションで覚えていたもの。状態ディレクトリの kill-ring に、古い順に1行に1つずつ書いてある
+    pub fn load() -> Self {
+        let kills = logger::state_dir()
+            .and_then(|dir| fs::read_to_string(dir.join("kill-ring")).ok())
+            .map(|text| text.lines().map(unescape).collect())
+            .unwrap_or_default();
+        KillRing { kills, append: false }
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let dir = logger::state_dir().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no state directory"))?;
+        fs::create_dir_all(&dir)?;
+        let text: String = self.kills.iter().map(|kill| format!("{}\n", escape(kill))).collect();
+        fs::write(dir.join("kill-ring"), text)
+    }
+}
+
+// 1行に収まるように、改行と \ を \ で書く。一覧にもこの形で出す
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unescape(line: &str) -> String {
+    let mut text = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            text.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => text.push('\n'),
+            Some('r') => text.push('\r'),
+            Some(c) => text.push(c),
+            None => text.push('\\'),
+        }
+    }
+    text
+}
@@ -0,0 +1,243 @@
+use std::cmp::{max, min};
+use std::ops::Range;
+use std::rc::Rc;
+use unicode_bidi::ParagraphBidiInfo;
+use crate::{special, Line};
+
+// これより長い行は折り返さずに、カーソルの周りだけを切り出して表示する
+// 塊に分けて持つ行と同じにして、配置を計算する行はいつも1つの塊で読めるようにする
+pub const LONG_LINE: usize = crate::line::CHUNK_LEN;
+// 配置を覚えておく行数。行数の多いファイルでもメモリを使いすぎないようにする
+pub const CACHE_LIMIT: usize = 20_000;
+
+pub fn is_long(line: &Line) -> bool {
+    line.len() > LONG_LINE
+}
+
+// 長い行を start 文字目から表示するとき、カーソルが画面に収まるように start を直す
+// 見る範囲はカーソルの周りの画面幅ぶんだけなので、行の長さによらない
+pub fn clip_start(line: &Line, start: usize, column: usize, cols: usize, escapes: bool) -> usize {
+    let mut start = min(start, column);
+    // 1文字の幅は1以上なので、画面幅より離れていたら画面幅まで詰めてから測る
+    start = max(start, column.saturating_sub(cols));
+    let mut width: usize = line
+        .slice(start..column)
+        .iter()
+        .map(|&c| special::display_width(c, escapes))
+        .sum();
+    while width + 1 >= cols && start < column {
+        width -= special::display_width(line[start], escapes);
+        start += 1;
+    }
+    start
+}
+
+// 1行ぶんの表示上の配置
+pub struct LineLayout {
+    // 計算したときの画面の幅
+    cols: usize,
+    // 計算したときに特殊な文字をエスケープして表示していたか
+    escapes: bool,
+    // 各文字 (と行末) の折り返したときの位置 (行内での表示行, 列)
+    pub pos: Vec<(usize, usize)>,
+    // 各文字 (と行末) の折り返さないときの列
+    pub x: Vec<usize>,
+    // 右から左に書く文字を含むとき、画面の左から順に並べた文字の添字
+    // (折り返すとき, 折り返さないとき)
+    pub visual: Option<(Vec<usize>, Vec<usize>)>,
+}
+
+impl LineLayout {
+    fn new(line: &[char], cols: usize, escapes: bool) -> Self {
+        let mut pos = Vec::with_capacity(line.len() + 1);
+        let mut x = Vec::with_capacity(line.len() + 1);
+        let (mut r, mut c, mut total) = (0, 0, 0);
+        for ch in line {
+            let width = special::display_width(*ch, escapes);
+            if c + width >= cols {
+                r += 1;
+                c = 0;
+            }
+            pos.push((r, c));
+            x.push(total);
+            c += width;
+            total += width;
+        }
+        pos.push((r, c));
+        x.push(total);
+
+        let mut layout = LineLayout {
+            cols,
+            escapes,
+            pos,
+            x,
+            visual: None,
+        };
+        layout.reorder(line);
+        layout
+    }
+
+    // 右から左に書く部分があれば、折り返した1行ごとに表示順に並べ替えて位置を振り直す
+    // カーソルの移動は論理順のままなので、位置だけを変える
+    fn reorder(&mut self, line: &[char]) {
+        // アラビア文字やヘブライ文字より前の文字しかなければ何もしない
+        if line.iter().all(|&c| c < '\u{0590}') {
+            return;
+        }
+        let text: String = line.iter().collect();
+        let bidi = ParagraphBidiInfo::new(&text, None);
+        if !bidi.has_rtl() {
+            return;
+        }
+        // 文字の添字とバイト位置の対応
+        let bytes: Vec<usize> = text
+            .char_indices()
+            .map(|(i, _)| i)
+            .chain(Some(text.len()))
+            .collect();
+        let visual_order = |chars: Range<usize>| -> Vec<usize> {
+            let (levels, runs) = bidi.visual_runs(bytes[chars.start]..bytes[chars.end]);
+            let mut order = Vec::new();
+            for run in runs {
+                let start = bytes.partition_point(|&b| b < run.start);
+                let end = bytes.partition_point(|&b| b < run.end);
+                if levels[run.start].is_rtl() {
+                    order.extend((start..end).rev());
+                } else {
+                    order.extend(start..end);
+                }
+            }
+            order
+        };
+
+        // 折り返したときは表示行ごとに並べ替える
+        let mut wrapped = Vec::with_capacity(line.len());
+        let mut start = 0;
+        while start < line.len() {
+            let row = self.pos[start].0;
+            let end = (start..line.len())
+                .find(|&j| self.pos[j].0 != row)
+                .unwrap_or(line.len());
+            let mut c = 0;
+            for j in visual_order(start..end) {
+                self.pos[j] = (row, c);
+                c += special::display_width(line[j], self.escapes);
+            }
+            wrapped.extend(visual_order(start..end));
+            start = end;
+        }
+
+        let unwrapped = visual_order(0..line.len());
+        let mut total = 0;
+        for &j in &unwrapped {
+            self.x[j] = total;
+            total += special::display_width(line[j], self.escapes);
+        }
+        self.visual = Some((wrapped, unwrapped));
+    }
+
+    // 描画する順に並べた文字の添字 (最後は行末)
+    pub fn draw_order(&self, wrap: bool) -> Vec<usize> {
+        let len = self.pos.len() - 1;
+        match self.visual.as_ref() {
+            Some((wrapped, unwrapped)) => {
+                let order = if wrap { wrapped } else { unwrapped };
+                order.iter().copied().chain(Some(len)).collect()
+            },
+            None => (0..=len).collect(),
+        }
+    }
+
+    // 折り返したときの表示行の数
+    pub fn rows(&self) -> usize {
+        self.pos.iter().map(|&(r, _)| r).max().unwrap_or(0) + 1
+    }
+
+    // 折り返さないときに列 x の位置にある文字の添字。行末より右なら行末
+    pub fn index_at_x(&self, x: usize) -> usize {
+        (0..self.x.len())
+            .filter(|&j| self.x[j] <= x)
+            .max_by_key(|&j| self.x[j])
+            .unwrap_or(self.x.len() - 1)
+    }
+
+    // 折り返した row 行目で、列 col の位置にある文字の添字
+    // col より右にしか文字がなければ、その行の最初の文字にする
+    pub fn index_at(&self, row: usize, col: usize) -> usize {
+        let on_row = || (0..self.pos.len()).filter(|&j| self.pos[j].0 == row);
+        on_row()
+            .filter(|&j| self.pos[j].1 <= col)
+            .max_by_key(|&j| self.pos[j].1)
+            .or_else(|| on_row().min_by_key(|&j| self.pos[j].1))
+            .unwrap_or(self.pos.len() - 1)
+    }
+}
+
+// 表示したことのある行の配置を覚えておく。編集された行だけ捨てる
+// CACHE_LIMIT 行を超えたら、今見ている行から遠いものを捨てる
+#[derive(Default)]
+pub struct LayoutCache {
+    lines: Vec<Option<Rc<LineLayout>>>,
+    // 覚えている行数
+    cached: usize,
+}
+
+impl LayoutCache {
+    pub fn get(&mut self, row: usize, line: &Line, cols: usize, escapes: bool) -> Rc<LineLayout> {
+        if self.lines.len() <= row {
+            self.lines.resize(row + 1, None);
+        }
+        match self.lines[row].as_ref() {
+            Some(layout) if layout.cols == cols && layout.escapes == escapes => layout.clone(),
+            _ => {
+                let layout = Rc::new(LineLayout::new(&line.slice(..), cols, escapes));
+                if self.lines[row].replace(layout.clone()).is_none() {
+                    self.cached += 1;
+                    if self.cached > CACHE_LIMIT {
+                        self.evict(row);
+                    }
+                }
+                layout
+            },
+        }
+    }
+
+    // row 行目の前後 CACHE_LIMIT / 2 行だけを残す
+    fn evict(&mut self, row: usize) {
+        let keep = row.saturating_sub(CACHE_LIMIT / 2)..row + CACHE_LIMIT / 2;
+        for (i, layout) in self.lines.iter_mut().enumerate() {
+            if !keep.contains(&i) {
+                *layout = None;
+            }
+        }
+        self.cached = self.lines.iter().filter(|layout| layout.is_some()).count();
+    }
+
+    // row 行目の内容が変わった
+    pub fn invalidate(&mut self, row: usize) {
+        if let Some(layout) = self.lines.get_mut(row) {
+            if layout.take().is_some() {
+                self.cached -= 1;
+            }
+        }
+    }
+
+    // row 行目に行が挿入された
+    pub fn insert_line(&mut self, row: usize) {
+        if row <= self.lines.len() {
+            self.lines.insert(row, None);
+        }
+    }
+
+    // row 行目が削除された
+    pub fn remove_line(&mut self, row: usize) {
+        if row < self.lines.len() && self.lines.remove(row).is_some() {
+            self.cached -= 1;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.lines.clear();
+        self.cached = 0;
+    }
+}
@@ -0,0 +1,10 @@
+// エディタの外 (プラグインなど) からも使える部分
+// バイト位置、(行, 列)、表示上の列を互いに変える関数と、そのための文字コードと文字幅、バッファの行
+pub mod address;
+pub mod encoding;
+pub mod line;
+pub mod special;
+
+pub use address::Cursor;
+pub use encoding::Encoding;
+pub use line::Line;
@@ -0,0 +1,196 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::ops::{Bound, Index, RangeBounds};
+
+// 1つの塊に入れる文字数の上限。これより短い行は1つの塊のまま持つので、切り出さずにそのまま読める
+pub const CHUNK_LEN: usize = 10_000;
+
+// バッファの1行。とても長い行 (1行に詰めたスクリプトなど) は塊に分けて持ち、
+// 文字を入れたり消したりしても、入れたところの塊だけを詰め直せばすむようにする
+#[derive(Clone)]
+pub struct Line {
+    // 空の塊は持たない。ただし空の行は空の塊1つにする
+    chunks: Vec<Vec<char>>,
+    len: usize,
+}
+
+impl Default for Line {
+    fn default() -> Self {
+        Line { chunks: vec![Vec::new()], len: 0 }
+    }
+}
+
+impl Line {
+    pub fn new() -> Self {
+        Line::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // index 文字目を含む塊と、その塊の中での添字。行末なら最後の塊の終わり
+    fn locate(&self, index: usize) -> (usize, usize) {
+        let mut start = 0;
+        for (k, chunk) in self.chunks.iter().enumerate() {
+            if index < start + chunk.len() {
+                return (k, index - start);
+            }
+            start += chunk.len();
+        }
+        let last = self.chunks.len() - 1;
+        (last, self.chunks[last].len())
+    }
+
+    pub fn get(&self, index: usize) -> Option<&char> {
+        if index >= self.len {
+            return None;
+        }
+        let (k, offset) = self.locate(index);
+        self.chunks[k].get(offset)
+    }
+
+    pub fn first(&self) -> Option<&char> {
+        self.get(0)
+    }
+
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &char> + Clone {
+        self.chunks.iter().flatten()
+    }
+
+    // range の文字。1つの塊に収まっていれば借りるだけで、またがるときだけつなげて作る
+    pub fn slice(&self, range: impl RangeBounds<usize>) -> Cow<'_, [char]> {
+        let start = match range.start_bound() {
+            Bound::Included(&start) => start,
+            Bound::Excluded(&start) => start + 1,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&end) => end + 1,
+            Bound::Excluded(&end) => end,
+            Bound::Unbounded => self.len,
+        };
+        assert!(start <= end && end <= self.len, "range {}..{} out of a line of {}", start, end, self.len);
+        let (k, offset) = self.locate(start);
+        if offset + (end - start) <= self.chunks[k].len() {
+            return Cow::Borrowed(&self.chunks[k][offset..offset + end - start]);
+        }
+        Cow::Owned(self.iter().skip(start).take(end - start).copied().collect())
+    }
+
+    pub fn to_vec(&self) -> Vec<char> {
+        self.iter().copied().collect()
+    }
+
+    pub fn insert(&mut self, index: usize, c: char) {
+        assert!(index <= self.len, "insertion index {} out of a line of {}", index, self.len);
+        let (k, offset) = self.locate(index);
+        self.chunks[k].insert(offset, c);
+        self.len += 1;
+        // いっぱいになった塊は半分に分ける
+        if self.chunks[k].len() > CHUNK_LEN {
+            let half = self.chunks[k].len() / 2;
+            let rest = self.chunks[k].split_off(half);
+            self.chunks.insert(k + 1, rest);
+        }
+    }
+
+    pub fn remove(&mut self, index: usize) -> char {
+        assert!(index < self.len, "removal index {} out of a line of {}", index, self.len);
+        let (k, offset) = self.locate(index);
+        let c = self.chunks[k].remove(offset);
+        self.len -= 1;
+        self.normalize();
+        c
+    }
+
+    // at 文字目から後ろを切り離して返す
+    pub fn split_off(&mut self, at: usize) -> Line {
+        assert!(at <= self.len, "split index {} out of a line of {}", at, self.len);
+        let (k, offset) = self.locate(at);
+        let mut chunks = vec![self.chunks[k].split_off(offset)];
+        chunks.extend(self.chunks.drain(k + 1..));
+        let mut rest = Line { chunks, len: self.len - at };
+        self.len = at;
+        self.normalize();
+        rest.normalize();
+        rest
+    }
+
+    // 後ろに other をつなげる
+    pub fn append(&mut self, other: Line) {
+        self.chunks.extend(other.chunks);
+        self.len += other.len;
+        self.normalize();
+    }
+
+    // 空の塊を捨て、短くなった行は1つの塊に戻す
+    fn normalize(&mut self) {
+        if self.chunks.len() == 1 {
+            return;
+        }
+        self.chunks.retain(|chunk| !chunk.is_empty());
+        if self.len <= CHUNK_LEN {
+            self.chunks = vec![self.chunks.concat()];
+        }
+    }
+}
+
+impl From<Vec<char>> for Line {
+    // 長い行は半分ずつ詰めた塊に分け、少し入れてもすぐには分けなくてすむようにする
+    fn from(chars: Vec<char>) -> Self {
+        let len = chars.len();
+        if len <= CHUNK_LEN {
+            return Line { chunks: vec![chars], len };
+        }
+        Line { chunks: chars.chunks(CHUNK_LEN / 2).map(<[char]>::to_vec).collect(), len }
+    }
+}
+
+impl From<&str> for Line {
+    fn from(s: &str) -> Self {
+        Line::from(s.chars().collect::<Vec<char>>())
+    }
+}
+
+impl FromIterator<char> for Line {
+    fn from_iter<I: IntoIterator<Item = char>>(iter: I) -> Self {
+        Line::from(iter.into_iter().collect::<Vec<char>>())
+    }
+}
+
+impl Index<usize> for Line {
+    type Output = char;
+
+    fn index(&self, index: usize) -> &char {
+        self.get(index).unwrap_or_else(|| panic!("index {} out of a line of {}", index, self.len))
+    }
+}
+
+impl<'a> IntoIterator for &'a Line {
+    type Item = &'a char;
+    type IntoIter = std::iter::Flatten<std::slice::Iter<'a, Vec<char>>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.chunks.iter().flatten()
+    }
+}
+
+// 塊の分け方によらず、文字が同じなら同じ行
+impl PartialEq for Line {
+    fn eq(&self, other: &Line) -> bool {
+        self.len == other.len && self.iter().eq(other.iter())
+    }
+}
+
+impl Eq for Line {}
+
+impl fmt::Debug for Line {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.iter().collect::<String>())
+    }
+}
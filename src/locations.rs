@@ -0,0 +1,67 @@
+// バッファの中の位置と、その説明
+pub struct Location {
+    pub row: usize,
+    pub column: usize,
+    pub text: String,
+}
+
+impl Location {
+    // 一覧に出す1行
+    pub fn line(&self) -> String {
+        format!("{}:{}: {}", self.row + 1, self.column + 1, self.text)
+    }
+}
+
+// 検査や検索で見つけた位置の一覧。バッファごとに1つ持ち、次/前へ順に移れる
+#[derive(Default)]
+pub struct LocationList {
+    items: Vec<Location>,
+    // 最後に移った位置
+    current: Option<usize>,
+}
+
+impl LocationList {
+    pub fn new(items: Vec<Location>) -> Self {
+        LocationList { items, current: None }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn current(&self) -> Option<usize> {
+        self.current
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        self.items.iter().map(Location::line).collect()
+    }
+
+    // 次の位置。最後まで行ったら最初に戻る
+    pub fn next(&mut self) -> Option<&Location> {
+        let index = match self.current {
+            Some(i) if i + 1 < self.items.len() => i + 1,
+            _ => 0,
+        };
+        self.select(index)
+    }
+
+    // 前の位置。最初まで行ったら最後に戻る
+    pub fn prev(&mut self) -> Option<&Location> {
+        let index = match self.current {
+            Some(i) if i > 0 => i - 1,
+            _ => self.items.len().checked_sub(1)?,
+        };
+        self.select(index)
+    }
+
+    pub fn select(&mut self, index: usize) -> Option<&Location> {
+        let location = self.items.get(index)?;
+        self.current = Some(index);
+        Some(location)
+    }
+}
@@ -0,0 +1,124 @@
+use std::env;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::panic;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// ログの重要度。Off より下のものだけ書き出す
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "off" => Ok(Level::Off),
+            "error" => Ok(Level::Error),
+            "warn" => Ok(Level::Warn),
+            "info" => Ok(Level::Info),
+            "debug" => Ok(Level::Debug),
+            _ => Err(format!("unknown log level: {}", s)),
+        }
+    }
+}
+
+struct Logger {
+    level: Level,
+    file: File,
+}
+
+static LOGGER: OnceLock<Mutex<Logger>> = OnceLock::new();
+
+// ~/.local/state/textedit (XDG_STATE_HOME があればその下)
+pub fn state_dir() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".local/state"),
+    };
+    Some(base.join("textedit"))
+}
+
+// ログファイルを開く。端末は画面の描画に使っているので、ログは決して端末に出さない
+pub fn init(level: Level) {
+    if level == Level::Off {
+        return;
+    }
+    let file = state_dir().and_then(|dir| {
+        fs::create_dir_all(&dir).ok()?;
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join("log"))
+            .ok()
+    });
+    if let Some(file) = file {
+        let _ = LOGGER.set(Mutex::new(Logger { level, file }));
+    }
+
+    // パニックしたときもログに残す
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        log(Level::Error, format_args!("panic: {}", info));
+        default_hook(info);
+    }));
+}
+
+pub fn log(level: Level, args: fmt::Arguments) {
+    let logger = match LOGGER.get() {
+        Some(logger) => logger,
+        None => return,
+    };
+    // パニック中にロックが壊れていても書けるだけ書く
+    let mut logger = logger.lock().unwrap_or_else(|e| e.into_inner());
+    if level > logger.level {
+        return;
+    }
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    // 落ちても残るように1行ずつ書き出す
+    let _ = writeln!(
+        logger.file,
+        "{}.{:03} {:?} {}",
+        now.as_secs(),
+        now.subsec_millis(),
+        level,
+        args
+    );
+    let _ = logger.file.flush();
+}
+
+macro_rules! log_error {
+    ($($arg:tt)*) => {
+        $crate::logger::log($crate::logger::Level::Error, format_args!($($arg)*))
+    };
+}
+
+macro_rules! log_warn {
+    ($($arg:tt)*) => {
+        $crate::logger::log($crate::logger::Level::Warn, format_args!($($arg)*))
+    };
+}
+
+macro_rules! log_info {
+    ($($arg:tt)*) => {
+        $crate::logger::log($crate::logger::Level::Info, format_args!($($arg)*))
+    };
+}
+
+macro_rules! log_debug {
+    ($($arg:tt)*) => {
+        $crate::logger::log($crate::logger::Level::Debug, format_args!($($arg)*))
+    };
+}
@@ -1,268 +1,6362 @@
-use clap::{App, Arg};
-use std::cmp::{max, min};
+#[macro_use]
+mod logger;
+#[macro_use]
+mod i18n;
+mod binary;
+mod dialog;
+mod bookmarks;
+mod clipboard;
+mod config;
+mod export;
+mod expr;
+mod git;
+mod history;
+mod idle;
+mod input;
+mod journal;
+mod keymap;
+mod killring;
+mod layout;
+mod locations;
+mod minibuffer;
+mod options;
+mod picker;
+mod places;
+mod popup;
+mod progress;
+mod project;
+mod recent;
+mod search;
+mod session;
+mod stats;
+mod status;
+mod sticky;
+mod syntax;
+mod theme;
+mod todo;
+mod translate;
+mod trash;
+mod vim;
+mod welcome;
+
+use textedit::{address, encoding, line, special, Line};
+use std::cmp::{max, min, Ordering};
+use std::collections::HashMap;
 use std::ffi::OsStr;
 use std::fs;
-use std::io::{stdin, stdout, Write};
+use input::{Input, Waker, GIT_BRANCH_READY, PASTE_END, PASTE_START};
+use address::{Cursor, LineEnding, LineStarts};
+use journal::Journal;
+use keymap::{KeyAction, Keymap, Lookup};
+use killring::KillRing;
+use bookmarks::Bookmark;
+use clipboard::Clipboard;
+use dialog::{Answer, Dialog};
+use history::{Edit, History};
+use layout::LayoutCache;
+use locations::{Location, LocationList};
+use minibuffer::{Minibuffer, Outcome};
+use picker::Picker;
+use popup::Popup;
+use stats::Stats;
+use status::{Segment, StatusLayout};
+use syntax::{Highlighter, Token};
+use theme::Theme;
+use sticky::{Modifier, Sticky};
+use vim::{Motion, Vim};
+use welcome::Welcome;
+use progress::{Cancel, Progress};
+use std::io::{self, stdin, stdout, Read, Seek, Write};
 use std::path;
+use std::process;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use unicode_segmentation::UnicodeSegmentation;
 use termion::clear;
 use termion::cursor;
-use termion::event::{Event, Key};
-use termion::input::TermRead;
+use termion::event::{Event, Key, MouseButton, MouseEvent};
 use termion::raw::IntoRawMode;
 use termion::screen::AlternateScreen;
-use unicode_width::UnicodeWidthChar;
+use termion::style;
+use options::{AmbiguousWidth, Encoding, Options};
+use places::{Jump, Place};
+
+
+// 単語で動くときの単語の種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CharClass {
+    Space,
+    Word,
+    Punctuation,
+}
 
+// ある位置の文字を含む単語。同じ単語の中の位置なら等しい
+// 単語は Unicode の単語の区切り (UAX #29) で分ける。漢字やひらがなは1文字ずつ、カタカナは続けて1つになる
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-struct Cursor {
+struct Word {
+    class: CharClass,
     row: usize,
-    column: usize,
+    // 単語の最初の文字の添字と、最後の文字の次の添字
+    start: usize,
+    end: usize,
+}
+
+// 単語を分けるときに column の前後で見る文字数。単語が窓の端にかかれば広げて分け直す
+const WORD_WINDOW: usize = 256;
+
+// 行の column 文字目を含む単語の (始まりの添字, 終わりの次の添字, 種類)。行末は改行なので空白
+// 長い行でも、column の周りだけを分ける
+fn word_in_line(line: &Line, column: usize) -> (usize, usize, CharClass) {
+    if column >= line.len() {
+        return (line.len(), line.len(), CharClass::Space);
+    }
+    let mut radius = WORD_WINDOW;
+    loop {
+        let from = column.saturating_sub(radius);
+        let to = min(line.len(), column + radius + 1);
+        let text: String = line.slice(from..to).iter().collect();
+        let segments: Vec<&str> = text.split_word_bounds().collect();
+        let mut start = from;
+        for (k, segment) in segments.iter().enumerate() {
+            let end = start + segment.chars().count();
+            if column < end {
+                // 窓の端の単語は途中で切れているかもしれないので、前後に1つずつ単語があるときだけ信じる
+                if (k == 0 && from > 0) || (k + 1 == segments.len() && to < line.len()) {
+                    break;
+                }
+                let class = if segment.chars().all(char::is_whitespace) {
+                    CharClass::Space
+                } else if segment.chars().any(|c| c.is_alphanumeric() || c == '_') {
+                    CharClass::Word
+                } else {
+                    CharClass::Punctuation
+                };
+                return (start, end, class);
+            }
+            start = end;
+        }
+        radius *= 2;
+    }
 }
 
 struct EditerState {
-    buffer: Vec<Vec<char>>,
+    buffer: Vec<Line>,
+    cursor: Cursor,
+    row_offset: usize,
+    // 折り返さないときに画面の左端に来る表示上の列
+    col_offset: usize,
+    // 長い行を表示するときに画面の左端に来る文字
+    clip_start: usize,
+    path: Option<path::PathBuf>,
+    // 最下行に表示するメッセージ
+    message: String,
+    // 入力中のプロンプト
+    prompt: Option<Minibuffer>,
+    // プロンプトの種類ごとの入力の履歴 (古い順)
+    histories: HashMap<&'static str, Vec<String>>,
+    // 画面下部に表示する読み取り専用のペイン
+    pane: Vec<String>,
+    // ペインの中で選択されている行
+    pane_cursor: Option<usize>,
+    // 選んでいないときにペインの先頭に出す行
+    pane_offset: usize,
+    // 差分のペインを本文のスクロールに合わせて動かすか
+    scroll_bind: bool,
+    // 差分を取るときに空白や大文字小文字の違いを無視するか
+    diff_filter: git::DiffFilter,
+    // ペインに出している差分の比べ元と、差分に出すファイル名。比べ方を変えたら出し直す
+    diff_base: Option<(String, path::PathBuf)>,
+    // ステータス行に並べる項目
+    status_layout: StatusLayout,
+    // 画面の要素ごとの色
+    theme: Theme,
+    // キーの割り当てと、続きを待っている途中まで押したキー
+    keymap: Keymap,
+    pending_keys: Vec<Key>,
+    // vim のようにモードを切り替えて編集するときのモード。None なら使わない
+    vim: Option<Vim>,
+    // 押して離した修飾キー。次のキーにつける
+    sticky: Sticky,
+    // 開いているファイルのリポジトリの今のブランチ。入力のたびではなく、手が止まったときに調べ直す
+    git_branch: Option<git::Branch>,
+    // COMMIT_EDITMSG を編集しているか
+    commit_mode: bool,
+    // 保存したときにコミットを作るか (git status ペインから開いたとき)
+    pending_commit: bool,
+    // 編集できないバッファ (過去のリビジョンを表示しているときなど)
+    read_only: bool,
+    // --readonly や :ro、リビジョンやコマンドの出力の表示。読み直しても read_only を外さない
+    forced_read_only: bool,
+    // 開いているファイルのプロジェクトのルート (.git や Cargo.toml のあるディレクトリ)
+    project_root: Option<path::PathBuf>,
+    // ファイルの文字コード
+    encoding: Encoding,
+    // ファイルの改行の書き方
+    line_ending: LineEnding,
+    // 画面の幅で行を折り返すか (操作している窓の設定)
+    wrap: bool,
+    // 保存した後に読み直して内容を確かめるか
+    verify_save: bool,
+    // Tab キーでタブ文字の代わりに空白を入れるか
+    expand_tab: bool,
+    // 見えない文字などをエスケープして表示するか
+    escapes: bool,
+    // スクリーンリーダー向けの表示にするか
+    accessible: bool,
+    // 上下に続けて動いている間、戻りたい位置
+    goal: Option<Goal>,
+    // 上下の矢印で、折り返した表示上の行ごとに動くか
+    visual_lines: bool,
+    // ほかのファイルを開く前や git の操作の前に保存するか
+    autowrite: bool,
+    // 端末がフォーカスを失ったときに保存するか
+    focus_autosave: bool,
+    // 最後に読み込んだか保存したときのファイルの更新時刻
+    disk_mtime: Option<SystemTime>,
+    // 外部での変更を知らせたときのファイルの更新時刻。同じ変更を何度も知らせない
+    reported_mtime: Option<SystemTime>,
+    // 読み込んでから、または保存してから編集したか
+    modified: bool,
+    // 編集していないバッファを、ファイルが変わったら読み直すか
+    auto_reload: bool,
+    // どの表示で読み込んだか。読み直すときに同じ表示にする
+    view: View,
+    // 表示した行の配置のキャッシュ
+    layouts: LayoutCache,
+    // 行の先頭のバイト位置のキャッシュ
+    line_starts: LineStarts,
+    // ファイルの種類ごとの色づけと、行ごとのその結果
+    highlighter: Highlighter,
+    // 取り消しとやり直しの履歴
+    history: History,
+    // 画面の上に重ねて出している窓
+    popup: Option<Popup>,
+    // 最後に描いたときに色をつけた文字の、画面上の位置
+    hotspots: Vec<Hotspot>,
+    // マウスを乗せている色つきの文字の説明 (画面の行, 列, 窓)
+    tooltip: Option<(usize, usize, Popup)>,
+    // ファイルを指定せずに起動したときのスタート画面。編集を始めたら消す
+    welcome: Option<Welcome>,
+    // これより大きなファイルは開く前にどう開くかを聞く (バイト)。None なら聞かない
+    large_file_limit: Option<u64>,
+    // どう開くかを聞く前で、まだ開いていない大きなファイル
+    large_file: Option<path::PathBuf>,
+    // ファイルの先頭だけを読み取り専用で開いているなら、読んだ大きさ
+    head_only: Option<u64>,
+    // シェルのコマンドの出力を出しているバッファなら、そのコマンドと終了した状態
+    command_output: Option<(String, process::ExitStatus)>,
+    // 下部ペインを隠してテキストを広げているか
+    zoomed: bool,
+    // 下部ペインが使ってよい画面の割合 (%)
+    pane_percent: usize,
+    // 下部ペインを区切り線だけにたたんでいるか
+    pane_collapsed: bool,
+    // 最近閉じたファイル。最後が一番新しい
+    closed: Vec<ClosedFile>,
+    // 開いているバッファ。表示している current 番目は中身を上のフィールドに出していて、ここには空のものを置く
+    buffers: Vec<OpenFile>,
+    current: usize,
+    // コマンドラインで渡したファイルと、最後に移ったのがその何番目か
+    args: Vec<path::PathBuf>,
+    arg_index: usize,
+    // 画面を分けた窓。操作している window 番目は位置を上のフィールドに出している
+    windows: Vec<Window>,
+    window: usize,
+    // 窓を左右に並べるか。そうでなければ上下に並べる
+    split_vertical: bool,
+    // 検査などで見つけた、このバッファの中の位置の一覧
+    locations: LocationList,
+    // メモをつけて印をつけた行。行の順に並べる
+    bookmarks: Vec<Bookmark>,
+    // vim の m でつけた、a から z の名前の印。名前の順に並べる
+    marks: Vec<(char, Cursor)>,
+    // プロジェクトの中で遠くに移った位置の履歴 (古い順) と、戻っている途中ならその位置
+    jumps: Vec<Jump>,
+    jump_index: usize,
+    // jumps を読んだプロジェクトのルートと、その履歴を書くデータベース
+    jumps_root: Option<path::PathBuf>,
+    jump_store: Option<places::Store>,
+    // 選択の始まり。選択している範囲は anchor とカーソルの間
+    anchor: Option<Cursor>,
+    // 選択を始めるキーで始めたか。そうなら Shift なしで動いても選択を続ける
+    mark: bool,
+    // 正規表現で分けた、今の選択のほかの選択 (始まり, カーソル)。文字を打つと、すべての選択で同じように編集する
+    extra_selections: Vec<(Cursor, Cursor)>,
+    clipboard: Clipboard,
+    stats: Stats,
+    kill_ring: KillRing,
+    // 終わるときに kill_ring を状態ディレクトリに残すか
+    save_kill_ring: bool,
+    journal: Option<Journal>,
+    // 左に行番号を出すか (操作している窓の設定)
+    line_numbers: bool,
+    // 行番号をカーソルのある行からの距離で出すか (カーソルのある行だけは本当の行番号)。操作している窓の設定
+    relative_numbers: bool,
+    // 検索で正規表現を使うか
+    search_regex: bool,
+    // F3 と Shift+F3 で探し直すもの
+    last_search: Option<search::Pattern>,
+    // 探して見つけて選んだ範囲。選択がこのままなら見つかったところの色で出す
+    search_hit: Option<(Cursor, Cursor)>,
+    // 時間のかかる処理を Esc で中断する合図
+    cancel: Cancel,
+    // 裏のスレッドの仕事が終わったらイベントのループを起こす
+    waker: Waker,
+    // 裏のスレッドで調べている git のブランチ。調べているディレクトリと、結果を受け取る口
+    git_branch_job: Option<(path::PathBuf, Receiver<Option<git::Branch>>)>,
+    // 手が止まっている間にする仕事
+    idle: idle::Scheduler,
+}
+
+// コミットメッセージの1行目と本文の推奨文字数
+const COMMIT_SUBJECT_LIMIT: usize = 50;
+const COMMIT_BODY_LIMIT: usize = 72;
+
+// 手が止まっている間の仕事を一度に進める行数。入力への反応が遅れないように小さくする
+const IDLE_STEP_LINES: usize = 200;
+// ファイルが外部で変更されていないか調べる間隔
+const DISK_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+// git のブランチを調べ直す間隔
+const GIT_BRANCH_INTERVAL: Duration = Duration::from_secs(5);
+// 画面の割り振りの名前と、下部ペインが使ってよい画面の割合 (%)。Alt+1 から順に割り当てる
+const LAYOUT_PRESETS: &[(&str, usize)] = &[("split", 50), ("main", 25), ("pane", 75)];
+// 下部ペインの大きさを変えるときの幅と範囲 (%)
+const PANE_PERCENT_STEP: usize = 5;
+const PANE_PERCENT_MIN: usize = 10;
+const PANE_PERCENT_MAX: usize = 90;
+// : で打てるコマンド。補完に使う
+const COMMANDS: &[&str] = &[
+    "q", "q!", "cq", "w", "wq", "x", "e", "hex", "text", "ro", "escapes", "nextspecial", "stripspecial",
+    "specials", "diff", "revert", "reopen", "zoom", "layout", "export", "bookmarks",
+    "todo", "stats", "replace",
+    "replace-regex", "rename", "delete", "number", "new", "splitsel",
+    "relative", "scrollbind", "undolist", "saveas", "open", "bnext", "bprev", "bclose", "next", "prev",
+    "split", "vsplit", "close", "wnext", "wprev", "wrap",
+    "diffwhite", "diffcase", "r", "vim", "cursorinfo", "goto",
+    "mark", "marks", "trash", "kills",
+];
+// 入力が続いている間に描画する間隔 (毎秒30回)
+const FRAME_INTERVAL: Duration = Duration::from_millis(33);
+// ファイルを読むときに一度に読む大きさ。この単位で進み具合を出し、中断できるようにする
+const READ_CHUNK: usize = 1 << 20;
+// シェルのコマンドが終わったか、中断されたかを見る間隔
+const COMMAND_POLL: Duration = Duration::from_millis(20);
+const MEGABYTE: u64 = 1 << 20;
+// 開き直せるように覚えておく、閉じたファイルの数
+const CLOSED_FILES_LIMIT: usize = 20;
+
+// 上下に動くときに戻りたい位置。短い行を通り過ぎても元の列に戻れるようにする
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Goal {
+    // 論理行での文字の位置
+    Column(usize),
+    // 折り返した表示行での列
+    Visual(usize),
+}
+
+// 閉じた (ほかのファイルに移る前に開いていた) ファイル
+struct ClosedFile {
+    path: path::PathBuf,
     cursor: Cursor,
     row_offset: usize,
+}
+
+// 開いているバッファのうち、今は表示していないものの中身と位置
+// 切り替えるときに EditerState の同じ名前のフィールドと入れ替える
+struct OpenFile {
+    buffer: Vec<Line>,
+    cursor: Cursor,
+    row_offset: usize,
+    col_offset: usize,
+    clip_start: usize,
     path: Option<path::PathBuf>,
+    project_root: Option<path::PathBuf>,
+    encoding: Encoding,
+    line_ending: LineEnding,
+    read_only: bool,
+    forced_read_only: bool,
+    modified: bool,
+    disk_mtime: Option<SystemTime>,
+    reported_mtime: Option<SystemTime>,
+    view: View,
+    layouts: LayoutCache,
+    line_starts: LineStarts,
+    highlighter: Highlighter,
+    history: History,
+    head_only: Option<u64>,
+    command_output: Option<(String, process::ExitStatus)>,
+    locations: LocationList,
+    bookmarks: Vec<Bookmark>,
+    marks: Vec<(char, Cursor)>,
+    anchor: Option<Cursor>,
+    mark: bool,
+    commit_mode: bool,
+    pending_commit: bool,
+    git_branch: Option<git::Branch>,
+}
+
+impl Default for OpenFile {
+    fn default() -> Self {
+        OpenFile {
+            buffer: vec![Line::new()],
+            cursor: Cursor { row: 0, column: 0 },
+            row_offset: 0,
+            col_offset: 0,
+            clip_start: 0,
+            path: None,
+            project_root: None,
+            encoding: Encoding::Utf8,
+            line_ending: LineEnding::Lf,
+            read_only: false,
+            forced_read_only: false,
+            modified: false,
+            disk_mtime: None,
+            reported_mtime: None,
+            view: View::Auto,
+            layouts: LayoutCache::default(),
+            line_starts: LineStarts::default(),
+            highlighter: Highlighter::default(),
+            history: History::default(),
+            head_only: None,
+            command_output: None,
+            locations: LocationList::default(),
+            bookmarks: Vec::new(),
+            marks: Vec::new(),
+            anchor: None,
+            mark: false,
+            commit_mode: false,
+            pending_commit: false,
+            git_branch: None,
+        }
+    }
+}
+
+// 画面を分けた窓の1つが表示しているバッファと位置、窓ごとの表示の設定
+// 操作している窓のものは EditerState の cursor などに出していて、windows のその場所は使わない
+#[derive(Debug, Clone, Copy, Default)]
+struct Window {
+    buffer: usize,
+    cursor: Cursor,
+    row_offset: usize,
+    col_offset: usize,
+    clip_start: usize,
+    wrap: bool,
+    line_numbers: bool,
+    relative_numbers: bool,
+}
+
+// 画面の中の長方形 (上の行、左の列、行数、桁数)
+#[derive(Debug, Clone, Copy)]
+struct Region {
+    top: usize,
+    left: usize,
+    rows: usize,
+    cols: usize,
+}
+
+// 窓を描いた結果
+struct WindowDrawn {
+    cursor: Option<(usize, usize)>,
+    hotspots: Vec<Hotspot>,
+    // 最後に描いた画面の行
+    at: usize,
+}
+
+// 画面に描いた、色つきの文字の位置
+struct Hotspot {
+    row: usize,
+    col: usize,
+    width: usize,
+    pos: Cursor,
+    // 文字ではなく、印をつけた行
+    line: bool,
+}
+
+// 文字の表示のされ方
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Highlight {
+    Normal,
+    Comment,
+    OverLimit,
+    // 見えない文字のエスケープ表示
+    Special,
+    Keyword,
+    String,
+    Number,
+}
+
+// ファイルをどのように読み込むか
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum View {
+    // 中身を見て決める
+    Auto,
+    Text,
+    Hex,
+}
+
+// コマンド実行後にエディタをどうするか
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Action {
+    Continue,
+    Quit(i32),
+    // コマンドが失敗した。バッチモードではそこで止める
+    Failed,
 }
 
 impl Default for EditerState {
     fn default() -> Self {
         Self {
-            buffer: vec![Vec::new()],
+            buffer: vec![Line::new()],
             cursor: Cursor { row: 0, column: 0 },
             row_offset: 0,
+            col_offset: 0,
+            clip_start: 0,
             path: None,
+            message: String::new(),
+            prompt: None,
+            histories: HashMap::new(),
+            pane: Vec::new(),
+            pane_cursor: None,
+            pane_offset: 0,
+            scroll_bind: false,
+            diff_filter: git::DiffFilter::default(),
+            diff_base: None,
+            status_layout: StatusLayout::default(),
+            theme: Theme::default(),
+            keymap: Keymap::default(),
+            pending_keys: Vec::new(),
+            vim: None,
+            sticky: Sticky::default(),
+            git_branch: None,
+            commit_mode: false,
+            pending_commit: false,
+            read_only: false,
+            forced_read_only: false,
+            project_root: None,
+            encoding: Encoding::Utf8,
+            line_ending: LineEnding::Lf,
+            wrap: true,
+            verify_save: false,
+            expand_tab: false,
+            escapes: false,
+            accessible: false,
+            goal: None,
+            visual_lines: false,
+            autowrite: false,
+            focus_autosave: false,
+            disk_mtime: None,
+            reported_mtime: None,
+            modified: false,
+            auto_reload: false,
+            view: View::Auto,
+            layouts: LayoutCache::default(),
+            line_starts: LineStarts::default(),
+            highlighter: Highlighter::default(),
+            history: History::default(),
+            popup: None,
+            hotspots: Vec::new(),
+            tooltip: None,
+            welcome: None,
+            large_file_limit: None,
+            large_file: None,
+            head_only: None,
+            command_output: None,
+            zoomed: false,
+            pane_percent: LAYOUT_PRESETS[0].1,
+            pane_collapsed: false,
+            closed: Vec::new(),
+            buffers: vec![OpenFile::default()],
+            current: 0,
+            args: Vec::new(),
+            arg_index: 0,
+            windows: vec![Window::default()],
+            window: 0,
+            split_vertical: false,
+            locations: LocationList::default(),
+            bookmarks: Vec::new(),
+            marks: Vec::new(),
+            jumps: Vec::new(),
+            jump_index: 0,
+            jumps_root: None,
+            jump_store: None,
+            anchor: None,
+            mark: false,
+            extra_selections: Vec::new(),
+            clipboard: Clipboard::default(),
+            stats: Stats::default(),
+            kill_ring: KillRing::default(),
+            save_kill_ring: false,
+            journal: None,
+            line_numbers: false,
+            relative_numbers: false,
+            search_regex: false,
+            last_search: None,
+            search_hit: None,
+            cancel: Cancel::default(),
+            waker: Waker::default(),
+            git_branch_job: None,
+            idle: idle::Scheduler::default(),
         }
     }
 }
 
 impl EditerState {
-    fn open(&mut self, path: &path::Path) {
-        self.buffer = fs::read_to_string(path)
-            .ok()
-            .map(|s| {
-                let buffer: Vec<Vec<char>> = s
-                    .lines()
-                    .map(|line| line.trim_end().chars().collect())
-                    .collect();
-                if buffer.is_empty() {
-                    vec![Vec::new()]
-                } else {
-                    buffer
+    fn new(options: &Options) -> Self {
+        let mut state = Self {
+            encoding: options.encoding,
+            wrap: options.wrap,
+            verify_save: options.verify_save,
+            expand_tab: options.expand_tab,
+            vim: options.vim.then(Vim::default),
+            accessible: options.accessible,
+            visual_lines: options.visual_lines,
+            autowrite: options.autowrite,
+            focus_autosave: options.focus_autosave,
+            auto_reload: options.auto_reload,
+            line_numbers: options.line_numbers,
+            relative_numbers: options.relative_numbers,
+            scroll_bind: options.scroll_bind,
+            diff_filter: git::DiffFilter {
+                ignore_whitespace: options.diff_ignore_whitespace,
+                ignore_case: options.diff_ignore_case,
+            },
+            status_layout: options.status.clone(),
+            large_file_limit: Some(options.large_file.saturating_mul(MEGABYTE)),
+            save_kill_ring: options.save_kill_ring,
+            ..Self::default()
+        };
+        if state.save_kill_ring {
+            state.kill_ring = KillRing::load();
+        }
+        let session = session::load();
+        state.pane_percent = session.pane_percent.clamp(PANE_PERCENT_MIN, PANE_PERCENT_MAX);
+        state.pane_collapsed = session.pane_collapsed;
+        if let Some(path) = options.journal.as_ref() {
+            match Journal::open(path) {
+                Ok(mut journal) => {
+                    // ファイルを開いたときは open で書く
+                    if options.files.is_empty() {
+                        journal.opened(None, state.buffer.len());
+                    }
+                    state.journal = Some(journal);
+                },
+                Err(e) => {
+                    log_error!("cannot open journal {}: {}", path.display(), e);
+                    state.message = tr!("journal-failed", path.display(), e);
+                },
+            }
+        }
+
+        state.args = options.files.clone();
+        match options.files.split_first() {
+            Some((file, rest)) => {
+                state.open(file);
+                if options.readonly {
+                    state.force_read_only();
                 }
-            })
-            .unwrap_or_else(|| vec![Vec::new()]);
+                // 残りのファイルを裏のバッファに開いて、最初のファイルに戻る
+                for file in rest {
+                    state.open_buffer(file);
+                    if options.readonly {
+                        state.force_read_only();
+                    }
+                }
+                state.switch_buffer(0);
+            },
+            None => state.welcome = Some(Welcome::new(recent::load())),
+        }
+        if options.readonly {
+            state.force_read_only();
+        }
+        if state.auto_reload {
+            state.idle.every(idle::Task::CheckDisk, DISK_CHECK_INTERVAL);
+        }
+        state.idle.every(idle::Task::GitBranch, GIT_BRANCH_INTERVAL);
+        // 前に開いていたときの位置より、コマンドラインで指定した位置を優先する
+        if let Some(line) = options.line {
+            state.goto_line(max(line, 1), None);
+        }
+        // 行も指定していれば、その行から探す
+        if let Some(text) = options.search.as_ref() {
+            let row = if options.line.is_some() { state.cursor.row } else { 0 };
+            state.search_start(text, Cursor { row, column: 0 });
+        }
 
-        self.path = Some(path.into());
-        self.cursor = Cursor { row: 0, column: 0 };
-        self.row_offset = 0;
+        if let Some(name) = options.theme.as_ref() {
+            match Theme::by_name(name) {
+                Some(theme) => state.theme = theme,
+                None => state.message = tr!("unknown-theme", name, Theme::names().join(", ")),
+            }
+        }
+        if let Some(name) = options.keymap.as_ref() {
+            if !state.keymap.apply_preset(name) {
+                state.message = tr!("unknown-keymap", name, keymap::preset_names().join(", "));
+            }
+        }
+        for (keys, action) in &options.keys {
+            state.keymap.bind(keys.clone(), action.clone());
+        }
+        if let Some(config) = options.config.as_ref() {
+            if !config.is_file() {
+                state.message = tr!("config-not-found", config.display());
+            }
+        }
+        for error in &options.config_errors {
+            log_warn!("config: {}", error);
+        }
+        if let Some(error) = options.config_errors.first() {
+            state.message = tr!("config-error", error);
+        }
+        state
     }
 
-    fn terminal_size() -> (usize, usize) {
-        let (rows, cols) = termion::terminal_size().unwrap();
-        (rows as usize, cols as usize)
+    // 読み込みを中断されたら false
+    // 大きなファイルはまだ開かずに、どう開くかを次に入力を待つ前に聞く (confirm_large)
+    fn open(&mut self, path: &path::Path) -> bool {
+        let size = fs::metadata(path).map(|metadata| metadata.len()).unwrap_or(0);
+        if self.large_file_limit.is_some_and(|limit| size > limit) {
+            self.large_file = Some(path.to_path_buf());
+            return false;
+        }
+        self.open_head(path, None)
     }
 
-    fn draw<T: Write>(&self, out: &mut T) {
-        let (rows, cols) = Self::terminal_size();
+    // head があれば、ファイルの先頭の head バイトだけを読み取り専用で開く
+    fn open_head(&mut self, path: &path::Path, head: Option<u64>) -> bool {
+        // ほかのファイルに移るときは、あとで開き直せるように覚えておく
+        let closed = self.path.clone().filter(|current| current != path).map(|current| ClosedFile {
+            path: current,
+            cursor: self.cursor,
+            row_offset: self.row_offset,
+        });
+        if closed.is_some() {
+            self.remember_place();
+            self.push_jump();
+        }
+        // ほかのファイルを開けば、前のファイルやコマンドの出力に決めた読み取り専用は外す
+        let saved_forced = self.forced_read_only;
+        self.forced_read_only &= self.path.as_deref() == Some(path);
+        let saved_head = std::mem::replace(&mut self.head_only, head);
+        if !self.load(path, View::Auto) {
+            self.head_only = saved_head;
+            self.forced_read_only = saved_forced;
+            return false;
+        }
+        if let Err(e) = recent::record(path) {
+            log_debug!("cannot record {} as recent: {}", path.display(), e);
+        }
+        self.welcome = None;
+        if let Some(closed) = closed {
+            self.closed.retain(|c| c.path != closed.path);
+            self.closed.push(closed);
+            if self.closed.len() > CLOSED_FILES_LIMIT {
+                self.closed.remove(0);
+            }
+        }
+        true
+    }
 
-        write!(out, "{}", clear::All);
-        write!(out, "{}", cursor::Goto(1, 1));
+    // 読み込みを中断されたら、今のバッファをそのままにして false を返す
+    fn load(&mut self, path: &path::Path, view: View) -> bool {
+        let bytes = match self.read_file(path) {
+            Some(bytes) => bytes,
+            None => {
+                log_info!("loading {} cancelled", path.display());
+                self.message = tr!("load-cancelled", path.display());
+                return false;
+            },
+        };
+        self.view = view;
 
-        // 画面上の行、列
-        let mut row = 0;
-        let mut col = 0;
+        // バイナリや壊れたファイルを黙って編集させないように、読み取り専用にして警告する
+        let mut warning = None;
+        let binary = binary::is_binary(&bytes);
+        let (buffer, read_only) = if view == View::Hex || (view == View::Auto && binary) {
+            if view == View::Auto {
+                warning = Some(tr!("binary-file"));
+            }
+            (binary::hex_dump(&bytes), true)
+        } else if self.head_only.is_some() {
+            // 途中で切った文字は壊れた文字として読む
+            (to_buffer(&String::from_utf8_lossy(&bytes)), true)
+        } else {
+            match self.encoding.decode(&bytes) {
+                Some(s) => {
+                    if binary::long_line(&bytes).is_some() {
+                        warning = Some(tr!("long-lines"));
+                    }
+                    (to_buffer(&s), binary)
+                },
+                None => {
+                    warning = Some(tr!("invalid-encoding"));
+                    (to_buffer(&String::from_utf8_lossy(&bytes)), true)
+                },
+            }
+        };
+        self.set_buffer(buffer);
+        self.line_ending = LineEnding::detect(&bytes);
+        self.disk_mtime = mtime(path);
+        log_info!("opened {} ({} lines)", path.display(), self.buffer.len());
 
-        let mut display_cursor: Option<(usize, usize)> = None;
+        self.path = Some(path.into());
+        self.command_output = None;
+        if let Some(journal) = self.journal.as_mut() {
+            journal.opened(Some(path), self.buffer.len());
+        }
+        self.cursor = Cursor { row: 0, column: 0 };
+        self.row_offset = 0;
+        self.col_offset = 0;
+        self.clip_start = 0;
+        self.read_only = read_only || self.head_only.is_some() || self.forced_read_only;
+        if let Some(head) = self.head_only {
+            warning = Some(tr!("head-only", head / MEGABYTE));
+        }
+        self.project_root = project::find_root(path);
+        // 16進ダンプやバイナリには色をつけない
+        self.highlighter = Highlighter::new(syntax::for_path(path).filter(|_| !binary));
+        self.idle.schedule(idle::Task::GitBranch);
+        self.message = tr!("file-info", self.display_path(), self.buffer.len());
+        if let Some(warning) = warning {
+            log_warn!("{}: {}", path.display(), warning);
+            self.message = format!("{} [{}]", self.message, warning);
+        }
 
-        'outer: for i in self.row_offset..self.buffer.len() {
-            for j in 0..=self.buffer[i].len() {
-                if self.cursor == (Cursor { row: i, column: j }) {
-                    // 画面上のカーソルの位置がわかった
-                    display_cursor = Some((row, col));
-                }
+        self.locations = LocationList::default();
+        let root = self.bookmark_root();
+        self.bookmarks = match root.as_ref() {
+            Some(root) => bookmarks::load(root, path),
+            None => Vec::new(),
+        };
+        // 前に開いていたときのカーソルの位置に戻る
+        let place = root.as_ref().map(|root| places::load(root, path)).unwrap_or_default();
+        self.marks = place.marks;
+        if let Some(cursor) = place.cursor.filter(|_| view != View::Hex) {
+            self.restore_cursor(cursor, cursor.row.saturating_sub(self.text_rows() / 2));
+        }
+        if root != self.jumps_root {
+            self.jump_store = root.as_deref().and_then(|root| match places::Store::open(root) {
+                Ok(store) => Some(store),
+                Err(e) => {
+                    log_warn!("cannot open the jump list of {}: {}", root.display(), e);
+                    None
+                },
+            });
+            self.jumps = self.jump_store.as_ref().map(places::Store::jumps).unwrap_or_default();
+            self.jump_index = self.jumps.len();
+            self.jumps_root = root;
+        }
+        self.commit_mode = path.file_name() == Some(OsStr::new("COMMIT_EDITMSG"));
+        self.pending_commit = false;
+        self.diff_base = None;
+        self.pane = if self.commit_mode {
+            git::open_repository(&parent_dir(path))
+                .and_then(|repo| git::staged_diff(&repo))
+                .unwrap_or_default()
+        } else {
+            Vec::new()
+        };
+        true
+    }
 
-                if let Some(c) = self.buffer[i].get(j) {
-                    let width = c.width().unwrap_or(0);
-                    if col + width >= cols {
-                        row += 1;
-                        col = 0;
-                        if row >= rows {
-                            break 'outer;
-                        } else {
-                            write!(out, "\r\n");
-                        }
-                    }
-                    write!(out, "{}", c);
-                    col += width;
+    // 大きなファイルは少しずつ読んで進み具合を出す。Esc で中断されたら None
+    fn read_file(&self, path: &path::Path) -> Option<Vec<u8>> {
+        let read = || -> io::Result<Option<Vec<u8>>> {
+            let file = fs::File::open(path)?;
+            let total = file.metadata()?.len();
+            let limit = self.head_only.unwrap_or(total);
+            let (mut file, total) = (file.take(limit), total.min(limit));
+            let mut bytes = Vec::with_capacity(total as usize);
+            let mut progress = Progress::start(&tr!("loading", path.display()), &self.cancel);
+            let mut chunk = vec![0; READ_CHUNK];
+            loop {
+                match file.read(&mut chunk) {
+                    Ok(0) => break,
+                    Ok(n) => bytes.extend_from_slice(&chunk[..n]),
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(e),
+                }
+                if !progress.update(bytes.len() as u64, total) {
+                    return Ok(None);
                 }
             }
-            row += 1;
-            col = 0;
-            if row >= rows {
-                break;
-            } else {
-                // 最後の行の最後では改行すると1行ずれてしまうのでこのようなコードになっている
-                write!(out, "\r\n");
+            Ok(Some(bytes))
+        };
+        read().unwrap_or_else(|e| {
+            if e.kind() != io::ErrorKind::NotFound {
+                log_warn!("cannot read {}: {}", path.display(), e);
             }
-        }
+            Some(Vec::new())
+        })
+    }
 
-        if let Some((r, c)) = display_cursor {
-            write!(out, "{}", cursor::Goto(c as u16 + 1, r as u16 + 1));
+    // プロジェクトのルートからの相対パスで表したファイル名
+    fn display_path(&self) -> String {
+        match (self.path.as_ref(), self.command_output.as_ref()) {
+            (Some(path), _) => project::display_path(self.project_root.as_deref(), path),
+            (None, Some((command, _))) => format!("!{}", command),
+            (None, None) => tr!("no-name"),
         }
+    }
 
-        out.flush().unwrap();
+    // 端末がないとき (バッチモードなど) は 80x24 として扱う
+    fn terminal_size() -> (usize, usize) {
+        let (cols, rows) = termion::terminal_size().unwrap_or((80, 24));
+        (rows as usize, cols as usize)
     }
 
-    fn scroll(&mut self) {
+    // 画面のうちメッセージ行 (と読み上げ用の行) を除いた行数
+    fn screen_rows(&self) -> usize {
         let (rows, _) = Self::terminal_size();
-        self.row_offset = min(self.row_offset, self.cursor.row);
-        if self.cursor.row + 1 >= rows {
-            self.row_offset = max(self.row_offset, self.cursor.row + 1 - rows);
-        }
+        // 下からメッセージ行、ステータス行。アクセシビリティモードではその間に読み上げ用の行を入れる
+        let reserved = if self.accessible { 3 } else { 2 };
+        max(rows, reserved + 1) - reserved
     }
 
-    fn cursor_up(&mut self) {
-        if self.cursor.row > 0 {
-            self.cursor.row -= 1;
-            self.cursor.column = min(self.buffer[self.cursor.row].len(), self.cursor.column);
+    // 下部ペインが使う行数 (区切り線を含む)
+    fn pane_rows(&self) -> usize {
+        // 拡大中もペインで選んでいる間は出す
+        if self.pane.is_empty() || (self.zoomed && self.pane_cursor.is_none()) {
+            0
+        } else if self.pane_collapsed && self.pane_cursor.is_none() {
+            // たたんでいるときは区切り線だけ
+            1
+        } else {
+            min(self.pane.len() + 1, max(2, self.screen_rows() * self.pane_percent / 100))
         }
-        self.scroll();
     }
 
-    fn cursor_dwon(&mut self) {
-        if self.cursor.row + 1 < self.buffer.len() {
-            self.cursor.row += 1;
-            self.cursor.column = min(self.cursor.column, self.buffer[self.cursor.row].len());
+    // テキストを画面いっぱいに広げる。もう一度で元に戻す
+    fn toggle_zoom(&mut self) {
+        self.zoomed = !self.zoomed;
+        if self.zoomed && !self.pane.is_empty() {
+            self.message = tr!("zoomed");
         }
         self.scroll();
     }
 
-    fn cursor_left(&mut self) {
-        if self.cursor.column > 0 {
-            self.cursor.column -= 1;
-        }
+    // 下部ペインを広げる (grow) か狭める
+    fn resize_pane(&mut self, grow: bool) {
+        self.pane_percent = if grow {
+            min(self.pane_percent + PANE_PERCENT_STEP, PANE_PERCENT_MAX)
+        } else {
+            max(self.pane_percent.saturating_sub(PANE_PERCENT_STEP), PANE_PERCENT_MIN)
+        };
+        self.pane_collapsed = false;
+        self.message = tr!("pane-size", self.pane_percent);
         self.scroll();
+        self.save_session();
     }
 
-    fn cursor_right(&mut self) {
-        self.cursor.column = min(self.cursor.column + 1, self.buffer[self.cursor.row].len());
+    // 下部ペインを区切り線だけにたたむ。もう一度で開く
+    fn toggle_collapse(&mut self) {
+        self.pane_collapsed = !self.pane_collapsed;
         self.scroll();
+        self.save_session();
     }
 
-    fn insert(&mut self, c: char) {
-        if c == '\n' {
-            let rest: Vec<char> = self.buffer[self.cursor.row]
-                .drain(self.cursor.column..)
-                .collect();
-            self.buffer.insert(self.cursor.row + 1, rest);
-            self.cursor.row += 1;
-            self.cursor.column = 0;
-            self.scroll();
-        } else if !c.is_control() {
-            self.buffer[self.cursor.row].insert(self.cursor.column, c);
-            self.cursor_right();
+    fn save_session(&self) {
+        let session = session::Session {
+            pane_percent: self.pane_percent,
+            pane_collapsed: self.pane_collapsed,
+        };
+        if let Err(e) = session::save(&session) {
+            log_warn!("cannot save session: {}", e);
         }
     }
 
-    fn back_space(&mut self) {
-        if self.cursor == (Cursor { row: 0, column: 0 }) {
-            return;
+    // 名前のついた画面の割り振りにする
+    fn apply_layout(&mut self, name: &str) {
+        match LAYOUT_PRESETS.iter().find(|&&(preset, _)| preset == name) {
+            Some(&(_, percent)) => {
+                self.pane_percent = percent;
+                self.zoomed = false;
+                self.pane_collapsed = false;
+                self.scroll();
+                self.save_session();
+            },
+            None => self.message = tr!("unknown-layout", name),
         }
+    }
 
-        if self.cursor.column == 0 {
-            let line = self.buffer.remove(self.cursor.row);
-            self.cursor.row -= 1;
-            self.cursor.column = self.buffer[self.cursor.row].len();
-            self.buffer[self.cursor.row].extend(line.iter());
-        } else {
-            self.cursor_left();
-            self.buffer[self.cursor.row].remove(self.cursor.column);
+    // 窓を並べるのに使える行数
+    fn text_area_rows(&self) -> usize {
+        self.screen_rows() - self.pane_rows()
+    }
+
+    // 窓ごとの画面上の領域。窓の間の区切りは含まない
+    fn window_regions(&self) -> Vec<Region> {
+        let (_, cols) = Self::terminal_size();
+        let rows = self.text_area_rows();
+        let count = self.windows.len();
+        // 区切りの分を除いて等分し、余りは最後の窓に足す
+        let (total, step) = if self.split_vertical { (cols, cols / count) } else { (rows, rows / count) };
+        let size = max(step.saturating_sub(1), 1);
+        (0..count)
+            .map(|k| {
+                let start = k * step;
+                let size = if k + 1 == count { max(total.saturating_sub(start), 1) } else { size };
+                if self.split_vertical {
+                    Region { top: 0, left: start, rows, cols: size }
+                } else {
+                    Region { top: start, left: 0, rows: size, cols }
+                }
+            })
+            .collect()
+    }
+
+    // 操作している窓でテキストの表示に使える行数
+    fn text_rows(&self) -> usize {
+        self.window_regions()[self.window].rows
+    }
+
+    // 行番号の欄の幅 (後ろの空白を含む)。出していなければ 0
+    fn gutter_width(&self) -> usize {
+        if !self.line_numbers && !self.relative_numbers {
+            return 0;
         }
+        max(self.buffer.len().to_string().len(), 3) + 1
     }
 
-    fn delete(&mut self) {
-        if self.cursor.row == self.buffer.len() - 1
-            && self.cursor.column == self.buffer[self.cursor.row].len()
-        {
-            return;
+    // 操作している窓でテキストを表示する桁数。行番号の欄の分だけ狭い
+    fn text_cols(&self) -> usize {
+        let cols = self.window_regions()[self.window].cols;
+        max(cols.saturating_sub(self.gutter_width()), 1)
+    }
+
+    // 行番号を相対にするか切り替える。相対にするときは行番号の欄も出す
+    fn toggle_relative_numbers(&mut self) {
+        self.relative_numbers = !self.relative_numbers;
+        self.scroll();
+    }
+
+    // 行番号の欄を書く。row が None なら折り返した続きの行なので空ける
+    fn write_gutter<T: Write>(&self, out: &mut T, row: Option<usize>) -> io::Result<()> {
+        let width = self.gutter_width();
+        if width == 0 {
+            return Ok(());
+        }
+        match row {
+            // カーソルのある行は目立たせる
+            Some(row) if row == self.cursor.row => {
+                let number = format!("{:>w$}", row + 1, w = width - 1);
+                write!(out, "{} ", self.theme.current_line_number.paint(number))
+            },
+            Some(row) => {
+                let number = if self.relative_numbers { row.abs_diff(self.cursor.row) } else { row + 1 };
+                write!(out, "{} ", self.theme.line_number.paint(format!("{:>w$}", number, w = width - 1)))
+            },
+            None => write!(out, "{}", " ".repeat(width)),
         }
+    }
 
-        if self.cursor.column == self.buffer[self.cursor.row].len() {
+    // 色づけの結果は draw_window などで先に highlighter.prepare しておく
+    fn highlight(&self, row: usize, column: usize) -> Highlight {
+        if !self.commit_mode {
+            return match self.highlighter.token(row, column) {
+                Token::Plain => Highlight::Normal,
+                Token::Keyword => Highlight::Keyword,
+                Token::String => Highlight::String,
+                Token::Number => Highlight::Number,
+                Token::Comment => Highlight::Comment,
+            };
+        }
+        if self.buffer[row].first() == Some(&'#') {
+            return Highlight::Comment;
+        }
+        // 1行目は件名、2行目は空行、それ以降は本文
+        let limit = match row {
+            0 => COMMIT_SUBJECT_LIMIT,
+            1 => 0,
+            _ => COMMIT_BODY_LIMIT,
+        };
+        if column >= limit {
+            Highlight::OverLimit
+        } else {
+            Highlight::Normal
+        }
+    }
 
-            let line = self.buffer.remove(self.cursor.row + 1);
-            self.buffer[self.cursor.row].extend(line.iter());
+    // エスケープ表示も含めた、文字の見た目
+    fn char_highlight(&self, row: usize, column: usize, c: char) -> Highlight {
+        if self.escapes && special::is_special(c) {
+            Highlight::Special
         } else {
-            self.buffer[self.cursor.row].remove(self.cursor.column);
+            self.highlight(row, column)
+        }
+    }
+
+    // row 行目 column 文字目の c を色をつけて書く
+    fn write_char<T: Write>(&self, out: &mut T, row: usize, column: usize, c: char) -> io::Result<()> {
+        let selected = self
+            .selection()
+            .is_some_and(|(start, end)| (start..end).contains(&Cursor { row, column }))
+            || self.in_extra_selection(Cursor { row, column });
+        if !selected {
+            return self.write_highlighted(out, row, column, c);
+        }
+        // 探して見つかったところは、ほかの選択と見分けられるようにする
+        let style = if self.selection() == self.search_hit { self.theme.search } else { self.theme.selection };
+        write!(out, "{}", style.start())?;
+        self.write_highlighted(out, row, column, c)?;
+        write!(out, "{}", style.end())
+    }
+
+    fn write_highlighted<T: Write>(&self, out: &mut T, row: usize, column: usize, c: char) -> io::Result<()> {
+        let highlight = self.char_highlight(row, column, c);
+        let mut style = self.theme.highlight(highlight);
+        match highlight {
+            Highlight::Special => write!(out, "{}", style.paint(special::escape(c))),
+            // タブは幅の分だけ空白で出す
+            _ if c == '\t' => write!(out, "{}", style.paint(" ".repeat(special::tab_width()))),
+            // 色だけに頼らないように、アクセシビリティモードでは下線も引く
+            Highlight::OverLimit if self.accessible => {
+                style.underline = true;
+                write!(out, "{}", style.paint(c))
+            },
+            _ => write!(out, "{}", style.paint(c)),
         }
     }
 
-    fn save(&self) {
-        if let Some(path) = self.path.as_ref() {
-            if let Ok(mut file) = fs::File::create(path) {
-                for line in &self.buffer {
-                    for &c in line {
-                        write!(file, "{}", c);
+    // 窓1つぶんのテキストを region の中に描く
+    // カーソルの画面上の位置、色をつけた文字の位置、最後に描いた画面の行を返す
+    fn draw_window<T: Write>(&mut self, out: &mut T, region: Region) -> io::Result<WindowDrawn> {
+        let Region { top, left, rows, cols: width } = region;
+        // テキストは行番号の欄の右に出す
+        let gutter = self.gutter_width();
+        let cols = max(width.saturating_sub(gutter), 1);
+
+        // 窓の中の行
+        let mut row = 0;
+        self.start_window_row(out, region, row)?;
+
+        let mut display_cursor: Option<(usize, usize)> = None;
+        let mut hotspots = Vec::new();
+        // 窓に入る行までの色づけを済ませておく。折り返しで1行が何行にもなるので rows 行より多くはならない
+        self.highlighter.prepare(&self.buffer, self.row_offset + rows);
+
+        'outer: for i in self.row_offset..self.buffer.len() {
+            if self.bookmark_index(i).is_some() {
+                hotspots.push(Hotspot { row: top + row, col: left, width, pos: Cursor { row: i, column: 0 }, line: true });
+            }
+            self.write_gutter(out, Some(i))?;
+            if layout::is_long(&self.buffer[i]) {
+                // 長い行は画面に入る部分だけを表示する
+                let start = if i == self.cursor.row { self.clip_start } else { 0 };
+                let mut col = 0;
+                for j in start..=self.buffer[i].len() {
+                    if self.cursor == (Cursor { row: i, column: j }) {
+                        display_cursor = Some((top + row, left + gutter + col));
+                    }
+                    let c = match self.buffer[i].get(j) {
+                        Some(&c) => c,
+                        None => break,
+                    };
+                    let width = special::display_width(c, self.escapes);
+                    if col + width >= cols {
+                        break;
+                    }
+                    self.write_char(out, i, j, c)?;
+                    if has_tooltip(self.char_highlight(i, j, c)) {
+                        let col = left + gutter + col;
+                        hotspots.push(Hotspot { row: top + row, col, width, pos: Cursor { row: i, column: j }, line: false });
+                    }
+                    col += width;
+                }
+                row += 1;
+                if row >= rows {
+                    break;
+                }
+                self.start_window_row(out, region, row)?;
+                continue;
+            }
+
+            let layout = self.layouts.get(i, &self.buffer[i], cols, self.escapes);
+            // 行内で何行目まで折り返したか
+            let mut line_row = 0;
+            for j in layout.draw_order(self.wrap) {
+                let (r, col) = if self.wrap {
+                    layout.pos[j]
+                } else {
+                    (0, layout.x[j].saturating_sub(self.col_offset))
+                };
+                if r > line_row {
+                    line_row = r;
+                    row += 1;
+                    if row >= rows {
+                        break 'outer;
+                    } else {
+                        self.start_window_row(out, region, row)?;
+                        self.write_gutter(out, None)?;
+                    }
+                }
+                let col = left + gutter + col;
+                if self.cursor == (Cursor { row: i, column: j }) {
+                    // 画面上のカーソルの位置がわかった
+                    display_cursor = Some((top + row, col));
+                }
+
+                if let Some(&c) = self.buffer[i].get(j) {
+                    // 折り返さないときは画面からはみ出す部分を表示しない
+                    let width = special::display_width(c, self.escapes);
+                    if !self.wrap
+                        && (layout.x[j] < self.col_offset || layout.x[j] + width >= self.col_offset + cols)
+                    {
+                        continue;
+                    }
+                    self.write_char(out, i, j, c)?;
+                    if has_tooltip(self.char_highlight(i, j, c)) {
+                        hotspots.push(Hotspot { row: top + row, col, width, pos: Cursor { row: i, column: j }, line: false });
                     }
-                    writeln!(file);
+                } else if self.in_extra_selection(Cursor { row: i, column: j })
+                    && (self.wrap || (layout.x[j] >= self.col_offset && layout.x[j] < self.col_offset + cols))
+                {
+                    // 行末にあるほかのカーソルは、空白に色をつけて見せる
+                    write!(out, "{}", self.theme.selection.paint(' '))?;
                 }
             }
+            row += 1;
+            if row >= rows {
+                break;
+            } else {
+                self.start_window_row(out, region, row)?;
+            }
         }
+        Ok(WindowDrawn { cursor: display_cursor, hotspots, at: top + min(row, rows - 1) })
     }
-}
 
-fn main() {
-    // clap
-    let matches = App::new("testediter")
-        .about("A text editer")
-        .bin_name("testediter")
-        .arg(Arg::with_name("file"))
-        .get_matches();
+    // 窓の中の row 行目の先頭に移る
+    // 画面の左端から始まる窓では、アクセシビリティモードでも読めるように改行で進む
+    // 最後の行の最後では改行すると1行ずれてしまうので、次の行に書くときに改行する
+    fn start_window_row<T: Write>(&self, out: &mut T, region: Region, row: usize) -> io::Result<()> {
+        if row > 0 && region.left == 0 {
+            write!(out, "\r\n")
+        } else {
+            write!(out, "{}", cursor::Goto(region.left as u16 + 1, (region.top + row) as u16 + 1))
+        }
+    }
 
-    let file_path: Option<&OsStr> = matches.value_of_os("file");
+    // 窓の区切り。上下に並べるときは窓の下に、そのファイルの名前を入れた線を引く。左右に並べるときは右に縦線を引く
+    fn draw_separator<T: Write>(&self, out: &mut T, region: Region) -> io::Result<()> {
+        if self.split_vertical {
+            for row in region.top..region.top + region.rows {
+                write!(out, "{}|", cursor::Goto((region.left + region.cols) as u16 + 1, row as u16 + 1))?;
+            }
+            return Ok(());
+        }
+        let modified = if self.modified { " [+]" } else { "" };
+        let label = format!("-- {}{} ", self.display_path(), modified);
+        let fill = region.cols.saturating_sub(special::str_width(&label));
+        write!(out, "{}", cursor::Goto(1, (region.top + region.rows) as u16 + 1))?;
+        write!(out, "{}", truncate(&format!("{}{}", label, "-".repeat(fill)), region.cols))
+    }
 
-    let mut state = EditerState::default();
+    fn draw<T: Write>(&mut self, out: &mut T) -> io::Result<()> {
+        let (_, screen_cols) = Self::terminal_size();
+        let rows = self.text_area_rows();
 
-    if let Some(file_path) = file_path {
-        state.open(path::Path::new(file_path));
-    }
+        write!(out, "{}", clear::All)?;
+        write!(out, "{}", cursor::Goto(1, 1))?;
 
-    let stdin = stdin();
-    let mut stdout = AlternateScreen::from(stdout().into_raw_mode().unwrap());
+        let regions = self.window_regions();
+        let mut drawn = None;
+        let mut at = 0;
+        for (k, &region) in regions.iter().enumerate() {
+            let last = k + 1 == regions.len();
+            if k == self.window {
+                let window = self.draw_window(out, region)?;
+                at = window.at;
+                drawn = Some(window);
+                if !last {
+                    self.draw_separator(out, region)?;
+                }
+            } else {
+                at = self.in_window(k, |state| {
+                    let window = state.draw_window(out, region)?;
+                    if !last {
+                        state.draw_separator(out, region)?;
+                    }
+                    Ok::<_, io::Error>(window.at)
+                })?;
+            }
+            if !last && !self.split_vertical {
+                at = region.top + region.rows;
+            }
+        }
+        let (display_cursor, hotspots) = drawn.map(|window| (window.cursor, window.hotspots)).unwrap_or_default();
 
-    state.draw(&mut stdout);
+        self.hotspots = hotspots;
+        // ここから下は画面の幅いっぱいを使う
+        let cols = screen_cols;
 
-    for evt in stdin.events() {
-        match evt.unwrap() {
-            Event::Key(Key::Ctrl('c')) => {
-                return;
-            },
-            Event::Key(Key::Ctrl('s')) => {
-                state.save();
+        if self.pane_rows() > 0 {
+            self.move_to_row(out, &mut at, rows)?;
+            if self.pane_rows() == 1 {
+                // たたんでいることと、隠れている行数がわかるようにする
+                let label = format!("-- {} ", tr!("pane-collapsed", self.pane.len()));
+                let fill = cols.saturating_sub(special::str_width(&label));
+                write!(out, "{}", truncate(&format!("{}{}", label, "-".repeat(fill)), cols))?;
+            } else {
+                write!(out, "{}", "-".repeat(cols))?;
             }
-            Event::Key(Key::Up) => {
-                state.cursor_up();
-            },
-            Event::Key(Key::Down) => {
-                state.cursor_dwon();
-            },
-            Event::Key(Key::Left) => {
-                state.cursor_left();
-            },
-            Event::Key(Key::Right) => {
-                state.cursor_right();
-            },
-            Event::Key(Key::Char(c)) => {
-                state.insert(c);
-            },
-            Event::Key(Key::Backspace) => {
-                state.back_space();
-            },
-            Event::Key(Key::Delete) => {
-                state.delete();
-            },
-            _ => {},
+            let height = self.pane_rows() - 1;
+            // 選択行が見えるようにスクロールする
+            let offset = self
+                .pane_cursor
+                .map(|k| (k + 1).saturating_sub(height))
+                .unwrap_or_else(|| min(self.pane_offset, self.pane.len().saturating_sub(height)));
+            for (k, line) in self.pane.iter().enumerate().skip(offset).take(height) {
+                self.move_to_row(out, &mut at, rows + k - offset + 1)?;
+                let line = truncate(line, cols);
+                if self.pane_cursor == Some(k) {
+                    // 反転表示だけでなく印もつける
+                    let line = if self.accessible {
+                        truncate(&format!("> {}", line), cols)
+                    } else {
+                        line
+                    };
+                    write!(out, "{}", self.theme.selection.paint(line))?;
+                } else if line.starts_with('+') {
+                    write!(out, "{}", self.theme.added.paint(line))?;
+                } else if line.starts_with('-') {
+                    write!(out, "{}", self.theme.removed.paint(line))?;
+                } else {
+                    write!(out, "{}", line)?;
+                }
+            }
+        }
+
+        let rows = self.screen_rows();
+        self.move_to_row(out, &mut at, rows)?;
+        self.line_starts.fill(&self.buffer, self.cursor.row, self.encoding, self.line_ending);
+        write!(out, "{}", self.theme.status.paint(self.status_line(cols)))?;
+
+        // 補完の候補や入力を確定できない理由
+        let hint = self.prompt.as_ref().and_then(|minibuffer| minibuffer.hint.clone());
+        if self.accessible {
+            self.move_to_row(out, &mut at, rows + 1)?;
+            let text = match hint.as_ref() {
+                Some(hint) => hint.replace('\n', "  "),
+                None => self.announcement(),
+            };
+            write!(out, "{}", truncate(&text, cols))?;
+        }
+
+        // 窓を出しているときはカーソルを窓の中に置く
+        // アクセシビリティモードでは改行で進むので、位置を飛ばして重ねる窓は出さない
+        let hint_popup = hint.map(|hint| Popup::new("", &hint, cols * 2 / 3));
+        let welcome_popup = self.welcome.as_ref().map(Welcome::popup);
+        let popup_cursor = match self.popup.as_ref().or(hint_popup.as_ref()).or(welcome_popup.as_ref()) {
+            Some(popup) if !self.accessible => Some(popup.draw_centered(out, rows, cols)?),
+            _ => None,
+        };
+        if let Some((row, col, tooltip)) = self.tooltip.as_ref() {
+            if popup_cursor.is_none() && !self.accessible {
+                tooltip.draw_near(out, *row, *col, rows, cols)?;
+            }
+        }
+
+        // 最下行にプロンプトかメッセージを表示する
+        let rows = self.screen_rows() + if self.accessible { 2 } else { 1 };
+        self.move_to_row(out, &mut at, rows)?;
+        if let Some(minibuffer) = self.prompt.as_ref() {
+            let line = truncate(&format!("{}{}", minibuffer.label, minibuffer.input), cols);
+            write!(out, "{}", line)?;
+            let width = min(special::str_width(&line), cols);
+            if minibuffer.input.is_empty() && !minibuffer.placeholder.is_empty() {
+                let placeholder = truncate(&minibuffer.placeholder, cols - width);
+                write!(out, "{}{}{}", style::Faint, placeholder, style::Reset)?;
+            }
+            write!(out, "{}", cursor::Goto(width as u16 + 1, rows as u16 + 1))?;
+            return out.flush();
+        }
+        write!(out, "{}", truncate(&self.message, cols))?;
+
+        if let Some((r, c)) = popup_cursor.or(display_cursor) {
+            write!(out, "{}", cursor::Goto(c as u16 + 1, r as u16 + 1))?;
+        }
+
+        out.flush()
+    }
+
+    // マウスを画面の row 行 col 列に動かした。色つきの文字の上なら、その意味を窓で出す
+    // 出すものが変わったら true
+    fn hover(&mut self, row: usize, col: usize) -> bool {
+        let hit = |spot: &&Hotspot| spot.row == row && (spot.col..spot.col + spot.width).contains(&col);
+        // 文字の説明を、その文字のある行の印のメモより優先する
+        let spot = self
+            .hotspots
+            .iter()
+            .filter(|spot| !spot.line)
+            .find(hit)
+            .or_else(|| self.hotspots.iter().find(hit));
+        let text = spot.and_then(|spot| {
+            let pos = spot.pos;
+            if spot.line {
+                let note = &self.bookmarks[self.bookmark_index(pos.row)?].note;
+                let text = if note.is_empty() { tr!("bookmark") } else { note.clone() };
+                return Some((spot.row, col, text));
+            }
+            let c = *self.buffer.get(pos.row)?.get(pos.column)?;
+            let text = match self.char_highlight(pos.row, pos.column, c) {
+                Highlight::Special => match special::name(c) {
+                    Some(name) => format!("U+{:04X} {}", c as u32, name),
+                    None => format!("U+{:04X}", c as u32),
+                },
+                Highlight::OverLimit if pos.row == 1 => tr!("second-line-not-blank"),
+                Highlight::OverLimit if pos.row == 0 => tr!("over-limit", COMMIT_SUBJECT_LIMIT),
+                Highlight::OverLimit => tr!("over-limit", COMMIT_BODY_LIMIT),
+                _ => return None,
+            };
+            Some((spot.row, spot.col, text))
+        });
+        let (_, cols) = Self::terminal_size();
+        let tooltip = text.map(|(row, col, text)| (row, col, Popup::new("", &text, cols / 2)));
+        let changed = tooltip.as_ref().map(|(row, col, _)| (*row, *col))
+            != self.tooltip.as_ref().map(|(row, col, _)| (*row, *col));
+        self.tooltip = tooltip;
+        changed
+    }
+
+    // 画面の row 行目の先頭に移る。アクセシビリティモードではカーソルを飛ばさずに改行で進む
+    fn move_to_row<T: Write>(&self, out: &mut T, at: &mut usize, row: usize) -> io::Result<()> {
+        if self.accessible && row >= *at {
+            for _ in *at..row {
+                write!(out, "\r\n")?;
+            }
+            write!(out, "\r")?;
+        } else {
+            write!(out, "{}", cursor::Goto(1, row as u16 + 1))?;
+        }
+        *at = row;
+        Ok(())
+    }
+
+    // ファイル名、変更の有無、カーソルの位置と行数。画面の幅いっぱいに空白で埋める
+    fn status_line(&self, cols: usize) -> String {
+        let join = |segments: &[Segment]| {
+            segments.iter().filter_map(|&segment| self.status_segment(segment)).collect::<Vec<_>>().join(" ")
+        };
+        let left = format!(" {}", join(&self.status_layout.left));
+        let right = format!("{} ", join(&self.status_layout.right));
+        // 狭ければファイル名の方を切る
+        let room = cols.saturating_sub(special::str_width(&right) + 1);
+        let left = truncate(&left, room);
+        let fill = cols.saturating_sub(special::str_width(&left) + special::str_width(&right));
+        truncate(&format!("{}{}{}", left, " ".repeat(fill), right), cols)
+    }
+
+    // ステータス行の項目1つ。出すものがなければ None
+    fn status_segment(&self, segment: Segment) -> Option<String> {
+        match segment {
+            Segment::Mode => {
+                let mut flags = Vec::new();
+                if self.read_only {
+                    flags.push(format!("[{}]", tr!("status-read-only")));
+                }
+                if let Some((_, status)) = self.command_output.as_ref() {
+                    flags.push(match status.code() {
+                        Some(code) => format!("[{}]", tr!("status-exit", code)),
+                        None => format!("[{}]", tr!("status-killed")),
+                    });
+                }
+                match self.vim.as_ref() {
+                    Some(vim) => {
+                        let mode = match vim.mode {
+                            vim::Mode::Normal => tr!("status-normal"),
+                            vim::Mode::Insert => tr!("status-insert"),
+                            vim::Mode::Visual => tr!("status-visual"),
+                        };
+                        flags.push(format!("[{}]", mode));
+                        let pending = vim.pending();
+                        if !pending.is_empty() {
+                            flags.push(pending);
+                        }
+                    },
+                    None if self.mark => flags.push(format!("[{}]", tr!("status-mark"))),
+                    None => {},
+                }
+                if self.sticky.active() {
+                    flags.push(self.sticky.pending());
+                }
+                Some(flags.join(" ")).filter(|flags| !flags.is_empty())
+            },
+            Segment::Path => Some(self.display_path()),
+            Segment::Args => self
+                .arg_position()
+                .filter(|_| self.args.len() > 1)
+                .map(|index| tr!("status-args", index + 1, self.args.len())),
+            Segment::Modified => self.modified.then(|| "[+]".to_string()),
+            Segment::Encoding => Some(self.encoding.name().to_string()),
+            Segment::Syntax => self.highlighter.name().map(str::to_string),
+            Segment::Branch => self.git_branch.as_ref().map(git::Branch::label),
+            // 描く前に draw がカーソルの行まで数えておく
+            Segment::Columns => self.line_starts.offset(&self.buffer, self.cursor, self.encoding).map(|offset| {
+                tr!(
+                    "status-columns",
+                    offset,
+                    self.cursor.column + 1,
+                    address::display_column(&self.buffer[self.cursor.row], self.cursor.column, self.escapes) + 1
+                )
+            }),
+            Segment::Position => Some(tr!(
+                "status-position",
+                self.cursor.row + 1,
+                self.buffer.len(),
+                self.cursor.column + 1
+            )),
+        }
+    }
+
+    // 開いているファイルのリポジトリのブランチを調べ直す
+    // 変更を数えるのにワークツリーを全部見るので、裏のスレッドで調べて、終わったら GIT_BRANCH_READY で知らせる
+    fn refresh_git_branch(&mut self) {
+        if self.git_branch_job.is_some() {
+            return;
+        }
+        let dir = match self.path.as_ref() {
+            Some(path) => parent_dir(path),
+            None => path::PathBuf::from("."),
+        };
+        let (tx, rx) = mpsc::channel();
+        let waker = self.waker.clone();
+        let worker_dir = dir.clone();
+        thread::spawn(move || {
+            let branch = git::open_repository(&worker_dir).and_then(|repo| git::current_branch(&repo)).ok();
+            if tx.send(branch).is_ok() {
+                waker.wake(GIT_BRANCH_READY);
+            }
+        });
+        self.git_branch_job = Some((dir, rx));
+    }
+
+    // 裏で調べたブランチを受け取る。変わっていれば true
+    // 調べている間にほかのファイルに移っていたら捨てて、次に調べ直すのを待つ
+    fn receive_git_branch(&mut self) -> bool {
+        let (dir, rx) = match self.git_branch_job.take() {
+            Some(job) => job,
+            None => return false,
+        };
+        let branch = match rx.try_recv() {
+            Ok(branch) => branch,
+            Err(mpsc::TryRecvError::Empty) => {
+                self.git_branch_job = Some((dir, rx));
+                return false;
+            },
+            Err(mpsc::TryRecvError::Disconnected) => return false,
+        };
+        let current = match self.path.as_ref() {
+            Some(path) => parent_dir(path),
+            None => path::PathBuf::from("."),
+        };
+        if dir != current || branch == self.git_branch {
+            return false;
+        }
+        self.git_branch = branch;
+        true
+    }
+
+    // 読み上げ用の行に出す、カーソルのある行の内容
+    fn announcement(&self) -> String {
+        if let Some(welcome) = self.welcome.as_ref() {
+            return welcome.selected_text();
+        }
+        let line: String = self.buffer[self.cursor.row].iter().collect();
+        tr!(
+            "announce-line",
+            self.cursor.row + 1,
+            self.buffer.len(),
+            self.cursor.column + 1,
+            line
+        )
+    }
+
+    // 最下行で文字列を入力させる。Esc で中断されたら None を返す
+    fn prompt<T: Write, I: Iterator<Item = io::Result<Event>>>(
+        &mut self,
+        out: &mut T,
+        events: &mut I,
+        minibuffer: Minibuffer,
+    ) -> io::Result<Option<String>> {
+        self.prompt = Some(minibuffer);
+        let result = loop {
+            self.draw(out)?;
+            let key = match events.next() {
+                Some(evt) => match evt? {
+                    Event::Key(key) => key,
+                    _ => continue,
+                },
+                None => break None,
+            };
+            let minibuffer = self.prompt.as_mut().unwrap();
+            let history = self.histories.get(minibuffer.history).map(Vec::as_slice).unwrap_or(&[]);
+            match minibuffer.handle(key, history) {
+                Outcome::Pending => {},
+                Outcome::Submit(input) => break Some(input),
+                Outcome::Cancel => break None,
+            }
+        };
+        if let (Some(minibuffer), Some(input)) = (self.prompt.take(), result.as_ref()) {
+            let history = self.histories.entry(minibuffer.history).or_default();
+            if !input.trim().is_empty() && history.last() != Some(input) {
+                history.push(input.clone());
+            }
+        }
+        Ok(result)
+    }
+
+    // 最下行で探す文字列を打たせ、打つたびに一致するところへ動く
+    // Ctrl+F で次に一致するところ、Enter でそこに決め、Esc で元の位置に戻る。Alt+r で正規表現に切り替える
+    fn incremental_search<T: Write, I: Iterator<Item = io::Result<Event>>>(
+        &mut self,
+        out: &mut T,
+        events: &mut I,
+    ) -> io::Result<()> {
+        let (origin, origin_offset) = (self.cursor, self.row_offset);
+        // 選んでいる途中なら、決めたときに選択の始まりを戻して一致したところまで広げる
+        let extending = self.anchor.filter(|_| self.mark);
+        self.clear_selection();
+        self.prompt = Some(Minibuffer::new(&search_label(self.search_regex)).history("search"));
+        let mut pattern = None;
+        let found = loop {
+            self.draw(out)?;
+            let key = match events.next() {
+                Some(evt) => match evt? {
+                    Event::Key(key) => key,
+                    _ => continue,
+                },
+                None => break false,
+            };
+            let minibuffer = self.prompt.as_mut().unwrap();
+            // 次を探すときは今の一致の次の文字から、打ち直したときは元の位置から探す
+            let from = match key {
+                Key::Ctrl('f') => {
+                    if minibuffer.input.is_empty() {
+                        // 空のまま Ctrl+F を押したら、前に探したものをもう一度探す
+                        if let Some(last) = self.histories.get("search").and_then(|history| history.last()) {
+                            minibuffer.input = last.clone();
+                        }
+                    }
+                    match self.anchor {
+                        Some(_) => Cursor { row: self.cursor.row, column: self.cursor.column + 1 },
+                        None => origin,
+                    }
+                },
+                Key::Alt('r') => {
+                    self.search_regex = !self.search_regex;
+                    minibuffer.label = search_label(self.search_regex);
+                    origin
+                },
+                key => {
+                    let history = self.histories.get(minibuffer.history).map(Vec::as_slice).unwrap_or(&[]);
+                    match minibuffer.handle(key, history) {
+                        Outcome::Pending => origin,
+                        Outcome::Submit(_) => break self.anchor.is_some(),
+                        Outcome::Cancel => break false,
+                    }
+                },
+            };
+            let minibuffer = self.prompt.as_mut().unwrap();
+            let result = match search::Pattern::new(&minibuffer.input, self.search_regex) {
+                Ok(compiled) => {
+                    let result = search::find(&self.buffer, &compiled, from);
+                    if result.is_none() && !compiled.is_empty() {
+                        minibuffer.hint = Some(tr!("not-found"));
+                    }
+                    pattern = Some(compiled);
+                    result
+                },
+                Err(reason) => {
+                    minibuffer.hint = Some(reason);
+                    pattern = None;
+                    None
+                },
+            };
+            match result {
+                Some((pos, len)) => {
+                    // 一致したところを選択の表示で目立たせる
+                    self.anchor = Some(Cursor { row: pos.row, column: pos.column + len });
+                    self.cursor = pos;
+                    self.search_hit = self.selection();
+                    self.scroll();
+                },
+                None => {
+                    self.anchor = None;
+                    self.cursor = origin;
+                    self.row_offset = origin_offset;
+                },
+            }
+        };
+        let hit = self.anchor.map(|end| (self.cursor, end));
+        self.clear_selection();
+        if let (true, Some(anchor), Some((start, end))) = (found, extending, hit) {
+            self.extend_to_match(anchor, start, end);
+        } else if let Some(anchor) = extending.filter(|_| !found) {
+            self.anchor = Some(anchor);
+            self.mark = true;
+        }
+        if let Some(minibuffer) = self.prompt.take() {
+            let history = self.histories.entry(minibuffer.history).or_default();
+            if found && history.last() != Some(&minibuffer.input) {
+                history.push(minibuffer.input);
+            }
+        }
+        if found {
+            self.last_search = pattern;
+        } else {
+            self.cursor = origin;
+            self.row_offset = origin_offset;
+        }
+        Ok(())
+    }
+
+    // +/pattern。from から正規表現で探して、一致したところに移る。n で続けて探せる
+    fn search_start(&mut self, text: &str, from: Cursor) {
+        let pattern = match search::Pattern::new(text, true) {
+            Ok(pattern) => pattern,
+            Err(reason) => {
+                self.message = reason;
+                return;
+            },
+        };
+        match search::find(&self.buffer, &pattern, from) {
+            Some((pos, _)) => {
+                self.cursor = pos;
+                self.row_offset = pos.row.saturating_sub(self.text_rows() / 2);
+                self.scroll();
+            },
+            None => self.message = tr!("not-found"),
+        }
+        self.last_search = Some(pattern);
+    }
+
+    // 最後に探したものの次 (forward) か前に一致するところを選ぶ
+    fn search_again(&mut self, forward: bool) {
+        let pattern = match self.last_search.as_ref() {
+            Some(pattern) => pattern,
+            None => {
+                self.message = tr!("no-previous-search");
+                return;
+            },
+        };
+        let result = if forward {
+            search::find(&self.buffer, pattern, Cursor { row: self.cursor.row, column: self.cursor.column + 1 })
+        } else {
+            search::find_back(&self.buffer, pattern, self.cursor)
+        };
+        match result {
+            Some((pos, len)) => {
+                self.push_jump();
+                let end = Cursor { row: pos.row, column: pos.column + len };
+                match self.anchor.filter(|_| self.mark) {
+                    Some(anchor) => self.extend_to_match(anchor, pos, end),
+                    None => {
+                        self.clear_selection();
+                        self.anchor = Some(end);
+                        self.cursor = pos;
+                        self.search_hit = self.selection();
+                    },
+                }
+                self.scroll();
+            },
+            None => self.message = tr!("not-found"),
+        }
+    }
+
+    // 選択の始まり anchor を残したまま、一致したところ (start から end) を含むように選択を広げる
+    // vim のビジュアルモードはカーソルの文字まで選ぶので、後ろへ広げるときは一致の最後の文字に置く
+    fn extend_to_match(&mut self, anchor: Cursor, start: Cursor, end: Cursor) {
+        self.anchor = Some(anchor);
+        self.mark = true;
+        self.cursor = if start < anchor {
+            start
+        } else if self.vim.is_some() {
+            self.previous_position(end).unwrap_or(start)
+        } else {
+            end
+        };
+        self.search_hit = Some((start, end));
+    }
+
+    // 選択を始めて (もう選んでいれば広げて)、最後に探したものの次か前に一致するところまで選ぶ
+    fn select_to_match(&mut self, forward: bool) {
+        self.extend_selection();
+        self.mark = true;
+        self.search_again(forward);
+    }
+
+    // 質問を窓に出し、ボタンが押されるまで待つ。押されたボタンのキーを返し、Esc で中断されたら None を返す
+    fn ask<T: Write, I: Iterator<Item = io::Result<Event>>>(
+        &mut self,
+        out: &mut T,
+        events: &mut I,
+        mut dialog: Dialog,
+    ) -> io::Result<Option<char>> {
+        let result = loop {
+            // スクリーンリーダーが読めるように、アクセシビリティモードでは最下行で聞く
+            if self.accessible {
+                self.prompt = Some(Minibuffer::new(&format!("{} {} ", dialog.question(), dialog.buttons())));
+            } else {
+                let (_, cols) = Self::terminal_size();
+                let text = format!("{}\n\n{}", dialog.question(), dialog.buttons());
+                self.popup = Some(Popup::new("", &text, cols * 2 / 3));
+            }
+            self.draw(out)?;
+            let key = match events.next() {
+                Some(evt) => match evt? {
+                    Event::Key(key) => key,
+                    _ => continue,
+                },
+                None => break None,
+            };
+            match dialog.handle(key) {
+                Answer::Pending => {},
+                Answer::Chosen(c) => break Some(c),
+                Answer::Cancel => break None,
+            }
+        };
+        self.prompt = None;
+        self.popup = None;
+        Ok(result)
+    }
+
+    // picker の一覧を下のペインに出して選ばせる。決めた項目の番号を返し、やめたら None
+    fn pick_from_pane<T: Write, I: Iterator<Item = io::Result<Event>>>(
+        &mut self,
+        out: &mut T,
+        events: &mut I,
+        picker: Picker,
+    ) -> io::Result<Option<usize>> {
+        let picked = self.pick_from_pane_with(out, events, picker, |state, out, _| state.draw(out))?;
+        Ok(picked.map(|(index, _)| index))
+    }
+
+    // 選んでいる項目が変わるたびに draw で描く。決めた項目の番号と、決めたキーを返す
+    fn pick_from_pane_with<T, I, F>(
+        &mut self,
+        out: &mut T,
+        events: &mut I,
+        mut picker: Picker,
+        mut draw: F,
+    ) -> io::Result<Option<(usize, char)>>
+    where
+        T: Write,
+        I: Iterator<Item = io::Result<Event>>,
+        F: FnMut(&mut Self, &mut T, usize) -> io::Result<()>,
+    {
+        let saved_pane = std::mem::replace(&mut self.pane, picker.take_lines());
+        let result = loop {
+            self.pane_cursor = picker.row();
+            self.message = picker.help().to_string();
+            let drawn = draw(self, out, picker.index());
+            self.message.clear();
+            if let Err(e) = drawn {
+                self.pane_cursor = None;
+                self.pane = saved_pane;
+                return Err(e);
+            }
+
+            let key = match events.next() {
+                Some(evt) => match evt {
+                    Ok(Event::Key(key)) => key,
+                    Ok(_) => continue,
+                    Err(e) => break Err(e),
+                },
+                None => break Ok(None),
+            };
+            match picker.handle(key) {
+                Answer::Pending => {},
+                Answer::Chosen(c) => break Ok(Some((picker.index(), c))),
+                Answer::Cancel => break Ok(None),
+            }
+        };
+        self.pane_cursor = None;
+        self.pane = saved_pane;
+        result
+    }
+
+    // 保存していないバッファがあれば、それぞれ出して、終わる前に保存するか捨てるかを聞く。終わってよければ true
+    fn confirm_quit<T: Write, I: Iterator<Item = io::Result<Event>>>(
+        &mut self,
+        out: &mut T,
+        events: &mut I,
+    ) -> io::Result<bool> {
+        let current = self.current;
+        let modified: Vec<usize> = (0..self.buffers.len())
+            .filter(|&index| if index == current { self.modified } else { self.buffers[index].modified })
+            .collect();
+        for index in modified {
+            self.switch_buffer(index);
+            let question = tr!("quit-question", self.display_path());
+            if !self.confirm_discard(out, events, &question)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    // 表示しているバッファに保存していない変更があれば、保存するか捨てるかを聞く。進めてよければ true
+    fn confirm_discard<T: Write, I: Iterator<Item = io::Result<Event>>>(
+        &mut self,
+        out: &mut T,
+        events: &mut I,
+        question: &str,
+    ) -> io::Result<bool> {
+        if !self.modified {
+            return Ok(true);
+        }
+        let dialog = Dialog::new(question)
+            .button('s', &tr!("button-save"))
+            .button('d', &tr!("button-discard"))
+            .button('c', &tr!("button-cancel"))
+            .default_button('c');
+        match self.ask(out, events, dialog)? {
+            Some('s') => self.save_interactive(out, events),
+            Some('d') => Ok(true),
+            _ => Ok(false),
+        }
+    }
+
+    // 保存する場所をこのバッファのファイル名にする。path がなければ聞く
+    // ほかのファイルがあれば上書きしてよいか聞く。やめたら false
+    fn choose_save_path<T: Write, I: Iterator<Item = io::Result<Event>>>(
+        &mut self,
+        out: &mut T,
+        events: &mut I,
+        path: Option<path::PathBuf>,
+    ) -> io::Result<bool> {
+        let path = match path {
+            Some(path) => path,
+            None => {
+                let minibuffer = Minibuffer::new(&tr!("save-as"))
+                    .history("path")
+                    .complete(minibuffer::complete_path)
+                    .validate(minibuffer::not_empty);
+                match self.prompt(out, events, minibuffer)? {
+                    Some(path) => path::PathBuf::from(path.trim()),
+                    None => {
+                        self.message = tr!("no-file-name");
+                        return Ok(false);
+                    },
+                }
+            },
+        };
+        if path.exists()
+            && self.path.as_ref() != Some(&path)
+            && self.ask(out, events, Dialog::yes_no(&tr!("overwrite-question", path.display())))? != Some('y')
+        {
+            return Ok(false);
+        }
+        self.project_root = project::find_root(&path);
+        self.highlighter = Highlighter::new(syntax::for_path(&path));
+        self.path = Some(path);
+        Ok(true)
+    }
+
+    // 別の名前で保存する。以降はそのファイルを編集する
+    fn save_as<T: Write, I: Iterator<Item = io::Result<Event>>>(
+        &mut self,
+        out: &mut T,
+        events: &mut I,
+        path: Option<path::PathBuf>,
+    ) -> io::Result<bool> {
+        if !self.check_writable() || !self.choose_save_path(out, events, path)? {
+            return Ok(false);
+        }
+        self.save_interactive(out, events)
+    }
+
+    // 保存に失敗したら、やり直すか別の場所に保存するかを聞く。保存できたら true
+    // まだファイル名がなければ、どこに保存するかを聞く
+    fn save_interactive<T: Write, I: Iterator<Item = io::Result<Event>>>(
+        &mut self,
+        out: &mut T,
+        events: &mut I,
+    ) -> io::Result<bool> {
+        if self.path.is_none() && !self.read_only {
+            return self.save_as(out, events, None);
+        }
+        while !self.save() {
+            if self.read_only || self.path.is_none() {
+                return Ok(false);
+            }
+            let dialog = Dialog::new(&tr!("save-failed-question", self.message))
+                .button('r', &tr!("button-retry"))
+                .button('a', &tr!("button-save-as"))
+                .button('d', &tr!("button-dump"))
+                .button('c', &tr!("button-cancel"));
+            match self.ask(out, events, dialog)? {
+                Some('r') => {},
+                Some('a') => {
+                    self.choose_save_path(out, events, None)?;
+                },
+                Some('d') => {
+                    match self.dump_emergency() {
+                        Ok(path) => {
+                            self.message = tr!("buffer-dumped", path.display());
+                        },
+                        Err(e) => self.message = tr!("dump-failed", e),
+                    }
+                    return Ok(false);
+                },
+                _ => {
+                    self.message = tr!("save-cancelled");
+                    return Ok(false);
+                },
+            }
+        }
+        Ok(true)
+    }
+
+    // 保存できないときのために、バッファを状態ディレクトリの時刻つきのファイルに書き出す
+    fn dump_emergency(&self) -> io::Result<path::PathBuf> {
+        let dir = logger::state_dir()
+            .ok_or_else(|| io::Error::other("no state directory"))?
+            .join("emergency");
+        fs::create_dir_all(&dir)?;
+        let name = self
+            .path
+            .as_ref()
+            .and_then(|path| path.file_name())
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "noname".to_string());
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let path = dir.join(format!("{}.{}", name, now.as_secs()));
+        write_file(&path, &self.encoding.encode(&self.contents()), false)?;
+        log_warn!("dumped buffer to {}", path.display());
+        Ok(path)
+    }
+
+    // `:` コマンドを実行する
+    fn execute_command<T: Write, I: Iterator<Item = io::Result<Event>>>(
+        &mut self,
+        out: &mut T,
+        events: &mut I,
+        command: &str,
+    ) -> io::Result<Action> {
+        let command = command.trim();
+        match command {
+            "" => {},
+            // 保存していなければ聞く。q! と cq は聞かずに変更を捨てて終わる
+            "q" => {
+                if !self.confirm_quit(out, events)? {
+                    self.message = tr!("quit-unsaved");
+                    return Ok(Action::Failed);
+                }
+                return Ok(Action::Quit(0));
+            },
+            "q!" => return Ok(Action::Quit(0)),
+            "cq" => return Ok(Action::Quit(1)),
+            "w" => {
+                if !self.save_interactive(out, events)? {
+                    return Ok(Action::Failed);
+                }
+            },
+            "e" => match self.path.clone() {
+                Some(path) => {
+                    self.open(&path);
+                },
+                None => self.message = tr!("no-file-name"),
+            },
+            "hex" | "text" => match self.path.clone() {
+                Some(path) => {
+                    let view = if command == "hex" { View::Hex } else { View::Text };
+                    self.load(&path, view);
+                },
+                None => self.message = tr!("no-file-name"),
+            },
+            "ro" => self.force_read_only(),
+            "escapes" => {
+                self.escapes = !self.escapes;
+                self.scroll();
+            },
+            "diff" => self.diff_unsaved(),
+            command if command.starts_with("export ") => {
+                self.export_html(path::Path::new(command[7..].trim()));
+            },
+            "revert" => self.revert(out, events)?,
+            "nextspecial" => self.next_special(),
+            "stripspecial" => self.strip_special(),
+            "specials" => self.list_special(),
+            "bookmarks" => self.list_bookmarks(),
+            "marks" => self.list_marks(),
+            "trash" => self.trash(out, events)?,
+            "kills" => self.kill_ring_list(out, events)?,
+            "todo" => self.todo_list(out, events)?,
+            "undolist" => self.undo_list(out, events)?,
+            "stats" => self.show_stats(),
+            "cursorinfo" => self.cursor_info(),
+            "number" => {
+                self.line_numbers = !self.line_numbers;
+                self.scroll();
+            },
+            "relative" => self.toggle_relative_numbers(),
+            "wrap" => {
+                self.wrap = !self.wrap;
+                self.col_offset = 0;
+                self.scroll();
+            },
+            "vim" => {
+                self.vim = match self.vim {
+                    Some(_) => None,
+                    None => Some(Vim::default()),
+                };
+                self.clear_selection();
+                self.message = if self.vim.is_some() { tr!("vim-on") } else { tr!("vim-off") };
+            },
+            "diffwhite" => self.toggle_diff_filter(true),
+            "diffcase" => self.toggle_diff_filter(false),
+            "scrollbind" => {
+                self.scroll_bind = !self.scroll_bind;
+                self.message = if self.scroll_bind { tr!("scroll-bind-on") } else { tr!("scroll-bind-off") };
+                self.scroll();
+            },
+            command if command.starts_with("splitsel ") => self.split_selection(command[9..].trim()),
+            command if command.starts_with("rename ") => {
+                self.rename_file(out, events, path::Path::new(command[7..].trim()))?;
+            },
+            "delete" => self.delete_file(out, events)?,
+            "new" => self.new_file(out, events)?,
+            "open" => self.prompt_open_buffer(out, events)?,
+            command if command.starts_with("open ") => self.open_buffer(path::Path::new(command[5..].trim())),
+            "bnext" => self.cycle_buffer(true),
+            "bprev" => self.cycle_buffer(false),
+            "next" => self.step_arg(true),
+            "prev" => self.step_arg(false),
+            "bclose" => self.close_buffer(out, events)?,
+            "split" => self.split_window(false),
+            "vsplit" => self.split_window(true),
+            "close" => self.close_window(),
+            "wnext" => self.cycle_window(true),
+            "wprev" => self.cycle_window(false),
+            "saveas" => {
+                if !self.save_as(out, events, None)? {
+                    return Ok(Action::Failed);
+                }
+            },
+            command if command.starts_with("saveas ") => {
+                let path = path::PathBuf::from(command[7..].trim());
+                if !self.save_as(out, events, Some(path))? {
+                    return Ok(Action::Failed);
+                }
+            },
+            command if command.starts_with("new ") => {
+                let path = self.new_file_path(path::Path::new(command[4..].trim()));
+                self.create_file(&path);
+            },
+            "replace" | "replace-regex" => self.query_replace(out, events, command == "replace-regex")?,
+            command if command.starts_with("stats ") => {
+                if !self.export_stats(path::Path::new(command[6..].trim())) {
+                    return Ok(Action::Failed);
+                }
+            },
+            "reopen" => self.reopen(),
+            command if is_write(command) => {
+                if !self.write_range(out, events, command)? {
+                    return Ok(Action::Failed);
+                }
+            },
+            command if is_substitute(command) => {
+                if !self.substitute(command) {
+                    return Ok(Action::Failed);
+                }
+            },
+            "zoom" => self.toggle_zoom(),
+            command if command.starts_with("layout ") => self.apply_layout(command[7..].trim()),
+            command if command.starts_with("goto ") => {
+                if !self.goto_byte(command[5..].trim()) {
+                    return Ok(Action::Failed);
+                }
+            },
+            command if command.starts_with("mark ") => {
+                let mut chars = command[5..].trim().chars();
+                match (chars.next(), chars.next()) {
+                    (Some(name), None) if name.is_ascii_lowercase() => self.set_mark(name),
+                    _ => {
+                        self.message = tr!("invalid-mark", command[5..].trim());
+                        return Ok(Action::Failed);
+                    },
+                }
+            },
+            command if command.starts_with('!') => {
+                let shell = command[1..].trim();
+                if shell.is_empty() {
+                    self.message = tr!("no-command");
+                    return Ok(Action::Failed);
+                }
+                self.run_to_buffer(shell);
+            },
+            command if command.starts_with("r ") => {
+                let arg = command[2..].trim();
+                match arg.strip_prefix('!') {
+                    Some(shell) => self.read_command(shell.trim()),
+                    None => self.read_into(path::Path::new(arg)),
+                }
+            },
+            command if command.starts_with("e ") => {
+                if self.autowrite() {
+                    self.open(path::Path::new(command[2..].trim()));
+                }
+            },
+            "wq" | "x" => {
+                if !self.save_interactive(out, events)? {
+                    return Ok(Action::Failed);
+                }
+                return Ok(Action::Quit(0));
+            },
+            command => {
+                self.message = tr!("unknown-command", command);
+                return Ok(Action::Failed);
+            },
+        }
+        Ok(Action::Continue)
+    }
+
+    fn scroll(&mut self) {
+        let rows = self.text_rows();
+        self.row_offset = min(self.row_offset, self.cursor.row);
+        if self.cursor.row + 1 >= rows {
+            self.row_offset = max(self.row_offset, self.cursor.row + 1 - rows);
+        }
+
+        let cols = self.text_cols();
+        let line = &self.buffer[self.cursor.row];
+        if layout::is_long(line) {
+            self.clip_start = layout::clip_start(line, self.clip_start, self.cursor.column, cols, self.escapes);
+        } else if !self.wrap {
+            let row = self.cursor.row;
+            let x = self.layouts.get(row, &self.buffer[row], cols, self.escapes).x[self.cursor.column];
+            self.col_offset = min(self.col_offset, x);
+            if x + 1 >= cols {
+                self.col_offset = max(self.col_offset, x + 1 - cols);
+            }
+        }
+        self.bind_pane();
+    }
+
+    // 差分のペインを、本文の一番上の行の差分が先頭に来るように動かす
+    fn bind_pane(&mut self) {
+        self.pane_offset = if self.scroll_bind {
+            git::diff_line_of(&self.pane, self.row_offset).unwrap_or(0)
+        } else {
+            0
+        };
+    }
+
+    // カーソルは動かさずに、画面を lines 行だけ下 (down) か上に動かす
+    // カーソルが画面から出てしまうときは、画面の端の行に移す
+    fn scroll_view(&mut self, down: bool, lines: usize) {
+        let rows = self.text_rows();
+        let last = self.buffer.len() - 1;
+        self.row_offset = if down { min(self.row_offset + lines, last) } else { self.row_offset.saturating_sub(lines) };
+        let row = self.cursor.row.clamp(self.row_offset, min(self.row_offset + rows - 1, last));
+        if row != self.cursor.row {
+            let goal = self.goal_column();
+            self.cursor = Cursor { row, column: min(goal, self.buffer[row].len()) };
+        }
+        self.scroll();
+    }
+
+    // 画面の (行, 列) にある文字の位置。窓の外なら None
+    // focus なら、その窓に移る。そうでなければ操作している窓の中だけを見る
+    fn position_at(&mut self, row: usize, col: usize, focus: bool) -> Option<Cursor> {
+        let regions = self.window_regions();
+        let index = regions.iter().position(|region| {
+            (region.top..region.top + region.rows).contains(&row)
+                && (region.left..region.left + region.cols).contains(&col)
+        })?;
+        if index != self.window {
+            if !focus {
+                return None;
+            }
+            self.focus_window(index);
+        }
+        let region = regions[index];
+        let (row, col) = (row - region.top, (col - region.left).saturating_sub(self.gutter_width()));
+        let cols = self.text_cols();
+        // 窓の一番上の行から、折り返した表示行を数えていく
+        let mut top = 0;
+        for i in self.row_offset..self.buffer.len() {
+            if layout::is_long(&self.buffer[i]) {
+                if row == top {
+                    let start = if i == self.cursor.row { self.clip_start } else { 0 };
+                    // 画面に出ている文字だけを見る
+                    let shown = self.buffer[i].slice(start..min(start + cols, self.buffer[i].len()));
+                    let column = start + address::column_at_display(&shown, col, self.escapes);
+                    return Some(Cursor { row: i, column });
+                }
+                top += 1;
+                continue;
+            }
+            let layout = self.layouts.get(i, &self.buffer[i], cols, self.escapes);
+            let rows = if self.wrap { layout.rows() } else { 1 };
+            if row < top + rows {
+                let column = if self.wrap {
+                    layout.index_at(row - top, col)
+                } else {
+                    layout.index_at_x(col + self.col_offset)
+                };
+                return Some(Cursor { row: i, column });
+            }
+            top += rows;
+        }
+        // 最後の行より下なら、最後の行の行末
+        let last = self.buffer.len() - 1;
+        Some(Cursor { row: last, column: self.buffer[last].len() })
+    }
+
+    // マウスのボタンとホイール。クリックした窓に移ってその文字にカーソルを置き、ドラッグで選ぶ
+    fn mouse(&mut self, event: MouseEvent) {
+        const WHEEL_LINES: usize = 3;
+        match event {
+            MouseEvent::Press(MouseButton::Left, x, y) => {
+                if let Some(pos) = self.position_at(y as usize - 1, x as usize - 1, true) {
+                    self.clear_selection();
+                    self.cursor = pos;
+                    self.scroll();
+                }
+            },
+            MouseEvent::Hold(x, y) => {
+                if let Some(pos) = self.position_at(y as usize - 1, x as usize - 1, false) {
+                    self.extend_selection();
+                    self.cursor = pos;
+                    self.scroll();
+                }
+            },
+            MouseEvent::Press(MouseButton::WheelDown, ..) => self.scroll_view(true, WHEEL_LINES),
+            MouseEvent::Press(MouseButton::WheelUp, ..) => self.scroll_view(false, WHEEL_LINES),
+            _ => {},
+        }
+    }
+
+    fn cursor_up(&mut self) {
+        if self.cursor.row > 0 {
+            let goal = self.goal_column();
+            self.cursor.row -= 1;
+            self.cursor.column = min(self.buffer[self.cursor.row].len(), goal);
+        }
+        self.scroll();
+    }
+
+    fn cursor_dwon(&mut self) {
+        if self.cursor.row + 1 < self.buffer.len() {
+            let goal = self.goal_column();
+            self.cursor.row += 1;
+            self.cursor.column = min(goal, self.buffer[self.cursor.row].len());
+        }
+        self.scroll();
+    }
+
+    // 画面の高さから1行引いた分だけ、カーソルと画面を上下に動かす。前の画面の端の1行は見えたまま残る
+    fn page(&mut self, down: bool) {
+        let lines = self.text_rows().saturating_sub(1).max(1);
+        let last = self.buffer.len() - 1;
+        let goal = self.goal_column();
+        let row = if down { min(self.cursor.row + lines, last) } else { self.cursor.row.saturating_sub(lines) };
+        self.row_offset = if down { min(self.row_offset + lines, last) } else { self.row_offset.saturating_sub(lines) };
+        self.cursor = Cursor { row, column: min(goal, self.buffer[row].len()) };
+        self.scroll();
+    }
+
+    // 論理行で上下に動くときに戻りたい位置。動き始めなら今の位置を覚える
+    fn goal_column(&mut self) -> usize {
+        match self.goal {
+            Some(Goal::Column(column)) => column,
+            _ => {
+                self.goal = Some(Goal::Column(self.cursor.column));
+                self.cursor.column
+            },
+        }
+    }
+
+    // 折り返した表示上の行で上下に動く。折り返していなければ論理行で動く
+    fn visual_up(&mut self) {
+        self.visual_move(false);
+    }
+
+    fn visual_down(&mut self) {
+        self.visual_move(true);
+    }
+
+    fn visual_move(&mut self, down: bool) {
+        let row = self.cursor.row;
+        if !self.wrap || layout::is_long(&self.buffer[row]) {
+            return if down { self.cursor_dwon() } else { self.cursor_up() };
+        }
+        let cols = self.text_cols();
+        let current = self.layouts.get(row, &self.buffer[row], cols, self.escapes);
+        let (r, col) = current.pos[self.cursor.column];
+        let col = match self.goal {
+            Some(Goal::Visual(col)) => col,
+            _ => {
+                self.goal = Some(Goal::Visual(col));
+                col
+            },
+        };
+        // 移る先の論理行と、その中の表示行 (None は最後の表示行)
+        let (row, r) = if down {
+            if r + 1 < current.rows() {
+                (row, Some(r + 1))
+            } else if row + 1 < self.buffer.len() {
+                (row + 1, Some(0))
+            } else {
+                return;
+            }
+        } else if r > 0 {
+            (row, Some(r - 1))
+        } else if row > 0 {
+            (row - 1, None)
+        } else {
+            return;
+        };
+        if layout::is_long(&self.buffer[row]) {
+            self.cursor.row = row;
+            self.cursor.column = min(self.cursor.column, self.buffer[row].len());
+        } else {
+            let target = self.layouts.get(row, &self.buffer[row], cols, self.escapes);
+            let r = r.unwrap_or(target.rows() - 1);
+            self.cursor = Cursor { row, column: target.index_at(r, col) };
+        }
+        self.scroll();
+    }
+
+    fn cursor_left(&mut self) {
+        if self.cursor.column > 0 {
+            self.cursor.column -= 1;
+        }
+        self.scroll();
+    }
+
+    fn cursor_right(&mut self) {
+        self.cursor.column = min(self.cursor.column + 1, self.buffer[self.cursor.row].len());
+        self.scroll();
+    }
+
+    fn line_start(&mut self) {
+        self.cursor.column = 0;
+        self.scroll();
+    }
+
+    fn line_end(&mut self) {
+        self.cursor.column = self.buffer[self.cursor.row].len();
+        self.scroll();
+    }
+
+    // バッファの先頭か末尾に移る
+    fn buffer_start(&mut self) {
+        self.push_jump();
+        self.cursor = Cursor { row: 0, column: 0 };
+        self.scroll();
+    }
+
+    fn buffer_end(&mut self) {
+        self.push_jump();
+        let row = self.buffer.len() - 1;
+        self.cursor = Cursor { row, column: self.buffer[row].len() };
+        self.scroll();
+    }
+
+    // 行の番号 (と列) を聞いて移る。コンパイラのエラーの位置の 12:5 のようにも書ける
+    fn goto_line_prompt<T: Write, I: Iterator<Item = io::Result<Event>>>(
+        &mut self,
+        out: &mut T,
+        events: &mut I,
+    ) -> io::Result<()> {
+        let minibuffer = Minibuffer::new(&tr!("goto-line"))
+            .history("line")
+            .placeholder(&tr!("goto-line-placeholder"))
+            .validate(check_line_column);
+        let input = match self.prompt(out, events, minibuffer)? {
+            Some(input) => input,
+            None => return Ok(()),
+        };
+        if let Some((line, column)) = parse_line_column(&input) {
+            self.push_jump();
+            self.goto_line(line, column);
+        }
+        Ok(())
+    }
+
+    // 1 から始まる行と列に移って、その行を画面の真ん中に出す。行や列が大きすぎれば最後の行や行末にする
+    fn goto_line(&mut self, line: usize, column: Option<usize>) {
+        let row = min(line, self.buffer.len()) - 1;
+        let column = column.map_or(0, |column| min(column - 1, self.buffer[row].len()));
+        // 選んでいる途中なら、どんな移動でも選択を広げる
+        if !self.mark {
+            self.clear_selection();
+        }
+        self.cursor = Cursor { row, column };
+        self.row_offset = row.saturating_sub(self.text_rows() / 2);
+        self.scroll();
+    }
+
+    // 行の番号 (0 始まり) の行に移って、行頭の空白の後に置く
+    fn goto_row(&mut self, row: usize) {
+        self.cursor.row = min(row, self.buffer.len() - 1);
+        self.cursor.column = self.buffer[self.cursor.row].iter().take_while(|c| c.is_whitespace()).count();
+        self.scroll();
+    }
+
+    // at の文字を含む単語
+    fn word_at(&self, at: Cursor) -> Word {
+        let (start, end, class) = word_in_line(&self.buffer[at.row], at.column);
+        Word { class, row: at.row, start, end }
+    }
+
+    // at から先の空白を飛ばした位置。stop_at_empty_line なら、at より後の空の行の行頭で止まる
+    // 空白の並びは1文字ずつではなく、その終わりまでまとめて飛ぶ
+    fn skip_spaces_forward(&self, mut at: Cursor, stop_at_empty_line: bool) -> Cursor {
+        let origin = at;
+        loop {
+            let word = self.word_at(at);
+            if word.class != CharClass::Space || (stop_at_empty_line && at != origin && self.is_empty_line_start(at)) {
+                return at;
+            }
+            if word.end > at.column {
+                at.column = word.end;
+                continue;
+            }
+            match self.next_position(at) {
+                Some(next) => at = next,
+                None => return at,
+            }
+        }
+    }
+
+    // 改行も1文字として数えて、次と前の位置
+    fn next_position(&self, at: Cursor) -> Option<Cursor> {
+        if at.column < self.buffer[at.row].len() {
+            Some(Cursor { row: at.row, column: at.column + 1 })
+        } else if at.row + 1 < self.buffer.len() {
+            Some(Cursor { row: at.row + 1, column: 0 })
+        } else {
+            None
+        }
+    }
+
+    fn previous_position(&self, at: Cursor) -> Option<Cursor> {
+        if at.column > 0 {
+            Some(Cursor { row: at.row, column: at.column - 1 })
+        } else if at.row > 0 {
+            Some(Cursor { row: at.row - 1, column: self.buffer[at.row - 1].len() })
+        } else {
+            None
+        }
+    }
+
+    fn is_empty_line_start(&self, at: Cursor) -> bool {
+        at.column == 0 && self.buffer[at.row].is_empty()
+    }
+
+    // 次の単語の始めに動く。空の行も1つの単語とみなす
+    fn word_forward(&mut self) {
+        let mut at = self.cursor;
+        let word = self.word_at(at);
+        if word.class != CharClass::Space {
+            at.column = word.end;
+        }
+        self.cursor = self.skip_spaces_forward(at, true);
+        self.scroll();
+    }
+
+    // 前の単語の始めに動く
+    fn word_backward(&mut self) {
+        let mut at = match self.previous_position(self.cursor) {
+            Some(at) => at,
+            None => return,
+        };
+        loop {
+            let word = self.word_at(at);
+            if word.class != CharClass::Space || self.is_empty_line_start(at) {
+                break;
+            }
+            // 空白の並びは、その始まりの前までまとめて戻る
+            let start = Cursor { row: at.row, column: word.start };
+            match self.previous_position(start) {
+                Some(previous) => at = previous,
+                None => {
+                    at = start;
+                    break;
+                },
+            }
+        }
+        let word = self.word_at(at);
+        if word.class != CharClass::Space {
+            at.column = word.start;
+        }
+        self.cursor = at;
+        self.scroll();
+    }
+
+    // 単語の終わりの文字に動く。もう終わりにいれば次の単語の終わりに動く
+    fn word_end(&mut self) {
+        let mut at = match self.next_position(self.cursor) {
+            Some(at) => self.skip_spaces_forward(at, false),
+            None => return,
+        };
+        let word = self.word_at(at);
+        if word.class != CharClass::Space {
+            at.column = word.end - 1;
+        }
+        self.cursor = at;
+        self.scroll();
+    }
+
+    // 印をつけた行のメモを保存する場所 (プロジェクトのルートか、ファイルのあるディレクトリ)
+    fn bookmark_root(&self) -> Option<path::PathBuf> {
+        self.project_root
+            .clone()
+            .or_else(|| parent_dir(self.path.as_ref()?).canonicalize().ok())
+    }
+
+    fn bookmark_index(&self, row: usize) -> Option<usize> {
+        self.bookmarks.binary_search_by_key(&row, |bookmark| bookmark.row).ok()
+    }
+
+    fn save_bookmarks(&self) {
+        let (root, path) = match (self.bookmark_root(), self.path.as_ref()) {
+            (Some(root), Some(path)) => (root, path),
+            _ => return,
+        };
+        if let Err(e) = bookmarks::save(&root, path, &self.bookmarks) {
+            log_warn!("cannot save bookmarks: {}", e);
+        }
+    }
+
+    // カーソルの位置と名前のついた印を、次に開いたときのために書いておく
+    fn remember_place(&self) {
+        let (root, path) = match (self.bookmark_root(), self.path.as_ref()) {
+            (Some(root), Some(path)) => (root, path),
+            _ => return,
+        };
+        let place = Place { cursor: Some(self.cursor), marks: self.marks.clone() };
+        if let Err(e) = places::save(&root, path, &place) {
+            log_warn!("cannot save the place in {}: {}", path.display(), e);
+        }
+    }
+
+    // 終わるときに、開いているすべてのバッファの位置を書いておく
+    fn remember_all_places(&mut self) {
+        self.remember_place();
+        for index in 0..self.buffers.len() {
+            if index == self.current {
+                continue;
+            }
+            let mut file = std::mem::take(&mut self.buffers[index]);
+            self.swap_buffer(&mut file);
+            self.remember_place();
+            self.swap_buffer(&mut file);
+            self.buffers[index] = file;
+        }
+    }
+
+    // 今の位置を移動の履歴の最後に加える。戻っている途中なら、そこより新しい履歴は残したまま最後に加える
+    // 同じ行の古い履歴は消すので、行き来しても履歴は増えない
+    fn push_jump(&mut self) -> bool {
+        let path = self.path.as_ref().and_then(|path| path.canonicalize().ok());
+        let path = match path {
+            Some(path) if self.jumps_root.is_some() => path,
+            _ => return false,
+        };
+        let row = self.cursor.row;
+        let jump = Jump { path, pos: self.cursor };
+        // データベースには加えた1件だけを書く
+        if let Some(Err(e)) = self.jump_store.as_mut().map(|store| store.push_jump(&jump)) {
+            log_warn!("cannot save the jump list: {}", e);
+        }
+        self.jumps.retain(|old| old.path != jump.path || old.pos.row != row);
+        self.jumps.push(jump);
+        let excess = self.jumps.len().saturating_sub(places::JUMPS_LIMIT);
+        self.jumps.drain(..excess);
+        self.jump_index = self.jumps.len();
+        true
+    }
+
+    // 移動の履歴を count 個戻る (back) か進む。ほかのファイルの履歴ならそのファイルを開く
+    fn jump(&mut self, back: bool, count: usize) {
+        // 最新の位置から戻るときは、戻ってこられるように今の位置を加えておく
+        if back && self.jump_index == self.jumps.len() && self.push_jump() {
+            self.jump_index -= 1;
+        }
+        let target = if back { self.jump_index.checked_sub(count) } else { Some(self.jump_index + count) };
+        let jump = match target.and_then(|index| Some((index, self.jumps.get(index)?.clone()))) {
+            Some(jump) => jump,
+            None => {
+                self.message = if back { tr!("jump-oldest") } else { tr!("jump-newest") };
+                return;
+            },
+        };
+        let (index, jump) = jump;
+        let current = self.path.as_ref().and_then(|path| path.canonicalize().ok());
+        if current.as_ref() != Some(&jump.path) {
+            // ファイルを移ると今の位置が履歴に加わるので、履歴の中を動いている間は元に戻す
+            // ほかのプロジェクトのファイルなら、そのプロジェクトの履歴に替わる
+            let (root, jumps) = (self.jumps_root.clone(), self.jumps.clone());
+            self.open_buffer(&jump.path);
+            if self.jumps_root != root {
+                return;
+            }
+            if self.jumps != jumps {
+                self.jumps = jumps;
+                if let Some(Err(e)) = self.jump_store.as_mut().map(|store| store.replace_jumps(&self.jumps)) {
+                    log_warn!("cannot save the jump list: {}", e);
+                }
+            }
+            self.jump_index = index;
+            if self.path.as_ref().and_then(|path| path.canonicalize().ok()) != Some(jump.path.clone()) {
+                return;
+            }
+        }
+        self.clear_selection();
+        self.restore_cursor(jump.pos, self.row_offset);
+        self.jump_index = index;
+    }
+
+    // vim の m。カーソルの位置に a から z の名前の印をつける。同じ名前の印は付け替える
+    fn set_mark(&mut self, name: char) {
+        if !name.is_ascii_lowercase() {
+            self.message = tr!("invalid-mark", name);
+            return;
+        }
+        self.marks.retain(|&(other, _)| other != name);
+        self.marks.push((name, self.cursor));
+        self.marks.sort_by_key(|&(name, _)| name);
+        self.remember_place();
+        self.message = tr!("mark-set", name);
+    }
+
+    // vim の ' と `。印の行の最初の文字 (exact なら印の位置そのもの) に移る
+    fn goto_mark(&mut self, name: char, exact: bool) {
+        let pos = match self.marks.iter().find(|&&(other, _)| other == name) {
+            Some(&(_, pos)) => pos,
+            None => {
+                self.message = tr!("no-mark", name);
+                return;
+            },
+        };
+        self.push_jump();
+        if !self.mark {
+            self.clear_selection();
+        }
+        self.restore_cursor(pos, self.row_offset);
+        if !exact {
+            self.goto_row(self.cursor.row);
+        }
+    }
+
+    // 名前のついた印を位置の一覧にする
+    fn list_marks(&mut self) {
+        if self.marks.is_empty() {
+            self.message = tr!("no-marks");
+            return;
+        }
+        let items = self
+            .marks
+            .iter()
+            .map(|&(name, pos)| {
+                let text = self.buffer.get(pos.row).map(|line| line.iter().collect::<String>()).unwrap_or_default();
+                Location { row: pos.row, column: pos.column, text: format!("{}  {}", name, text.trim()) }
+            })
+            .collect();
+        self.message = tr!("listed-marks", self.marks.len());
+        self.locations = LocationList::new(items);
+    }
+
+    // カーソルのある行に印がなければメモを聞いてつけ、あれば外す
+    fn toggle_bookmark<T: Write, I: Iterator<Item = io::Result<Event>>>(
+        &mut self,
+        out: &mut T,
+        events: &mut I,
+    ) -> io::Result<()> {
+        let row = self.cursor.row;
+        match self.bookmark_index(row) {
+            Some(index) => {
+                self.bookmarks.remove(index);
+                self.message = tr!("bookmark-removed", row + 1);
+            },
+            None => {
+                let minibuffer = Minibuffer::new(&tr!("bookmark-note")).history("note");
+                let note = match self.prompt(out, events, minibuffer)? {
+                    Some(note) => note.trim().to_string(),
+                    None => return Ok(()),
+                };
+                let index = self.bookmarks.partition_point(|bookmark| bookmark.row < row);
+                self.bookmarks.insert(index, Bookmark { row, note });
+                self.message = tr!("bookmark-added", row + 1);
+            },
+        }
+        self.save_bookmarks();
+        Ok(())
+    }
+
+    // カーソルのある行の印のメモを書き直す
+    fn edit_bookmark<T: Write, I: Iterator<Item = io::Result<Event>>>(
+        &mut self,
+        out: &mut T,
+        events: &mut I,
+    ) -> io::Result<()> {
+        let index = match self.bookmark_index(self.cursor.row) {
+            Some(index) => index,
+            None => {
+                self.message = tr!("not-bookmarked");
+                return Ok(());
+            },
+        };
+        let mut minibuffer = Minibuffer::new(&tr!("bookmark-note")).history("note");
+        minibuffer.input = self.bookmarks[index].note.clone();
+        if let Some(note) = self.prompt(out, events, minibuffer)? {
+            self.bookmarks[index].note = note.trim().to_string();
+            self.save_bookmarks();
+        }
+        Ok(())
+    }
+
+    // 印をつけた行を位置の一覧にする
+    fn list_bookmarks(&mut self) {
+        if self.bookmarks.is_empty() {
+            self.message = tr!("no-bookmarks");
+            return;
+        }
+        let items = self
+            .bookmarks
+            .iter()
+            .map(|bookmark| Location { row: bookmark.row, column: 0, text: bookmark.note.clone() })
+            .collect();
+        self.message = tr!("listed-bookmarks", self.bookmarks.len());
+        self.locations = LocationList::new(items);
+    }
+
+    // 特殊な文字の位置をすべて位置の一覧にする
+    fn list_special(&mut self) {
+        let mut items = Vec::new();
+        for (row, line) in self.buffer.iter().enumerate() {
+            for (column, &c) in line.iter().enumerate() {
+                if special::is_special(c) {
+                    items.push(Location { row, column, text: special::escape(c) });
+                }
+            }
+        }
+        if items.is_empty() {
+            self.message = tr!("no-special");
+            return;
+        }
+        self.message = tr!("listed-special", items.len());
+        self.locations = LocationList::new(items);
+    }
+
+    // 位置の一覧の次 (forward) か前の位置へ移る
+    fn step_location(&mut self, forward: bool) {
+        let location = if forward { self.locations.next() } else { self.locations.prev() };
+        match location.map(|l| (Cursor { row: l.row, column: l.column }, l.text.clone())) {
+            Some((pos, text)) => self.jump_location(pos, &text),
+            None => self.message = tr!("no-locations"),
+        }
+    }
+
+    // 一覧を作った後に編集していても、行や列が残っている範囲に移る
+    fn jump_location(&mut self, pos: Cursor, text: &str) {
+        self.push_jump();
+        self.cursor.row = min(pos.row, self.buffer.len() - 1);
+        self.cursor.column = min(pos.column, self.buffer[self.cursor.row].len());
+        self.scroll();
+        let index = self.locations.current().unwrap_or(0);
+        self.message = tr!("location", index + 1, self.locations.len(), text);
+    }
+
+    // 位置の一覧を下のペインに出して選ばせる
+    fn location_list<T: Write, I: Iterator<Item = io::Result<Event>>>(
+        &mut self,
+        out: &mut T,
+        events: &mut I,
+    ) -> io::Result<()> {
+        if self.locations.is_empty() {
+            self.message = tr!("no-locations");
+            return Ok(());
+        }
+        let picker = Picker::new(self.locations.lines(), &tr!("locations-help"))
+            .selected(self.locations.current().unwrap_or(0));
+        if let Some(selected) = self.pick_from_pane(out, events, picker)? {
+            if let Some((pos, text)) = self
+                .locations
+                .select(selected)
+                .map(|l| (Cursor { row: l.row, column: l.column }, l.text.clone()))
+            {
+                self.jump_location(pos, &text);
+            }
+        }
+        Ok(())
+    }
+
+    // プロジェクトの中の TODO、FIXME、XXX をファイルごとに下のペインに出して選ばせる
+    fn todo_list<T: Write, I: Iterator<Item = io::Result<Event>>>(
+        &mut self,
+        out: &mut T,
+        events: &mut I,
+    ) -> io::Result<()> {
+        let root = match self.project_root.clone() {
+            Some(root) => root,
+            None => std::env::current_dir()?,
+        };
+        // 一覧から選んでいる間も Esc を中断の合図に取られないように、探し終えたら進み具合を捨てる
+        let scanned = {
+            let mut progress = Progress::start(&tr!("scanning-todo", root.display()), &self.cancel);
+            todo::scan(&root, &mut progress)
+        };
+        let todos = match scanned {
+            Some(todos) => todos,
+            None => {
+                self.message = tr!("scan-cancelled");
+                return Ok(());
+            },
+        };
+        if todos.is_empty() {
+            self.message = tr!("no-todo");
+            return Ok(());
+        }
+
+        // ファイル名の見出しの下に、そのファイルの印を並べる。rows[k] は k 番目の印のペインでの行
+        let mut lines = Vec::new();
+        let mut rows = Vec::new();
+        for (k, todo) in todos.iter().enumerate() {
+            if k == 0 || todos[k - 1].path != todo.path {
+                let count = todos[k..].iter().take_while(|t| t.path == todo.path).count();
+                lines.push(format!("{} ({})", project::display_path(Some(&root), &todo.path), count));
+            }
+            rows.push(lines.len());
+            lines.push(format!("  {}:{}: {}", todo.row + 1, todo.column + 1, todo.text));
+        }
+        let picker = Picker::new(lines, &tr!("todo-help", todos.len())).rows(rows);
+        if let Some(selected) = self.pick_from_pane(out, events, picker)? {
+            let todo = &todos[selected];
+            let current = self.path.as_ref().and_then(|path| path.canonicalize().ok());
+            if current.as_ref() != Some(&todo.path) && !(self.autowrite() && self.open(&todo.path)) {
+                return Ok(());
+            }
+            self.cursor.row = min(todo.row, self.buffer.len() - 1);
+            self.cursor.column = min(todo.column, self.buffer[self.cursor.row].len());
+            self.scroll();
+            self.message = todo.text.clone();
+        }
+        Ok(())
+    }
+
+    // 読み直しても外れない読み取り専用にする
+    fn force_read_only(&mut self) {
+        self.forced_read_only = true;
+        self.read_only = true;
+    }
+
+    // 読み取り専用なら編集を拒否してメッセージを出す
+    fn check_writable(&mut self) -> bool {
+        if self.read_only {
+            self.message = tr!("read-only");
+        }
+        !self.read_only
+    }
+
+    // カーソルより後ろにある次の特殊な文字へ移動する
+    fn next_special(&mut self) {
+        let start = self.cursor;
+        let positions = (start.row..self.buffer.len())
+            .chain(0..=start.row)
+            .enumerate()
+            .flat_map(|(k, row)| {
+                let line = &self.buffer[row];
+                // 1周目の最初の行はカーソルより後ろ、最後に戻ってきた行はカーソルまで
+                let range = if k == 0 {
+                    min(start.column + 1, line.len())..line.len()
+                } else if row == start.row {
+                    0..min(start.column + 1, line.len())
+                } else {
+                    0..line.len()
+                };
+                range.map(move |column| Cursor { row, column })
+            });
+        let mut found = None;
+        for pos in positions {
+            if special::is_special(self.buffer[pos.row][pos.column]) {
+                found = Some(pos);
+                break;
+            }
+        }
+        match found {
+            Some(pos) => {
+                self.cursor = pos;
+                let c = self.buffer[pos.row][pos.column];
+                self.message = format!("U+{:04X} {}", c as u32, special::escape(c));
+                self.scroll();
+            },
+            None => self.message = tr!("no-special"),
+        }
+    }
+
+    // s/old/new/[g] で、すべての行の old を new に置き換える (正規表現ではなく文字列のまま)
+    // g がなければ各行の最初の1つだけ。書式が正しくなければ false
+    fn substitute(&mut self, command: &str) -> bool {
+        let (from, to, global) = match parse_substitute(command) {
+            Some(parsed) => parsed,
+            None => {
+                self.message = tr!("invalid-substitute");
+                return false;
+            },
+        };
+        if !self.check_writable() {
+            return false;
+        }
+        let mut count = 0;
+        for row in 0..self.buffer.len() {
+            let line: String = self.buffer[row].iter().collect();
+            let n = line.matches(from.as_str()).count();
+            if n == 0 {
+                continue;
+            }
+            let replaced = if global { line.replace(&from, &to) } else { line.replacen(&from, &to, 1) };
+            count += if global { n } else { 1 };
+            self.replace_line(row, replaced.chars().collect());
+        }
+        self.cursor.column = min(self.cursor.column, self.buffer[self.cursor.row].len());
+        self.scroll();
+        self.message = tr!("substituted", count);
+        true
+    }
+
+    // 一致するところを先頭から1つずつ選んで、置き換えるかを聞く
+    // 正規表現なら、置き換える文字列の $1 や ${name} は捕まえた部分になる
+    fn query_replace<T: Write, I: Iterator<Item = io::Result<Event>>>(
+        &mut self,
+        out: &mut T,
+        events: &mut I,
+        regex: bool,
+    ) -> io::Result<()> {
+        if !self.check_writable() {
+            return Ok(());
+        }
+        let label = if regex { tr!("replace-regex-pattern") } else { tr!("replace-pattern") };
+        let minibuffer = Minibuffer::new(&label).history("search").validate(minibuffer::not_empty);
+        let text = match self.prompt(out, events, minibuffer)? {
+            Some(text) => text,
+            None => return Ok(()),
+        };
+        let pattern = match search::Pattern::new(&text, regex) {
+            Ok(pattern) => pattern,
+            Err(reason) => {
+                self.message = reason;
+                return Ok(());
+            },
+        };
+        let minibuffer = Minibuffer::new(&tr!("replace-with", text)).history("replace");
+        let template = match self.prompt(out, events, minibuffer)? {
+            Some(template) => template,
+            None => return Ok(()),
+        };
+
+        let origin = self.cursor;
+        let mut from = Cursor { row: 0, column: 0 };
+        let mut all = false;
+        let mut count = 0;
+        // 1回で取り消せるように、置き換えをまとめる
+        self.history.seal(self.cursor);
+        // 先頭に戻ったら終わり
+        while let Some((pos, len)) = search::find(&self.buffer, &pattern, from).filter(|&(pos, _)| pos >= from) {
+            self.cursor = pos;
+            self.scroll();
+            let answer = if all {
+                Some('a')
+            } else {
+                self.anchor = Some(Cursor { row: pos.row, column: pos.column + len });
+                let dialog = Dialog::new(&tr!("replace-question", count))
+                    .button('y', &tr!("button-yes"))
+                    .button('n', &tr!("button-no"))
+                    .button('a', &tr!("button-all"))
+                    .button('q', &tr!("button-quit"))
+                    .default_button('y');
+                let answer = self.ask(out, events, dialog)?;
+                self.clear_selection();
+                answer
+            };
+            match answer {
+                Some('y') | Some('a') => {
+                    let mut line = self.buffer[pos.row].to_vec();
+                    let replacement: Vec<char> = pattern.expand(&line, pos.column, &template).chars().collect();
+                    line.splice(pos.column..pos.column + len, replacement.iter().copied());
+                    self.replace_line(pos.row, line);
+                    count += 1;
+                    all = answer == Some('a');
+                    // 置き換えた文字列の中はもう探さない
+                    from = Cursor { row: pos.row, column: pos.column + replacement.len() };
+                },
+                Some('n') => from = Cursor { row: pos.row, column: pos.column + len },
+                _ => break,
+            }
+        }
+        self.history.seal(self.cursor);
+        if count == 0 {
+            self.cursor = origin;
+            self.scroll();
+        }
+        self.message = tr!("substituted", count);
+        Ok(())
+    }
+
+    // 特殊な文字を全て取り除く
+    fn strip_special(&mut self) {
+        if !self.check_writable() {
+            return;
+        }
+        let mut count = 0;
+        for row in 0..self.buffer.len() {
+            let mut column = 0;
+            while column < self.buffer[row].len() {
+                if special::is_special(self.buffer[row][column]) {
+                    self.remove_char_at(Cursor { row, column });
+                    if self.cursor.row == row && self.cursor.column > column {
+                        self.cursor.column -= 1;
+                    }
+                    count += 1;
+                } else {
+                    column += 1;
+                }
+            }
+        }
+        self.scroll();
+        self.message = tr!("removed-special", count);
+    }
+
+    // 急がない仕事を少しだけ進める
+    fn idle_step(&mut self, task: idle::Task) -> idle::Step {
+        match task {
+            idle::Task::WarmLayouts { row } => {
+                let cols = self.text_cols();
+                // 覚えておける行数より先は、表示するときに計算する
+                let last = min(self.buffer.len(), layout::CACHE_LIMIT);
+                let end = min(row + IDLE_STEP_LINES, last);
+                for i in row..end {
+                    if !layout::is_long(&self.buffer[i]) {
+                        self.layouts.get(i, &self.buffer[i], cols, self.escapes);
+                    }
+                }
+                if end < last {
+                    idle::Step::Continue(idle::Task::WarmLayouts { row: end })
+                } else {
+                    log_debug!("warmed layouts of {} lines", end);
+                    idle::Step::Done
+                }
+            },
+            idle::Task::CheckDisk => {
+                if self.check_disk() {
+                    idle::Step::Redraw
+                } else {
+                    idle::Step::Done
+                }
+            },
+            idle::Task::GitBranch => {
+                self.refresh_git_branch();
+                idle::Step::Done
+            },
+        }
+    }
+
+    // バッファを書き換えるときは必ず以下の関数を通して、表示のキャッシュを捨て、編集したことと取り消し方を記録する
+
+    // ファイルなどから読み込んだ内容に置き換える。取り消しの履歴も捨てる
+    fn set_buffer(&mut self, buffer: Vec<Line>) {
+        self.buffer = buffer;
+        self.extra_selections.clear();
+        self.modified = false;
+        self.history.clear();
+        self.layouts.clear();
+        self.line_starts.clear();
+        self.highlighter.invalidate(0);
+        self.idle.schedule(idle::Task::WarmLayouts { row: 0 });
+    }
+
+    // 取り消し方と、変更した行を記録する
+    fn record(&mut self, edit: Edit) {
+        let row = match &edit {
+            Edit::Insert { pos, .. } | Edit::Remove { pos, .. } | Edit::Split { pos } => pos.row,
+            Edit::Join { row, .. } | Edit::Replace { row, .. } => *row,
+        };
+        self.stats.changed(self.path.as_deref(), row);
+        for (anchor, head) in &mut self.extra_selections {
+            *anchor = edit.shift(*anchor);
+            *head = edit.shift(*head);
+        }
+        if let Some(journal) = self.journal.as_mut() {
+            journal.edit(&edit);
+        }
+        self.history.record(edit, self.cursor);
+    }
+
+    fn insert_char_at(&mut self, pos: Cursor, c: char) {
+        self.record(Edit::Insert { pos, c });
+        self.buffer[pos.row].insert(pos.column, c);
+        self.layouts.invalidate(pos.row);
+        self.line_starts.invalidate(pos.row);
+        self.highlighter.invalidate(pos.row);
+        self.modified = true;
+    }
+
+    fn replace_line(&mut self, row: usize, line: Vec<char>) {
+        let old = std::mem::replace(&mut self.buffer[row], Line::from(line.clone())).to_vec();
+        self.record(Edit::Replace { row, old, new: line });
+        self.layouts.invalidate(row);
+        self.line_starts.invalidate(row);
+        self.highlighter.invalidate(row);
+        self.modified = true;
+    }
+
+    fn remove_char_at(&mut self, pos: Cursor) -> char {
+        self.layouts.invalidate(pos.row);
+        self.line_starts.invalidate(pos.row);
+        self.highlighter.invalidate(pos.row);
+        self.modified = true;
+        let c = self.buffer[pos.row].remove(pos.column);
+        self.record(Edit::Remove { pos, c });
+        c
+    }
+
+    // pos で行を2つに分ける
+    fn split_line(&mut self, pos: Cursor) {
+        self.record(Edit::Split { pos });
+        // 印は行の内容についていく。行頭で分けたら、印のある行は下に動く
+        for bookmark in self.bookmarks.iter_mut() {
+            if bookmark.row > pos.row || (bookmark.row == pos.row && pos.column == 0) {
+                bookmark.row += 1;
+            }
+        }
+        for (_, mark) in self.marks.iter_mut() {
+            if mark.row > pos.row || (mark.row == pos.row && mark.column >= pos.column) {
+                *mark = if mark.row == pos.row {
+                    Cursor { row: mark.row + 1, column: mark.column - pos.column }
+                } else {
+                    Cursor { row: mark.row + 1, column: mark.column }
+                };
+            }
+        }
+        let rest = self.buffer[pos.row].split_off(pos.column);
+        self.buffer.insert(pos.row + 1, rest);
+        self.layouts.invalidate(pos.row);
+        self.line_starts.invalidate(pos.row);
+        self.highlighter.invalidate(pos.row);
+        self.layouts.insert_line(pos.row + 1);
+        self.modified = true;
+    }
+
+    // row 行目に次の行をつなげる
+    fn join_line(&mut self, row: usize) {
+        self.record(Edit::Join { row, column: self.buffer[row].len() });
+        for bookmark in self.bookmarks.iter_mut() {
+            if bookmark.row > row {
+                bookmark.row -= 1;
+            }
+        }
+        // つなげた2行の両方に印があれば、上の行の印を残す
+        self.bookmarks.dedup_by_key(|bookmark| bookmark.row);
+        let column = self.buffer[row].len();
+        for (_, mark) in self.marks.iter_mut() {
+            if mark.row == row + 1 {
+                *mark = Cursor { row, column: column + mark.column };
+            } else if mark.row > row {
+                mark.row -= 1;
+            }
+        }
+        let line = self.buffer.remove(row + 1);
+        self.buffer[row].append(line);
+        self.layouts.remove_line(row + 1);
+        self.layouts.invalidate(row);
+        self.line_starts.invalidate(row);
+        self.highlighter.invalidate(row);
+        self.modified = true;
+    }
+
+    fn insert(&mut self, c: char) {
+        if !self.check_writable() {
+            return;
+        }
+        if c == '\n' {
+            self.split_line(self.cursor);
+            self.cursor.row += 1;
+            self.cursor.column = 0;
+            self.scroll();
+        } else if !c.is_control() || c == '\t' {
+            self.insert_char_at(self.cursor, c);
+            self.cursor_right();
+        }
+    }
+
+    // Tab キー。空白にするときは次のタブ位置まで入れる
+    fn insert_tab(&mut self) {
+        if !self.expand_tab {
+            return self.insert('\t');
+        }
+        let width = special::tab_width();
+        let line = &self.buffer[self.cursor.row];
+        let column: usize = line.iter().take(self.cursor.column).map(|&c| special::char_width(c)).sum();
+        for _ in 0..width - column % width {
+            self.insert(' ');
+        }
+    }
+
+    // 選択している範囲 (始め, 終わり)。何も選んでいなければ None
+    // 選んだ後に編集して行や列がなくなっていたら、残っている範囲に詰める
+    fn selection(&self) -> Option<(Cursor, Cursor)> {
+        let anchor = self.anchor?;
+        let row = min(anchor.row, self.buffer.len() - 1);
+        let anchor = Cursor { row, column: min(anchor.column, self.buffer[row].len()) };
+        match anchor.cmp(&self.cursor) {
+            Ordering::Less => Some((anchor, self.cursor)),
+            Ordering::Greater => Some((self.cursor, anchor)),
+            Ordering::Equal => None,
+        }
+    }
+
+    // Shift を押して動く前に呼ぶ。まだ選んでいなければ今の位置から選び始める
+    fn extend_selection(&mut self) {
+        if self.anchor.is_none() {
+            self.anchor = Some(self.cursor);
+        }
+    }
+
+    // 選択を始めるキー。もう選んでいればやめる
+    fn toggle_mark(&mut self) {
+        if self.mark {
+            self.clear_selection();
+        } else {
+            self.anchor = Some(self.cursor);
+            self.mark = true;
+        }
+    }
+
+    // Esc で、やりかけのものを1つだけやめる。優先する順に
+    //   1. 選択 (Shift+矢印や Ctrl+Space で選んでいる範囲)
+    //   2. 下のペイン
+    // 入力欄、検索、質問、一覧の中の Esc はそれぞれのループで、時間のかかる処理の Esc は入力のスレッドで扱う
+    fn cancel(&mut self) {
+        if self.anchor.is_some() || self.mark {
+            self.clear_selection();
+        } else if !self.pane.is_empty() {
+            self.pane.clear();
+            self.pane_cursor = None;
+            self.diff_base = None;
+        } else {
+            self.message = tr!("nothing-to-cancel");
+        }
+    }
+
+    fn clear_selection(&mut self) {
+        self.anchor = None;
+        self.mark = false;
+    }
+
+    // 今の選択とほかの選択を、前から順に今の選択にして f をする。ほかの選択は f の編集に合わせてずらす
+    fn each_selection(&mut self, mut f: impl FnMut(&mut Self)) {
+        let primary = (self.anchor.unwrap_or(self.cursor), self.cursor);
+        let mut selections = std::mem::take(&mut self.extra_selections);
+        selections.push(primary);
+        selections.sort_by_key(|&(anchor, head)| min(anchor, head));
+        let index = selections.iter().position(|&selection| selection == primary).unwrap_or(0);
+        self.extra_selections = selections;
+        for k in 0..self.extra_selections.len() {
+            let (anchor, head) = self.extra_selections[k];
+            self.anchor = Some(anchor).filter(|&anchor| anchor != head);
+            self.cursor = head;
+            f(self);
+            self.extra_selections[k] = (self.anchor.unwrap_or(self.cursor), self.cursor);
+        }
+        let (anchor, head) = self.extra_selections.remove(index);
+        self.anchor = Some(anchor).filter(|&anchor| anchor != head);
+        self.cursor = head;
+        // 消して重なった選択は1つにする
+        self.extra_selections.dedup();
+        self.extra_selections.retain(|&selection| selection != (anchor, head));
+        self.scroll();
+    }
+
+    // ほかの選択もあるときに、文字を打つ、消す、コピーする、切り取る
+    // コピーと切り取りは、選んだ文字列を前から順に改行でつないで1つにする
+    fn edit_selections<T: Write>(&mut self, out: &mut T, action: &KeyAction) -> io::Result<()> {
+        if matches!(action, KeyAction::Copy | KeyAction::CopyOrQuit | KeyAction::Cut) {
+            if *action == KeyAction::Cut && !self.check_writable() {
+                return Ok(());
+            }
+            let mut texts = Vec::new();
+            self.each_selection(|state| texts.extend(state.selected_text()));
+            if texts.is_empty() {
+                self.message = tr!("nothing-selected");
+                return Ok(());
+            }
+            let text = texts.join("\n");
+            self.clipboard.copy(out, &text)?;
+            self.message = tr!("copied", text.chars().count());
+            self.kill_ring.push(text);
+            if *action == KeyAction::Cut {
+                self.each_selection(|state| {
+                    state.delete_selection();
+                });
+            }
+            return Ok(());
+        }
+        self.each_selection(|state| match action {
+            KeyAction::Insert(c) => {
+                state.delete_selection();
+                state.insert(*c);
+            },
+            KeyAction::Tab => {
+                state.delete_selection();
+                state.insert_tab();
+            },
+            KeyAction::Backspace => state.back_space(),
+            KeyAction::Delete => state.delete(),
+            _ => {},
+        });
+        Ok(())
+    }
+
+    // 選択している範囲の中で pattern に一致するところを、それぞれ1つの選択にする (kakoune の s)
+    fn split_selection(&mut self, pattern: &str) {
+        let (start, end) = match self.selection() {
+            Some(range) => range,
+            None => {
+                self.message = tr!("nothing-selected");
+                return;
+            },
+        };
+        let pattern = match search::Pattern::new(pattern, true) {
+            Ok(pattern) => pattern,
+            Err(reason) => {
+                self.message = reason;
+                return;
+            },
+        };
+        let mut selections = search::find_all(&self.buffer, &pattern, start, end)
+            .into_iter()
+            .map(|(pos, len)| (pos, Cursor { row: pos.row, column: pos.column + len }));
+        let (anchor, head) = match selections.next() {
+            Some(first) => first,
+            None => {
+                self.message = tr!("not-found");
+                return;
+            },
+        };
+        self.extra_selections = selections.collect();
+        self.anchor = Some(anchor);
+        self.mark = false;
+        self.cursor = head;
+        self.scroll();
+        self.message = tr!("selections", self.extra_selections.len() + 1);
+    }
+
+    // ほかの選択に入っているか、空のほかの選択のカーソルがあるところか
+    fn in_extra_selection(&self, pos: Cursor) -> bool {
+        self.extra_selections.iter().any(|&(anchor, head)| {
+            let (start, end) = (min(anchor, head), max(anchor, head));
+            (start..end).contains(&pos) || (start == end && head == pos)
+        })
+    }
+
+    // 選択している範囲の文字列
+    fn selected_text(&self) -> Option<String> {
+        let (start, end) = self.selection()?;
+        let mut text = String::new();
+        for row in start.row..=end.row {
+            let from = if row == start.row { start.column } else { 0 };
+            let to = if row == end.row { end.column } else { self.buffer[row].len() };
+            text.extend(self.buffer[row].iter().skip(from).take(to - from));
+            if row != end.row {
+                text.push('\n');
+            }
+        }
+        Some(text)
+    }
+
+    // 選択している範囲をクリップボードにコピーする。選んでいなければ false
+    fn copy<T: Write>(&mut self, out: &mut T) -> io::Result<bool> {
+        let text = match self.selected_text() {
+            Some(text) => text,
+            None => return Ok(false),
+        };
+        self.clipboard.copy(out, &text)?;
+        self.message = tr!("copied", text.chars().count());
+        self.kill_ring.push(text);
+        Ok(true)
+    }
+
+    fn cut<T: Write>(&mut self, out: &mut T) -> io::Result<()> {
+        if !self.check_writable() {
+            return Ok(());
+        }
+        if !self.copy(out)? {
+            self.message = tr!("nothing-selected");
+            return Ok(());
+        }
+        self.delete_selection();
+        self.history.seal(self.cursor);
+        Ok(())
+    }
+
+    // カーソルから行末までを消して覚える。行末にいれば次の行をつなげる
+    fn kill_line(&mut self) {
+        if !self.check_writable() {
+            return;
+        }
+        let Cursor { row, column } = self.cursor;
+        if column < self.buffer[row].len() {
+            let mut line = self.buffer[row].to_vec();
+            let killed: String = line.drain(column..).collect();
+            self.replace_line(row, line);
+            self.kill_ring.kill(killed);
+        } else if row + 1 < self.buffer.len() {
+            self.join_line(row);
+            self.kill_ring.kill("\n".to_string());
+        }
+    }
+
+    // カーソルのある行を改行ごと消して覚える
+    fn kill_whole_line(&mut self) {
+        if !self.check_writable() {
+            return;
+        }
+        let row = self.cursor.row;
+        let mut killed: String = self.buffer[row].iter().collect();
+        killed.push('\n');
+        self.replace_line(row, Vec::new());
+        if row + 1 < self.buffer.len() {
+            self.join_line(row);
+        }
+        self.cursor.column = 0;
+        self.kill_ring.kill(killed);
+    }
+
+    // 最後に消したものをカーソルの位置に入れる
+    fn yank(&mut self) {
+        if !self.check_writable() {
+            return;
+        }
+        let text = match self.kill_ring.yank() {
+            Some(text) => text.to_string(),
+            None => {
+                self.message = tr!("kill-ring-empty");
+                return;
+            },
+        };
+        self.delete_selection();
+        self.insert_text(&text);
+    }
+
+    // 覚えている消したものやコピーしたものを新しい順に下のペインに出して選ばせ、カーソルの位置に貼り付ける
+    fn kill_ring_list<T: Write, I: Iterator<Item = io::Result<Event>>>(
+        &mut self,
+        out: &mut T,
+        events: &mut I,
+    ) -> io::Result<()> {
+        if !self.check_writable() {
+            return Ok(());
+        }
+        let lines: Vec<String> = self.kill_ring.entries().map(killring::escape).collect();
+        if lines.is_empty() {
+            self.message = tr!("kill-ring-empty");
+            return Ok(());
+        }
+        if let Some(selected) = self.pick_from_pane(out, events, Picker::new(lines, &tr!("kills-help")))? {
+            if let Some(text) = self.kill_ring.select(selected).map(str::to_string) {
+                self.delete_selection();
+                self.insert_text(&text);
+                self.history.seal(self.cursor);
+            }
+        }
+        Ok(())
+    }
+
+    // 選択している範囲を消す。選んでいなければ false
+    fn delete_selection(&mut self) -> bool {
+        let (start, end) = match self.selection() {
+            Some(range) => range,
+            None => return false,
+        };
+        self.clear_selection();
+        if !self.check_writable() {
+            return true;
+        }
+        self.cursor = end;
+        while self.cursor > start {
+            self.back_space();
+        }
+        true
+    }
+
+    // 選んでいれば、その範囲を消す
+    fn back_space(&mut self) {
+        if self.delete_selection() || !self.check_writable() {
+            return;
+        }
+        if self.cursor == (Cursor { row: 0, column: 0 }) {
+            return;
+        }
+
+        if self.cursor.column == 0 {
+            self.cursor.row -= 1;
+            self.cursor.column = self.buffer[self.cursor.row].len();
+            self.join_line(self.cursor.row);
+            self.scroll();
+        } else {
+            self.cursor_left();
+            self.remove_char_at(self.cursor);
+        }
+    }
+
+    fn apply(&mut self, edit: &Edit) {
+        match edit.clone() {
+            Edit::Insert { pos, c } => self.insert_char_at(pos, c),
+            Edit::Remove { pos, .. } => {
+                self.remove_char_at(pos);
+            },
+            Edit::Split { pos } => self.split_line(pos),
+            Edit::Join { row, .. } => self.join_line(row),
+            Edit::Replace { row, new, .. } => self.replace_line(row, new),
+        }
+    }
+
+    // 最後の変更のまとまりを取り消す
+    fn undo(&mut self) {
+        if !self.check_writable() {
+            return;
+        }
+        let group = match self.history.start_undo(self.cursor) {
+            Some(group) => group,
+            None => {
+                self.message = tr!("oldest-change");
+                return;
+            },
+        };
+        for edit in group.edits.iter().rev() {
+            self.apply(&edit.inverse());
+        }
+        self.cursor = group.before;
+        self.history.finish_undo(group);
+        self.scroll();
+    }
+
+    // 取り消した変更をやり直す
+    fn redo(&mut self) {
+        if !self.check_writable() {
+            return;
+        }
+        let group = match self.history.start_redo(self.cursor) {
+            Some(group) => group,
+            None => {
+                self.message = tr!("newest-change");
+                return;
+            },
+        };
+        for edit in &group.edits {
+            self.apply(edit);
+        }
+        self.cursor = group.after;
+        self.history.finish_redo(group);
+        self.scroll();
+    }
+
+    // 取り消しの履歴を下のペインに出す。選んだ状態のバッファを読み取り専用で先に見せ、Enter でそこまで取り消すかやり直す
+    fn undo_list<T: Write, I: Iterator<Item = io::Result<Event>>>(
+        &mut self,
+        out: &mut T,
+        events: &mut I,
+    ) -> io::Result<()> {
+        if !self.check_writable() {
+            return Ok(());
+        }
+        self.history.seal(self.cursor);
+        let (undo, redo) = self.history.groups();
+        if undo.is_empty() && redo.is_empty() {
+            self.message = tr!("no-undo-history");
+            return Ok(());
+        }
+        // 一番古い状態から順に、その状態にした変更のまとまり。今の状態は current 番目
+        let current = undo.len();
+        let groups: Vec<history::Group> = undo.iter().chain(redo.iter().rev()).cloned().collect();
+        let mark = |k: usize| if k == current { "*" } else { " " };
+        let mut lines = vec![tr!("undo-original", mark(0))];
+        lines.extend(groups.iter().enumerate().map(|(k, group)| {
+            tr!("undo-entry", mark(k + 1), k + 1, group.edits.len(), group.after.row + 1)
+        }));
+
+        let saved_cursor = self.cursor;
+        let saved_offset = self.row_offset;
+        let saved_read_only = self.read_only;
+        let picker = Picker::new(lines, &tr!("undo-list-help")).selected(current);
+        let picked = self.pick_from_pane_with(out, events, picker, |state, out, selected| {
+            // 選んだ状態の内容を、本当のバッファを触らずに作って差し替える
+            let mut preview = state.buffer.clone();
+            let mut cursor = saved_cursor;
+            for group in groups.iter().take(current).skip(selected).rev() {
+                for edit in group.edits.iter().rev() {
+                    history::apply(&mut preview, &edit.inverse());
+                }
+                cursor = group.before;
+            }
+            for group in groups.iter().take(selected).skip(current) {
+                for edit in &group.edits {
+                    history::apply(&mut preview, edit);
+                }
+                cursor = group.after;
+            }
+            let real = std::mem::replace(&mut state.buffer, preview);
+            state.layouts.clear();
+            state.line_starts.clear();
+            state.highlighter.invalidate(0);
+            state.read_only = true;
+            state.cursor = cursor;
+            state.scroll();
+
+            let drawn = state.draw(out);
+            state.buffer = real;
+            state.layouts.clear();
+            state.line_starts.clear();
+            state.highlighter.invalidate(0);
+            drawn
+        });
+        self.read_only = saved_read_only;
+        self.cursor = saved_cursor;
+        self.row_offset = saved_offset;
+        self.scroll();
+
+        if let Some((selected, _)) = picked? {
+            for _ in selected..current {
+                self.undo();
+            }
+            for _ in current..selected {
+                self.redo();
+            }
+        }
+        Ok(())
+    }
+
+    fn delete(&mut self) {
+        if self.delete_selection() || !self.check_writable() {
+            return;
+        }
+        if self.cursor.row == self.buffer.len() - 1
+            && self.cursor.column == self.buffer[self.cursor.row].len()
+        {
+            return;
+        }
+
+        if self.cursor.column == self.buffer[self.cursor.row].len() {
+            self.join_line(self.cursor.row);
+        } else {
+            self.remove_char_at(self.cursor);
+        }
+    }
+
+    // ファイルに保存される内容
+    fn contents(&self) -> String {
+        let mut contents = String::new();
+        for line in &self.buffer {
+            // コミットメッセージのコメントは保存しない
+            if self.commit_mode && line.first() == Some(&'#') {
+                if is_scissors(line) {
+                    break;
+                }
+                continue;
+            }
+            contents.extend(line.iter());
+            contents.push_str(self.line_ending.as_str());
+        }
+        contents
+    }
+
+    // 保存できたら true
+    fn save(&mut self) -> bool {
+        if !self.check_writable() {
+            return false;
+        }
+        let path = match self.path.clone() {
+            Some(path) => path,
+            None => return false,
+        };
+        let bytes = self.encoding.encode(&self.contents());
+        match write_file(&path, &bytes, self.verify_save) {
+            Ok(()) => {
+                self.disk_mtime = mtime(&path);
+                self.modified = false;
+                log_info!("saved {} ({} bytes)", path.display(), bytes.len());
+                if let Some(journal) = self.journal.as_mut() {
+                    journal.saved(&path);
+                }
+                // 編集で動いた印の行を、ファイルの内容と合わせて残す
+                if !self.bookmarks.is_empty() {
+                    self.save_bookmarks();
+                }
+                self.message = tr!("written", self.display_path(), self.buffer.len(), bytes.len());
+            },
+            Err(e) => {
+                log_error!("cannot save {}: {}", path.display(), e);
+                self.message = tr!("save-failed", e);
+                return false;
+            },
+        }
+        if self.pending_commit {
+            self.finish_commit();
+        }
+        true
+    }
+
+    // 保存するとファイルがどう変わるかを下のペインに出す
+    fn diff_unsaved(&mut self) {
+        let path = match self.path.clone() {
+            Some(path) => path,
+            None => {
+                self.message = tr!("no-file-name");
+                return;
+            },
+        };
+        // まだないファイルは空のファイルとして比べる
+        let bytes = fs::read(&path).unwrap_or_default();
+        let disk = self
+            .encoding
+            .decode(&bytes)
+            .unwrap_or_else(|| String::from_utf8_lossy(&bytes).into_owned());
+        let label = path::PathBuf::from(self.display_path());
+        self.show_diff(disk, &label);
+    }
+
+    // old から今の内容への差分を下のペインに出す
+    fn show_diff(&mut self, old: String, label: &path::Path) {
+        match git::diff_text(&old, &self.contents(), label, self.diff_filter) {
+            Ok(diff) if diff.is_empty() => self.message = tr!("no-differences"),
+            Ok(diff) => {
+                self.pane = diff;
+                self.diff_base = Some((old, label.to_path_buf()));
+                self.scroll();
+            },
+            Err(e) => self.message = e.message().to_string(),
+        }
+    }
+
+    // 差分で空白 (whitespace) か大文字小文字の違いを無視するかを切り替え、出している差分を取り直す
+    fn toggle_diff_filter(&mut self, whitespace: bool) {
+        let filter = &mut self.diff_filter;
+        let (flag, on, off) = if whitespace {
+            (&mut filter.ignore_whitespace, "diff-whitespace-on", "diff-whitespace-off")
+        } else {
+            (&mut filter.ignore_case, "diff-case-on", "diff-case-off")
+        };
+        *flag = !*flag;
+        let message = if *flag { tr!(on) } else { tr!(off) };
+        if let Some((old, label)) = self.diff_base.take() {
+            // 違いがなくなったら前の差分を残さない
+            self.pane.clear();
+            self.show_diff(old, &label);
+        }
+        self.message = message;
+    }
+
+    // ほかのファイルに移ったり外部のツールがファイルを読んだりする前に、編集した内容を保存する
+    // 保存に失敗したら false を返すので、バッファを置き換える操作はやめる
+    fn autowrite(&mut self) -> bool {
+        if self.autowrite && self.modified && self.path.is_some() && !self.read_only {
+            log_info!("autowriting {}", self.display_path());
+            return self.save();
+        }
+        true
+    }
+
+    // 保存していない変更を捨てて読み直す。編集していれば確かめる
+    fn revert<T: Write, I: Iterator<Item = io::Result<Event>>>(
+        &mut self,
+        out: &mut T,
+        events: &mut I,
+    ) -> io::Result<()> {
+        if self.path.is_none() {
+            self.message = tr!("no-file-name");
+            return Ok(());
+        }
+        if self.modified && self.ask(out, events, Dialog::yes_no(&tr!("revert-question")))? != Some('y') {
+            return Ok(());
+        }
+        // 捨てる変更は、:trash で戻せるように控えを取っておく
+        let trashed = self.modified;
+        if trashed && !self.trash_buffer(trash::Kind::Revert) {
+            return Ok(());
+        }
+        self.reload();
+        self.message = if trashed {
+            tr!("reverted-trashed", self.display_path())
+        } else {
+            tr!("reverted", self.display_path())
+        };
+        Ok(())
+    }
+
+    // バッファの内容を、保存するときと同じ形でごみ箱に控える。控えられなければ知らせて false
+    fn trash_buffer(&mut self, kind: trash::Kind) -> bool {
+        let path = match self.path.as_ref() {
+            Some(path) => path.canonicalize().unwrap_or_else(|_| path.clone()),
+            None => return false,
+        };
+        match trash::put(kind, &path, &self.encoding.encode(&self.contents()), self.encoding) {
+            Ok(()) => true,
+            Err(e) => {
+                log_error!("cannot back up {} to the trash: {}", path.display(), e);
+                self.message = tr!("trash-failed", e);
+                false
+            },
+        }
+    }
+
+    // ごみ箱の控えを新しい順に下のペインに出して選ばせ、元のファイルのバッファに戻す
+    // ディスクには書かないので、保存するまで元のファイルはそのまま
+    fn trash<T: Write, I: Iterator<Item = io::Result<Event>>>(
+        &mut self,
+        out: &mut T,
+        events: &mut I,
+    ) -> io::Result<()> {
+        let entries = trash::list();
+        if entries.is_empty() {
+            self.message = tr!("trash-empty");
+            return Ok(());
+        }
+        let picker = Picker::new(entries.iter().map(trash::Entry::line).collect(), &tr!("trash-help"));
+        if let Some(selected) = self.pick_from_pane(out, events, picker)? {
+            self.restore_trash(&entries[selected]);
+        }
+        Ok(())
+    }
+
+    fn restore_trash(&mut self, entry: &trash::Entry) {
+        let text = match trash::read(entry) {
+            // 今のバッファではなく、控えを取ったときの文字コードで読む
+            Ok(bytes) => entry.encoding.decode(&bytes).filter(|_| !binary::is_binary(&bytes)),
+            Err(e) => {
+                log_error!("cannot read the backup of {}: {}", entry.path.display(), e);
+                self.message = tr!("trash-failed", e);
+                return;
+            },
+        };
+        let text = match text {
+            Some(text) => text,
+            None => {
+                self.message = tr!("trash-not-text", entry.path.display());
+                return;
+            },
+        };
+        // 消したファイルなら、名前だけの空のバッファになる。大きなファイルで開くのをやめたら何もしない
+        self.open_buffer(&entry.path);
+        let current = self.path.as_ref().map(|path| path.canonicalize().unwrap_or_else(|_| path.clone()));
+        if current.as_ref() != Some(&entry.path) {
+            return;
+        }
+        if !self.check_writable() {
+            return;
+        }
+        // 今のバッファにも保存していない変更があれば、それも控えてから置き換える
+        if self.modified && self.contents() != text && !self.trash_buffer(trash::Kind::Revert) {
+            return;
+        }
+        let (cursor, row_offset) = (self.cursor, self.row_offset);
+        self.set_buffer(to_buffer(&text));
+        self.line_ending = LineEnding::detect(text.as_bytes());
+        self.encoding = entry.encoding;
+        self.modified = true;
+        self.restore_cursor(cursor, row_offset);
+        self.message = tr!("trash-restored", self.display_path());
+    }
+
+    // 開くのを待たせている大きなファイルを、すべて開くか、先頭だけを開くか、やめるかを聞く
+    fn confirm_large<T: Write, I: Iterator<Item = io::Result<Event>>>(
+        &mut self,
+        out: &mut T,
+        events: &mut I,
+    ) -> io::Result<()> {
+        let (path, limit) = match (self.large_file.take(), self.large_file_limit) {
+            (Some(path), Some(limit)) => (path, limit),
+            _ => return Ok(()),
+        };
+        let size = fs::metadata(&path).map(|metadata| metadata.len()).unwrap_or(0);
+        let dialog = Dialog::new(&tr!("large-file-question", path.display(), size / MEGABYTE))
+            .button('f', &tr!("button-open-fully"))
+            .button('r', &tr!("button-open-head", limit / MEGABYTE))
+            .button('c', &tr!("button-cancel"))
+            .default_button('c');
+        match self.ask(out, events, dialog)? {
+            Some('f') => {
+                self.open_head(&path, None);
+            },
+            Some('r') => {
+                self.open_head(&path, Some(limit));
+            },
+            _ => self.message = tr!("open-cancelled", path.display()),
+        }
+        Ok(())
+    }
+
+    // スタート画面でのキー。スタート画面で処理したら true
+    // 文字を打つなど編集を始めるキーは、スタート画面を閉じて空のバッファで処理させる
+    fn welcome_key<T: Write, I: Iterator<Item = io::Result<Event>>>(
+        &mut self,
+        out: &mut T,
+        events: &mut I,
+        evt: &Event,
+    ) -> io::Result<bool> {
+        let welcome = match self.welcome.as_mut() {
+            Some(welcome) => welcome,
+            None => return Ok(false),
+        };
+        match evt {
+            Event::Key(Key::Up) => welcome.up(),
+            Event::Key(Key::Down) => welcome.down(),
+            Event::Key(Key::Esc) => self.welcome = None,
+            Event::Key(Key::Char('\n')) => match welcome.choice() {
+                welcome::Choice::Open(path) => {
+                    self.open(&path);
+                },
+                welcome::Choice::New => self.new_file(out, events)?,
+            },
+            Event::Key(Key::Char(_)) | Event::Key(Key::Backspace) | Event::Key(Key::Delete) => {
+                self.welcome = None;
+                return Ok(false);
+            },
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    // 押したキーを、それまでに押したキーと合わせて操作にする
+    // 割り当てのない文字はそのまま入れる。続きを待つときや割り当てがないときは None
+    fn resolve_key(&mut self, key: Key) -> Option<KeyAction> {
+        self.pending_keys.push(key);
+        match self.keymap.lookup(&self.pending_keys) {
+            Lookup::Found(action) => {
+                self.pending_keys.clear();
+                Some(action)
+            },
+            Lookup::Prefix => {
+                self.message = tr!("key-prefix", keymap::keys_name(&self.pending_keys));
+                None
+            },
+            Lookup::Unbound => {
+                let keys = std::mem::take(&mut self.pending_keys);
+                match keys.as_slice() {
+                    [Key::Char(c)] => Some(KeyAction::Insert(*c)),
+                    // 続きの途中で Esc か Ctrl+G を押したら、何もせずにやめる
+                    [_] | [.., Key::Esc] | [.., Key::Ctrl('g')] => None,
+                    _ => {
+                        self.message = tr!("key-unbound", keymap::keys_name(&keys));
+                        None
+                    },
+                }
+            },
+        }
+    }
+
+    // キーに割り当てた操作をする。終わるときは終了コードを返す
+    fn run_key_action<T: Write, I: Iterator<Item = io::Result<Event>>>(
+        &mut self,
+        out: &mut T,
+        events: &mut I,
+        action: KeyAction,
+    ) -> io::Result<Option<i32>> {
+        if !self.extra_selections.is_empty() {
+            self.edit_selections(out, &action)?;
+            return Ok(None);
+        }
+        match action {
+            // 選んでいればコピー。選んでいなければ終わる (保存していなければ聞く)
+            KeyAction::CopyOrQuit => {
+                if !self.copy(out)? {
+                    if self.confirm_quit(out, events)? {
+                        return Ok(Some(0));
+                    }
+                } else {
+                    self.clear_selection();
+                }
+            },
+            KeyAction::Quit => {
+                if self.confirm_quit(out, events)? {
+                    return Ok(Some(0));
+                }
+            },
+            KeyAction::Copy => {
+                if self.copy(out)? {
+                    self.clear_selection();
+                } else {
+                    self.message = tr!("nothing-selected");
+                }
+            },
+            KeyAction::Cut => {
+                self.cut(out)?;
+            },
+            KeyAction::Paste => {
+                self.paste_clipboard();
+            },
+            KeyAction::Save => {
+                self.save_interactive(out, events)?;
+            },
+            KeyAction::Undo => {
+                self.undo();
+            },
+            KeyAction::Redo => {
+                self.redo();
+            },
+            KeyAction::Search => {
+                self.incremental_search(out, events)?;
+            },
+            KeyAction::SearchNext => {
+                self.search_again(true);
+            },
+            KeyAction::SelectNextMatch => {
+                self.select_to_match(true);
+            },
+            KeyAction::SelectPreviousMatch => {
+                self.select_to_match(false);
+            },
+            KeyAction::KillLine => {
+                self.kill_line();
+            },
+            KeyAction::KillWholeLine => {
+                self.kill_whole_line();
+            },
+            KeyAction::Yank => {
+                self.yank();
+            },
+            KeyAction::InsertExpression => {
+                self.insert_expression(out, events)?;
+            },
+            KeyAction::GitStatus => {
+                self.git_status(out, events)?;
+            },
+            KeyAction::FileHistory => {
+                self.file_history(out, events)?;
+            },
+            KeyAction::NextLocation => {
+                self.step_location(true);
+            },
+            KeyAction::PreviousLocation => {
+                self.step_location(false);
+            },
+            KeyAction::LocationList => {
+                self.location_list(out, events)?;
+            },
+            KeyAction::ToggleBookmark => {
+                self.toggle_bookmark(out, events)?;
+            },
+            KeyAction::EditBookmark => {
+                self.edit_bookmark(out, events)?;
+            },
+            KeyAction::Reopen => {
+                self.reopen();
+            },
+            KeyAction::Zoom => {
+                self.toggle_zoom();
+            },
+            KeyAction::GrowPane => {
+                self.resize_pane(true);
+            },
+            KeyAction::ShrinkPane => {
+                self.resize_pane(false);
+            },
+            KeyAction::CollapsePane => {
+                self.toggle_collapse();
+            },
+            KeyAction::RelativeNumbers => {
+                self.toggle_relative_numbers();
+            },
+            KeyAction::OpenBuffer => {
+                self.prompt_open_buffer(out, events)?;
+            },
+            KeyAction::NextBuffer => {
+                self.cycle_buffer(true);
+            },
+            KeyAction::PreviousBuffer => {
+                self.cycle_buffer(false);
+            },
+            KeyAction::NextFile => {
+                self.step_arg(true);
+            },
+            KeyAction::PreviousFile => {
+                self.step_arg(false);
+            },
+            KeyAction::Split => {
+                self.split_window(false);
+            },
+            KeyAction::VerticalSplit => {
+                self.split_window(true);
+            },
+            KeyAction::CloseWindow => {
+                self.close_window();
+            },
+            KeyAction::NextWindow => {
+                self.cycle_window(true);
+            },
+            KeyAction::PreviousWindow => {
+                self.cycle_window(false);
+            },
+            KeyAction::Layout(n) => {
+                if let Some(&(name, _)) = LAYOUT_PRESETS.get(n) {
+                    self.apply_layout(name);
+                }
+            },
+            KeyAction::Cancel => {
+                self.cancel();
+            },
+            KeyAction::Mark => {
+                self.toggle_mark();
+            },
+            KeyAction::SplitSelection => {
+                let minibuffer = Minibuffer::new(&tr!("split-selection"))
+                    .history("search")
+                    .validate(minibuffer::not_empty);
+                if let Some(pattern) = self.prompt(out, events, minibuffer)? {
+                    self.split_selection(&pattern);
+                }
+            },
+            KeyAction::StickyCtrl => {
+                self.sticky.toggle(Modifier::Ctrl);
+            },
+            KeyAction::StickyAlt => {
+                self.sticky.toggle(Modifier::Alt);
+            },
+            KeyAction::CommandPrompt => {
+                let minibuffer = Minibuffer::new(":")
+                    .placeholder(&tr!("command-placeholder"))
+                    .history("command")
+                    .complete(complete_command);
+                if let Some(command) = self.prompt(out, events, minibuffer)? {
+                    if let Action::Quit(code) = self.execute_command(out, events, &command)? {
+                        return Ok(Some(code));
+                    }
+                }
+            },
+            KeyAction::Run(command) => {
+                if let Action::Quit(code) = self.execute_command(out, events, &command)? {
+                    return Ok(Some(code));
+                }
+            },
+            // 上下は論理行と表示行の移動を visual_lines で使い分け、alternate はその逆で動く
+            KeyAction::Up => {
+                if self.visual_lines {
+                    self.visual_up();
+                } else {
+                    self.cursor_up();
+                }
+            },
+            KeyAction::Down => {
+                if self.visual_lines {
+                    self.visual_down();
+                } else {
+                    self.cursor_dwon();
+                }
+            },
+            KeyAction::UpAlternate => {
+                if self.visual_lines {
+                    self.cursor_up();
+                } else {
+                    self.visual_up();
+                }
+            },
+            KeyAction::DownAlternate => {
+                if self.visual_lines {
+                    self.cursor_dwon();
+                } else {
+                    self.visual_down();
+                }
+            },
+            KeyAction::Left => {
+                self.cursor_left();
+            },
+            KeyAction::Right => {
+                self.cursor_right();
+            },
+            KeyAction::PageUp => {
+                self.page(false);
+            },
+            KeyAction::PageDown => {
+                self.page(true);
+            },
+            KeyAction::LineStart => {
+                self.line_start();
+            },
+            KeyAction::LineEnd => {
+                self.line_end();
+            },
+            KeyAction::BufferStart => {
+                self.buffer_start();
+            },
+            KeyAction::BufferEnd => {
+                self.buffer_end();
+            },
+            KeyAction::GotoLine => {
+                self.goto_line_prompt(out, events)?;
+            },
+            KeyAction::JumpBack => {
+                self.jump(true, 1);
+            },
+            KeyAction::JumpForward => {
+                self.jump(false, 1);
+            },
+            KeyAction::WordForward => {
+                self.word_forward();
+            },
+            KeyAction::WordBackward => {
+                self.word_backward();
+            },
+            KeyAction::Tab => {
+                self.delete_selection();
+                self.insert_tab();
+            },
+            // 選んでいる範囲は、文字を打つと置き換える
+            KeyAction::Insert(c) => {
+                self.delete_selection();
+                self.insert(c);
+            },
+            KeyAction::Backspace => {
+                self.back_space();
+            },
+            KeyAction::Delete => {
+                self.delete();
+            },
+        }
+        Ok(None)
+    }
+
+    // vim のモードで、動きを1回分する
+    fn vim_move(&mut self, motion: Motion) {
+        match motion {
+            Motion::Left => self.cursor_left(),
+            Motion::Right => self.cursor_right(),
+            Motion::Up => self.cursor_up(),
+            Motion::Down => self.cursor_dwon(),
+            Motion::WordForward => self.word_forward(),
+            Motion::WordBackward => self.word_backward(),
+            Motion::WordEnd => self.word_end(),
+            Motion::LineStart => self.line_start(),
+            Motion::LineEnd => self.line_end(),
+            Motion::FirstLine => self.goto_row(0),
+            Motion::LastLine => self.goto_row(self.buffer.len() - 1),
+            Motion::Line(line) => self.goto_row(line - 1),
+        }
+    }
+
+    // ノーマルモードとビジュアルモードでは、カーソルを行末の改行の上に置かない
+    fn vim_clamp(&mut self) {
+        if self.vim.as_ref().is_some_and(|vim| vim.mode != vim::Mode::Insert) {
+            let len = self.buffer[self.cursor.row].len();
+            self.cursor.column = min(self.cursor.column, len.saturating_sub(1));
+        }
+    }
+
+    // ビジュアルモードの範囲。カーソルのある文字も含めるので、終わりは1文字後ろにする
+    fn vim_selection(&self) -> (Cursor, Cursor) {
+        let anchor = self.anchor.unwrap_or(self.cursor);
+        let (start, end) = if anchor <= self.cursor { (anchor, self.cursor) } else { (self.cursor, anchor) };
+        let end = if end.column < self.buffer[end.row].len() {
+            Cursor { row: end.row, column: end.column + 1 }
+        } else {
+            self.next_position(end).unwrap_or(end)
+        };
+        (start, end)
+    }
+
+    // start から end の手前までの文字列
+    fn range_string(&self, start: Cursor, end: Cursor) -> String {
+        let mut text = String::new();
+        for row in start.row..=end.row {
+            let from = if row == start.row { start.column } else { 0 };
+            let to = if row == end.row { end.column } else { self.buffer[row].len() };
+            text.extend(self.buffer[row].iter().skip(from).take(to - from));
+            if row != end.row {
+                text.push('\n');
+            }
+        }
+        text
+    }
+
+    // start から end の手前までに演算子をかける。消したものとコピーしたものは貼り付けられるように覚える
+    fn vim_operate_range(&mut self, operator: vim::Operator, start: Cursor, end: Cursor) {
+        let text = self.range_string(start, end);
+        if operator != vim::Operator::Yank {
+            if !self.check_writable() {
+                return;
+            }
+            self.anchor = Some(start);
+            self.cursor = end;
+            self.delete_selection();
+        }
+        self.kill_ring.push(text);
+        self.cursor = start;
+        self.scroll();
+    }
+
+    // first から last までの行に演算子をかける。c は行を1つ空けて残す
+    fn vim_operate_lines(&mut self, operator: vim::Operator, first: usize, last: usize) {
+        let end = Cursor { row: last, column: self.buffer[last].len() };
+        let mut text = self.range_string(Cursor { row: first, column: 0 }, end);
+        text.push('\n');
+        match operator {
+            vim::Operator::Yank => {
+                self.kill_ring.push(text);
+                self.cursor.row = first;
+                self.vim_clamp();
+                return;
+            },
+            _ if !self.check_writable() => return,
+            vim::Operator::Change => {
+                self.anchor = Some(Cursor { row: first, column: 0 });
+                self.cursor = end;
+            },
+            // 最後の行まで消すときは、前の行の改行から消す
+            vim::Operator::Delete if last + 1 < self.buffer.len() => {
+                self.anchor = Some(Cursor { row: first, column: 0 });
+                self.cursor = Cursor { row: last + 1, column: 0 };
+            },
+            vim::Operator::Delete => {
+                let start = self.previous_position(Cursor { row: first, column: 0 }).unwrap_or_default();
+                self.anchor = Some(start);
+                self.cursor = end;
+            },
+        }
+        self.delete_selection();
+        self.kill_ring.push(text);
+        if operator == vim::Operator::Delete {
+            self.goto_row(min(first, self.buffer.len() - 1));
+        }
+    }
+
+    fn vim_operate(&mut self, operator: vim::Operator, target: vim::Target, count: usize) {
+        let origin = self.cursor;
+        let (mut motion, mut count) = match target {
+            vim::Target::Lines => {
+                let last = min(origin.row + count - 1, self.buffer.len() - 1);
+                return self.vim_operate_lines(operator, origin.row, last);
+            },
+            vim::Target::Motion(motion) => (motion, count),
+        };
+        // cw は ce のように、単語の後の空白を残す。単語の最後の文字にいれば、その文字を1つ目と数える
+        let word = self.word_at(origin);
+        if operator == vim::Operator::Change && motion == Motion::WordForward && word.class != CharClass::Space {
+            motion = Motion::WordEnd;
+            if origin.column + 1 == word.end {
+                count -= 1;
+            }
+        }
+        for _ in 0..count {
+            self.vim_move(motion);
+        }
+        let target = self.cursor;
+        self.cursor = origin;
+        self.goal = None;
+        if motion.linewise() {
+            return self.vim_operate_lines(operator, min(origin.row, target.row), max(origin.row, target.row));
+        }
+        let (start, mut end) = if target < origin { (target, origin) } else { (origin, target) };
+        if motion.inclusive() {
+            end = self.next_position(end).filter(|at| at.row == end.row).unwrap_or(end);
+        }
+        // dw で次の行まで進んだときは、改行を残す
+        if motion == Motion::WordForward && end.row > start.row {
+            end = Cursor { row: start.row, column: self.buffer[start.row].len() };
+            if start == end {
+                return;
+            }
+        }
+        self.vim_operate_range(operator, start, end);
+    }
+
+    // vim のモードのキーを実行する。終わるときは終了コードを返す
+    fn run_vim_command<T: Write, I: Iterator<Item = io::Result<Event>>>(
+        &mut self,
+        out: &mut T,
+        events: &mut I,
+        command: vim::Command,
+    ) -> io::Result<Option<i32>> {
+        match command {
+            vim::Command::Pending | vim::Command::Ignore | vim::Command::Pass => {},
+            vim::Command::Move(motion, count) => {
+                if matches!(motion, vim::Motion::FirstLine | vim::Motion::LastLine | vim::Motion::Line(_)) {
+                    self.push_jump();
+                }
+                for _ in 0..count {
+                    self.vim_move(motion);
+                }
+            },
+            vim::Command::Operate(operator, target, count) => self.vim_operate(operator, target, count),
+            vim::Command::OperateSelection(operator) => {
+                let (start, end) = self.vim_selection();
+                self.clear_selection();
+                self.vim_operate_range(operator, start, end);
+            },
+            vim::Command::Insert(at) => match at {
+                vim::InsertAt::Cursor => {},
+                vim::InsertAt::After => self.cursor_right(),
+                vim::InsertAt::LineStart => self.goto_row(self.cursor.row),
+                vim::InsertAt::LineEnd => self.line_end(),
+                vim::InsertAt::LineBelow => {
+                    self.line_end();
+                    self.insert('\n');
+                },
+                vim::InsertAt::LineAbove => {
+                    self.line_start();
+                    self.insert('\n');
+                    self.cursor_up();
+                },
+            },
+            // 挿入モードから抜けると、入れた最後の文字の上に戻る
+            vim::Command::ExitInsert => self.cursor_left(),
+            vim::Command::ExitVisual => self.clear_selection(),
+            vim::Command::Visual => {
+                self.anchor = Some(self.cursor);
+                self.mark = true;
+            },
+            vim::Command::DeleteChar(count) => {
+                let end = min(self.cursor.column + count, self.buffer[self.cursor.row].len());
+                if end > self.cursor.column {
+                    let end = Cursor { row: self.cursor.row, column: end };
+                    self.vim_operate_range(vim::Operator::Delete, self.cursor, end);
+                }
+            },
+            vim::Command::Put { before, count } => self.vim_put(before, count),
+            vim::Command::Undo(count) => {
+                for _ in 0..count {
+                    self.undo();
+                }
+            },
+            vim::Command::Redo(count) => {
+                for _ in 0..count {
+                    self.redo();
+                }
+            },
+            vim::Command::Prompt => {
+                return self.run_key_action(out, events, KeyAction::CommandPrompt);
+            },
+            // ビジュアルモードの : は、選んだ範囲を '<,'> にして入力させる
+            vim::Command::RangePrompt => {
+                let (start, end) = self.vim_selection();
+                self.anchor = Some(start);
+                self.cursor = end;
+                let minibuffer = Minibuffer::new(":")
+                    .placeholder(&tr!("command-placeholder"))
+                    .history("command")
+                    .complete(complete_command)
+                    .input("'<,'>");
+                let result = match self.prompt(out, events, minibuffer)? {
+                    Some(command) => self.execute_command(out, events, &command)?,
+                    None => Action::Continue,
+                };
+                self.clear_selection();
+                self.cursor = start;
+                if let Action::Quit(code) = result {
+                    return Ok(Some(code));
+                }
+            },
+            vim::Command::Search => {
+                return self.run_key_action(out, events, KeyAction::Search);
+            },
+            vim::Command::SearchNext => self.search_again(true),
+            vim::Command::SetMark(name) => self.set_mark(name),
+            vim::Command::GotoMark { name, exact } => self.goto_mark(name, exact),
+            vim::Command::JumpBack(count) => self.jump(true, count),
+            vim::Command::JumpForward(count) => self.jump(false, count),
+        }
+        Ok(None)
+    }
+
+    // 覚えているものを貼り付ける。行ごと覚えたものは、カーソルの行の下 (P なら上) に入れる
+    fn vim_put(&mut self, before: bool, count: usize) {
+        if !self.check_writable() {
+            return;
+        }
+        let text = match self.kill_ring.yank() {
+            Some(text) => text.repeat(count),
+            None => {
+                self.message = tr!("kill-ring-empty");
+                return;
+            },
+        };
+        if !text.ends_with('\n') {
+            if !before {
+                self.cursor_right();
+            }
+            self.insert_text(&text);
+            self.cursor_left();
+            return;
+        }
+        let row = self.cursor.row;
+        if before {
+            self.cursor = Cursor { row, column: 0 };
+            self.insert_text(&text);
+            self.goto_row(row);
+        } else if row + 1 < self.buffer.len() {
+            self.cursor = Cursor { row: row + 1, column: 0 };
+            self.insert_text(&text);
+            self.goto_row(row + 1);
+        } else {
+            // 最後の行の下には、改行を前に置いて足す
+            self.line_end();
+            self.insert_text(&format!("\n{}", &text[..text.len() - 1]));
+            self.goto_row(row + 1);
+        }
+    }
+
+    // 貼り付けられた文字列を入れる。ファイルをドラッグ&ドロップしたようなら、開くかどうかを聞く
+    fn paste<T: Write, I: Iterator<Item = io::Result<Event>>>(
+        &mut self,
+        out: &mut T,
+        events: &mut I,
+        text: &str,
+    ) -> io::Result<()> {
+        if let Some(path) = dropped_path(text) {
+            let dialog = Dialog::yes_no(&tr!("open-dropped", path.display())).default_button('y');
+            if self.ask(out, events, dialog)? == Some('y') {
+                if self.autowrite() {
+                    self.open(&path);
+                }
+                return Ok(());
+            }
+        }
+        self.insert_text(text);
+        Ok(())
+    }
+
+    // 式を聞いて、計算した結果をカーソルの位置に入れる (vim の "= のように)
+    fn insert_expression<T: Write, I: Iterator<Item = io::Result<Event>>>(
+        &mut self,
+        out: &mut T,
+        events: &mut I,
+    ) -> io::Result<()> {
+        if !self.check_writable() {
+            return Ok(());
+        }
+        let minibuffer = Minibuffer::new(&tr!("expression"))
+            .history("expression")
+            .placeholder(&tr!("expression-placeholder"))
+            .validate(expr::check);
+        let source = match self.prompt(out, events, minibuffer)? {
+            Some(source) => source,
+            None => return Ok(()),
+        };
+        match expr::eval(&source) {
+            Ok(value) => {
+                self.delete_selection();
+                self.insert_text(&value.to_string());
+            },
+            // 確定する前に確かめているが、日付や環境変数は変わることがある
+            Err(e) => self.message = e,
+        }
+        Ok(())
+    }
+
+    fn paste_clipboard(&mut self) {
+        if !self.check_writable() {
+            return;
+        }
+        let text = self.clipboard.paste();
+        self.delete_selection();
+        self.insert_text(&text);
+    }
+
+    // ほかのファイルの内容をカーソルの位置に入れる (:r)
+    fn read_into(&mut self, path: &path::Path) {
+        if !self.check_writable() {
+            return;
+        }
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                self.message = tr!("read-failed", path.display(), e);
+                return;
+            },
+        };
+        let text = self
+            .encoding
+            .decode(&bytes)
+            .unwrap_or_else(|| String::from_utf8_lossy(&bytes).into_owned());
+        self.insert_lines(&text);
+    }
+
+    // シェルのコマンドを実行し、標準出力をカーソルの位置に入れる (:r !command)
+    fn read_command(&mut self, command: &str) {
+        if !self.check_writable() {
+            return;
+        }
+        let output = match self.run_shell(command, command) {
+            Ok(Some(output)) => output,
+            Ok(None) => {
+                self.message = tr!("command-cancelled", command);
+                return;
+            },
+            Err(e) => {
+                self.message = tr!("command-failed", command, e);
+                return;
+            },
+        };
+        log_info!("ran {} ({})", command, output.status);
+        self.insert_lines(&String::from_utf8_lossy(&output.stdout));
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let reason = stderr.lines().next().map(str::to_string).unwrap_or_else(|| output.status.to_string());
+            self.message = tr!("command-failed", command, reason);
+        }
+    }
+
+    // sh -c script を開いているファイルのディレクトリで実行する。command は進み具合に出す名前
+    // 終わるまで進み具合を出し、Esc で中断されたらシェルを止めて None
+    fn run_shell(&self, command: &str, script: &str) -> io::Result<Option<process::Output>> {
+        let dir = self.path.as_deref().map(parent_dir).unwrap_or_else(|| path::PathBuf::from("."));
+        let mut child = process::Command::new("sh")
+            .arg("-c")
+            .arg(script)
+            .current_dir(dir)
+            .stdin(process::Stdio::null())
+            .stdout(process::Stdio::piped())
+            .stderr(process::Stdio::piped())
+            .spawn()?;
+        // パイプがいっぱいになって止まらないように、出力は別のスレッドで読む
+        let stdout = child.stdout.take().map(read_to_end_in_background);
+        let stderr = child.stderr.take().map(read_to_end_in_background);
+        let mut progress = Progress::start(&tr!("running", command), &self.cancel);
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if !progress.update(0, 0) {
+                log_info!("cancelled {}", command);
+                child.kill()?;
+                child.wait()?;
+                // シェルが始めたプロセスがパイプを持ったまま残ることがあるので、読むスレッドは待たない
+                return Ok(None);
+            }
+            thread::sleep(COMMAND_POLL);
+        };
+        let join = |reader: Option<thread::JoinHandle<Vec<u8>>>| {
+            reader.and_then(|reader| reader.join().ok()).unwrap_or_default()
+        };
+        Ok(Some(process::Output { status, stdout: join(stdout), stderr: join(stderr) }))
+    }
+
+    // シェルのコマンドを実行して、標準出力と標準エラー出力を新しい読み取り専用のバッファに出す
+    fn run_to_buffer(&mut self, command: &str) {
+        // 出てきた順に並ぶように、標準エラー出力も標準出力に送る
+        let output = match self.run_shell(command, &format!("exec 2>&1\n{}", command)) {
+            Ok(Some(output)) => output,
+            Ok(None) => {
+                self.message = tr!("command-cancelled", command);
+                return;
+            },
+            Err(e) => {
+                self.message = tr!("command-failed", command, e);
+                return;
+            },
+        };
+        log_info!("ran {} ({}) into a buffer", command, output.status);
+        // 何も書いていない名前のないバッファなら、新しく作らずにそこに出す
+        let empty = self.path.is_none() && !self.modified && self.buffer == [Line::new()];
+        if !empty || self.large_file.is_some() {
+            let index = self.current + 1;
+            self.insert_buffer(index);
+            self.switch_buffer(index);
+        }
+        self.set_buffer(to_buffer(&String::from_utf8_lossy(&output.stdout)));
+        self.cursor = Cursor { row: 0, column: 0 };
+        self.row_offset = 0;
+        self.col_offset = 0;
+        self.force_read_only();
+        self.command_output = Some((command.to_string(), output.status));
+        self.message = tr!("command-output", command, self.buffer.len());
+    }
+
+    // text をカーソルの位置にそのまま (タブなども) 入れ、入れた後ろにカーソルを置く。1回で取り消せる
+    // 改行で終わっていれば、カーソルから後ろは次の行になる
+    fn insert_lines(&mut self, text: &str) {
+        self.history.seal(self.cursor);
+        let mut lines = to_buffer(text);
+        if text.ends_with('\n') {
+            lines.push(Line::new());
+        }
+        let count = lines.len() - 1;
+        let Cursor { row, column } = self.cursor;
+        let tail = self.buffer[row].slice(column..).to_vec();
+        for (k, line) in lines.into_iter().enumerate() {
+            if k > 0 {
+                self.split_line(Cursor { row: row + k - 1, column: self.buffer[row + k - 1].len() });
+            }
+            let mut new = if k == 0 { self.buffer[row].slice(..column).to_vec() } else { Vec::new() };
+            new.extend(line.iter());
+            self.cursor = Cursor { row: row + k, column: new.len() };
+            if k == count {
+                new.extend(tail.iter());
+            }
+            self.replace_line(row + k, new);
+        }
+        self.history.seal(self.cursor);
+        self.scroll();
+        self.message = tr!("inserted-lines", text.lines().count());
+    }
+
+    fn insert_text(&mut self, text: &str) {
+        for c in text.chars() {
+            self.insert(c);
+        }
+        // 貼り付けの後に打った文字とは別に取り消す
+        self.history.seal(self.cursor);
+    }
+
+    // 画面と同じ色をつけて書き出す
+    fn export(&mut self, format: export::Format) -> String {
+        self.highlighter.prepare(&self.buffer, self.buffer.len());
+        let lines: Vec<export::Line> = (0..self.buffer.len())
+            .map(|row| {
+                let mut line: export::Line = Vec::new();
+                for (column, &c) in self.buffer[row].iter().enumerate() {
+                    let highlight = self.char_highlight(row, column, c);
+                    let text = if highlight == Highlight::Special {
+                        special::escape(c)
+                    } else {
+                        c.to_string()
+                    };
+                    match line.last_mut() {
+                        Some((last, run)) if *last == highlight => run.push_str(&text),
+                        _ => line.push((highlight, text)),
+                    }
+                }
+                line
+            })
+            .collect();
+        export::render(format, &self.display_path(), &lines, &self.theme)
+    }
+
+    fn export_html(&mut self, path: &path::Path) {
+        let html = self.export(export::Format::Html);
+        match write_file(path, html.as_bytes(), self.verify_save) {
+            Ok(()) => self.message = tr!("exported", path.display()),
+            Err(e) => {
+                log_error!("cannot export to {}: {}", path.display(), e);
+                self.message = tr!("export-failed", e);
+            },
+        }
+    }
+
+    // [範囲]w パス で、バッファの一部か全体を別のファイルに書き出す。このバッファのファイル名は変えない
+    // ファイルがもうあれば上書きしてよいか聞く。w >> パス なら聞かずに後ろに足す。書き出せたら true
+    fn write_range<T: Write, I: Iterator<Item = io::Result<Event>>>(
+        &mut self,
+        out: &mut T,
+        events: &mut I,
+        command: &str,
+    ) -> io::Result<bool> {
+        let (range, rest) = split_range(command);
+        let rest = rest[1..].trim();
+        let (append, path) = match rest.strip_prefix(">>") {
+            Some(path) => (true, path::Path::new(path.trim())),
+            None => (false, path::Path::new(rest)),
+        };
+        if path.as_os_str().is_empty() {
+            self.message = tr!("no-file-name");
+            return Ok(false);
+        }
+        let text = match self.range_text(range) {
+            Ok(text) => text,
+            Err(message) => {
+                self.message = message;
+                return Ok(false);
+            },
+        };
+        if !append
+            && path.exists()
+            && self.ask(out, events, Dialog::yes_no(&tr!("overwrite-question", path.display())))? != Some('y')
+        {
+            self.message = tr!("save-cancelled");
+            return Ok(false);
+        }
+        let bytes = self.encoding.encode(&text);
+        let result = if append { append_file(path, &bytes) } else { write_file(path, &bytes, self.verify_save) };
+        match result {
+            Ok(()) => {
+                log_info!("wrote {} ({} bytes{})", path.display(), bytes.len(), if append { ", appended" } else { "" });
+                let key = if append { "appended" } else { "written" };
+                self.message = tr!(key, path.display(), text.lines().count(), bytes.len());
+                Ok(true)
+            },
+            Err(e) => {
+                log_error!("cannot write {}: {}", path.display(), e);
+                self.message = tr!("save-failed", e);
+                Ok(false)
+            },
+        }
+    }
+
+    // 範囲の文字列。'<,'> は選んでいる範囲、% か空なら全体、N,M は行の範囲
+    // 最後の行にも改行をつける
+    fn range_text(&self, range: &str) -> Result<String, String> {
+        if range.is_empty() || range == "%" {
+            return Ok(self.contents());
+        }
+        if range == "'<,'>" {
+            let mut text = self.selected_text().ok_or_else(|| tr!("nothing-selected"))?;
+            if !text.ends_with('\n') {
+                text.push('\n');
+            }
+            return Ok(text);
+        }
+        let (start, end) = range.split_once(',').unwrap_or((range, range));
+        match (self.line_address(start), self.line_address(end)) {
+            (Some(start), Some(end)) if start <= end => {
+                let mut text = String::new();
+                for line in &self.buffer[start..=end] {
+                    text.extend(line.iter());
+                    text.push_str(self.line_ending.as_str());
+                }
+                Ok(text)
+            },
+            _ => Err(tr!("invalid-range", range)),
+        }
+    }
+
+    // 1 から始まる行の番号、. (今の行)、$ (最後の行) を行の位置にする。ない行なら None
+    fn line_address(&self, address: &str) -> Option<usize> {
+        match address {
+            "." => Some(self.cursor.row),
+            "$" => Some(self.buffer.len() - 1),
+            n => n.parse::<usize>().ok().filter(|&n| (1..=self.buffer.len()).contains(&n)).map(|n| n - 1),
+        }
+    }
+
+    // コンパイラなどが出す、ファイルの先頭からのバイト数 (0 始まり、0x をつければ16進) に移る
+    fn goto_byte(&mut self, arg: &str) -> bool {
+        let offset = match arg.strip_prefix("0x").or_else(|| arg.strip_prefix("0X")) {
+            Some(hex) => usize::from_str_radix(hex, 16).ok(),
+            None => arg.parse().ok(),
+        };
+        let offset = match offset {
+            Some(offset) => offset,
+            None => {
+                self.message = tr!("invalid-offset", arg);
+                return false;
+            },
+        };
+        match self.line_starts.position_at_byte(&self.buffer, offset, self.encoding, self.line_ending) {
+            Some(pos) => {
+                self.push_jump();
+                if !self.mark {
+                    self.clear_selection();
+                }
+                self.cursor = pos;
+                self.scroll();
+                true
+            },
+            None => {
+                let size = self.line_starts.byte_size(&self.buffer, self.encoding, self.line_ending);
+                self.message = tr!("offset-out-of-range", offset, size);
+                false
+            },
+        }
+    }
+
+    // カーソルの位置を、文字、表示上の列、バイトのそれぞれで数えて出す
+    fn cursor_info(&mut self) {
+        let (encoding, ending) = (self.encoding, self.line_ending);
+        let row_start = Cursor { row: self.cursor.row, column: 0 };
+        let line_start = self.line_starts.byte_offset(&self.buffer, row_start, encoding, ending);
+        let offset = self.line_starts.byte_offset(&self.buffer, self.cursor, encoding, ending);
+        let size = self.line_starts.byte_size(&self.buffer, encoding, ending);
+        let line = &self.buffer[self.cursor.row];
+        self.message = tr!(
+            "cursor-info",
+            self.cursor.row + 1,
+            self.cursor.column + 1,
+            line.len(),
+            address::display_column(line, self.cursor.column, self.escapes) + 1,
+            offset - line_start,
+            address::line_bytes(line, encoding),
+            offset,
+            size
+        );
+    }
+
+    // この起動での編集の記録を下のペインに出す
+    fn show_stats(&mut self) {
+        let mut lines = vec![tr!("stats-header")];
+        for (path, stats) in self.stats.files() {
+            let name = match path {
+                Some(path) => project::display_path(self.project_root.as_deref(), path),
+                None => tr!("no-name"),
+            };
+            let seconds = stats.time.as_secs();
+            lines.push(format!(
+                "{:>8} {:>8} {:>4}:{:02}:{:02}  {}",
+                stats.keystrokes,
+                stats.lines.len(),
+                seconds / 3600,
+                seconds / 60 % 60,
+                seconds % 60,
+                name
+            ));
+        }
+        self.pane = lines;
+        self.diff_base = None;
+    }
+
+    fn export_stats(&mut self, path: &path::Path) -> bool {
+        match write_file(path, self.stats.to_json().as_bytes(), self.verify_save) {
+            Ok(()) => {
+                self.message = tr!("exported", path.display());
+                true
+            },
+            Err(e) => {
+                log_error!("cannot export stats to {}: {}", path.display(), e);
+                self.message = tr!("export-failed", e);
+                false
+            },
+        }
+    }
+
+    // 読み込んだ後に他のプログラムがファイルを書き換えたか
+    fn disk_changed(&self) -> bool {
+        match self.path.as_ref() {
+            Some(path) => mtime(path) != self.disk_mtime,
+            None => false,
+        }
+    }
+
+    fn focus_lost(&mut self) {
+        if !self.focus_autosave || self.read_only || self.disk_changed() {
+            return;
+        }
+        // 保存するとコミットしてしまうので、書きかけのコミットメッセージは書かない
+        if self.pending_commit || self.commit_mode {
+            return;
+        }
+        let path = match self.path.as_ref() {
+            Some(path) => path,
+            None => return,
+        };
+        // 内容が変わっていなければ書かない
+        if fs::read(path).ok() != Some(self.encoding.encode(&self.contents())) {
+            log_info!("autosaving {} on focus loss", path.display());
+            self.save();
+        }
+    }
+
+    fn focus_gained(&mut self) {
+        self.check_disk();
+    }
+
+    // ファイルが外部で変更されていたら、編集していなければ読み直し、編集していれば知らせる
+    fn check_disk(&mut self) -> bool {
+        let current = match self.path.as_ref() {
+            Some(path) => mtime(path),
+            None => return false,
+        };
+        if current == self.disk_mtime || current == self.reported_mtime {
+            return false;
+        }
+        if self.auto_reload && !self.modified && current.is_some() {
+            log_info!("reloading {}: changed on disk", self.display_path());
+            self.reload();
+            self.message = tr!("reloaded", self.display_path());
+        } else {
+            self.reported_mtime = current;
+            self.message = tr!("changed-on-disk");
+        }
+        true
+    }
+
+    // ディスクから読み直す。カーソルはできるだけ元の位置に残す
+    fn reload(&mut self) {
+        let path = match self.path.clone() {
+            Some(path) => path,
+            None => return,
+        };
+        let (cursor, row_offset) = (self.cursor, self.row_offset);
+        self.load(&path, self.view);
+        self.restore_cursor(cursor, row_offset);
+    }
+
+    // 開いているファイルの名前を変える (mv)。まだ保存していなければ、保存する先を変えるだけ
+    fn rename_file<T: Write, I: Iterator<Item = io::Result<Event>>>(
+        &mut self,
+        out: &mut T,
+        events: &mut I,
+        new: &path::Path,
+    ) -> io::Result<()> {
+        let old = match self.path.clone() {
+            Some(path) => path,
+            None => {
+                self.message = tr!("no-file-name");
+                return Ok(());
+            },
+        };
+        if new.exists() && self.ask(out, events, Dialog::yes_no(&tr!("overwrite-question", new.display())))? != Some('y') {
+            return Ok(());
+        }
+        // 動かした後は元のパスを絶対パスにできないので、先に求めておく
+        let absolute = old.canonicalize().ok();
+        let root = self.bookmark_root();
+        if let Some(absolute) = absolute.as_ref() {
+            // 別のファイルシステムへは rename できないので、写してから消す
+            let moved = fs::rename(&old, new).or_else(|_| fs::copy(&old, new).and_then(|_| fs::remove_file(&old)));
+            if let Err(e) = moved {
+                log_error!("cannot rename {} to {}: {}", old.display(), new.display(), e);
+                self.message = tr!("rename-failed", e);
+                return Ok(());
+            }
+            log_info!("renamed {} to {}", old.display(), new.display());
+            if let Some(root) = root.as_ref() {
+                if let Err(e) = bookmarks::save(root, absolute, &[]) {
+                    log_warn!("cannot save bookmarks: {}", e);
+                }
+                if let Err(e) = places::forget(root, absolute) {
+                    log_warn!("cannot forget the place in {}: {}", old.display(), e);
+                }
+            }
+            if let Err(e) = recent::rename(absolute, new) {
+                log_debug!("cannot rename {} in recent files: {}", old.display(), e);
+            }
+            self.disk_mtime = mtime(new);
+        }
+        self.closed.retain(|closed| closed.path != old && closed.path != new);
+        self.path = Some(new.to_path_buf());
+        self.project_root = project::find_root(new);
+        self.highlighter = Highlighter::new(syntax::for_path(new));
+        if !self.bookmarks.is_empty() {
+            self.save_bookmarks();
+        }
+        self.message = tr!("renamed", self.display_path());
+        Ok(())
+    }
+
+    // 新しいファイルの名前を聞いて作る。今のファイルと同じディレクトリを入れておく
+    fn new_file<T: Write, I: Iterator<Item = io::Result<Event>>>(
+        &mut self,
+        out: &mut T,
+        events: &mut I,
+    ) -> io::Result<()> {
+        let mut minibuffer = Minibuffer::new(&tr!("new-file"))
+            .history("path")
+            .complete(minibuffer::complete_path)
+            .validate(minibuffer::not_empty);
+        if let Some(dir) = self.new_file_dir().filter(|dir| dir.as_os_str() != ".") {
+            minibuffer.input = format!("{}/", dir.display());
+        }
+        if let Some(path) = self.prompt(out, events, minibuffer)? {
+            self.create_file(path::Path::new(path.trim()));
+        }
+        Ok(())
+    }
+
+    // 新しいファイルを作るディレクトリ
+    // ファイルがなければプロジェクトのルート、それもなければ今のディレクトリ (None)
+    fn new_file_dir(&self) -> Option<path::PathBuf> {
+        match (self.path.as_ref(), self.project_root.as_ref()) {
+            (Some(path), _) => Some(parent_dir(path)),
+            (None, Some(root)) => Some(root.clone()),
+            (None, None) => None,
+        }
+    }
+
+    // :new に書いた相対パスは、今のディレクトリではなく new_file_dir から見たものにする
+    fn new_file_path(&self, path: &path::Path) -> path::PathBuf {
+        match self.new_file_dir() {
+            Some(dir) if path.is_relative() => dir.join(path),
+            _ => path.to_path_buf(),
+        }
+    }
+
+    // path を親のディレクトリごと作って開く。もうあればそのまま開く
+    fn create_file(&mut self, path: &path::Path) {
+        if !self.autowrite() {
+            return;
+        }
+        let result = fs::create_dir_all(parent_dir(path))
+            .and_then(|_| fs::OpenOptions::new().write(true).create_new(true).open(path));
+        match result {
+            Ok(_) => log_info!("created {}", path.display()),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {},
+            Err(e) => {
+                log_error!("cannot create {}: {}", path.display(), e);
+                self.message = tr!("create-failed", e);
+                return;
+            },
+        }
+        self.open(path);
+    }
+
+    // 開いているファイルを消す。バッファは残すので、保存すれば元に戻せる
+    fn delete_file<T: Write, I: Iterator<Item = io::Result<Event>>>(
+        &mut self,
+        out: &mut T,
+        events: &mut I,
+    ) -> io::Result<()> {
+        let path = match self.path.clone() {
+            Some(path) => path,
+            None => {
+                self.message = tr!("no-file-name");
+                return Ok(());
+            },
+        };
+        if self.ask(out, events, Dialog::yes_no(&tr!("delete-question", self.display_path())))? != Some('y') {
+            return Ok(());
+        }
+        let absolute = path.canonicalize().unwrap_or_else(|_| path.clone());
+        let root = self.bookmark_root();
+        // 消す前にディスクの内容をごみ箱に控える。控えられなければ消さない
+        let encoding = self.encoding;
+        if let Err(e) = fs::read(&path).and_then(|bytes| trash::put(trash::Kind::Delete, &absolute, &bytes, encoding)) {
+            log_error!("cannot back up {} to the trash: {}", path.display(), e);
+            self.message = tr!("trash-failed", e);
+            return Ok(());
+        }
+        if let Err(e) = fs::remove_file(&path) {
+            log_error!("cannot delete {}: {}", path.display(), e);
+            self.message = tr!("delete-failed", e);
+            return Ok(());
+        }
+        log_info!("deleted {}", path.display());
+        if let Some(root) = root.as_ref() {
+            if let Err(e) = bookmarks::save(root, &absolute, &[]) {
+                log_warn!("cannot save bookmarks: {}", e);
+            }
+            if let Err(e) = places::forget(root, &absolute) {
+                log_warn!("cannot forget the place in {}: {}", path.display(), e);
+            }
+        }
+        if let Err(e) = recent::forget(&absolute) {
+            log_debug!("cannot remove {} from recent files: {}", path.display(), e);
+        }
+        // ディスクの内容とはもう合わないので、書き換えられたとは知らせずに保存していない扱いにする
+        self.modified = true;
+        self.disk_mtime = None;
+        self.reported_mtime = None;
+        self.message = tr!("deleted", self.display_path());
+        Ok(())
+    }
+
+    // 最後に閉じたファイルを、閉じたときのカーソルの位置で開き直す
+    fn reopen(&mut self) {
+        let closed = match self.closed.pop() {
+            Some(closed) => closed,
+            None => {
+                self.message = tr!("no-closed-files");
+                return;
+            },
+        };
+        if !self.autowrite() {
+            self.closed.push(closed);
+            return;
+        }
+        if self.open(&closed.path) {
+            self.restore_cursor(closed.cursor, closed.row_offset);
+        } else {
+            self.closed.push(closed);
+        }
+    }
+
+    // 表示しているバッファと file の中身を入れ替える
+    fn swap_buffer(&mut self, file: &mut OpenFile) {
+        std::mem::swap(&mut self.buffer, &mut file.buffer);
+        std::mem::swap(&mut self.cursor, &mut file.cursor);
+        std::mem::swap(&mut self.row_offset, &mut file.row_offset);
+        std::mem::swap(&mut self.col_offset, &mut file.col_offset);
+        std::mem::swap(&mut self.clip_start, &mut file.clip_start);
+        std::mem::swap(&mut self.path, &mut file.path);
+        std::mem::swap(&mut self.project_root, &mut file.project_root);
+        std::mem::swap(&mut self.encoding, &mut file.encoding);
+        std::mem::swap(&mut self.line_ending, &mut file.line_ending);
+        std::mem::swap(&mut self.read_only, &mut file.read_only);
+        std::mem::swap(&mut self.forced_read_only, &mut file.forced_read_only);
+        std::mem::swap(&mut self.modified, &mut file.modified);
+        std::mem::swap(&mut self.disk_mtime, &mut file.disk_mtime);
+        std::mem::swap(&mut self.reported_mtime, &mut file.reported_mtime);
+        std::mem::swap(&mut self.view, &mut file.view);
+        std::mem::swap(&mut self.history, &mut file.history);
+        std::mem::swap(&mut self.head_only, &mut file.head_only);
+        std::mem::swap(&mut self.command_output, &mut file.command_output);
+        std::mem::swap(&mut self.locations, &mut file.locations);
+        std::mem::swap(&mut self.bookmarks, &mut file.bookmarks);
+        std::mem::swap(&mut self.marks, &mut file.marks);
+        std::mem::swap(&mut self.anchor, &mut file.anchor);
+        std::mem::swap(&mut self.mark, &mut file.mark);
+        std::mem::swap(&mut self.commit_mode, &mut file.commit_mode);
+        std::mem::swap(&mut self.pending_commit, &mut file.pending_commit);
+        std::mem::swap(&mut self.git_branch, &mut file.git_branch);
+        std::mem::swap(&mut self.layouts, &mut file.layouts);
+        std::mem::swap(&mut self.line_starts, &mut file.line_starts);
+        std::mem::swap(&mut self.highlighter, &mut file.highlighter);
+    }
+
+    // 操作している窓の位置と設定を window と入れ替える
+    fn swap_view(&mut self, window: &mut Window) {
+        std::mem::swap(&mut self.cursor, &mut window.cursor);
+        std::mem::swap(&mut self.row_offset, &mut window.row_offset);
+        std::mem::swap(&mut self.col_offset, &mut window.col_offset);
+        std::mem::swap(&mut self.clip_start, &mut window.clip_start);
+        std::mem::swap(&mut self.wrap, &mut window.wrap);
+        std::mem::swap(&mut self.line_numbers, &mut window.line_numbers);
+        std::mem::swap(&mut self.relative_numbers, &mut window.relative_numbers);
+    }
+
+    // 操作している窓の今の位置と設定
+    fn current_window(&self) -> Window {
+        Window {
+            buffer: self.current,
+            cursor: self.cursor,
+            row_offset: self.row_offset,
+            col_offset: self.col_offset,
+            clip_start: self.clip_start,
+            wrap: self.wrap,
+            line_numbers: self.line_numbers,
+            relative_numbers: self.relative_numbers,
+        }
+    }
+
+    // index 番目の窓のバッファと位置を一時的に出して f を呼ぶ。操作していない窓を描くときに使う
+    fn in_window<R>(&mut self, index: usize, f: impl FnOnce(&mut Self) -> R) -> R {
+        let mut window = self.windows[index];
+        let buffer = window.buffer;
+        let mut file = (buffer != self.current).then(|| std::mem::take(&mut self.buffers[buffer]));
+        if let Some(file) = file.as_mut() {
+            self.swap_buffer(file);
+        }
+        self.swap_view(&mut window);
+        // 選択している範囲は操作している窓にだけ出す
+        let anchor = self.anchor.take();
+        let result = f(self);
+        self.anchor = anchor;
+        self.swap_view(&mut window);
+        if let Some(mut file) = file {
+            self.swap_buffer(&mut file);
+            self.buffers[buffer] = file;
+        }
+        result
+    }
+
+    // index 番目のバッファを表示する
+    fn switch_buffer(&mut self, index: usize) {
+        if index == self.current {
+            return;
+        }
+        self.history.seal(self.cursor);
+        self.remember_place();
+        let mut file = std::mem::take(&mut self.buffers[index]);
+        self.swap_buffer(&mut file);
+        self.buffers[self.current] = file;
+        self.current = index;
+        self.goal = None;
+        // ペインの差分は前のバッファのもの
+        self.diff_base = None;
+        self.idle.schedule(idle::Task::WarmLayouts { row: 0 });
+        self.idle.schedule(idle::Task::GitBranch);
+        if let Some(journal) = self.journal.as_mut() {
+            journal.switched(self.path.as_deref());
+        }
+        self.message = tr!("buffer-switched", index + 1, self.buffers.len(), self.display_path());
+    }
+
+    // 次 (forward) か前のバッファに移る。最後の次は最初
+    fn cycle_buffer(&mut self, forward: bool) {
+        let count = self.buffers.len();
+        if count == 1 {
+            self.message = tr!("only-buffer");
+            return;
+        }
+        let index = if forward { (self.current + 1) % count } else { (self.current + count - 1) % count };
+        self.switch_buffer(index);
+    }
+
+    // コマンドラインで渡した index 番目のファイルが今のバッファか。a.rs と ./a.rs のような書き方の違いは見ない
+    fn is_arg(&self, index: usize) -> bool {
+        let (path, arg) = match (self.path.as_ref(), self.args.get(index)) {
+            (Some(path), Some(arg)) => (path, arg),
+            _ => return false,
+        };
+        let target = path.canonicalize().ok();
+        arg == path || (target.is_some() && arg.canonicalize().ok() == target)
+    }
+
+    // 今のバッファが、コマンドラインで渡したファイルの何番目か
+    // 同じファイルを2度渡していれば、最後に移った方とする
+    fn arg_position(&self) -> Option<usize> {
+        if self.is_arg(self.arg_index) {
+            return Some(self.arg_index);
+        }
+        (0..self.args.len()).find(|&index| self.is_arg(index))
+    }
+
+    // コマンドラインで渡したファイルの次 (forward) か前に移る。ほかのファイルにいれば、最後に移ったものの隣
+    fn step_arg(&mut self, forward: bool) {
+        let count = self.args.len();
+        if count < 2 {
+            self.message = tr!("no-file-args");
+            return;
+        }
+        let index = self.arg_position().unwrap_or(self.arg_index);
+        let index = match (forward, index) {
+            (true, index) if index + 1 < count => index + 1,
+            (false, index) if index > 0 => index - 1,
+            (true, _) => {
+                self.message = tr!("last-file-arg");
+                return;
+            },
+            (false, _) => {
+                self.message = tr!("first-file-arg");
+                return;
+            },
+        };
+        let path = self.args[index].clone();
+        self.open_buffer(&path);
+        if self.is_arg(index) {
+            self.arg_index = index;
+            self.message = tr!("file-arg", index + 1, count, self.display_path());
+        }
+    }
+
+    // path を新しいバッファに開く。もう開いていればそのバッファに移る
+    // 今のバッファが名前もなく空なら、そこに開く
+    fn open_buffer(&mut self, path: &path::Path) {
+        let target = path.canonicalize().ok();
+        let same = |other: &path::Path| other == path || (target.is_some() && other.canonicalize().ok() == target);
+        if self.path.as_deref().is_some_and(same) {
+            return;
+        }
+        self.push_jump();
+        if let Some(index) = self.buffers.iter().position(|file| file.path.as_deref().is_some_and(same)) {
+            self.switch_buffer(index);
+            return;
+        }
+        if self.path.is_none() && !self.modified && self.large_file.is_none() && self.buffer == [Line::new()] {
+            self.open(path);
+            return;
+        }
+        let previous = self.current;
+        self.insert_buffer(previous + 1);
+        self.switch_buffer(previous + 1);
+        // 大きなファイルは、どう開くかを聞いた後でこのバッファに開く
+        if !self.open(path) && self.large_file.is_none() {
+            let message = std::mem::take(&mut self.message);
+            self.switch_buffer(previous);
+            self.remove_buffer(previous + 1);
+            self.message = message;
+        }
+    }
+
+    // 新しいバッファに開くファイルを聞く
+    fn prompt_open_buffer<T: Write, I: Iterator<Item = io::Result<Event>>>(
+        &mut self,
+        out: &mut T,
+        events: &mut I,
+    ) -> io::Result<()> {
+        let minibuffer = Minibuffer::new(&tr!("open-buffer"))
+            .history("path")
+            .complete(minibuffer::complete_path)
+            .validate(minibuffer::not_empty);
+        if let Some(path) = self.prompt(out, events, minibuffer)? {
+            self.open_buffer(path::Path::new(path.trim()));
+        }
+        Ok(())
+    }
+
+    // index 番目に空のバッファを入れる。窓が表示しているバッファの番号もずらす
+    fn insert_buffer(&mut self, index: usize) {
+        self.buffers.insert(index, OpenFile { encoding: self.encoding, ..OpenFile::default() });
+        if index <= self.current {
+            self.current += 1;
+        }
+        for window in self.windows.iter_mut().filter(|window| window.buffer >= index) {
+            window.buffer += 1;
+        }
+    }
+
+    // 表示していない index 番目のバッファを除く。それを表示していた窓は今のバッファを表示する
+    fn remove_buffer(&mut self, index: usize) {
+        self.buffers.remove(index);
+        if index < self.current {
+            self.current -= 1;
+        }
+        let current = self.current;
+        for window in self.windows.iter_mut() {
+            if window.buffer == index {
+                *window = Window {
+                    buffer: current,
+                    wrap: window.wrap,
+                    line_numbers: window.line_numbers,
+                    relative_numbers: window.relative_numbers,
+                    ..Window::default()
+                };
+            } else if window.buffer > index {
+                window.buffer -= 1;
+            }
+        }
+    }
+
+    // 操作している窓を上下 (vertical なら左右) に分ける。新しい窓にも同じバッファを同じ位置と設定で出す
+    fn split_window(&mut self, vertical: bool) {
+        self.split_vertical = vertical;
+        self.windows.insert(self.window + 1, self.current_window());
+        self.scroll();
+    }
+
+    // index 番目の窓を操作する
+    fn focus_window(&mut self, index: usize) {
+        if index == self.window {
+            return;
+        }
+        self.windows[self.window] = self.current_window();
+        let window = self.windows[index];
+        self.window = index;
+        self.switch_buffer(window.buffer);
+        self.message.clear();
+        self.col_offset = window.col_offset;
+        self.clip_start = window.clip_start;
+        self.wrap = window.wrap;
+        self.line_numbers = window.line_numbers;
+        self.relative_numbers = window.relative_numbers;
+        // ほかの窓で編集して行が減っていても、残っている範囲に戻す
+        self.restore_cursor(window.cursor, window.row_offset);
+    }
+
+    // 次 (forward) か前の窓に移る
+    fn cycle_window(&mut self, forward: bool) {
+        let count = self.windows.len();
+        if count == 1 {
+            self.message = tr!("only-window");
+            return;
+        }
+        let index = if forward { (self.window + 1) % count } else { (self.window + count - 1) % count };
+        self.focus_window(index);
+    }
+
+    // 操作している窓を閉じて、次の窓に移る。バッファは開いたまま
+    fn close_window(&mut self) {
+        if self.windows.len() == 1 {
+            self.message = tr!("only-window");
+            return;
+        }
+        let closed = self.window;
+        let next = if closed + 1 < self.windows.len() { closed + 1 } else { closed - 1 };
+        self.focus_window(next);
+        self.windows.remove(closed);
+        if closed < self.window {
+            self.window -= 1;
+        }
+        self.scroll();
+    }
+
+    // 表示しているバッファを閉じて、次のバッファを出す。保存していなければ聞く
+    fn close_buffer<T: Write, I: Iterator<Item = io::Result<Event>>>(
+        &mut self,
+        out: &mut T,
+        events: &mut I,
+    ) -> io::Result<()> {
+        if self.buffers.len() == 1 {
+            self.message = tr!("only-buffer");
+            return Ok(());
+        }
+        let question = tr!("close-question", self.display_path());
+        if !self.confirm_discard(out, events, &question)? {
+            return Ok(());
+        }
+        let closed = self.current;
+        let next = if closed + 1 < self.buffers.len() { closed + 1 } else { closed - 1 };
+        self.switch_buffer(next);
+        self.remove_buffer(closed);
+        self.message = tr!("buffer-switched", self.current + 1, self.buffers.len(), self.display_path());
+        Ok(())
+    }
+
+    // 読み込み直した内容に収まるように、元のカーソルと表示の位置に戻す
+    fn restore_cursor(&mut self, cursor: Cursor, row_offset: usize) {
+        self.cursor.row = min(cursor.row, self.buffer.len() - 1);
+        self.cursor.column = min(cursor.column, self.buffer[self.cursor.row].len());
+        self.row_offset = min(row_offset, self.cursor.row);
+        self.scroll();
+    }
+
+    // git status ペインから始めたコミットを作る
+    fn finish_commit(&mut self) {
+        let message = self.contents();
+        if message.trim().is_empty() {
+            self.message = tr!("commit-empty");
+            return;
+        }
+        let path = self.path.clone().unwrap_or_default();
+        let result = git::open_repository(&parent_dir(&path))
+            .and_then(|repo| git::commit(&repo, message.trim_end()));
+        match result {
+            Ok(oid) => {
+                self.pending_commit = false;
+                self.message = tr!("committed", format!("{:.7}", oid));
+            },
+            Err(e) => {
+                log_warn!("commit failed: {}", e);
+                self.message = tr!("commit-failed", e.message());
+            },
+        }
+    }
+
+    // git status ペインを開いて、閉じられるまでキー入力を処理する
+    fn git_status<T: Write, I: Iterator<Item = io::Result<Event>>>(
+        &mut self,
+        out: &mut T,
+        events: &mut I,
+    ) -> io::Result<()> {
+        // 状態を調べたりステージしたりする前に、ディスクの内容を新しくしておく
+        self.autowrite();
+        let dir = match self.path.as_ref() {
+            Some(path) => parent_dir(path),
+            None => path::PathBuf::from("."),
+        };
+        let repo = match git::open_repository(&dir) {
+            Ok(repo) => repo,
+            Err(e) => {
+                self.message = e.message().to_string();
+                return Ok(());
+            },
+        };
+        let workdir = repo.workdir().unwrap_or(&dir).to_path_buf();
+
+        let saved_pane = std::mem::take(&mut self.pane);
+        let mut selected = 0;
+        let mut opened = false;
+        loop {
+            let entries = match git::status(&repo) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    self.message = e.message().to_string();
+                    break;
+                },
+            };
+            selected = min(selected, entries.len().saturating_sub(1));
+            self.pane = entries.iter().map(|entry| entry.line()).collect();
+            if self.pane.is_empty() {
+                self.pane.push(tr!("status-clean"));
+                self.pane_cursor = None;
+            } else {
+                self.pane_cursor = Some(selected);
+            }
+            if self.message.is_empty() {
+                self.message = tr!("status-help");
+            }
+            self.draw(out)?;
+            self.message.clear();
+
+            let evt = match events.next() {
+                Some(evt) => evt?,
+                None => break,
+            };
+            let entry = entries.get(selected);
+            let result = match evt {
+                Event::Key(Key::Char('q')) | Event::Key(Key::Esc) | Event::Key(Key::Ctrl('c')) => {
+                    break;
+                },
+                Event::Key(Key::Up) | Event::Key(Key::Char('k')) => {
+                    selected = selected.saturating_sub(1);
+                    Ok(())
+                },
+                Event::Key(Key::Down) | Event::Key(Key::Char('j')) => {
+                    selected += 1;
+                    Ok(())
+                },
+                Event::Key(Key::Char('s')) => match entry {
+                    Some(entry) => git::stage(&repo, &entry.path),
+                    None => Ok(()),
+                },
+                Event::Key(Key::Char('u')) => match entry {
+                    Some(entry) => git::unstage(&repo, &entry.path),
+                    None => Ok(()),
+                },
+                Event::Key(Key::Char('\n')) => {
+                    if let Some(entry) = entry {
+                        // 保存できなければ編集中の内容を捨てないように開かない
+                        if self.autowrite() {
+                            self.open(&workdir.join(&entry.path));
+                            opened = true;
+                        }
+                        break;
+                    }
+                    Ok(())
+                },
+                Event::Key(Key::Char('c')) => {
+                    if self.autowrite() {
+                        self.start_commit(&repo, &entries);
+                        opened = true;
+                    }
+                    break;
+                },
+                _ => Ok(()),
+            };
+            if let Err(e) = result {
+                self.message = e.message().to_string();
+            }
+        }
+
+        self.pane_cursor = None;
+        if !opened {
+            self.pane = saved_pane;
+        }
+        Ok(())
+    }
+
+    // コミットメッセージを書くためのバッファを開く
+    fn start_commit(&mut self, repo: &git2::Repository, entries: &[git::StatusEntry]) {
+        let path = repo.path().join("COMMIT_EDITMSG");
+        let mut template = String::from(
+            "\n# Please enter the commit message for your changes. Lines starting\n\
+             # with '#' will be ignored. Save to commit, an empty message aborts.\n#\n\
+             # Changes to be committed:\n",
+        );
+        for entry in entries.iter().filter(|entry| entry.staged.is_some()) {
+            template.push_str(&format!("#\t{}\n", entry.line()));
+        }
+        let _ = fs::write(&path, template);
+        self.open(&path);
+        self.pending_commit = true;
+    }
+
+    // 現在のファイルのリビジョン一覧を開く
+    fn file_history<T: Write, I: Iterator<Item = io::Result<Event>>>(
+        &mut self,
+        out: &mut T,
+        events: &mut I,
+    ) -> io::Result<()> {
+        let path = match self.path.clone() {
+            Some(path) => path,
+            None => {
+                self.message = tr!("no-file-name");
+                return Ok(());
+            },
+        };
+        let result = git::open_repository(&parent_dir(&path)).and_then(|repo| {
+            let relative = git::relative_path(&repo, &path)
+                .ok_or_else(|| git2::Error::from_str("file is not in the working tree"))?;
+            let revisions = git::file_history(&repo, &relative)?;
+            Ok((repo, relative, revisions))
+        });
+        let (repo, relative, revisions) = match result {
+            Ok(result) => result,
+            Err(e) => {
+                self.message = e.message().to_string();
+                return Ok(());
+            },
+        };
+        if revisions.is_empty() {
+            self.message = tr!("no-history");
+            return Ok(());
+        }
+
+        let lines = revisions.iter().map(|revision| revision.line()).collect();
+        let picker = Picker::new(lines, &tr!("history-help")).key('d');
+        let (selected, key) = match self.pick_from_pane_with(out, events, picker, |state, out, _| state.draw(out))? {
+            Some(picked) => picked,
+            None => return Ok(()),
+        };
+        let revision = &revisions[selected];
+        let contents = git::contents_at(&repo, revision.oid, &relative);
+        let contents = match contents {
+            Ok(contents) => contents,
+            Err(e) => {
+                self.message = e.message().to_string();
+                return Ok(());
+            },
+        };
+        if key == 'd' {
+            self.show_diff(contents, &relative);
+        } else if self.autowrite() {
+            self.set_buffer(to_buffer(&contents));
+            self.line_ending = LineEnding::detect(contents.as_bytes());
+            self.cursor = Cursor { row: 0, column: 0 };
+            self.row_offset = 0;
+            self.force_read_only();
+            self.message = tr!(
+                "viewing-revision",
+                self.display_path(),
+                format!("{:.7}", revision.oid)
+            );
+        }
+        Ok(())
+    }
+}
+
+// マウスを重ねたときに説明を出す見た目か
+fn has_tooltip(highlight: Highlight) -> bool {
+    matches!(highlight, Highlight::Special | Highlight::OverLimit)
+}
+
+fn mtime(path: &path::Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+// 書き込みとディスクへの反映のエラー (ENOSPC など) を全て返す
+fn write_file(path: &path::Path, bytes: &[u8], verify: bool) -> io::Result<()> {
+    let mut file = fs::File::create(path)?;
+    file.write_all(bytes)?;
+    file.flush()?;
+    file.sync_all()?;
+    drop(file);
+
+    if verify && fs::read(path)? != bytes {
+        return Err(io::Error::other("file on disk does not match the buffer"));
+    }
+    Ok(())
+}
+
+// ファイルの後ろに足す。なければ作る。最後の行に改行がなければ、改行してから足す
+fn append_file(path: &path::Path, bytes: &[u8]) -> io::Result<()> {
+    let mut file = fs::OpenOptions::new().read(true).append(true).create(true).open(path)?;
+    let len = file.metadata()?.len();
+    if len > 0 {
+        let mut last = [0];
+        file.seek(io::SeekFrom::Start(len - 1))?;
+        file.read_exact(&mut last)?;
+        if last[0] != b'\n' {
+            file.write_all(b"\n")?;
+        }
+    }
+    file.write_all(bytes)?;
+    file.flush()?;
+    file.sync_all()
+}
+
+// ファイルのあるディレクトリ。相対パスのファイル名だけならカレントディレクトリ
+fn parent_dir(path: &path::Path) -> path::PathBuf {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => path::PathBuf::from("."),
+    }
+}
+
+// ファイルの内容をバッファにする
+fn to_buffer(s: &str) -> Vec<Line> {
+    let buffer: Vec<Line> = s
+        .lines()
+        .map(|line| Line::from(line.trim_end()))
+        .collect();
+    if buffer.is_empty() {
+        vec![Line::new()]
+    } else {
+        buffer
+    }
+}
+
+// 表示幅が width に収まるように文字列を切り詰める
+fn truncate(s: &str, width: usize) -> String {
+    let mut result = String::new();
+    let mut w = 0;
+    for c in s.chars() {
+        w += special::char_width(c);
+        if w > width {
+            break;
+        }
+        result.push(c);
+    }
+    result
+}
+
+// : コマンドの名前の補完。ファイル名や画面の割り振りを取るコマンドは引数も補完する
+fn complete_command(input: &str) -> Vec<String> {
+    if let Some((command, arg)) = input.split_once(' ') {
+        let arg = arg.trim_start();
+        return match command {
+            "e" | "r" | "export" | "rename" | "new" | "saveas" | "open" => minibuffer::complete_path(arg)
+                .into_iter()
+                .map(|path| format!("{} {}", command, path))
+                .collect(),
+            "layout" => LAYOUT_PRESETS
+                .iter()
+                .filter(|(name, _)| name.starts_with(arg))
+                .map(|(name, _)| format!("layout {}", name))
+                .collect(),
+            _ => Vec::new(),
+        };
+    }
+    COMMANDS
+        .iter()
+        .filter(|command| command.starts_with(input))
+        .map(|command| command.to_string())
+        .collect()
+}
+
+// 端末にファイルをドロップすると、引用符で囲むか空白を \\ でエスケープしたパス、または file:// の URI が貼り付けられる
+// そのようなパスで、ファイルがあればそのパスを返す
+fn dropped_path(text: &str) -> Option<path::PathBuf> {
+    let text = text.trim();
+    if text.is_empty() || text.contains('\n') {
+        return None;
+    }
+    let unquoted = ['\'', '"']
+        .iter()
+        .find_map(|&q| text.strip_prefix(q)?.strip_suffix(q))
+        .map(str::to_string);
+    let path = match unquoted {
+        Some(path) => path,
+        None => match text.strip_prefix("file://") {
+            Some(uri) => percent_decode(uri.trim_start_matches(|c| c != '/'))?,
+            None => {
+                let mut path = String::new();
+                let mut chars = text.chars();
+                while let Some(c) = chars.next() {
+                    path.push(if c == '\\' { chars.next()? } else { c });
+                }
+                path
+            },
+        },
+    };
+    let path = path::PathBuf::from(path);
+    if path.is_file() {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+// %20 のような URI のエスケープを戻す
+fn percent_decode(s: &str) -> Option<String> {
+    let mut bytes = Vec::new();
+    let mut rest = s.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        if b == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(b);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+// 検索の入力欄の見出し
+fn search_label(regex: bool) -> String {
+    if regex {
+        tr!("regex-search")
+    } else {
+        tr!("search")
+    }
+}
+
+// "12" や "12:5" を (行, 列) にする。どちらも 1 から始まる
+fn parse_line_column(text: &str) -> Option<(usize, Option<usize>)> {
+    let number = |s: &str| s.trim().parse::<usize>().ok().filter(|&n| n > 0);
+    match text.split_once(':') {
+        Some((line, column)) => Some((number(line)?, Some(number(column)?))),
+        None => Some((number(text)?, None)),
+    }
+}
+
+fn check_line_column(text: &str) -> Result<(), String> {
+    parse_line_column(text).map(|_| ()).ok_or_else(|| tr!("invalid-line", text.trim()))
+}
+
+// 先頭の行の範囲 ('<,'> や % や 1,10 や .,$) と、残りのコマンドに分ける
+fn split_range(command: &str) -> (&str, &str) {
+    let end = command.find(|c: char| !(c.is_ascii_digit() || ",.$%'<>".contains(c))).unwrap_or(command.len());
+    command.split_at(end)
+}
+
+// [範囲]w パス か [範囲]w >> パス
+fn is_write(command: &str) -> bool {
+    let (range, rest) = split_range(command);
+    rest.starts_with("w ") || rest.starts_with("w>>") || (!range.is_empty() && rest == "w")
+}
+
+// s の次に記号が来れば置換のコマンド (specials などと区別する)
+fn is_substitute(command: &str) -> bool {
+    let mut chars = command.chars();
+    chars.next() == Some('s') && chars.next().is_some_and(|c| c.is_ascii_punctuation())
+}
+
+// s/old/new/[g] を (old, new, g があるか) に分ける。区切りの文字は \ で書ける
+fn parse_substitute(command: &str) -> Option<(String, String, bool)> {
+    let mut chars = command.chars().skip(1);
+    let delimiter = chars.next()?;
+    let mut parts = vec![String::new()];
+    let mut escaped = false;
+    for c in chars {
+        if escaped {
+            if c != delimiter {
+                parts.last_mut()?.push('\\');
+            }
+            parts.last_mut()?.push(c);
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == delimiter {
+            parts.push(String::new());
+        } else {
+            parts.last_mut()?.push(c);
+        }
+    }
+    let (from, to, flags) = match parts.as_slice() {
+        [from, to] => (from, to, ""),
+        [from, to, flags] => (from, to, flags.as_str()),
+        _ => return None,
+    };
+    if from.is_empty() || !(flags.is_empty() || flags == "g") {
+        return None;
+    }
+    Some((from.clone(), to.clone(), flags == "g"))
+}
+
+// `git commit -v` が挿入する切り取り線。これより下は全て捨てる
+fn is_scissors(line: &Line) -> bool {
+    let line: String = line.iter().collect();
+    line.starts_with("# ") && line.contains(">8")
+}
+
+fn main() {
+    let options = Options::from_args();
+    i18n::set_lang(i18n::detect());
+    logger::init(options.log_level);
+    log_info!("starting textedit {}", env!("CARGO_PKG_VERSION"));
+
+    // 端末を使わずに、色をつけた内容を標準出力に書き出して終わる
+    if let Some(format) = options.export {
+        let mut state = EditerState {
+            encoding: options.encoding,
+            theme: options.theme.as_deref().and_then(Theme::by_name).unwrap_or_default(),
+            ..EditerState::default()
+        };
+        if let Some(file) = options.files.first() {
+            state.open(file);
+        }
+        print!("{}", state.export(format));
+        process::exit(0);
+    }
+
+    // 端末を使わずに -c のコマンドを順に実行して終わる
+    if options.batch {
+        process::exit(batch(&options));
+    }
+
+    // 端末でなければ raw モードにできないので、パニックせずにエラーで終了する
+    if !termion::is_tty(&stdin()) || !termion::is_tty(&stdout()) {
+        eprintln!("testediter: {}", tr!("not-a-terminal"));
+        process::exit(1);
+    }
+
+    special::set_ambiguous_wide(options.ambiguous_width == AmbiguousWidth::Wide);
+    special::set_tab_width(options.tab_width);
+    let mut state = EditerState::new(&options);
+
+    match run(&mut state, &options) {
+        Ok(code) => process::exit(code),
+        Err(e) => {
+            log_error!("{}", e);
+            eprintln!("testediter: {}", e);
+            process::exit(1);
+        },
+    }
+}
+
+// -c で渡されたコマンドを画面なしで実行する。終了コードを返す
+// 保存しないまま終われば変更は捨てる (ex と同じ)
+fn batch(options: &Options) -> i32 {
+    let mut state = EditerState {
+        encoding: options.encoding,
+        ..EditerState::default()
+    };
+    if let Some(file) = options.files.first() {
+        state.open(file);
+    }
+    if options.readonly {
+        state.force_read_only();
+    }
+    for command in &options.commands {
+        state.message.clear();
+        let action = state.execute_command(&mut io::sink(), &mut std::iter::empty(), command);
+        match action {
+            Ok(Action::Continue) => {},
+            Ok(Action::Quit(code)) => return code,
+            Ok(Action::Failed) => {
+                eprintln!("testediter: {}", state.message);
+                return 1;
+            },
+            Err(e) => {
+                eprintln!("testediter: {}", e);
+                return 1;
+            },
+        }
+    }
+    0
+}
+
+// 読み終わるまで別のスレッドで読む
+fn read_to_end_in_background<R: Read + Send + 'static>(mut reader: R) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut bytes = Vec::new();
+        if let Err(e) = reader.read_to_end(&mut bytes) {
+            log_debug!("cannot read the command output: {}", e);
+        }
+        bytes
+    })
+}
+
+// フォーカスの報告を有効/無効にする
+const FOCUS_REPORTING_ON: &str = "\x1b[?1004h";
+const FOCUS_REPORTING_OFF: &str = "\x1b[?1004l";
+// 貼り付けた文字列を ESC [ 200 ~ と ESC [ 201 ~ で囲んで送ってもらう
+const BRACKETED_PASTE_ON: &str = "\x1b[?2004h";
+const BRACKETED_PASTE_OFF: &str = "\x1b[?2004l";
+// マウスの動きを (ボタンを押していなくても) SGR 形式で報告してもらう
+const MOUSE_TRACKING_ON: &str = "\x1b[?1003h\x1b[?1006h";
+const MOUSE_TRACKING_OFF: &str = "\x1b[?1006l\x1b[?1003l";
+// kitty キーボードプロトコルで、修飾キーの組み合わせを区別して送ってもらう。対応していない端末は無視する
+const KITTY_KEYBOARD_ON: &str = "\x1b[>1u";
+// 固定キーを使うときは、修飾キーだけを押したことも (8)、Shift で変わった文字も (4) 送ってもらう
+const KITTY_KEYBOARD_STICKY: &str = "\x1b[>13u";
+const KITTY_KEYBOARD_OFF: &str = "\x1b[<u";
+// フォーカスを得た/失ったときに端末から送られてくる
+const FOCUS_IN: &[u8] = b"\x1b[I";
+const FOCUS_OUT: &[u8] = b"\x1b[O";
+
+// 曖昧な幅の文字を実際に表示して、カーソルがどれだけ進むかで端末の扱いを調べる
+fn detect_ambiguous_wide<T: Write>(out: &mut T, events: &mut Input) -> io::Result<bool> {
+    write!(out, "{}{}\u{25cb}\x1b[6n", clear::All, cursor::Goto(1, 1))?;
+    out.flush()?;
+    // 応答は ESC [ 行 ; 列 R
+    let reply = events.wait_reply(Duration::from_millis(500), |bytes| {
+        bytes.starts_with(b"\x1b[") && bytes.ends_with(b"R")
+    });
+    let column = reply.and_then(|bytes| {
+        let reply = String::from_utf8_lossy(&bytes[2..bytes.len() - 1]).into_owned();
+        reply.split(';').nth(1)?.parse::<usize>().ok()
+    });
+    Ok(column == Some(3))
+}
+
+// 終了コードを返す
+fn run(state: &mut EditerState, options: &Options) -> io::Result<i32> {
+    let mut stdout = AlternateScreen::from(stdout().into_raw_mode()?);
+    let mut events = Input::spawn(Duration::from_millis(options.debounce));
+    state.cancel = events.cancel();
+    state.waker = events.waker();
+
+    if options.ambiguous_width == AmbiguousWidth::Auto {
+        let wide = detect_ambiguous_wide(&mut stdout, &mut events)?;
+        log_info!("ambiguous width detected as {}", if wide { "wide" } else { "narrow" });
+        special::set_ambiguous_wide(wide);
+        state.layouts.clear();
+        state.idle.schedule(idle::Task::WarmLayouts { row: 0 });
+        state.scroll();
+    }
+
+    // 端末のフォーカスが変わったら知らせてもらう
+    write!(stdout, "{}{}", FOCUS_REPORTING_ON, BRACKETED_PASTE_ON)?;
+    if options.kitty_keyboard {
+        write!(stdout, "{}", if options.sticky_keys { KITTY_KEYBOARD_STICKY } else { KITTY_KEYBOARD_ON })?;
+    }
+    if options.mouse {
+        write!(stdout, "{}", MOUSE_TRACKING_ON)?;
+    }
+    let result = event_loop(state, &mut stdout, &mut events);
+    state.remember_all_places();
+    if state.save_kill_ring {
+        if let Err(e) = state.kill_ring.save() {
+            log_warn!("cannot save the kill ring: {}", e);
+        }
+    }
+    if options.mouse {
+        write!(stdout, "{}", MOUSE_TRACKING_OFF)?;
+    }
+    if options.kitty_keyboard {
+        write!(stdout, "{}", KITTY_KEYBOARD_OFF)?;
+    }
+    write!(stdout, "{}{}", BRACKETED_PASTE_OFF, FOCUS_REPORTING_OFF)?;
+    stdout.flush()?;
+    result
+}
+
+fn event_loop<T: Write>(state: &mut EditerState, stdout: &mut T, events: &mut Input) -> io::Result<i32> {
+    state.confirm_large(stdout, events)?;
+    state.draw(stdout)?;
+    let mut drawn = Instant::now();
+
+    loop {
+        // 入力が途切れている間だけ、急がない仕事を少しずつ進める
+        while let Some(wait) = state.idle.wait() {
+            if events.poll(wait) {
+                break;
+            }
+            if let Some(task) = state.idle.take() {
+                match state.idle_step(task) {
+                    idle::Step::Continue(rest) => state.idle.resume(rest),
+                    idle::Step::Done => {},
+                    idle::Step::Redraw => state.draw(stdout)?,
+                }
+            }
+        }
+        let evt = match events.next() {
+            Some(evt) => evt,
+            None => break,
+        };
+        // 裏で調べたブランチが届いた。打ったキーではないので、メッセージも消さずに描き直すだけにする
+        if matches!(&evt, Ok(Event::Unsupported(bytes)) if bytes == GIT_BRANCH_READY) {
+            if state.receive_git_branch() {
+                state.draw(stdout)?;
+                drawn = Instant::now();
+            }
+            continue;
+        }
+        state.idle.touch();
+        let evt = evt?;
+        // マウスを動かしただけなら、メッセージは消さずに説明の窓だけを出し直す
+        if let Event::Unsupported(bytes) = &evt {
+            if let Some((row, col)) = input::mouse_motion(bytes) {
+                if state.hover(row, col) {
+                    state.draw(stdout)?;
+                    drawn = Instant::now();
+                }
+                continue;
+            }
+        }
+        state.message.clear();
+        state.tooltip = None;
+        log_debug!("event: {:?}", evt);
+        if let Event::Key(_) = evt {
+            state.stats.keystroke(state.path.as_deref());
+        }
+        let welcomed = state.welcome_key(stdout, events, &evt)?;
+        // Shift を押しながらの矢印キーは、選択を広げてから普通の矢印キーとして扱う
+        let mut shifted = false;
+        let evt = match &evt {
+            Event::Unsupported(bytes) => match input::shift_arrow(bytes) {
+                Some(key) => {
+                    state.extend_selection();
+                    shifted = true;
+                    Event::Key(key)
+                },
+                None if input::is_shift_f3(bytes) => {
+                    state.search_again(false);
+                    Event::Unsupported(Vec::new())
+                },
+                None => evt,
+            },
+            _ => evt,
+        };
+        // 修飾キーだけを押して離したら、次のキーにつける
+        if let Event::Unsupported(bytes) = &evt {
+            if let Some(modifier) = sticky::modifier_press(bytes) {
+                state.sticky.toggle(modifier);
+            }
+        }
+        let evt = match evt {
+            Event::Key(key) if state.sticky.active() => Event::Key(state.sticky.apply(key)),
+            evt => evt,
+        };
+        // vim のモードでは、キーをまず vim のコマンドとして読む
+        let vim_command = match (&evt, state.vim.as_mut()) {
+            (Event::Key(key), Some(vim)) if !welcomed && state.pending_keys.is_empty() => vim.key(*key),
+            _ => vim::Command::Pass,
+        };
+        // キーを割り当てにしたがって操作にする。続きのあるキーなら次のキーを待つ
+        let action = match &evt {
+            Event::Key(key) if !welcomed && vim_command == vim::Command::Pass => state.resolve_key(*key),
+            Event::Unsupported(bytes) if !welcomed => match (input::ctrl_key(bytes), input::alt_key(bytes)) {
+                (Some(Key::Home), _) => Some(KeyAction::BufferStart),
+                (Some(Key::End), _) => Some(KeyAction::BufferEnd),
+                (Some(Key::Left), _) => Some(KeyAction::WordBackward),
+                (Some(Key::Right), _) => Some(KeyAction::WordForward),
+                (_, Some(Key::Left)) => Some(KeyAction::JumpBack),
+                (_, Some(Key::Right)) => Some(KeyAction::JumpForward),
+                _ => None,
+            },
+            _ => None,
+        };
+        // 続けて打った単語は1回で取り消せるように、空白とそれ以外の操作の前で区切る
+        if !matches!(action, Some(KeyAction::Insert(c)) if !c.is_whitespace()) {
+            state.history.seal(state.cursor);
+        }
+        // 続けて消した行は、まとめて1回で貼り付けられるようにする
+        if !matches!(action, Some(KeyAction::KillLine) | Some(KeyAction::KillWholeLine)) {
+            state.kill_ring.interrupt();
+        }
+        if !shifted
+            && !state.mark
+            && matches!(
+                action,
+                Some(
+                    KeyAction::Up
+                        | KeyAction::Down
+                        | KeyAction::Left
+                        | KeyAction::Right
+                        | KeyAction::PageUp
+                        | KeyAction::PageDown
+                )
+            )
+        {
+            state.clear_selection();
+        }
+        let vertical = matches!(
+            action,
+            Some(
+                KeyAction::Up
+                    | KeyAction::Down
+                    | KeyAction::UpAlternate
+                    | KeyAction::DownAlternate
+                    | KeyAction::PageUp
+                    | KeyAction::PageDown
+            )
+        ) || matches!(vim_command, vim::Command::Move(Motion::Up | Motion::Down, _));
+        // 上下以外の操作をしたら、戻りたい列を忘れる
+        if !vertical {
+            state.goal = None;
+        }
+        // ほかの選択は、文字を打つ、消す、コピーする、切り取るときだけ残す。Esc はほかの選択をやめるだけにする
+        let keeps_selections = vim_command == vim::Command::Pass
+            && matches!(
+                action,
+                Some(
+                    KeyAction::Insert(_)
+                        | KeyAction::Tab
+                        | KeyAction::Backspace
+                        | KeyAction::Delete
+                        | KeyAction::Copy
+                        | KeyAction::CopyOrQuit
+                        | KeyAction::Cut
+                )
+            );
+        let action = if !keeps_selections && !state.extra_selections.is_empty() {
+            state.extra_selections.clear();
+            action.filter(|action| *action != KeyAction::Cancel)
+        } else {
+            action
+        };
+        match (action, evt) {
+            _ if welcomed => {},
+            _ if vim_command != vim::Command::Pass => {
+                if let Some(code) = state.run_vim_command(stdout, events, vim_command)? {
+                    return Ok(code);
+                }
+            },
+            (Some(action), _) => {
+                if let Some(code) = state.run_key_action(stdout, events, action)? {
+                    return Ok(code);
+                }
+            },
+            (None, Event::Mouse(event)) => {
+                state.mouse(event);
+            },
+            (None, Event::Unsupported(bytes)) if bytes == PASTE_START => {
+                let text = events.read_paste(PASTE_END)?;
+                state.paste(stdout, events, &text)?;
+            },
+            (None, Event::Unsupported(bytes)) if bytes == FOCUS_IN => {
+                state.focus_gained();
+            },
+            (None, Event::Unsupported(bytes)) if bytes == FOCUS_OUT => {
+                state.focus_lost();
+            },
+            _ => {},
+        }
+        state.vim_clamp();
+        state.confirm_large(stdout, events)?;
+        // 貼り付けやキーリピートで入力が溜まっている間は、描画を間引いてまとめて処理する
+        if events.pending() && drawn.elapsed() < FRAME_INTERVAL {
+            continue;
         }
-        state.draw(&mut stdout);
+        state.draw(stdout)?;
+        drawn = Instant::now();
     }
+    Ok(0)
 }
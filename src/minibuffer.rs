@@ -0,0 +1,187 @@
+use std::fs;
+use std::path::Path;
+use termion::event::Key;
+
+// 入力を確定してよいか調べる。だめなら理由を返す
+pub type Validate = fn(&str) -> Result<(), String>;
+// 入力の続きの候補を返す
+pub type Complete = fn(&str) -> Vec<String>;
+
+// キーを処理した結果
+pub enum Outcome {
+    Pending,
+    Submit(String),
+    Cancel,
+}
+
+// 最下行での1行入力。ファイル名や : コマンドなど、入力させるものはすべてこれを使う
+pub struct Minibuffer {
+    pub label: String,
+    pub input: String,
+    // 何も入力していないときに薄く出す説明
+    pub placeholder: String,
+    // 補完の候補や確定できない理由
+    pub hint: Option<String>,
+    // 履歴をどの入力と共有するか
+    pub history: &'static str,
+    validate: Option<Validate>,
+    complete: Option<Complete>,
+    // 履歴をいくつさかのぼっているか
+    back: usize,
+    // 履歴をたどる前に入力していたもの
+    draft: String,
+}
+
+impl Minibuffer {
+    pub fn new(label: &str) -> Self {
+        Minibuffer {
+            label: label.to_string(),
+            input: String::new(),
+            placeholder: String::new(),
+            hint: None,
+            history: "",
+            validate: None,
+            complete: None,
+            back: 0,
+            draft: String::new(),
+        }
+    }
+
+    pub fn placeholder(mut self, placeholder: &str) -> Self {
+        self.placeholder = placeholder.to_string();
+        self
+    }
+
+    // 最初から入力してある文字列
+    pub fn input(mut self, input: &str) -> Self {
+        self.input = input.to_string();
+        self
+    }
+
+    pub fn history(mut self, history: &'static str) -> Self {
+        self.history = history;
+        self
+    }
+
+    pub fn validate(mut self, validate: Validate) -> Self {
+        self.validate = Some(validate);
+        self
+    }
+
+    pub fn complete(mut self, complete: Complete) -> Self {
+        self.complete = Some(complete);
+        self
+    }
+
+    // history は古いものから順に並んだ、この入力の履歴
+    pub fn handle(&mut self, key: Key, history: &[String]) -> Outcome {
+        self.hint = None;
+        match key {
+            Key::Char('\n') => {
+                if let Some(validate) = self.validate {
+                    if let Err(reason) = validate(&self.input) {
+                        self.hint = Some(reason);
+                        return Outcome::Pending;
+                    }
+                }
+                return Outcome::Submit(self.input.clone());
+            },
+            Key::Esc | Key::Ctrl('c') => return Outcome::Cancel,
+            Key::Backspace => {
+                self.input.pop();
+                self.back = 0;
+            },
+            Key::Up if self.back < history.len() => {
+                if self.back == 0 {
+                    self.draft = self.input.clone();
+                }
+                self.back += 1;
+                self.input = history[history.len() - self.back].clone();
+            },
+            Key::Down if self.back > 0 => {
+                self.back -= 1;
+                self.input = if self.back == 0 {
+                    self.draft.clone()
+                } else {
+                    history[history.len() - self.back].clone()
+                };
+            },
+            Key::Char('\t') => self.complete_input(),
+            Key::Char(c) if !c.is_control() => {
+                self.input.push(c);
+                self.back = 0;
+            },
+            _ => {},
+        }
+        Outcome::Pending
+    }
+
+    // 候補が1つならそれにし、複数なら共通する部分まで埋めて候補を見せる
+    fn complete_input(&mut self) {
+        let complete = match self.complete {
+            Some(complete) => complete,
+            None => return,
+        };
+        let candidates = complete(&self.input);
+        match candidates.len() {
+            0 => self.hint = Some(tr!("no-completions")),
+            1 => self.input = candidates[0].clone(),
+            _ => {
+                self.input = common_prefix(&candidates);
+                self.hint = Some(candidates.join("\n"));
+            },
+        }
+        self.back = 0;
+    }
+}
+
+fn common_prefix(candidates: &[String]) -> String {
+    let first = &candidates[0];
+    let mut len = first.len();
+    for candidate in &candidates[1..] {
+        len = first
+            .char_indices()
+            .zip(candidate.chars())
+            .take_while(|((_, a), b)| a == b)
+            .map(|((i, a), _)| i + a.len_utf8())
+            .last()
+            .unwrap_or(0)
+            .min(len);
+    }
+    first[..len].to_string()
+}
+
+// ファイル名の補完。ディレクトリには / をつける
+pub fn complete_path(input: &str) -> Vec<String> {
+    let (dir, prefix) = match input.rfind('/') {
+        Some(i) => (&input[..=i], &input[i + 1..]),
+        None => ("", input),
+    };
+    let entries = match fs::read_dir(if dir.is_empty() { Path::new(".") } else { Path::new(dir) }) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut candidates: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            // 隠しファイルは . から打ったときだけ出す
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+            let slash = if entry.path().is_dir() { "/" } else { "" };
+            Some(format!("{}{}{}", dir, name, slash))
+        })
+        .collect();
+    candidates.sort();
+    candidates
+}
+
+// 空でないこと
+pub fn not_empty(input: &str) -> Result<(), String> {
+    if input.trim().is_empty() {
+        Err(tr!("input-required"))
+    } else {
+        Ok(())
+    }
+}
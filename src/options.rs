@@ -0,0 +1,511 @@
+use crate::config::{self, Config};
+use crate::export::Format;
+use crate::keymap::KeyAction;
+use crate::logger::Level;
+use crate::status::StatusLayout;
+use clap::{value_t, App, Arg, ArgMatches};
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+use termion::event::Key;
+
+pub use crate::encoding::Encoding;
+
+// 東アジアの曖昧な幅の文字の扱い
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AmbiguousWidth {
+    Narrow,
+    Wide,
+    // 端末に実際に表示させて確かめる
+    Auto,
+}
+
+impl FromStr for AmbiguousWidth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "narrow" => Ok(AmbiguousWidth::Narrow),
+            "wide" => Ok(AmbiguousWidth::Wide),
+            "auto" => Ok(AmbiguousWidth::Auto),
+            _ => Err(format!("unknown ambiguous width: {}", s)),
+        }
+    }
+}
+
+// タブ文字の幅の既定値
+const TAB_WIDTH: usize = 4;
+
+// コマンドライン引数と設定ファイル。コマンドラインで指定したものを優先する
+pub struct Options {
+    // 開くファイル。2つ目からは裏のバッファに開く
+    pub files: Vec<PathBuf>,
+    pub readonly: bool,
+    // 最初にカーソルを置く行 (1 始まり)
+    pub line: Option<usize>,
+    // 最初にカーソルを置く、正規表現に一致するところ (+/pattern)
+    pub search: Option<String>,
+    pub theme: Option<String>,
+    pub keymap: Option<String>,
+    pub config: Option<PathBuf>,
+    // 設定ファイルの読めなかった行
+    pub config_errors: Vec<String>,
+    pub encoding: Encoding,
+    pub wrap: bool,
+    pub tab_width: usize,
+    pub expand_tab: bool,
+    pub verify_save: bool,
+    pub ambiguous_width: AmbiguousWidth,
+    pub accessible: bool,
+    pub visual_lines: bool,
+    pub line_numbers: bool,
+    pub relative_numbers: bool,
+    pub scroll_bind: bool,
+    // 差分で空白や大文字小文字の違いを無視する
+    pub diff_ignore_whitespace: bool,
+    pub diff_ignore_case: bool,
+    // ステータス行に並べる項目
+    pub status: StatusLayout,
+    pub autowrite: bool,
+    pub focus_autosave: bool,
+    pub auto_reload: bool,
+    pub kitty_keyboard: bool,
+    pub mouse: bool,
+    // vim のようにモードを切り替えて編集する
+    pub vim: bool,
+    // 修飾キーを押して離してから次のキーを押せるようにする
+    pub sticky_keys: bool,
+    // 消したりコピーしたりしたものを、次に起動したときにも貼り付けられるように残す
+    pub save_kill_ring: bool,
+    // 同じキーがこの時間 (ミリ秒) より短い間に続けて届いたら捨てる。0 なら捨てない
+    pub debounce: u64,
+    // これより大きなファイル (MB) は、開く前にどう開くかを聞く
+    pub large_file: u64,
+    // 端末を使わずに色つきで標準出力に書き出す
+    pub export: Option<Format>,
+    // 端末を使わずに commands を実行する
+    pub batch: bool,
+    pub commands: Vec<String>,
+    // 編集をすべて書き足していくファイル
+    pub journal: Option<PathBuf>,
+    pub log_level: Level,
+    // 設定ファイルで変えたキーの割り当て
+    pub keys: Vec<(Vec<Key>, Option<KeyAction>)>,
+}
+
+impl Options {
+    pub fn from_args() -> Self {
+        let matches = App::new("testediter")
+            .about("A text editer")
+            .bin_name("testediter")
+            .arg(
+                Arg::with_name("file")
+                    .multiple(true)
+                    .help("Files to open; +N or +/PATTERN before them moves to line N or the first match"),
+            )
+            .arg(
+                Arg::with_name("readonly")
+                    .long("readonly")
+                    .help("Opens the file read-only"),
+            )
+            .arg(
+                Arg::with_name("line")
+                    .long("line")
+                    .value_name("N")
+                    .help("Moves the cursor to line N"),
+            )
+            .arg(
+                Arg::with_name("theme")
+                    .long("theme")
+                    .value_name("NAME")
+                    .help("Sets the color theme (dark, light)"),
+            )
+            .arg(
+                Arg::with_name("keymap")
+                    .long("keymap")
+                    .value_name("NAME")
+                    .help("Adds a key binding preset on top of the defaults (default, emacs)"),
+            )
+            .arg(
+                Arg::with_name("config")
+                    .long("config")
+                    .value_name("PATH")
+                    .help("Reads the configuration from PATH instead of ~/.config/textedit/config.toml"),
+            )
+            .arg(
+                Arg::with_name("encoding")
+                    .long("encoding")
+                    .value_name("ENC")
+                    .help("Sets the file encoding (utf-8, latin1)"),
+            )
+            .arg(
+                Arg::with_name("no-wrap")
+                    .long("no-wrap")
+                    .overrides_with("wrap")
+                    .help("Disables line wrapping"),
+            )
+            .arg(
+                Arg::with_name("wrap")
+                    .long("wrap")
+                    .overrides_with("no-wrap")
+                    .help("Wraps long lines (the default)"),
+            )
+            .arg(
+                Arg::with_name("tab-width")
+                    .long("tab-width")
+                    .value_name("N")
+                    .help("Sets the display width of a tab (default 4)"),
+            )
+            .arg(
+                Arg::with_name("verify-save")
+                    .long("verify-save")
+                    .overrides_with("no-verify-save")
+                    .help("Reads the file back after saving to verify it"),
+            )
+            .arg(
+                Arg::with_name("no-verify-save")
+                    .long("no-verify-save")
+                    .overrides_with("verify-save")
+                    .help("Does not read the file back after saving"),
+            )
+            .arg(
+                Arg::with_name("ambiwidth")
+                    .long("ambiwidth")
+                    .value_name("WIDTH")
+                    .help("Width of East Asian ambiguous characters (narrow, wide, auto)"),
+            )
+            .arg(
+                Arg::with_name("accessible")
+                    .long("accessible")
+                    .help("Screen-reader-friendly output"),
+            )
+            .arg(
+                Arg::with_name("visual-lines")
+                    .long("visual-lines")
+                    .overrides_with("no-visual-lines")
+                    .help("Moves Up/Down by wrapped display lines (Alt+k/j move by logical lines)"),
+            )
+            .arg(
+                Arg::with_name("no-visual-lines")
+                    .long("no-visual-lines")
+                    .overrides_with("visual-lines")
+                    .help("Moves Up/Down by logical lines (the default)"),
+            )
+            .arg(
+                Arg::with_name("line-numbers")
+                    .long("line-numbers")
+                    .overrides_with("no-line-numbers")
+                    .help("Shows line numbers (toggle with :number)"),
+            )
+            .arg(
+                Arg::with_name("no-line-numbers")
+                    .long("no-line-numbers")
+                    .overrides_with("line-numbers")
+                    .help("Hides line numbers (the default)"),
+            )
+            .arg(
+                Arg::with_name("relative-numbers")
+                    .long("relative-numbers")
+                    .overrides_with("no-relative-numbers")
+                    .help("Numbers lines by their distance from the cursor line (toggle with Alt+# or :relative)"),
+            )
+            .arg(
+                Arg::with_name("no-relative-numbers")
+                    .long("no-relative-numbers")
+                    .overrides_with("relative-numbers")
+                    .help("Numbers lines from the top of the file (the default)"),
+            )
+            .arg(
+                Arg::with_name("scroll-bind")
+                    .long("scroll-bind")
+                    .overrides_with("no-scroll-bind")
+                    .help("Scrolls the diff pane together with the text (toggle with :scrollbind)"),
+            )
+            .arg(
+                Arg::with_name("no-scroll-bind")
+                    .long("no-scroll-bind")
+                    .overrides_with("scroll-bind")
+                    .help("Scrolls the diff pane on its own (the default)"),
+            )
+            .arg(
+                Arg::with_name("diff-ignore-whitespace")
+                    .long("diff-ignore-whitespace")
+                    .help("Ignores whitespace-only changes in diffs (toggle with :diffwhite)"),
+            )
+            .arg(
+                Arg::with_name("diff-ignore-case")
+                    .long("diff-ignore-case")
+                    .help("Ignores case changes in diffs (toggle with :diffcase)"),
+            )
+            .arg(
+                Arg::with_name("status")
+                    .long("status")
+                    .value_name("SEGMENTS")
+                    .help("Sets the status line segments as LEFT|RIGHT lists of mode, path, args, modified, encoding, syntax, branch, position and columns (default: path,args,modified,mode|branch,position)"),
+            )
+            .arg(
+                Arg::with_name("autowrite")
+                    .long("autowrite")
+                    .overrides_with("no-autowrite")
+                    .help("Saves the file before opening another file or running git operations"),
+            )
+            .arg(
+                Arg::with_name("no-autowrite")
+                    .long("no-autowrite")
+                    .overrides_with("autowrite")
+                    .help("Does not save before opening another file or running git operations"),
+            )
+            .arg(
+                Arg::with_name("focus-autosave")
+                    .long("focus-autosave")
+                    .overrides_with("no-focus-autosave")
+                    .help("Saves the file when the terminal loses focus"),
+            )
+            .arg(
+                Arg::with_name("no-focus-autosave")
+                    .long("no-focus-autosave")
+                    .overrides_with("focus-autosave")
+                    .help("Does not save when the terminal loses focus"),
+            )
+            .arg(
+                Arg::with_name("no-auto-reload")
+                    .long("no-auto-reload")
+                    .overrides_with("auto-reload")
+                    .help("Disables reloading unmodified files changed on disk"),
+            )
+            .arg(
+                Arg::with_name("auto-reload")
+                    .long("auto-reload")
+                    .overrides_with("no-auto-reload")
+                    .help("Reloads unmodified files changed on disk (the default)"),
+            )
+            .arg(
+                Arg::with_name("no-kitty-keyboard")
+                    .long("no-kitty-keyboard")
+                    .help("Does not ask the terminal for the kitty keyboard protocol"),
+            )
+            .arg(
+                Arg::with_name("mouse")
+                    .long("mouse")
+                    .overrides_with("no-mouse")
+                    .help("Enables the mouse (click to move, drag to select, wheel to scroll, hover over highlights)"),
+            )
+            .arg(
+                Arg::with_name("no-mouse")
+                    .long("no-mouse")
+                    .overrides_with("mouse")
+                    .help("Disables the mouse (the default)"),
+            )
+            .arg(
+                Arg::with_name("vim")
+                    .long("vim")
+                    .overrides_with("no-vim")
+                    .help("Starts in vim-style modal editing (normal, insert and visual modes)"),
+            )
+            .arg(
+                Arg::with_name("no-vim")
+                    .long("no-vim")
+                    .overrides_with("vim")
+                    .help("Starts in modeless editing (the default)"),
+            )
+            .arg(
+                Arg::with_name("sticky-keys")
+                    .long("sticky-keys")
+                    .overrides_with("no-sticky-keys")
+                    .help("Lets Ctrl and Alt be pressed and released before the next key (kitty keyboard protocol)"),
+            )
+            .arg(
+                Arg::with_name("no-sticky-keys")
+                    .long("no-sticky-keys")
+                    .overrides_with("sticky-keys")
+                    .help("Disables sticky keys (the default)"),
+            )
+            .arg(
+                Arg::with_name("save-kill-ring")
+                    .long("save-kill-ring")
+                    .overrides_with("no-save-kill-ring")
+                    .help("Keeps the copied and cut text for the next session (see :kills)"),
+            )
+            .arg(
+                Arg::with_name("no-save-kill-ring")
+                    .long("no-save-kill-ring")
+                    .overrides_with("save-kill-ring")
+                    .help("Does not keep the copied and cut text for the next session"),
+            )
+            .arg(
+                Arg::with_name("debounce")
+                    .long("debounce")
+                    .value_name("MS")
+                    .help("Ignores a key pressed again within MS milliseconds (for bouncy keyboards or tremors)"),
+            )
+            .arg(
+                Arg::with_name("large-file")
+                    .long("large-file")
+                    .value_name("MB")
+                    .help("Asks before opening files larger than MB megabytes (default 64)"),
+            )
+            .arg(
+                Arg::with_name("export")
+                    .long("export")
+                    .value_name("FORMAT")
+                    .help("Writes the file with highlighting to standard output as html or ansi and exits"),
+            )
+            .arg(
+                Arg::with_name("batch")
+                    .long("batch")
+                    .help("Runs the -c commands on the file without a screen and exits"),
+            )
+            .arg(
+                Arg::with_name("command")
+                    .short("c")
+                    .value_name("COMMAND")
+                    .multiple(true)
+                    .number_of_values(1)
+                    .help("Runs COMMAND (a : command such as s/old/new/g or w) in batch mode"),
+            )
+            .arg(
+                Arg::with_name("journal")
+                    .long("journal")
+                    .value_name("PATH")
+                    .help("Appends every edit to PATH so the editing history can be replayed"),
+            )
+            .arg(
+                Arg::with_name("log-level")
+                    .long("log-level")
+                    .value_name("LEVEL")
+                    .help("Sets the log level (off, error, warn, info, debug)"),
+            )
+            .get_matches();
+
+        // 既定の場所に設定ファイルがないのは普通のことなので黙っている
+        let config_path = matches.value_of_os("config").map(PathBuf::from).or_else(config::default_path);
+        let (config, config_errors) = match config_path.as_deref().map(|path| (path, config::load(path))) {
+            Some((_, Ok(loaded))) => loaded,
+            Some((path, Err(e))) if e.kind() != io::ErrorKind::NotFound => {
+                (Config::default(), vec![format!("{}: {}", path.display(), e)])
+            },
+            _ => (Config::default(), Vec::new()),
+        };
+
+        let mut line = if matches.is_present("line") {
+            Some(value_t!(matches, "line", usize).unwrap_or_else(|e| e.exit()))
+        } else {
+            None
+        };
+        // vi のように +120 で行、+/pattern で一致するところ、+ だけなら最後の行に移る
+        // + で始まる名前のファイルは ./+name のように書く
+        let mut search = None;
+        let mut files = Vec::new();
+        for value in matches.values_of_os("file").into_iter().flatten() {
+            match value.to_str().and_then(|arg| arg.strip_prefix('+')) {
+                Some(pattern) if pattern.starts_with('/') => search = Some(pattern[1..].to_string()),
+                Some(number) if number.bytes().all(|b| b.is_ascii_digit()) => {
+                    line = Some(number.parse().unwrap_or(usize::MAX));
+                },
+                _ => files.push(PathBuf::from(value)),
+            }
+        }
+        let encoding = if matches.is_present("encoding") {
+            value_t!(matches, "encoding", Encoding).unwrap_or_else(|e| e.exit())
+        } else {
+            config.encoding.unwrap_or(Encoding::Utf8)
+        };
+        let ambiguous_width = if matches.is_present("ambiwidth") {
+            value_t!(matches, "ambiwidth", AmbiguousWidth).unwrap_or_else(|e| e.exit())
+        } else {
+            config.ambiguous_width.unwrap_or(AmbiguousWidth::Narrow)
+        };
+        let log_level = if matches.is_present("log-level") {
+            value_t!(matches, "log-level", Level).unwrap_or_else(|e| e.exit())
+        } else {
+            Level::Warn
+        };
+        let large_file = if matches.is_present("large-file") {
+            value_t!(matches, "large-file", u64).unwrap_or_else(|e| e.exit())
+        } else {
+            config.large_file.unwrap_or(64)
+        };
+        let debounce = if matches.is_present("debounce") {
+            value_t!(matches, "debounce", u64).unwrap_or_else(|e| e.exit())
+        } else {
+            config.debounce.unwrap_or(0)
+        };
+        let status = if matches.is_present("status") {
+            value_t!(matches, "status", StatusLayout).unwrap_or_else(|e| e.exit())
+        } else {
+            config.status.clone().unwrap_or_default()
+        };
+        let tab_width = if matches.is_present("tab-width") {
+            value_t!(matches, "tab-width", usize).unwrap_or_else(|e| e.exit()).clamp(1, 16)
+        } else {
+            config.tab_width.unwrap_or(TAB_WIDTH)
+        };
+        let export = if matches.is_present("export") {
+            Some(value_t!(matches, "export", Format).unwrap_or_else(|e| e.exit()))
+        } else {
+            None
+        };
+
+        Options {
+            files,
+            readonly: matches.is_present("readonly"),
+            line,
+            search,
+            theme: matches.value_of("theme").map(str::to_string).or(config.theme),
+            keymap: matches.value_of("keymap").map(str::to_string).or(config.keymap),
+            config: matches.value_of_os("config").map(PathBuf::from),
+            config_errors,
+            encoding,
+            wrap: switch(&matches, "wrap", "no-wrap", config.wrap, true),
+            tab_width,
+            expand_tab: config.expand_tab.unwrap_or(false),
+            verify_save: switch(&matches, "verify-save", "no-verify-save", config.verify_save, false),
+            ambiguous_width,
+            accessible: matches.is_present("accessible"),
+            visual_lines: switch(&matches, "visual-lines", "no-visual-lines", config.visual_lines, false),
+            line_numbers: switch(&matches, "line-numbers", "no-line-numbers", config.line_numbers, false),
+            relative_numbers: switch(
+                &matches,
+                "relative-numbers",
+                "no-relative-numbers",
+                config.relative_numbers,
+                false,
+            ),
+            scroll_bind: switch(&matches, "scroll-bind", "no-scroll-bind", config.scroll_bind, false),
+            diff_ignore_whitespace: matches.is_present("diff-ignore-whitespace"),
+            diff_ignore_case: matches.is_present("diff-ignore-case"),
+            status,
+            autowrite: switch(&matches, "autowrite", "no-autowrite", config.autowrite, false),
+            focus_autosave: switch(&matches, "focus-autosave", "no-focus-autosave", config.focus_autosave, false),
+            auto_reload: switch(&matches, "auto-reload", "no-auto-reload", config.auto_reload, true),
+            kitty_keyboard: !matches.is_present("no-kitty-keyboard"),
+            mouse: switch(&matches, "mouse", "no-mouse", config.mouse, false),
+            vim: switch(&matches, "vim", "no-vim", config.vim, false),
+            sticky_keys: switch(&matches, "sticky-keys", "no-sticky-keys", config.sticky_keys, false),
+            save_kill_ring: switch(&matches, "save-kill-ring", "no-save-kill-ring", config.save_kill_ring, false),
+            debounce,
+            large_file,
+            export,
+            batch: matches.is_present("batch"),
+            commands: matches
+                .values_of("command")
+                .map(|values| values.map(str::to_string).collect())
+                .unwrap_or_default(),
+            journal: matches.value_of_os("journal").map(PathBuf::from),
+            log_level,
+            keys: config.keys,
+        }
+    }
+}
+
+// 設定ファイルでも変えられる有効/無効。コマンドラインの --on か --off があれば、設定ファイルよりそちらを使う
+// 両方あれば overrides_with で後に書いたほうだけが残る
+fn switch(matches: &ArgMatches, on: &str, off: &str, config: Option<bool>, default: bool) -> bool {
+    if matches.is_present(on) {
+        true
+    } else if matches.is_present(off) {
+        false
+    } else {
+        config.unwrap_or(default)
+    }
+}
@@ -0,0 +1,75 @@
+use std::cmp::min;
+use termion::event::Key;
+use crate::dialog::Answer;
+
+// 下のペインに並べて選ばせる一覧
+// 上下の矢印か k j で選んでいる項目を動かし、Enter か key で加えたキーで決める。q、Esc、Ctrl+C でやめる
+pub struct Picker {
+    lines: Vec<String>,
+    // 項目のペインでの行。見出しの行は選べない
+    rows: Vec<usize>,
+    selected: usize,
+    help: String,
+    keys: Vec<char>,
+}
+
+impl Picker {
+    // 1行に1つの項目を並べる
+    pub fn new(lines: Vec<String>, help: &str) -> Self {
+        let rows = (0..lines.len()).collect();
+        Picker { lines, rows, selected: 0, help: help.to_string(), keys: vec!['\n'] }
+    }
+
+    // 見出しの行をはさむときは、項目の行だけを渡す
+    pub fn rows(mut self, rows: Vec<usize>) -> Self {
+        self.rows = rows;
+        self.selected = min(self.selected, self.rows.len().saturating_sub(1));
+        self
+    }
+
+    // 最初に選んでおく項目
+    pub fn selected(mut self, index: usize) -> Self {
+        self.selected = min(index, self.rows.len().saturating_sub(1));
+        self
+    }
+
+    // Enter のほかに、選んだ項目で決めるキー
+    pub fn key(mut self, c: char) -> Self {
+        self.keys.push(c);
+        self
+    }
+
+    pub fn handle(&mut self, key: Key) -> Answer {
+        match key {
+            Key::Char('q') | Key::Esc | Key::Ctrl('c') => return Answer::Cancel,
+            Key::Up | Key::Char('k') => {
+                self.selected = self.selected.saturating_sub(1);
+            },
+            Key::Down | Key::Char('j') => {
+                self.selected = min(self.selected + 1, self.rows.len().saturating_sub(1));
+            },
+            Key::Char(c) if self.keys.contains(&c) && !self.rows.is_empty() => return Answer::Chosen(c),
+            _ => {},
+        }
+        Answer::Pending
+    }
+
+    // ペインに出す行。一度だけ取り出せる
+    pub fn take_lines(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.lines)
+    }
+
+    // 選んでいる項目の番号
+    pub fn index(&self) -> usize {
+        self.selected
+    }
+
+    // 選んでいる項目のペインでの行
+    pub fn row(&self) -> Option<usize> {
+        self.rows.get(self.selected).copied()
+    }
+
+    pub fn help(&self) -> &str {
+        &self.help
+    }
+}
@@ -0,0 +1,224 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use rusqlite::{params, Connection, OptionalExtension};
+use crate::logger;
+use crate::project;
+use crate::Cursor;
+
+// 覚えておく移動の履歴の数
+pub const JUMPS_LIMIT: usize = 100;
+
+// 1つのファイルについて、次に開いたときのために覚えておく位置
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Place {
+    // 最後にいたカーソルの位置
+    pub cursor: Option<Cursor>,
+    // a から z の名前をつけた印
+    pub marks: Vec<(char, Cursor)>,
+}
+
+// 移動の履歴の1つ。ファイルをまたいで戻れるように、絶対パスで持つ
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Jump {
+    pub path: PathBuf,
+    pub pos: Cursor,
+}
+
+// ファイルはルートからの相対パス (ルートの外なら絶対パス) で区別する
+// 移動の履歴は id の順が古い順
+// 書くたびにディスクと同期しなくてもよいので WAL にして、同期はチェックポイントのときだけにする
+const SCHEMA: &str = "
+    PRAGMA journal_mode = WAL;
+    PRAGMA synchronous = NORMAL;
+    CREATE TABLE IF NOT EXISTS places (file TEXT PRIMARY KEY, row INTEGER NOT NULL, col INTEGER NOT NULL);
+    CREATE TABLE IF NOT EXISTS marks (
+        file TEXT NOT NULL,
+        name TEXT NOT NULL,
+        row INTEGER NOT NULL,
+        col INTEGER NOT NULL,
+        PRIMARY KEY (file, name)
+    );
+    CREATE TABLE IF NOT EXISTS jumps (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        file TEXT NOT NULL,
+        row INTEGER NOT NULL,
+        col INTEGER NOT NULL
+    );
+";
+
+// プロジェクトごとに、状態ディレクトリの places の下に SQLite のデータベースを1つ持つ
+// 大きなプロジェクトでも、開いたファイルの行だけを引けばよい
+fn database(root: &Path) -> Option<PathBuf> {
+    let name: String = root
+        .to_string_lossy()
+        .chars()
+        .map(|c| if c == '/' || c == '\\' { '%' } else { c })
+        .collect();
+    Some(logger::state_dir()?.join("places").join(format!("{}.sqlite", name)))
+}
+
+fn no_state_dir() -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, "no state directory")
+}
+
+// 1つのプロジェクトのデータベース
+pub struct Store {
+    root: PathBuf,
+    db: Connection,
+}
+
+impl Store {
+    pub fn open(root: &Path) -> io::Result<Self> {
+        let path = database(root).ok_or_else(no_state_dir)?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let db = Connection::open(&path).map_err(io::Error::other)?;
+        db.execute_batch(SCHEMA).map_err(io::Error::other)?;
+        Ok(Store { root: root.to_path_buf(), db })
+    }
+
+    fn key(&self, file: &Path) -> String {
+        project::relative_key(&self.root, file)
+    }
+
+    // 読めなければ何も覚えていないことにする
+    pub fn load(&self, file: &Path) -> Place {
+        let key = self.key(file);
+        let read = || -> rusqlite::Result<Place> {
+            let cursor = self
+                .db
+                .query_row("SELECT row, col FROM places WHERE file = ?1", [&key], |row| {
+                    Ok(Cursor { row: row.get(0)?, column: row.get(1)? })
+                })
+                .optional()?;
+            let mut statement = self.db.prepare("SELECT name, row, col FROM marks WHERE file = ?1 ORDER BY name")?;
+            let marks = statement
+                .query_map([&key], |row| {
+                    let name: String = row.get(0)?;
+                    Ok((name.chars().next(), Cursor { row: row.get(1)?, column: row.get(2)? }))
+                })?
+                .filter_map(|mark| match mark {
+                    Ok((Some(name), pos)) => Some(Ok((name, pos))),
+                    Ok((None, _)) => None,
+                    Err(e) => Some(Err(e)),
+                })
+                .collect::<rusqlite::Result<_>>()?;
+            Ok(Place { cursor, marks })
+        };
+        read().unwrap_or_else(|e| {
+            log_warn!("cannot read the place in {}: {}", file.display(), e);
+            Place::default()
+        })
+    }
+
+    pub fn save(&mut self, file: &Path, place: &Place) -> io::Result<()> {
+        let key = self.key(file);
+        let mut write = || -> rusqlite::Result<()> {
+            let tx = self.db.transaction()?;
+            match place.cursor {
+                Some(cursor) => tx.execute(
+                    "INSERT OR REPLACE INTO places (file, row, col) VALUES (?1, ?2, ?3)",
+                    params![key, cursor.row, cursor.column],
+                )?,
+                None => tx.execute("DELETE FROM places WHERE file = ?1", [&key])?,
+            };
+            tx.execute("DELETE FROM marks WHERE file = ?1", [&key])?;
+            for (name, pos) in &place.marks {
+                tx.execute(
+                    "INSERT INTO marks (file, name, row, col) VALUES (?1, ?2, ?3, ?4)",
+                    params![key, name.to_string(), pos.row, pos.column],
+                )?;
+            }
+            tx.commit()
+        };
+        write().map_err(io::Error::other)
+    }
+
+    // 消したファイルや名前を変えたファイルの位置と印を忘れる
+    pub fn forget(&mut self, file: &Path) -> io::Result<()> {
+        let key = self.key(file);
+        let mut write = || -> rusqlite::Result<()> {
+            let tx = self.db.transaction()?;
+            tx.execute("DELETE FROM places WHERE file = ?1", [&key])?;
+            tx.execute("DELETE FROM marks WHERE file = ?1", [&key])?;
+            tx.commit()
+        };
+        write().map_err(io::Error::other)
+    }
+
+    // 移動の履歴はプロジェクトで1つ。古い順
+    pub fn jumps(&self) -> Vec<Jump> {
+        let read = || -> rusqlite::Result<Vec<Jump>> {
+            let mut statement = self.db.prepare("SELECT file, row, col FROM jumps ORDER BY id")?;
+            let jumps = statement
+                .query_map([], |row| {
+                    let file: String = row.get(0)?;
+                    // ルートの外のファイルは絶対パスで書いてあるので、join でそのままになる
+                    Ok(Jump { path: self.root.join(file), pos: Cursor { row: row.get(1)?, column: row.get(2)? } })
+                })?
+                .collect();
+            jumps
+        };
+        read().unwrap_or_else(|e| {
+            log_warn!("cannot read the jump list: {}", e);
+            Vec::new()
+        })
+    }
+
+    // 履歴の最後に加える。同じ行の古い履歴は消し、JUMPS_LIMIT より古いものは捨てる
+    // 履歴の全部ではなく変わった行だけを書く
+    pub fn push_jump(&mut self, jump: &Jump) -> io::Result<()> {
+        let key = self.key(&jump.path);
+        let mut write = || -> rusqlite::Result<()> {
+            let tx = self.db.transaction()?;
+            tx.execute("DELETE FROM jumps WHERE file = ?1 AND row = ?2", params![key, jump.pos.row])?;
+            tx.execute(
+                "INSERT INTO jumps (file, row, col) VALUES (?1, ?2, ?3)",
+                params![key, jump.pos.row, jump.pos.column],
+            )?;
+            tx.execute(
+                "DELETE FROM jumps WHERE id NOT IN (SELECT id FROM jumps ORDER BY id DESC LIMIT ?1)",
+                [JUMPS_LIMIT],
+            )?;
+            tx.commit()
+        };
+        write().map_err(io::Error::other)
+    }
+
+    // 履歴をまるごと jumps にする
+    pub fn replace_jumps(&mut self, jumps: &[Jump]) -> io::Result<()> {
+        let keys: Vec<String> = jumps.iter().map(|jump| self.key(&jump.path)).collect();
+        let mut write = || -> rusqlite::Result<()> {
+            let tx = self.db.transaction()?;
+            tx.execute("DELETE FROM jumps", [])?;
+            for (key, jump) in keys.iter().zip(jumps) {
+                tx.execute(
+                    "INSERT INTO jumps (file, row, col) VALUES (?1, ?2, ?3)",
+                    params![key, jump.pos.row, jump.pos.column],
+                )?;
+            }
+            tx.commit()
+        };
+        write().map_err(io::Error::other)
+    }
+}
+
+pub fn load(root: &Path, file: &Path) -> Place {
+    match Store::open(root) {
+        Ok(store) => store.load(file),
+        Err(e) => {
+            log_warn!("cannot open the places of {}: {}", root.display(), e);
+            Place::default()
+        },
+    }
+}
+
+pub fn save(root: &Path, file: &Path, place: &Place) -> io::Result<()> {
+    Store::open(root)?.save(file, place)
+}
+
+pub fn forget(root: &Path, file: &Path) -> io::Result<()> {
+    Store::open(root)?.forget(file)
+}
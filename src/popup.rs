@@ -0,0 +1,120 @@
+use std::cmp::min;
+use std::io::{self, Write};
+use termion::{cursor, style};
+use crate::special;
+use crate::truncate;
+
+// 画面の上に重ねて描く、枠で囲んだ小さな窓
+// 枠は曖昧な幅の罫線を避けて ASCII で描く
+pub struct Popup {
+    title: String,
+    lines: Vec<String>,
+    // 反転表示する行。見えるようにスクロールする
+    pub selected: Option<usize>,
+}
+
+impl Popup {
+    // text を width 桁 (枠を除く) で折り返して中身にする
+    pub fn new(title: &str, text: &str, width: usize) -> Self {
+        let width = width.max(1);
+        let mut lines = Vec::new();
+        for paragraph in text.lines() {
+            let mut line = String::new();
+            let mut w = 0;
+            for c in paragraph.chars() {
+                let cw = special::char_width(c);
+                if w + cw > width {
+                    lines.push(line);
+                    line = String::new();
+                    w = 0;
+                }
+                line.push(c);
+                w += cw;
+            }
+            lines.push(line);
+        }
+        Popup {
+            title: title.to_string(),
+            lines,
+            selected: None,
+        }
+    }
+
+    // 枠を含めた大きさ (幅, 高さ)。画面に入るように切り詰める
+    fn size(&self, rows: usize, cols: usize) -> (usize, usize) {
+        let inner = self
+            .lines
+            .iter()
+            .map(|line| special::str_width(line))
+            .chain(Some(special::str_width(&self.title) + 2))
+            .max()
+            .unwrap_or(0);
+        (min(inner + 4, cols), min(self.lines.len() + 2, rows))
+    }
+
+    // 画面の中央に描く。中身の最後の行の末尾の位置 (行, 列) を返す
+    pub fn draw_centered<T: Write>(&self, out: &mut T, rows: usize, cols: usize) -> io::Result<(usize, usize)> {
+        let (width, height) = self.size(rows, cols);
+        self.draw(out, (rows - height) / 2, (cols - width) / 2, width, height)
+    }
+
+    // 画面の row 行 col 列を指すように、その下 (入らなければ上) に描く
+    pub fn draw_near<T: Write>(&self, out: &mut T, row: usize, col: usize, rows: usize, cols: usize) -> io::Result<()> {
+        let (width, height) = self.size(rows, cols);
+        let top = if row + 1 + height <= rows {
+            row + 1
+        } else {
+            row.saturating_sub(height)
+        };
+        self.draw(out, top, min(col, cols - width), width, height)?;
+        Ok(())
+    }
+
+    // top 行 left 列から width x height (枠を含む) の大きさで描く
+    pub fn draw<T: Write>(
+        &self,
+        out: &mut T,
+        top: usize,
+        left: usize,
+        width: usize,
+        height: usize,
+    ) -> io::Result<(usize, usize)> {
+        if width < 4 || height < 2 {
+            return Ok((top, left));
+        }
+        let inner_width = width - 4;
+        let inner_height = height - 2;
+        let goto = |row: usize, col: usize| cursor::Goto(col as u16 + 1, row as u16 + 1);
+
+        let title = if self.title.is_empty() {
+            String::new()
+        } else {
+            truncate(&format!(" {} ", self.title), width - 2)
+        };
+        let fill = width - 2 - special::str_width(&title);
+        write!(out, "{}+{}{}+", goto(top, left), title, "-".repeat(fill))?;
+
+        let offset = self
+            .selected
+            .map(|k| (k + 1).saturating_sub(inner_height))
+            .unwrap_or(0);
+        let mut end = (top + 1, left + 2);
+        for k in 0..inner_height {
+            let row = top + 1 + k;
+            let line = self.lines.get(offset + k).map(|line| truncate(line, inner_width)).unwrap_or_default();
+            let pad = inner_width - special::str_width(&line);
+            write!(out, "{}| ", goto(row, left))?;
+            if self.selected == Some(offset + k) {
+                write!(out, "{}{}{}", style::Invert, line, style::Reset)?;
+            } else {
+                write!(out, "{}", line)?;
+            }
+            write!(out, "{} |", " ".repeat(pad))?;
+            if offset + k < self.lines.len() {
+                end = (row, left + 2 + special::str_width(&line));
+            }
+        }
+        write!(out, "{}+{}+", goto(top + height - 1, left), "-".repeat(width - 2))?;
+        Ok(end)
+    }
+}
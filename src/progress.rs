@@ -0,0 +1,122 @@
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use termion::{clear, cursor};
+use crate::special;
+
+// これより早く終わる処理には進み具合を出さない
+const SHOW_AFTER: Duration = Duration::from_millis(200);
+// 表示を書き換える間隔
+const REDRAW_EVERY: Duration = Duration::from_millis(100);
+const SPINNER: &[char] = &['|', '/', '-', '\\'];
+
+// 時間のかかる処理を中断する合図。処理の間だけ、入力のスレッドが Esc を見たら立てる
+#[derive(Clone, Default)]
+pub struct Cancel {
+    // 入力のスレッドとつながっているか。つながっていなければ画面もない
+    connected: bool,
+    armed: Arc<AtomicBool>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Cancel {
+    pub fn connected() -> Self {
+        Cancel {
+            connected: true,
+            ..Self::default()
+        }
+    }
+
+    // Esc が押された。処理の途中なら中断の合図にして true を返す
+    pub fn on_escape(&self) -> bool {
+        if self.armed.load(Ordering::Relaxed) {
+            self.cancelled.store(true, Ordering::Relaxed);
+            true
+        } else {
+            false
+        }
+    }
+
+    fn arm(&self, armed: bool) {
+        self.cancelled.store(false, Ordering::Relaxed);
+        self.armed.store(armed, Ordering::Relaxed);
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+// 時間のかかる処理の進み具合を最下行に出す。処理は update が false を返したらやめる
+pub struct Progress {
+    label: String,
+    cancel: Cancel,
+    started: Instant,
+    drawn: Option<Instant>,
+    frame: usize,
+}
+
+impl Progress {
+    pub fn start(label: &str, cancel: &Cancel) -> Self {
+        cancel.arm(true);
+        Progress {
+            label: label.to_string(),
+            cancel: cancel.clone(),
+            started: Instant::now(),
+            drawn: None,
+            frame: 0,
+        }
+    }
+
+    // total のうち done まで進んだ (total が 0 ならどこまで進むかわからない)
+    // 中断されていたら false
+    pub fn update(&mut self, done: u64, total: u64) -> bool {
+        if self.cancel.is_cancelled() {
+            return false;
+        }
+        let now = Instant::now();
+        let due = match self.drawn {
+            Some(drawn) => now - drawn >= REDRAW_EVERY,
+            None => now - self.started >= SHOW_AFTER,
+        };
+        if self.cancel.connected && due {
+            self.drawn = Some(now);
+            self.frame += 1;
+            let _ = self.draw(done, total);
+        }
+        true
+    }
+
+    // 次に画面全体を描き直すまでの仮の表示なので、最下行だけを書き換える
+    fn draw(&self, done: u64, total: u64) -> io::Result<()> {
+        let (cols, rows) = termion::terminal_size()?;
+        let percent = (done * 100)
+            .checked_div(total)
+            .map(|percent| format!(" {}%", percent))
+            .unwrap_or_default();
+        let text = format!(
+            "{} {}{} {}",
+            SPINNER[self.frame % SPINNER.len()],
+            self.label,
+            percent,
+            tr!("esc-to-cancel")
+        );
+        let mut line = String::new();
+        for c in text.chars() {
+            if special::str_width(&line) + special::char_width(c) > cols as usize {
+                break;
+            }
+            line.push(c);
+        }
+        let mut out = io::stdout();
+        write!(out, "{}{}{}", cursor::Goto(1, rows), clear::CurrentLine, line)?;
+        out.flush()
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        self.cancel.arm(false);
+    }
+}
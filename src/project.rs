@@ -0,0 +1,31 @@
+use std::path::{Path, PathBuf};
+
+// これらのどれかを含むディレクトリをプロジェクトのルートとみなす
+const ROOT_MARKERS: [&str; 2] = [".git", "Cargo.toml"];
+
+// path を含むプロジェクトのルートを探す
+pub fn find_root(path: &Path) -> Option<PathBuf> {
+    let path = path
+        .canonicalize()
+        .or_else(|_| path.parent().unwrap_or(path).canonicalize())
+        .ok()?;
+    path.ancestors()
+        .find(|dir| ROOT_MARKERS.iter().any(|marker| dir.join(marker).exists()))
+        .map(Path::to_path_buf)
+}
+
+// 状態ディレクトリにファイルごとのことを書くときの名前
+// ルートからの相対パス。ルートの外なら絶対パス
+pub fn relative_key(root: &Path, file: &Path) -> String {
+    let file = file.canonicalize().unwrap_or_else(|_| file.to_path_buf());
+    file.strip_prefix(root).unwrap_or(&file).to_string_lossy().into_owned()
+}
+
+// root からの相対パスで表示する。root の外ならそのまま
+pub fn display_path(root: Option<&Path>, path: &Path) -> String {
+    let relative = root.and_then(|root| {
+        let path = path.canonicalize().ok()?;
+        path.strip_prefix(root).ok().map(Path::to_path_buf)
+    });
+    relative.as_deref().unwrap_or(path).display().to_string()
+}
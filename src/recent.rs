@@ -0,0 +1,50 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use crate::logger;
+
+// 覚えておく最近開いたファイルの数
+const LIMIT: usize = 10;
+
+// 最近開いたファイル。新しいものから順に、状態ディレクトリの recent に1行ずつ書く
+pub fn load() -> Vec<PathBuf> {
+    logger::state_dir()
+        .and_then(|dir| fs::read_to_string(dir.join("recent")).ok())
+        .map(|text| text.lines().filter(|line| !line.is_empty()).map(PathBuf::from).collect())
+        .unwrap_or_default()
+}
+
+// path を一番新しいものにする
+pub fn record(path: &Path) -> io::Result<()> {
+    let path = path.canonicalize()?;
+    let mut paths = load();
+    paths.retain(|p| *p != path);
+    paths.insert(0, path);
+    paths.truncate(LIMIT);
+    write(&paths)
+}
+
+// 名前を変えたファイルを、同じ順番のまま新しい名前にする
+pub fn rename(old: &Path, new: &Path) -> io::Result<()> {
+    let new = new.canonicalize()?;
+    let paths: Vec<PathBuf> = load()
+        .into_iter()
+        .filter(|p| *p != new)
+        .map(|p| if p == old { new.clone() } else { p })
+        .collect();
+    write(&paths)
+}
+
+// 消したファイルを一覧から外す。path はもうないので、開いていたときの絶対パスで渡す
+pub fn forget(path: &Path) -> io::Result<()> {
+    let mut paths = load();
+    paths.retain(|p| p != path);
+    write(&paths)
+}
+
+fn write(paths: &[PathBuf]) -> io::Result<()> {
+    let dir = logger::state_dir().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no state directory"))?;
+    fs::create_dir_all(&dir)?;
+    let text: String = paths.iter().map(|p| format!("{}\n", p.display())).collect();
+    fs::write(dir.join("recent"), text)
+}
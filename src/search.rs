@@ -0,0 +1,119 @@
+use std::cmp::min;
+use regex::Regex;
+use crate::address;
+use crate::{Cursor, Line};
+
+// 探すもの。正規表現は1行ずつに当てるので、^ と $ は行の始めと終わりになり、行をまたいでは一致しない
+pub enum Pattern {
+    Literal(Vec<char>),
+    Regex(Regex),
+}
+
+impl Pattern {
+    // 正規表現として正しくなければ、その理由を返す
+    pub fn new(text: &str, regex: bool) -> Result<Self, String> {
+        if !regex {
+            return Ok(Pattern::Literal(text.chars().collect()));
+        }
+        Regex::new(text).map(Pattern::Regex).map_err(|e| match e {
+            regex::Error::Syntax(message) => message.lines().last().unwrap_or_default().trim().to_string(),
+            e => e.to_string(),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        match self {
+            Pattern::Literal(chars) => chars.is_empty(),
+            Pattern::Regex(regex) => regex.as_str().is_empty(),
+        }
+    }
+
+    // line の column 文字目から一致したところを置き換える文字列
+    // 正規表現なら template の $1 や ${name} を、捕まえた部分に置き換える
+    pub fn expand(&self, line: &[char], column: usize, template: &str) -> String {
+        match self {
+            Pattern::Literal(_) => template.to_string(),
+            Pattern::Regex(regex) => {
+                let text: String = line.iter().collect();
+                let start = address::char_to_byte(line, column);
+                let mut result = String::new();
+                if let Some(captures) = regex.captures_at(&text, start) {
+                    captures.expand(template, &mut result);
+                }
+                result
+            },
+        }
+    }
+
+    // 行の中で一致するところ (列, 文字数) を前から順に。空の一致は数えない
+    fn matches(&self, line: &[char]) -> Vec<(usize, usize)> {
+        match self {
+            Pattern::Literal(pattern) => {
+                if pattern.is_empty() || line.len() < pattern.len() {
+                    return Vec::new();
+                }
+                (0..=line.len() - pattern.len())
+                    .filter(|&column| line[column..column + pattern.len()] == **pattern)
+                    .map(|column| (column, pattern.len()))
+                    .collect()
+            },
+            Pattern::Regex(regex) => {
+                let text: String = line.iter().collect();
+                regex
+                    .find_iter(&text)
+                    .filter(|m| !m.is_empty())
+                    .map(|m| (address::byte_to_char(&text, m.start()), m.as_str().chars().count()))
+                    .collect()
+            },
+        }
+    }
+}
+
+// from から後ろへ探し、最後まで行ったら先頭に戻って from の手前まで探す
+// 見つかった位置の先頭と文字数を返す
+pub fn find(buffer: &[Line], pattern: &Pattern, from: Cursor) -> Option<(Cursor, usize)> {
+    if pattern.is_empty() || buffer.is_empty() {
+        return None;
+    }
+    let rows = buffer.len();
+    (0..=rows).find_map(|k| {
+        let row = (from.row + k) % rows;
+        // 1周目の最初の行は from から、1周して戻ってきた行は from の手前まで
+        pattern
+            .matches(&buffer[row].slice(..))
+            .into_iter()
+            .find(|&(column, _)| (k != 0 || column >= from.column) && (k != rows || column < from.column))
+            .map(|(column, len)| (Cursor { row, column }, len))
+    })
+}
+
+// from の手前から前へ探し、先頭まで行ったら最後に戻って from まで探す
+pub fn find_back(buffer: &[Line], pattern: &Pattern, from: Cursor) -> Option<(Cursor, usize)> {
+    if pattern.is_empty() || buffer.is_empty() {
+        return None;
+    }
+    let rows = buffer.len();
+    (0..=rows).find_map(|k| {
+        let row = (from.row + rows * 2 - k) % rows;
+        pattern
+            .matches(&buffer[row].slice(..))
+            .into_iter()
+            .rev()
+            .find(|&(column, _)| (k != 0 || column < from.column) && (k != rows || column >= from.column))
+            .map(|(column, len)| (Cursor { row, column }, len))
+    })
+}
+
+// start から end までの範囲に収まる、一致するところを前から順にすべて
+pub fn find_all(buffer: &[Line], pattern: &Pattern, start: Cursor, end: Cursor) -> Vec<(Cursor, usize)> {
+    if pattern.is_empty() {
+        return Vec::new();
+    }
+    (start.row..=min(end.row, buffer.len().saturating_sub(1)))
+        .flat_map(|row| {
+            let matches = pattern.matches(&buffer[row].slice(..)).into_iter();
+            matches.map(move |(column, len)| (Cursor { row, column }, len))
+        })
+        .filter(|&(pos, len)| pos >= start && Cursor { row: pos.row, column: pos.column + len } <= end)
+        .collect()
+}
@@ -0,0 +1,54 @@
+use std::fs;
+use std::io;
+use crate::logger;
+
+// 次に起動したときにも引き継ぐ画面の設定。状態ディレクトリの session に key=value で書く
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Session {
+    // 下部ペインが使ってよい画面の割合 (%)
+    pub pane_percent: usize,
+    // 下部ペインを区切り線だけにたたんでいるか
+    pub pane_collapsed: bool,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Session {
+            pane_percent: 50,
+            pane_collapsed: false,
+        }
+    }
+}
+
+// 読めないときや知らない項目は既定のままにする
+pub fn load() -> Session {
+    let mut session = Session::default();
+    let text = match logger::state_dir().and_then(|dir| fs::read_to_string(dir.join("session")).ok()) {
+        Some(text) => text,
+        None => return session,
+    };
+    for line in text.lines() {
+        match line.split_once('=') {
+            Some(("pane_percent", value)) => {
+                if let Ok(percent) = value.trim().parse() {
+                    session.pane_percent = percent;
+                }
+            },
+            Some(("pane_collapsed", value)) => session.pane_collapsed = value.trim() == "true",
+            _ => {},
+        }
+    }
+    session
+}
+
+pub fn save(session: &Session) -> io::Result<()> {
+    let dir = logger::state_dir().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no state directory"))?;
+    fs::create_dir_all(&dir)?;
+    fs::write(
+        dir.join("session"),
+        format!(
+            "pane_percent={}\npane_collapsed={}\n",
+            session.pane_percent, session.pane_collapsed
+        ),
+    )
+}
@@ -0,0 +1,102 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use unicode_width::UnicodeWidthChar;
+
+// 東アジアの曖昧な幅の文字 (○ や ─ など) を全角として扱うか。端末ごとに違うので起動時に決める
+static AMBIGUOUS_WIDE: AtomicBool = AtomicBool::new(false);
+
+pub fn set_ambiguous_wide(wide: bool) {
+    AMBIGUOUS_WIDE.store(wide, Ordering::Relaxed);
+}
+
+// タブ文字の幅。タブ位置にはそろえず、いつもこの幅で出す
+static TAB_WIDTH: AtomicUsize = AtomicUsize::new(4);
+
+pub fn set_tab_width(width: usize) {
+    TAB_WIDTH.store(width, Ordering::Relaxed);
+}
+
+pub fn tab_width() -> usize {
+    TAB_WIDTH.load(Ordering::Relaxed)
+}
+
+// 画面上の文字の幅。配置の計算もカーソルの位置も必ずこれを使う
+pub fn char_width(c: char) -> usize {
+    if c == '\t' {
+        tab_width()
+    } else if AMBIGUOUS_WIDE.load(Ordering::Relaxed) {
+        c.width_cjk().unwrap_or(0)
+    } else {
+        c.width().unwrap_or(0)
+    }
+}
+
+// 文字列の画面上の幅
+pub fn str_width(s: &str) -> usize {
+    s.chars().map(char_width).sum()
+}
+
+// 見えない、または見た目で区別しにくい文字
+pub fn is_special(c: char) -> bool {
+    c != '\t'
+        && (c.is_control()
+            || matches!(
+                c,
+                '\u{00a0}'
+                    | '\u{00ad}'
+                    | '\u{200b}'..='\u{200f}'
+                    | '\u{2028}'..='\u{202e}'
+                    | '\u{2060}'..='\u{2064}'
+                    | '\u{2066}'..='\u{2069}'
+                    | '\u{feff}'
+            ))
+}
+
+// 特殊な文字の名前。制御文字は名前を持たない
+pub fn name(c: char) -> Option<&'static str> {
+    let name = match c {
+        '\u{00a0}' => "NO-BREAK SPACE",
+        '\u{00ad}' => "SOFT HYPHEN",
+        '\u{200b}' => "ZERO WIDTH SPACE",
+        '\u{200c}' => "ZERO WIDTH NON-JOINER",
+        '\u{200d}' => "ZERO WIDTH JOINER",
+        '\u{200e}' => "LEFT-TO-RIGHT MARK",
+        '\u{200f}' => "RIGHT-TO-LEFT MARK",
+        '\u{2028}' => "LINE SEPARATOR",
+        '\u{2029}' => "PARAGRAPH SEPARATOR",
+        '\u{202a}' => "LEFT-TO-RIGHT EMBEDDING",
+        '\u{202b}' => "RIGHT-TO-LEFT EMBEDDING",
+        '\u{202c}' => "POP DIRECTIONAL FORMATTING",
+        '\u{202d}' => "LEFT-TO-RIGHT OVERRIDE",
+        '\u{202e}' => "RIGHT-TO-LEFT OVERRIDE",
+        '\u{2060}' => "WORD JOINER",
+        '\u{2061}' => "FUNCTION APPLICATION",
+        '\u{2062}' => "INVISIBLE TIMES",
+        '\u{2063}' => "INVISIBLE SEPARATOR",
+        '\u{2064}' => "INVISIBLE PLUS",
+        '\u{2066}' => "LEFT-TO-RIGHT ISOLATE",
+        '\u{2067}' => "RIGHT-TO-LEFT ISOLATE",
+        '\u{2068}' => "FIRST STRONG ISOLATE",
+        '\u{2069}' => "POP DIRECTIONAL ISOLATE",
+        '\u{feff}' => "ZERO WIDTH NO-BREAK SPACE",
+        _ => return None,
+    };
+    Some(name)
+}
+
+// エスケープ表示での見た目。制御文字は ^A、それ以外は <200b> のようにする
+pub fn escape(c: char) -> String {
+    match c as u32 {
+        n @ 0..=0x1f => format!("^{}", (n as u8 + b'@') as char),
+        0x7f => "^?".to_string(),
+        n => format!("<{:04x}>", n),
+    }
+}
+
+// 画面上の幅
+pub fn display_width(c: char, escapes: bool) -> usize {
+    if escapes && is_special(c) {
+        escape(c).len()
+    } else {
+        char_width(c)
+    }
+}
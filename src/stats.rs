@@ -0,0 +1,98 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// キーを押す間がこれより空いたら、その間は離れていたとみなして数えない
+const IDLE_GAP: Duration = Duration::from_secs(120);
+
+// 1つのファイルでの記録
+#[derive(Default)]
+pub struct FileStats {
+    pub keystrokes: u64,
+    // 変更した行。後で行を足したり消したりしてずれても直さないので、目安
+    pub lines: HashSet<usize>,
+    pub time: Duration,
+}
+
+// 起動してからの打鍵数、変更した行、ファイルごとの時間
+pub struct Stats {
+    started: SystemTime,
+    last: Option<Instant>,
+    // 開いた順。名前のないバッファは None
+    files: Vec<(Option<PathBuf>, FileStats)>,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Stats { started: SystemTime::now(), last: None, files: Vec::new() }
+    }
+}
+
+impl Stats {
+    fn file(&mut self, path: Option<&Path>) -> &mut FileStats {
+        let index = match self.files.iter().position(|(p, _)| p.as_deref() == path) {
+            Some(index) => index,
+            None => {
+                self.files.push((path.map(Path::to_path_buf), FileStats::default()));
+                self.files.len() - 1
+            },
+        };
+        &mut self.files[index].1
+    }
+
+    // キーが押された。前のキーからの時間をそのファイルで過ごした時間にする
+    pub fn keystroke(&mut self, path: Option<&Path>) {
+        let now = Instant::now();
+        let spent = self.last.map(|last| now - last).filter(|&gap| gap < IDLE_GAP).unwrap_or_default();
+        self.last = Some(now);
+        let file = self.file(path);
+        file.keystrokes += 1;
+        file.time += spent;
+    }
+
+    pub fn changed(&mut self, path: Option<&Path>, row: usize) {
+        self.file(path).lines.insert(row);
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = (Option<&Path>, &FileStats)> {
+        self.files.iter().map(|(path, stats)| (path.as_deref(), stats))
+    }
+
+    // 外の時間管理の道具に渡すための JSON
+    pub fn to_json(&self) -> String {
+        let started = self.started.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let files: Vec<String> = self
+            .files
+            .iter()
+            .map(|(path, stats)| {
+                // 別の場所から読んでもわかるように絶対パスで書く
+                let path = match path {
+                    Some(path) => json_string(&path.canonicalize().unwrap_or_else(|_| path.clone()).display().to_string()),
+                    None => "null".to_string(),
+                };
+                format!(
+                    "    {{\"path\": {}, \"keystrokes\": {}, \"lines_changed\": {}, \"seconds\": {}}}",
+                    path,
+                    stats.keystrokes,
+                    stats.lines.len(),
+                    stats.time.as_secs()
+                )
+            })
+            .collect();
+        format!("{{\n  \"started\": {},\n  \"files\": [\n{}\n  ]\n}}\n", started, files.join(",\n"))
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut result = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            c if (c as u32) < 0x20 => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
@@ -0,0 +1,69 @@
+use std::str::FromStr;
+
+// ステータス行に並べる項目
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Segment {
+    // 読み取り専用や範囲選択中かどうか
+    Mode,
+    Path,
+    // コマンドラインで渡したファイルのうち何番目か。2つ以上渡したときだけ出す
+    Args,
+    Modified,
+    Encoding,
+    // 色づけに使っているファイルの種類
+    Syntax,
+    Position,
+    // ファイルの先頭からのバイト数、行内の文字の位置、表示上の列
+    Columns,
+    // git のブランチ、上流との差、コミットしていない変更があるか
+    Branch,
+}
+
+impl FromStr for Segment {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "mode" => Ok(Segment::Mode),
+            "path" => Ok(Segment::Path),
+            "args" => Ok(Segment::Args),
+            "modified" => Ok(Segment::Modified),
+            "encoding" => Ok(Segment::Encoding),
+            "syntax" => Ok(Segment::Syntax),
+            "position" => Ok(Segment::Position),
+            "columns" => Ok(Segment::Columns),
+            "branch" => Ok(Segment::Branch),
+            _ => Err(format!("unknown status segment: {}", s.trim())),
+        }
+    }
+}
+
+// ステータス行の左に寄せる項目と右に寄せる項目
+// "path,modified,mode|encoding,position" のように、| の左と右をそれぞれ , で区切って並べる
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatusLayout {
+    pub left: Vec<Segment>,
+    pub right: Vec<Segment>,
+}
+
+impl Default for StatusLayout {
+    fn default() -> Self {
+        StatusLayout {
+            left: vec![Segment::Path, Segment::Args, Segment::Modified, Segment::Mode],
+            right: vec![Segment::Branch, Segment::Position],
+        }
+    }
+}
+
+impl FromStr for StatusLayout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (left, right) = s.split_once('|').unwrap_or((s, ""));
+        Ok(StatusLayout { left: segments(left)?, right: segments(right)? })
+    }
+}
+
+fn segments(s: &str) -> Result<Vec<Segment>, String> {
+    s.split(',').filter(|name| !name.trim().is_empty()).map(str::parse).collect()
+}
@@ -0,0 +1,68 @@
+use termion::event::Key;
+
+// 固定キーにできる修飾キー
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Modifier {
+    Ctrl,
+    Alt,
+}
+
+// 固定キー。修飾キーを押して離してから次のキーを押すと、いっしょに押したことにする
+#[derive(Debug, Default)]
+pub struct Sticky {
+    ctrl: bool,
+    alt: bool,
+}
+
+impl Sticky {
+    // 同じ修飾キーをもう一度押すと取り消す
+    pub fn toggle(&mut self, modifier: Modifier) {
+        match modifier {
+            Modifier::Ctrl => self.ctrl = !self.ctrl,
+            Modifier::Alt => self.alt = !self.alt,
+        }
+    }
+
+    pub fn active(&self) -> bool {
+        self.ctrl || self.alt
+    }
+
+    // 固定した修飾キーを key につけて、固定を解く
+    // 修飾キーをつけられないキーや、termion のキーで表せない組み合わせはそのまま返す
+    pub fn apply(&mut self, key: Key) -> Key {
+        let ctrl = std::mem::take(&mut self.ctrl);
+        let alt = std::mem::take(&mut self.alt);
+        match (key, ctrl, alt) {
+            (Key::Char(' '), true, false) => Key::Null,
+            (Key::Char(c), true, false) => Key::Ctrl(c),
+            (Key::Char(c), false, true) => Key::Alt(c),
+            (key, ..) => key,
+        }
+    }
+
+    // ステータス行に出す、固定している修飾キー
+    pub fn pending(&self) -> String {
+        let mut text = String::new();
+        if self.ctrl {
+            text.push_str("ctrl-");
+        }
+        if self.alt {
+            text.push_str("alt-");
+        }
+        text
+    }
+}
+
+// kitty キーボードプロトコルで修飾キーだけを押したときの報告 (ESC [ 57442 ; 5 u など)
+// すべてのキーを報告してもらうフラグ (8) を立てたときだけ届く
+pub fn modifier_press(bytes: &[u8]) -> Option<Modifier> {
+    let body = std::str::from_utf8(bytes.strip_prefix(b"\x1b[")?.strip_suffix(b"u")?).ok()?;
+    let code = body.split([';', ':']).next()?;
+    match code {
+        // 左と右の Ctrl
+        "57442" | "57448" => Some(Modifier::Ctrl),
+        // 左と右の Alt
+        "57443" | "57449" => Some(Modifier::Alt),
+        _ => None,
+    }
+}
@@ -0,0 +1,315 @@
+use std::cmp::min;
+use std::path::Path;
+use crate::layout;
+use crate::Line;
+
+// 色をつける字句の種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Token {
+    Plain,
+    Keyword,
+    String,
+    Number,
+    Comment,
+}
+
+// 行の始まりの状態。前の行から続いているもの
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Normal,
+    BlockComment,
+    // long_strings の何番目で始まった文字列の中か
+    LongString(usize),
+    // Markdown の ``` で囲んだコードの中
+    Fence,
+}
+
+// 言語ごとの規則
+pub struct Syntax {
+    pub name: &'static str,
+    extensions: &'static [&'static str],
+    keywords: &'static [&'static str],
+    line_comment: Option<&'static str>,
+    block_comment: Option<(&'static str, &'static str)>,
+    // 文字列を囲む記号。行をまたがない
+    quotes: &'static [char],
+    // 行をまたげる文字列の囲み (Python の """ など)
+    long_strings: &'static [&'static str],
+    // ' を文字のリテラルのときだけ文字列とみなす (Rust のライフタイムと区別する)
+    char_literals: bool,
+    // 行頭の # で始まる指令 (C のプリプロセッサ)
+    directives: bool,
+    markdown: bool,
+}
+
+const RUST: Syntax = Syntax {
+    name: "Rust",
+    extensions: &["rs"],
+    keywords: &[
+        "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern", "false",
+        "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref", "return",
+        "self", "Self", "static", "struct", "super", "trait", "true", "type", "unsafe", "use", "where", "while",
+    ],
+    line_comment: Some("//"),
+    block_comment: Some(("/*", "*/")),
+    quotes: &['"', '\''],
+    long_strings: &[],
+    char_literals: true,
+    directives: false,
+    markdown: false,
+};
+
+const C: Syntax = Syntax {
+    name: "C",
+    extensions: &["c", "h"],
+    keywords: &[
+        "auto", "bool", "break", "case", "char", "const", "continue", "default", "do", "double", "else", "enum",
+        "extern", "false", "float", "for", "goto", "if", "inline", "int", "long", "NULL", "register", "restrict",
+        "return", "short", "signed", "sizeof", "static", "struct", "switch", "true", "typedef", "union",
+        "unsigned", "void", "volatile", "while",
+    ],
+    line_comment: Some("//"),
+    block_comment: Some(("/*", "*/")),
+    quotes: &['"', '\''],
+    long_strings: &[],
+    char_literals: false,
+    directives: true,
+    markdown: false,
+};
+
+const PYTHON: Syntax = Syntax {
+    name: "Python",
+    extensions: &["py", "pyw"],
+    keywords: &[
+        "False", "None", "True", "and", "as", "assert", "async", "await", "break", "class", "continue", "def",
+        "del", "elif", "else", "except", "finally", "for", "from", "global", "if", "import", "in", "is",
+        "lambda", "nonlocal", "not", "or", "pass", "raise", "return", "try", "while", "with", "yield",
+    ],
+    line_comment: Some("#"),
+    block_comment: None,
+    quotes: &['"', '\''],
+    long_strings: &["\"\"\"", "'''"],
+    char_literals: false,
+    directives: false,
+    markdown: false,
+};
+
+const MARKDOWN: Syntax = Syntax {
+    name: "Markdown",
+    extensions: &["md", "markdown"],
+    keywords: &[],
+    line_comment: None,
+    block_comment: None,
+    quotes: &[],
+    long_strings: &[],
+    char_literals: false,
+    directives: false,
+    markdown: true,
+};
+
+const SYNTAXES: &[&Syntax] = &[&RUST, &C, &PYTHON, &MARKDOWN];
+
+// 拡張子から言語を決める。知らなければ None
+pub fn for_path(path: &Path) -> Option<&'static Syntax> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    SYNTAXES.iter().copied().find(|syntax| syntax.extensions.contains(&extension.as_str()))
+}
+
+fn starts_at(line: &[char], i: usize, pattern: &str) -> bool {
+    pattern.chars().enumerate().all(|(k, p)| line.get(i + k) == Some(&p))
+}
+
+fn is_ident(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+impl Syntax {
+    // 1行を字句に分ける。前の行から続く状態と、次の行に続く状態
+    pub fn tokenize(&self, line: &[char], state: State) -> (Vec<Token>, State) {
+        if self.markdown {
+            return markdown(line, state);
+        }
+        let mut tokens = vec![Token::Plain; line.len()];
+        let mut state = state;
+        let mut i = 0;
+        if self.directives && line.iter().find(|c| !c.is_whitespace()) == Some(&'#') && state == State::Normal {
+            let start = line.iter().position(|&c| c == '#').unwrap_or(0);
+            let mut end = start + 1;
+            while end < line.len() && is_ident(line[end]) {
+                end += 1;
+            }
+            tokens[start..end].fill(Token::Keyword);
+            i = end;
+        }
+        while i < line.len() {
+            // 前から続いているコメントや文字列の終わりを探す
+            let close = match state {
+                State::BlockComment => self.block_comment.map(|(_, end)| (end, Token::Comment)),
+                State::LongString(k) => Some((self.long_strings[k], Token::String)),
+                State::Normal | State::Fence => None,
+            };
+            if let Some((end, token)) = close {
+                if starts_at(line, i, end) {
+                    let len = end.chars().count();
+                    tokens[i..i + len].fill(token);
+                    i += len;
+                    state = State::Normal;
+                } else {
+                    tokens[i] = token;
+                    i += 1;
+                }
+                continue;
+            }
+
+            if self.line_comment.is_some_and(|start| starts_at(line, i, start)) {
+                tokens[i..].fill(Token::Comment);
+                break;
+            }
+            if let Some((start, _)) = self.block_comment.filter(|(start, _)| starts_at(line, i, start)) {
+                let len = start.chars().count();
+                tokens[i..i + len].fill(Token::Comment);
+                i += len;
+                state = State::BlockComment;
+                continue;
+            }
+            if let Some(k) = self.long_strings.iter().position(|start| starts_at(line, i, start)) {
+                let len = self.long_strings[k].chars().count();
+                tokens[i..i + len].fill(Token::String);
+                i += len;
+                state = State::LongString(k);
+                continue;
+            }
+
+            let c = line[i];
+            if self.quotes.contains(&c) {
+                let end = match self.string_end(line, i) {
+                    Some(end) => end,
+                    None => {
+                        i += 1;
+                        continue;
+                    },
+                };
+                tokens[i..end].fill(Token::String);
+                i = end;
+            } else if c.is_ascii_digit() && (i == 0 || !is_ident(line[i - 1])) {
+                let mut end = i + 1;
+                while end < line.len() && (is_ident(line[end]) || line[end] == '.') {
+                    end += 1;
+                }
+                tokens[i..end].fill(Token::Number);
+                i = end;
+            } else if is_ident(c) {
+                let mut end = i + 1;
+                while end < line.len() && is_ident(line[end]) {
+                    end += 1;
+                }
+                let word: String = line[i..end].iter().collect();
+                if self.keywords.contains(&word.as_str()) {
+                    tokens[i..end].fill(Token::Keyword);
+                }
+                i = end;
+            } else {
+                i += 1;
+            }
+        }
+        (tokens, state)
+    }
+
+    // start の引用符で始まる文字列の終わり (閉じる引用符の次)。閉じていなければ行末
+    // 文字のリテラルとみなさない ' なら None
+    fn string_end(&self, line: &[char], start: usize) -> Option<usize> {
+        let quote = line[start];
+        let mut i = start + 1;
+        while i < line.len() {
+            match line[i] {
+                '\\' => i += 2,
+                c if c == quote => return Some(i + 1),
+                _ => i += 1,
+            }
+            // 'a のようなライフタイムは、すぐに閉じないので文字列ではない
+            if self.char_literals && quote == '\'' && i - start > if line[start + 1] == '\\' { 10 } else { 2 } {
+                return None;
+            }
+        }
+        if self.char_literals && quote == '\'' {
+            return None;
+        }
+        Some(min(i, line.len()))
+    }
+}
+
+// 見出しは Keyword、``` で囲んだコードと `コード` は String にする
+fn markdown(line: &[char], state: State) -> (Vec<Token>, State) {
+    let text: String = line.iter().collect();
+    let trimmed = text.trim_start();
+    if trimmed.starts_with("```") {
+        let next = if state == State::Fence { State::Normal } else { State::Fence };
+        return (vec![Token::String; line.len()], next);
+    }
+    if state == State::Fence {
+        return (vec![Token::String; line.len()], state);
+    }
+    if trimmed.starts_with('#') {
+        return (vec![Token::Keyword; line.len()], state);
+    }
+    let mut tokens = vec![Token::Plain; line.len()];
+    let mut open: Option<usize> = None;
+    for (i, &c) in line.iter().enumerate() {
+        if c != '`' {
+            continue;
+        }
+        match open.take() {
+            Some(start) => tokens[start..=i].fill(Token::String),
+            None => open = Some(i),
+        }
+    }
+    (tokens, state)
+}
+
+// 行ごとの字句と、次の行に続く状態を覚えておく
+// 編集した行から後ろは捨てて、表示するときに前から計算し直す
+#[derive(Default)]
+pub struct Highlighter {
+    syntax: Option<&'static Syntax>,
+    lines: Vec<(Vec<Token>, State)>,
+}
+
+impl Highlighter {
+    pub fn new(syntax: Option<&'static Syntax>) -> Self {
+        Highlighter { syntax, lines: Vec::new() }
+    }
+
+    pub fn name(&self) -> Option<&'static str> {
+        self.syntax.map(|syntax| syntax.name)
+    }
+
+    // row 行目から後ろを計算し直させる
+    pub fn invalidate(&mut self, row: usize) {
+        self.lines.truncate(row);
+    }
+
+    // 先頭から rows 行目までの字句を求めておく
+    pub fn prepare(&mut self, buffer: &[Line], rows: usize) {
+        let syntax = match self.syntax {
+            Some(syntax) => syntax,
+            None => return,
+        };
+        let end = min(rows, buffer.len());
+        while self.lines.len() < end {
+            let state = self.lines.last().map(|&(_, state)| state).unwrap_or(State::Normal);
+            // とても長い行は、打つたびに行全体を読み直さないように色をつけない
+            let line = &buffer[self.lines.len()];
+            let line = if layout::is_long(line) {
+                (Vec::new(), state)
+            } else {
+                syntax.tokenize(&line.slice(..), state)
+            };
+            self.lines.push(line);
+        }
+    }
+
+    // prepare で求めていない行は Plain
+    pub fn token(&self, row: usize, column: usize) -> Token {
+        self.lines.get(row).and_then(|(tokens, _)| tokens.get(column)).copied().unwrap_or(Token::Plain)
+    }
+}
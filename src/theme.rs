@@ -0,0 +1,254 @@
+use std::fmt::{self, Display};
+use termion::{color, style};
+use crate::Highlight;
+
+// 文字の色と飾り。色は端末の 256 色の番号
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Style {
+    pub fg: Option<u8>,
+    pub bg: Option<u8>,
+    pub bold: bool,
+    pub faint: bool,
+    pub underline: bool,
+    pub invert: bool,
+}
+
+const PLAIN: Style = Style { fg: None, bg: None, bold: false, faint: false, underline: false, invert: false };
+
+const fn fg(color: u8) -> Style {
+    Style { fg: Some(color), ..PLAIN }
+}
+
+const fn bg(color: u8) -> Style {
+    Style { bg: Some(color), ..PLAIN }
+}
+
+impl Style {
+    const fn on(self, color: u8) -> Self {
+        Style { bg: Some(color), ..self }
+    }
+
+    // text をこの見た目で書く
+    pub fn paint<D: Display>(self, text: D) -> Painted<D> {
+        Painted { style: self, text }
+    }
+
+    // 中に別の見た目のものを書くときは、start と end で挟む
+    pub fn start(self) -> Edge {
+        Edge { style: self, start: true }
+    }
+
+    // 自分がつけたものだけを戻すので、外側の反転や背景色は残る
+    pub fn end(self) -> Edge {
+        Edge { style: self, start: false }
+    }
+
+    // HTML に書き出すときの CSS の宣言。反転は paper の色を入れ替えて表す
+    pub fn css(self, paper: Style) -> String {
+        let (mut fg, mut bg) = (self.fg, self.bg);
+        if self.invert {
+            (fg, bg) = (bg.or(paper.bg), fg.or(paper.fg));
+        }
+        let mut css = String::new();
+        if let Some(c) = fg {
+            css.push_str(&format!("color: {}; ", rgb(c)));
+        }
+        if let Some(c) = bg {
+            css.push_str(&format!("background: {}; ", rgb(c)));
+        }
+        if self.bold {
+            css.push_str("font-weight: bold; ");
+        }
+        if self.faint {
+            css.push_str("opacity: 0.6; ");
+        }
+        if self.underline {
+            css.push_str("text-decoration: underline; ");
+        }
+        css.trim_end().to_string()
+    }
+}
+
+// 256 色の番号を #rrggbb にする。0 から 15 は xterm の既定の色
+fn rgb(color: u8) -> String {
+    const BASIC: [u32; 16] = [
+        0x000000, 0x800000, 0x008000, 0x808000, 0x000080, 0x800080, 0x008080, 0xc0c0c0,
+        0x808080, 0xff0000, 0x00ff00, 0xffff00, 0x0000ff, 0xff00ff, 0x00ffff, 0xffffff,
+    ];
+    let value = match color {
+        0..=15 => BASIC[color as usize],
+        // 6x6x6 の色の立方体
+        16..=231 => {
+            let level = |n: u8| if n == 0 { 0 } else { 55 + 40 * n as u32 };
+            let n = color - 16;
+            level(n / 36) << 16 | level(n / 6 % 6) << 8 | level(n % 6)
+        },
+        // 灰色の階調
+        _ => {
+            let gray = 8 + 10 * (color - 232) as u32;
+            gray << 16 | gray << 8 | gray
+        },
+    };
+    format!("#{:06x}", value)
+}
+
+pub struct Edge {
+    style: Style,
+    start: bool,
+}
+
+impl Display for Edge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = self.style;
+        if self.start {
+            if let Some(c) = s.fg {
+                write!(f, "{}", color::Fg(color::AnsiValue(c)))?;
+            }
+            if let Some(c) = s.bg {
+                write!(f, "{}", color::Bg(color::AnsiValue(c)))?;
+            }
+            if s.bold {
+                write!(f, "{}", style::Bold)?;
+            }
+            if s.faint {
+                write!(f, "{}", style::Faint)?;
+            }
+            if s.underline {
+                write!(f, "{}", style::Underline)?;
+            }
+            if s.invert {
+                write!(f, "{}", style::Invert)?;
+            }
+            return Ok(());
+        }
+        if s.invert {
+            write!(f, "{}", style::NoInvert)?;
+        }
+        if s.underline {
+            write!(f, "{}", style::NoUnderline)?;
+        }
+        // 22m で太字と薄字の両方が戻る
+        if s.bold || s.faint {
+            write!(f, "{}", style::NoFaint)?;
+        }
+        if s.bg.is_some() {
+            write!(f, "{}", color::Bg(color::Reset))?;
+        }
+        if s.fg.is_some() {
+            write!(f, "{}", color::Fg(color::Reset))?;
+        }
+        Ok(())
+    }
+}
+
+pub struct Painted<D> {
+    style: Style,
+    text: D,
+}
+
+impl<D: Display> Display for Painted<D> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}{}", self.style.start(), self.text, self.style.end())
+    }
+}
+
+// 画面の要素ごとの見た目
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Theme {
+    pub name: &'static str,
+    pub text: Style,
+    pub status: Style,
+    pub line_number: Style,
+    // カーソルのある行の番号
+    pub current_line_number: Style,
+    pub selection: Style,
+    // 探して見つかったところ
+    pub search: Style,
+    pub comment: Style,
+    pub keyword: Style,
+    pub string: Style,
+    pub number: Style,
+    // 見えない文字のエスケープ表示
+    pub special: Style,
+    // コミットメッセージの長すぎる部分
+    pub over_limit: Style,
+    // 差分の足した行と消した行
+    pub added: Style,
+    pub removed: Style,
+    // HTML に書き出すときの地の色。端末では端末の色をそのまま使う
+    pub paper: Style,
+}
+
+const DARK: Theme = Theme {
+    name: "dark",
+    text: PLAIN,
+    status: Style { invert: true, ..PLAIN },
+    line_number: Style { faint: true, ..PLAIN },
+    current_line_number: PLAIN,
+    selection: Style { invert: true, ..PLAIN },
+    search: fg(16).on(220),
+    comment: fg(8),
+    keyword: fg(3),
+    string: fg(2),
+    number: fg(6),
+    special: fg(5),
+    over_limit: bg(1),
+    added: fg(2),
+    removed: fg(1),
+    paper: fg(252).on(234),
+};
+
+// 明るい背景の端末向け。黄色などの読みにくい色を避ける
+const LIGHT: Theme = Theme {
+    name: "light",
+    text: PLAIN,
+    status: fg(16).on(250),
+    line_number: fg(246),
+    current_line_number: Style { bold: true, ..PLAIN },
+    selection: bg(153),
+    search: fg(16).on(222),
+    comment: fg(244),
+    keyword: Style { bold: true, ..fg(25) },
+    string: fg(28),
+    number: fg(130),
+    special: fg(127),
+    over_limit: bg(217),
+    added: fg(28),
+    removed: fg(160),
+    paper: fg(16).on(231),
+};
+
+const THEMES: &[&Theme] = &[&DARK, &LIGHT];
+
+impl Default for Theme {
+    fn default() -> Self {
+        DARK
+    }
+}
+
+impl Theme {
+    // "default" は dark
+    pub fn by_name(name: &str) -> Option<Theme> {
+        let name = name.to_ascii_lowercase();
+        if name == "default" {
+            return Some(DARK);
+        }
+        THEMES.iter().find(|theme| theme.name == name).map(|&&theme| theme)
+    }
+
+    pub fn names() -> Vec<&'static str> {
+        THEMES.iter().map(|theme| theme.name).collect()
+    }
+
+    pub fn highlight(&self, highlight: Highlight) -> Style {
+        match highlight {
+            Highlight::Normal => self.text,
+            Highlight::Comment => self.comment,
+            Highlight::OverLimit => self.over_limit,
+            Highlight::Special => self.special,
+            Highlight::Keyword => self.keyword,
+            Highlight::String => self.string,
+            Highlight::Number => self.number,
+        }
+    }
+}
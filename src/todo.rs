@@ -0,0 +1,96 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::address;
+use crate::binary;
+use crate::progress::Progress;
+
+// 探す印
+const MARKERS: &[&str] = &["TODO", "FIXME", "XXX"];
+// 中を探さないディレクトリ。. で始まるものも探さない
+const SKIP_DIRS: &[&str] = &["target", "node_modules"];
+// これより大きいファイルは生成されたものとみなして読まない
+const FILE_LIMIT: u64 = 1 << 20;
+// 一覧に出す説明の長さ
+const TEXT_LIMIT: usize = 100;
+
+// 見つけた印
+pub struct Todo {
+    pub path: PathBuf,
+    pub row: usize,
+    pub column: usize,
+    pub text: String,
+}
+
+// root の下のファイルから印を探す。ファイルごとにまとめ、パスの順に並べる
+// 中断されたら None
+pub fn scan(root: &Path, progress: &mut Progress) -> Option<Vec<Todo>> {
+    let mut files = Vec::new();
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                log_debug!("cannot read {}: {}", dir.display(), e);
+                continue;
+            },
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let file_type = match entry.file_type() {
+                Ok(file_type) => file_type,
+                Err(_) => continue,
+            };
+            if file_type.is_dir() {
+                if !name.starts_with('.') && !SKIP_DIRS.contains(&name.as_str()) {
+                    dirs.push(entry.path());
+                }
+            } else if file_type.is_file() {
+                files.push(entry.path());
+            }
+        }
+        if !progress.update(files.len() as u64, 0) {
+            return None;
+        }
+    }
+    files.sort();
+
+    let mut todos = Vec::new();
+    for (done, path) in files.iter().enumerate() {
+        if !progress.update(done as u64, files.len() as u64) {
+            return None;
+        }
+        if fs::metadata(path).map(|meta| meta.len() > FILE_LIMIT).unwrap_or(true) {
+            continue;
+        }
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(_) => continue,
+        };
+        if binary::is_binary(&bytes) {
+            continue;
+        }
+        for (row, line) in String::from_utf8_lossy(&bytes).lines().enumerate() {
+            if let Some((column, text)) = find_marker(line) {
+                todos.push(Todo { path: path.clone(), row, column, text });
+            }
+        }
+    }
+    Some(todos)
+}
+
+// 行の中の最初の印の位置 (文字数) と、そこから行末までの説明
+// FIXMES や XXXL のように、ほかの語の一部になっているものは数えない
+fn find_marker(line: &str) -> Option<(usize, String)> {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    line.char_indices().find_map(|(start, c)| {
+        if !c.is_ascii_uppercase() || line[..start].chars().next_back().is_some_and(is_word) {
+            return None;
+        }
+        let marker = MARKERS.iter().find(|marker| line[start..].starts_with(*marker))?;
+        if line[start + marker.len()..].chars().next().is_some_and(is_word) {
+            return None;
+        }
+        let column = address::byte_to_char(line, start);
+        Some((column, line[start..].trim_end().chars().take(TEXT_LIMIT).collect()))
+    })
+}
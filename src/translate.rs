@@ -0,0 +1,263 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use termion::event::{Event, Key};
+
+// terminfo の文字列の項目のうち、キーとして読むものの番号
+const TERMINFO_KEYS: &[(usize, Key)] = &[
+    (59, Key::Delete),
+    (61, Key::Down),
+    (76, Key::Home),
+    (77, Key::Insert),
+    (79, Key::Left),
+    (81, Key::PageDown),
+    (82, Key::PageUp),
+    (83, Key::Right),
+    (87, Key::Up),
+    (164, Key::End),
+];
+
+// termion が知らないが、よく使われている送り方
+const BUILTIN: &[(&[u8], Key)] = &[
+    (b"\x1bOH", Key::Home),
+    (b"\x1bOF", Key::End),
+    (b"\x1bOA", Key::Up),
+    (b"\x1bOB", Key::Down),
+    (b"\x1bOC", Key::Right),
+    (b"\x1bOD", Key::Left),
+];
+
+// 端末が送ってくるバイト列を、どのキーとして扱うかの表
+// 組み込みの表、terminfo、ユーザーの設定 (~/.config/textedit/keys) の順に後のものが優先する
+pub struct Translator {
+    table: HashMap<Vec<u8>, Key>,
+}
+
+impl Translator {
+    pub fn load() -> Self {
+        let mut table: HashMap<Vec<u8>, Key> = BUILTIN.iter().map(|(seq, key)| (seq.to_vec(), *key)).collect();
+        if let Some(term) = env::var_os("TERM") {
+            match read_terminfo(&term.to_string_lossy()) {
+                Ok(strings) => {
+                    for &(index, key) in TERMINFO_KEYS {
+                        if let Some(Some(seq)) = strings.get(index) {
+                            table.insert(seq.clone(), key);
+                        }
+                    }
+                },
+                Err(e) => log_debug!("no terminfo for {}: {}", term.to_string_lossy(), e),
+            }
+        }
+        if let Some(path) = config_dir().map(|dir| dir.join("keys")) {
+            if let Ok(text) = fs::read_to_string(&path) {
+                for (n, line) in text.lines().enumerate() {
+                    let line = line.trim();
+                    if line.is_empty() || line.starts_with('#') {
+                        continue;
+                    }
+                    match parse_line(line) {
+                        Some((seq, key)) => {
+                            table.insert(seq, key);
+                        },
+                        None => log_warn!("{}:{}: cannot parse key translation", path.display(), n + 1),
+                    }
+                }
+            }
+        }
+        Translator { table }
+    }
+
+    // 表にあるバイト列ならそのキーにし、なければ termion が読んだとおりにする
+    pub fn translate(&self, evt: io::Result<(Event, Vec<u8>)>) -> io::Result<Event> {
+        let (evt, raw) = evt?;
+        // 1バイトのキーを置き換えると入力できない文字ができるので、エスケープシーケンスだけを見る
+        if raw.len() > 1 {
+            if let Some(&key) = self.table.get(&raw) {
+                return Ok(Event::Key(key));
+            }
+            if let Some(key) = decode_csi_u(&raw) {
+                return Ok(Event::Key(key));
+            }
+        }
+        Ok(evt)
+    }
+}
+
+// kitty キーボードプロトコルの ESC [ コード ; 修飾 u を読む
+// termion のキーで表せない組み合わせは、termion が作らない形で表す
+// Ctrl+Shift+文字は Ctrl(大文字)、Ctrl+Enter は Ctrl('\n')、Ctrl+I は Ctrl('i') (Tab と区別する)
+fn decode_csi_u(raw: &[u8]) -> Option<Key> {
+    let body = std::str::from_utf8(raw.strip_prefix(b"\x1b[")?.strip_suffix(b"u")?).ok()?;
+    let mut fields = body.split(';');
+    // コードの後ろには、送ってもらえば :Shift で変わった文字 が続く
+    let mut codes = fields.next()?.split(':');
+    let code: u32 = codes.next()?.parse().ok()?;
+    let shifted = codes.next().and_then(|code| code.parse().ok()).and_then(char::from_u32);
+    let modifiers: u32 = match fields.next() {
+        Some(field) => field.split(':').next()?.parse().ok()?,
+        None => 1,
+    };
+    let bits = modifiers.checked_sub(1)?;
+    let (shift, alt, ctrl) = (bits & 1 != 0, bits & 2 != 0, bits & 4 != 0);
+    let key = match code {
+        27 => Key::Esc,
+        127 => Key::Backspace,
+        13 if ctrl => Key::Ctrl('\n'),
+        13 if alt => Key::Alt('\n'),
+        13 => Key::Char('\n'),
+        9 if shift => Key::BackTab,
+        9 if ctrl => Key::Ctrl('\t'),
+        9 => Key::Char('\t'),
+        code => {
+            let c = char::from_u32(code)?;
+            // 私用領域はテンキーなどの機能キーなので扱わない
+            if ('\u{e000}'..='\u{f8ff}').contains(&c) {
+                return None;
+            }
+            let c = match shifted {
+                Some(shifted) if shift => shifted,
+                _ if shift => c.to_uppercase().next()?,
+                _ => c,
+            };
+            match (ctrl, alt) {
+                (true, false) => Key::Ctrl(c),
+                (false, true) => Key::Alt(c),
+                (false, false) => Key::Char(c),
+                (true, true) => return None,
+            }
+        },
+    };
+    Some(key)
+}
+
+// ~/.config/textedit (XDG_CONFIG_HOME があればその下)
+fn config_dir() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("textedit"))
+}
+
+// 「\e[7~ home」のように、バイト列とキーの名前を空白で区切って書く
+fn parse_line(line: &str) -> Option<(Vec<u8>, Key)> {
+    let mut words = line.split_whitespace();
+    let seq = unescape(words.next()?)?;
+    let key = key_by_name(words.next()?)?;
+    if words.next().is_some() || seq.len() < 2 {
+        return None;
+    }
+    Some((seq, key))
+}
+
+// \e (ESC) と \xNN と \\ を読む
+fn unescape(s: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        match chars.next()? {
+            'e' => bytes.push(0x1b),
+            '\\' => bytes.push(b'\\'),
+            'x' => {
+                let hex: String = chars.by_ref().take(2).collect();
+                bytes.push(u8::from_str_radix(&hex, 16).ok()?);
+            },
+            _ => return None,
+        }
+    }
+    Some(bytes)
+}
+
+fn key_by_name(name: &str) -> Option<Key> {
+    let key = match name.to_ascii_lowercase().as_str() {
+        "home" => Key::Home,
+        "end" => Key::End,
+        "delete" => Key::Delete,
+        "insert" => Key::Insert,
+        "pageup" => Key::PageUp,
+        "pagedown" => Key::PageDown,
+        "up" => Key::Up,
+        "down" => Key::Down,
+        "left" => Key::Left,
+        "right" => Key::Right,
+        "backspace" => Key::Backspace,
+        "backtab" => Key::BackTab,
+        name => {
+            let n: u8 = name.strip_prefix('f')?.parse().ok()?;
+            if !(1..=12).contains(&n) {
+                return None;
+            }
+            Key::F(n)
+        },
+    };
+    Some(key)
+}
+
+// コンパイルされた terminfo の文字列の項目を読む。ない項目は None
+fn read_terminfo(term: &str) -> io::Result<Vec<Option<Vec<u8>>>> {
+    let first = term
+        .chars()
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "empty TERM"))?;
+    let mut dirs = Vec::new();
+    if let Some(dir) = env::var_os("TERMINFO") {
+        dirs.push(PathBuf::from(dir));
+    }
+    if let Some(home) = env::var_os("HOME") {
+        dirs.push(PathBuf::from(home).join(".terminfo"));
+    }
+    if let Some(list) = env::var_os("TERMINFO_DIRS") {
+        dirs.extend(env::split_paths(&list).filter(|dir| !dir.as_os_str().is_empty()));
+    }
+    dirs.extend(["/etc/terminfo", "/lib/terminfo", "/usr/share/terminfo"].iter().map(PathBuf::from));
+    // macOS では先頭の文字の16進数がディレクトリ名になっている
+    let bytes = dirs
+        .iter()
+        .flat_map(|dir| {
+            vec![
+                dir.join(first.to_string()).join(term),
+                dir.join(format!("{:x}", first as u32)).join(term),
+            ]
+        })
+        .find_map(|path| fs::read(path).ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "terminfo entry not found"))?;
+    parse_terminfo(&bytes).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "broken terminfo entry"))
+}
+
+fn parse_terminfo(bytes: &[u8]) -> Option<Vec<Option<Vec<u8>>>> {
+    let word = |i: usize| -> Option<i16> { Some(i16::from_le_bytes([*bytes.get(i)?, *bytes.get(i + 1)?])) };
+    // 数値の項目の大きさは形式で違う
+    let number_size = match word(0)? {
+        0o432 => 2,
+        0o1036 => 4,
+        _ => return None,
+    };
+    let names = word(2)? as usize;
+    let booleans = word(4)? as usize;
+    let numbers = word(6)? as usize;
+    let strings = word(8)? as usize;
+    let mut offset = 12 + names + booleans;
+    // 数値の項目は偶数の位置から始まる
+    offset += offset % 2;
+    offset += numbers * number_size;
+    let table = offset + strings * 2;
+    let mut result = Vec::with_capacity(strings);
+    for k in 0..strings {
+        let start = word(offset + k * 2)?;
+        if start < 0 {
+            result.push(None);
+            continue;
+        }
+        let rest = bytes.get(table + start as usize..)?;
+        let end = rest.iter().position(|&b| b == 0)?;
+        result.push(Some(rest[..end].to_vec()));
+    }
+    Some(result)
+}
@@ -0,0 +1,133 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::expr;
+use crate::logger;
+use crate::options::Encoding;
+
+// 残しておく控えの数。これより古いものは消す
+const LIMIT: usize = 50;
+
+// 控えを取ったわけ
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    // 読み直して捨てた、保存していない変更
+    Revert,
+    // ディスクから消したファイル
+    Delete,
+}
+
+impl Kind {
+    fn name(self) -> &'static str {
+        match self {
+            Kind::Revert => "revert",
+            Kind::Delete => "delete",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "revert" => Some(Kind::Revert),
+            "delete" => Some(Kind::Delete),
+            _ => None,
+        }
+    }
+}
+
+// ごみ箱に入れた控えの1つ。中身は id の名前のファイルにある
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    id: String,
+    pub kind: Kind,
+    // 控えを取った時刻 (UNIX 時間)
+    pub time: u64,
+    // 控えの中身の文字コード
+    pub encoding: Encoding,
+    // 元のファイルの絶対パス
+    pub path: PathBuf,
+}
+
+impl Entry {
+    // 一覧に出す1行
+    pub fn line(&self) -> String {
+        format!(
+            "{} {:<6} {}",
+            expr::format_time(self.time as i64, "%Y-%m-%d %H:%M:%S"),
+            self.kind.name(),
+            self.path.display()
+        )
+    }
+}
+
+// 状態ディレクトリの trash に、控えの中身のファイルと、古い順に「id 種類 時刻 文字コード パス」をタブで区切った index を置く
+fn dir() -> io::Result<PathBuf> {
+    logger::state_dir()
+        .map(|dir| dir.join("trash"))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no state directory"))
+}
+
+fn load(dir: &Path) -> Vec<Entry> {
+    let text = fs::read_to_string(dir.join("index")).unwrap_or_default();
+    text.lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(5, '\t');
+            let id = fields.next()?.to_string();
+            let kind = Kind::from_name(fields.next()?)?;
+            let time = fields.next()?.parse().ok()?;
+            let encoding = fields.next()?.parse().ok()?;
+            let path = PathBuf::from(fields.next()?);
+            Some(Entry { id, kind, time, encoding, path })
+        })
+        .collect()
+}
+
+fn write_index(dir: &Path, entries: &[Entry]) -> io::Result<()> {
+    let text: String = entries
+        .iter()
+        .map(|entry| {
+            let Entry { id, kind, time, encoding, path } = entry;
+            format!("{}\t{}\t{}\t{}\t{}\n", id, kind.name(), time, encoding.name(), path.display())
+        })
+        .collect();
+    fs::write(dir.join("index"), text)
+}
+
+// path の内容 bytes の控えを取る。戻すときに読めるように bytes の文字コードも書いておく
+// 書けなければ元の操作をやめられるようにエラーを返す
+pub fn put(kind: Kind, path: &Path, bytes: &[u8], encoding: Encoding) -> io::Result<()> {
+    let dir = dir()?;
+    fs::create_dir_all(&dir)?;
+    let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    // 同じ秒に取った控えと名前がぶつからないように番号をつける
+    let id = (0..)
+        .map(|n| format!("{}-{}", time, n))
+        .find(|id| !dir.join(id).exists())
+        .unwrap_or_default();
+    fs::write(dir.join(&id), bytes)?;
+    let mut entries = load(&dir);
+    entries.push(Entry { id, kind, time, encoding, path: path.to_path_buf() });
+    let excess = entries.len().saturating_sub(LIMIT);
+    for old in entries.drain(..excess) {
+        if let Err(e) = fs::remove_file(dir.join(&old.id)) {
+            log_debug!("cannot remove {} from the trash: {}", old.id, e);
+        }
+    }
+    write_index(&dir, &entries)
+}
+
+// 新しい順の控え。中身のファイルがなくなったものは出さない
+pub fn list() -> Vec<Entry> {
+    let dir = match dir() {
+        Ok(dir) => dir,
+        Err(_) => return Vec::new(),
+    };
+    let mut entries = load(&dir);
+    entries.retain(|entry| dir.join(&entry.id).is_file());
+    entries.reverse();
+    entries
+}
+
+pub fn read(entry: &Entry) -> io::Result<Vec<u8>> {
+    fs::read(dir()?.join(&entry.id))
+}
@@ -0,0 +1,360 @@
+use termion::event::Key;
+
+// vim のような編集のモード
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Mode {
+    #[default]
+    Normal,
+    Insert,
+    Visual,
+}
+
+// カーソルを動かす先
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Motion {
+    // h l k j
+    Left,
+    Right,
+    Up,
+    Down,
+    // w b e
+    WordForward,
+    WordBackward,
+    WordEnd,
+    // 0 $
+    LineStart,
+    LineEnd,
+    // gg G
+    FirstLine,
+    LastLine,
+    // 数をつけた gg や G。1 から始まる行の番号
+    Line(usize),
+}
+
+impl Motion {
+    // 演算子をかけると行全体が対象になる動き
+    pub fn linewise(self) -> bool {
+        matches!(self, Motion::Up | Motion::Down | Motion::FirstLine | Motion::LastLine | Motion::Line(_))
+    }
+
+    // 行き先の文字も対象に含める動き
+    pub fn inclusive(self) -> bool {
+        self == Motion::WordEnd
+    }
+}
+
+// d y c
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Delete,
+    Yank,
+    Change,
+}
+
+// 演算子をかける範囲
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Motion(Motion),
+    // dd のように同じ演算子を重ねたときは、数だけの行
+    Lines,
+}
+
+// 挿入モードに入るときのカーソルの位置 (i a I A o O)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InsertAt {
+    Cursor,
+    After,
+    LineStart,
+    LineEnd,
+    LineBelow,
+    LineAbove,
+}
+
+// キーから読み取った操作。数は1以上
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    // 続きのキーを待つ
+    Pending,
+    // 割り当てのないキー。何もしない
+    Ignore,
+    // vim のモードでは扱わないキー。普通のキーの割り当てにまかせる
+    Pass,
+    Move(Motion, usize),
+    Operate(Operator, Target, usize),
+    // ビジュアルモードで選んだ範囲に演算子をかける
+    OperateSelection(Operator),
+    Insert(InsertAt),
+    // 挿入モードとビジュアルモードから抜ける
+    ExitInsert,
+    ExitVisual,
+    Visual,
+    // x
+    DeleteChar(usize),
+    // p は後ろに、P は前に貼り付ける
+    Put { before: bool, count: usize },
+    Undo(usize),
+    Redo(usize),
+    // : / n
+    Prompt,
+    // ビジュアルモードの : 。選んだ範囲を '<,'> にする
+    RangePrompt,
+    Search,
+    SearchNext,
+    // m の後の名前で印をつける
+    SetMark(char),
+    // ' は印の行の行頭の空白の後に、` は印の位置そのものに移る
+    GotoMark { name: char, exact: bool },
+    // Ctrl+O と Ctrl+I (Tab) で移動の履歴を戻る、進む
+    JumpBack(usize),
+    JumpForward(usize),
+}
+
+// モードと、打ちかけの数や演算子
+#[derive(Debug, Default)]
+pub struct Vim {
+    pub mode: Mode,
+    // 0 なら数を打っていない
+    count: usize,
+    // 打った演算子と、その前に打った数
+    operator: Option<(Operator, usize)>,
+    // g を打って次のキーを待っている
+    g: bool,
+    // m ' ` を打って、印の名前を待っている
+    mark: Option<char>,
+}
+
+impl Vim {
+    // ステータス行に出す、打ちかけのキー
+    pub fn pending(&self) -> String {
+        let mut text = String::new();
+        if let Some((operator, count)) = self.operator {
+            if count > 0 {
+                text.push_str(&count.to_string());
+            }
+            text.push(match operator {
+                Operator::Delete => 'd',
+                Operator::Yank => 'y',
+                Operator::Change => 'c',
+            });
+        }
+        if self.count > 0 {
+            text.push_str(&self.count.to_string());
+        }
+        if self.g {
+            text.push('g');
+        }
+        if let Some(c) = self.mark {
+            text.push(c);
+        }
+        text
+    }
+
+    fn reset(&mut self) {
+        self.count = 0;
+        self.operator = None;
+        self.g = false;
+        self.mark = None;
+    }
+
+    // 演算子の前と後の数をかけた回数。打っていなければ1
+    fn take_count(&mut self) -> usize {
+        let before = self.operator.map(|(_, count)| count).unwrap_or(0);
+        let count = before.max(1) * self.count.max(1);
+        self.count = 0;
+        count
+    }
+
+    pub fn key(&mut self, key: Key) -> Command {
+        if self.mode == Mode::Insert {
+            if key == Key::Esc {
+                self.mode = Mode::Normal;
+                return Command::ExitInsert;
+            }
+            return Command::Pass;
+        }
+        let c = match key {
+            Key::Char(c) => c,
+            Key::Esc if self.operator.is_some() || self.count > 0 || self.g || self.mark.is_some() => {
+                self.reset();
+                return Command::Ignore;
+            },
+            Key::Esc if self.mode == Mode::Visual => {
+                self.mode = Mode::Normal;
+                return Command::ExitVisual;
+            },
+            Key::Ctrl('r') => {
+                let count = self.take_count();
+                self.reset();
+                return Command::Redo(count);
+            },
+            Key::Ctrl('o') if self.operator.is_none() && self.mark.is_none() => {
+                let count = self.take_count();
+                self.reset();
+                return Command::JumpBack(count);
+            },
+            Key::Backspace => 'h',
+            Key::Delete => 'x',
+            _ => {
+                self.reset();
+                return Command::Pass;
+            },
+        };
+        if let Some(prefix) = self.mark {
+            self.reset();
+            if !c.is_ascii_lowercase() {
+                return Command::Ignore;
+            }
+            return match prefix {
+                'm' => Command::SetMark(c),
+                _ => Command::GotoMark { name: c, exact: prefix == '`' },
+            };
+        }
+        if c.is_ascii_digit() && (c != '0' || self.count > 0) && !self.g {
+            self.count = self.count.saturating_mul(10).saturating_add(c as usize - '0' as usize);
+            return Command::Pending;
+        }
+        let motion = if self.g {
+            self.g = false;
+            if c != 'g' {
+                self.reset();
+                return Command::Ignore;
+            }
+            Some(if self.count > 0 { Motion::Line(self.count) } else { Motion::FirstLine })
+        } else {
+            match c {
+                'h' => Some(Motion::Left),
+                'l' | ' ' => Some(Motion::Right),
+                'k' => Some(Motion::Up),
+                'j' | '\n' => Some(Motion::Down),
+                'w' => Some(Motion::WordForward),
+                'b' => Some(Motion::WordBackward),
+                'e' => Some(Motion::WordEnd),
+                '0' => Some(Motion::LineStart),
+                '$' => Some(Motion::LineEnd),
+                'G' if self.count > 0 => Some(Motion::Line(self.count)),
+                'G' => Some(Motion::LastLine),
+                'g' => {
+                    self.g = true;
+                    return Command::Pending;
+                },
+                _ => None,
+            }
+        };
+        if let Some(motion) = motion {
+            // 行の番号は数として使ったので、繰り返さない
+            let count = if let Motion::Line(_) = motion {
+                self.count = 0;
+                self.take_count();
+                1
+            } else {
+                self.take_count()
+            };
+            let command = match self.operator {
+                Some((operator, _)) => self.operate(operator, Target::Motion(motion), count),
+                None => Command::Move(motion, count),
+            };
+            self.reset();
+            return command;
+        }
+
+        let operator = match c {
+            'd' => Some(Operator::Delete),
+            'y' => Some(Operator::Yank),
+            'c' => Some(Operator::Change),
+            _ => None,
+        };
+        if let Some(operator) = operator {
+            if self.mode == Mode::Visual {
+                self.reset();
+                self.mode = if operator == Operator::Change { Mode::Insert } else { Mode::Normal };
+                return Command::OperateSelection(operator);
+            }
+            let command = match self.operator {
+                Some((pending, _)) if pending == operator => {
+                    let count = self.take_count();
+                    self.operate(operator, Target::Lines, count)
+                },
+                Some(_) => Command::Ignore,
+                None => {
+                    self.operator = Some((operator, self.count));
+                    self.count = 0;
+                    return Command::Pending;
+                },
+            };
+            self.reset();
+            return command;
+        }
+        // ここから下は演算子の後には打てない
+        if self.operator.is_some() {
+            self.reset();
+            return Command::Ignore;
+        }
+        // 印の名前は次のキーで読む
+        if matches!(c, 'm' | '\'' | '`') && self.mode == Mode::Normal {
+            self.count = 0;
+            self.mark = Some(c);
+            return Command::Pending;
+        }
+        let count = self.take_count();
+        self.reset();
+        if self.mode == Mode::Visual {
+            return match c {
+                'x' => {
+                    self.mode = Mode::Normal;
+                    Command::OperateSelection(Operator::Delete)
+                },
+                'v' => {
+                    self.mode = Mode::Normal;
+                    Command::ExitVisual
+                },
+                ':' => {
+                    self.mode = Mode::Normal;
+                    Command::RangePrompt
+                },
+                // 探したところまで選択を広げる
+                '/' => Command::Search,
+                'n' => Command::SearchNext,
+                _ => Command::Ignore,
+            };
+        }
+        let insert = match c {
+            'i' => Some(InsertAt::Cursor),
+            'a' => Some(InsertAt::After),
+            'I' => Some(InsertAt::LineStart),
+            'A' => Some(InsertAt::LineEnd),
+            'o' => Some(InsertAt::LineBelow),
+            'O' => Some(InsertAt::LineAbove),
+            _ => None,
+        };
+        if let Some(at) = insert {
+            self.mode = Mode::Insert;
+            return Command::Insert(at);
+        }
+        match c {
+            'D' => Command::Operate(Operator::Delete, Target::Motion(Motion::LineEnd), 1),
+            'C' => self.operate(Operator::Change, Target::Motion(Motion::LineEnd), 1),
+            'Y' => Command::Operate(Operator::Yank, Target::Lines, count),
+            'x' => Command::DeleteChar(count),
+            'p' => Command::Put { before: false, count },
+            'P' => Command::Put { before: true, count },
+            'u' => Command::Undo(count),
+            'v' => {
+                self.mode = Mode::Visual;
+                Command::Visual
+            },
+            ':' => Command::Prompt,
+            '/' => Command::Search,
+            'n' => Command::SearchNext,
+            '\t' => Command::JumpForward(count),
+            _ => Command::Ignore,
+        }
+    }
+
+    // c は変えた後に挿入モードに入る
+    fn operate(&mut self, operator: Operator, target: Target, count: usize) -> Command {
+        if operator == Operator::Change {
+            self.mode = Mode::Insert;
+        }
+        Command::Operate(operator, target, count)
+    }
+}
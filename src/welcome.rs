@@ -0,0 +1,61 @@
+use std::path::PathBuf;
+use crate::popup::Popup;
+
+// スタート画面で選べるもの
+pub enum Choice {
+    Open(PathBuf),
+    New,
+}
+
+// ファイルを指定せずに起動したときのスタート画面
+pub struct Welcome {
+    recent: Vec<PathBuf>,
+    // 選んでいる項目。最近のファイルの後に「新しいファイル」が続く
+    selected: usize,
+}
+
+impl Welcome {
+    pub fn new(recent: Vec<PathBuf>) -> Self {
+        Welcome { recent, selected: 0 }
+    }
+
+    pub fn up(&mut self) {
+        self.selected = self.selected.saturating_sub(1);
+    }
+
+    pub fn down(&mut self) {
+        self.selected = (self.selected + 1).min(self.recent.len());
+    }
+
+    pub fn choice(&self) -> Choice {
+        match self.recent.get(self.selected) {
+            Some(path) => Choice::Open(path.clone()),
+            None => Choice::New,
+        }
+    }
+
+    // 選んでいる項目の説明。読み上げ用の行に出す
+    pub fn selected_text(&self) -> String {
+        match self.recent.get(self.selected) {
+            Some(path) => path.display().to_string(),
+            None => tr!("welcome-new-file"),
+        }
+    }
+
+    pub fn popup(&self) -> Popup {
+        let mut lines = Vec::new();
+        if !self.recent.is_empty() {
+            lines.push(tr!("welcome-recent"));
+        }
+        let first = lines.len();
+        lines.extend(self.recent.iter().map(|path| format!("  {}", path.display())));
+        lines.push(format!("  {}", tr!("welcome-new-file")));
+        lines.push(String::new());
+        lines.push(tr!("welcome-keys"));
+        lines.push(tr!("welcome-commands"));
+        // パスは折り返さずに、窓に入らない分を切る
+        let mut popup = Popup::new(&format!("textedit {}", env!("CARGO_PKG_VERSION")), &lines.join("\n"), usize::MAX);
+        popup.selected = Some(first + self.selected);
+        popup
+    }
+}